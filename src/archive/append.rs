@@ -0,0 +1,304 @@
+//! Adding members to an existing uncompressed archive (`append` and `update`).
+
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use anyhow::{anyhow, Context, Result};
+use camino::Utf8Path;
+use tar::{Archive, Builder};
+
+use super::{
+    collect_entries, enforce_link, is_stdio, map_policy_error_for_create, print_plan, resolve_base,
+    resolve_hardlink_source, scan_members, write_entries, ArchiveEntry, CreateOptions, EntryKind,
+};
+use crate::error::UserInputError;
+use crate::io::Compression;
+use crate::manifest::{self, ManifestEntry};
+use crate::names;
+use crate::policy::{PolicyError, SecurityPolicy, UsageTracker, ValidatedPath};
+
+/// Which inputs [`append_archive`] adds to the archive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AppendMode {
+    /// Add every input, even when a member with the same name is already archived.
+    Append,
+    /// Add inputs that are not archived yet, or that are newer than and differ from the
+    /// archived copy.
+    Update,
+}
+
+/// Add members to the end of an uncompressed archive, creating it if it does not exist.
+///
+/// The existing members are read back and checked against `policy` together with the
+/// new ones, so the combined archive honours the same limits as one written by
+/// [`create_archive`](super::create_archive). `options.compression` must be
+/// [`Compression::None`]. Returns the manifest of the members that were added; when
+/// `options.manifest_out` is set, the manifest written there covers the whole archive.
+pub fn append_archive(
+    options: &CreateOptions,
+    mode: AppendMode,
+    policy: &SecurityPolicy,
+) -> Result<Vec<ManifestEntry>> {
+    if options.compression != Compression::None {
+        return Err(UserInputError::new("cannot append to a compressed archive").into());
+    }
+    if is_stdio(&options.archive_path) {
+        return Err(UserInputError::new(
+            "cannot append to stdin or stdout; append and update rewrite the archive in place",
+        )
+        .into());
+    }
+    let base = resolve_base(options.work_dir.as_ref())?;
+    let base_utf8 = names::from_path(&base, policy.byte_paths())
+        .with_context(|| format!("unusable working directory {}", base.display()))?;
+    let archive_fs_path = names::to_path(&options.archive_path);
+
+    let mut usage = policy.usage();
+    let existing = match File::open(&archive_fs_path) {
+        Ok(file) => scan_existing(&file, options, policy, &base_utf8, &mut usage)?,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => ExistingMembers::default(),
+        Err(err) => {
+            return Err(
+                anyhow!(err).context(format!("failed to open archive {}", options.archive_path))
+            )
+        }
+    };
+
+    let archive_abs = fs::canonicalize(&archive_fs_path).ok();
+    let mut candidates = collect_entries(options, policy, &base_utf8)?;
+    candidates.retain(|entry| {
+        let is_archive = archive_abs
+            .as_deref()
+            .is_some_and(|archive| names::to_path(&entry.absolute) == archive);
+        if is_archive && !options.quiet {
+            eprintln!(
+                "safetar: skipping {} (it is the archive)",
+                names::escaped(&entry.relative)
+            );
+        }
+        !is_archive
+    });
+    let candidate_items: Vec<_> = candidates
+        .iter()
+        .map(ArchiveEntry::to_manifest_item)
+        .collect();
+    let candidate_manifest: BTreeMap<_, _> = manifest::collect_manifest(&candidate_items)?
+        .into_iter()
+        .map(|entry| (manifest_key(&entry), entry))
+        .collect();
+
+    let mut entries = Vec::new();
+    let mut added = Vec::new();
+    for entry in candidates {
+        let key = (
+            names::escaped(&entry.relative),
+            names::base64(&entry.relative),
+        );
+        let manifest_entry = candidate_manifest
+            .get(&key)
+            .cloned()
+            .ok_or_else(|| anyhow!("no manifest entry for {}", key.0))?;
+        if mode == AppendMode::Update
+            && !is_update(&entry, &manifest_entry, existing.manifest.get(&key))
+        {
+            continue;
+        }
+        let validated = ValidatedPath {
+            rel: entry.relative.clone(),
+            abs: entry.absolute.clone(),
+        };
+        usage
+            .observe_sparse(&validated, entry.size, entry.stored_size())
+            .map_err(map_policy_error_for_create)?;
+        entries.push(entry);
+        added.push(manifest_entry);
+    }
+
+    if options.print_plan {
+        if !options.quiet {
+            print_plan(&entries);
+        }
+        return Ok(added);
+    }
+
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&archive_fs_path)
+        .with_context(|| format!("failed to open archive {}", options.archive_path))?;
+    // Drop the end-of-archive blocks (and any padding after them) before writing.
+    file.set_len(existing.end)
+        .and_then(|()| file.seek(SeekFrom::Start(existing.end)))
+        .with_context(|| format!("failed to rewind archive {}", options.archive_path))?;
+    let mut builder = Builder::new(io::BufWriter::new(file));
+    let done_message = match mode {
+        AppendMode::Append => "append complete",
+        AppendMode::Update => "update complete",
+    };
+    write_entries(&mut builder, &entries, options, done_message)?;
+    builder.finish().context("failed to finalise tar archive")?;
+    builder
+        .into_inner()
+        .context("failed to finalise tar builder")?
+        .flush()
+        .with_context(|| format!("failed to write archive {}", options.archive_path))?;
+
+    if let Some(manifest_path) = &options.manifest_out {
+        let mut combined = existing.manifest;
+        for entry in &added {
+            combined.insert(manifest_key(entry), entry.clone());
+        }
+        let combined: Vec<_> = combined.into_values().collect();
+        manifest::write_manifest_json(&combined, manifest_path)?;
+    }
+
+    Ok(added)
+}
+
+/// Members already in the archive.
+#[derive(Default)]
+struct ExistingMembers {
+    /// Manifest entry of the last member stored under each name.
+    manifest: BTreeMap<(String, Option<String>), ManifestEntry>,
+    /// Offset of the end-of-archive blocks, where new members are written.
+    end: u64,
+}
+
+/// Read back the members of an uncompressed archive, applying the policy to each.
+fn scan_existing(
+    file: &File,
+    options: &CreateOptions,
+    policy: &SecurityPolicy,
+    root: &Utf8Path,
+    usage: &mut UsageTracker,
+) -> Result<ExistingMembers> {
+    let mut magic = [0_u8; 8];
+    let read = file
+        .take(magic.len() as u64)
+        .read(&mut magic)
+        .with_context(|| format!("failed to read archive {}", options.archive_path))?;
+    let codec = Compression::detect(&magic[..read]);
+    if codec != Compression::None {
+        return Err(UserInputError::new(format!(
+            "{} is {codec}-compressed; append and update need an uncompressed archive",
+            options.archive_path
+        ))
+        .into());
+    }
+    let mut reader = file;
+    reader
+        .seek(SeekFrom::Start(0))
+        .with_context(|| format!("failed to read archive {}", options.archive_path))?;
+
+    let mut archive = Archive::new(TrackedReader::new(reader));
+    let mut manifest = BTreeMap::new();
+    let mut linkable = HashSet::new();
+    scan_members(&mut archive, |member| {
+        let validated = policy
+            .normalize_and_validate_bytes(&names::to_bytes(&member.path), root)
+            .map_err(map_policy_error_for_create)?;
+        usage
+            .observe_sparse(&validated, member.size, member.stored)
+            .map_err(map_policy_error_for_create)?;
+        if let Some(target) = &member.link_target {
+            names::from_bytes(&names::to_bytes(target), policy.byte_paths())
+                .map_err(|err| map_policy_error_for_create(PolicyError::InvalidName(err)))?;
+        }
+        match (member.kind, &member.link_target) {
+            (EntryKind::Symlink, Some(target)) => {
+                enforce_link(policy, root, &validated, target)?;
+            }
+            (EntryKind::Hardlink, Some(target)) => {
+                resolve_hardlink_source(policy, root, &validated, target, &linkable)
+                    .map_err(map_policy_error_for_create)?;
+                linkable.insert(validated.rel);
+            }
+            (EntryKind::File, _) => {
+                linkable.insert(validated.rel);
+            }
+            _ => {}
+        }
+        let mut entry = member.manifest;
+        entry.pax = None;
+        manifest.insert(manifest_key(&entry), entry);
+        Ok(())
+    })
+    .with_context(|| {
+        format!(
+            "failed to check existing members of {}",
+            options.archive_path
+        )
+    })?;
+
+    Ok(ExistingMembers {
+        manifest,
+        end: archive.into_inner().end_of_archive(),
+    })
+}
+
+/// Whether `update` should add `entry`, given the archived copy of the same name.
+///
+/// Archives written by safetar carry a fixed mtime unless times are preserved, so a
+/// newer file is only added when its contents (or kind) actually differ from the
+/// archived member.
+fn is_update(
+    entry: &ArchiveEntry,
+    candidate: &ManifestEntry,
+    archived: Option<&ManifestEntry>,
+) -> bool {
+    let Some(archived) = archived else {
+        return true;
+    };
+    if archived.kind == candidate.kind && archived.sha256 == candidate.sha256 {
+        return false;
+    }
+    let modified = entry
+        .mtime
+        .and_then(|mtime| mtime.duration_since(std::time::UNIX_EPOCH).ok())
+        .map_or(0, |since| since.as_secs());
+    modified > archived.mtime.unwrap_or(0)
+}
+
+fn manifest_key(entry: &ManifestEntry) -> (String, Option<String>) {
+    (entry.path.clone(), entry.path_b64.clone())
+}
+
+/// Reader that remembers how far `tar` read, to locate the end-of-archive blocks.
+struct TrackedReader<R> {
+    inner: R,
+    position: u64,
+    at_eof: bool,
+}
+
+impl<R> TrackedReader<R> {
+    fn new(inner: R) -> Self {
+        Self {
+            inner,
+            position: 0,
+            at_eof: false,
+        }
+    }
+
+    /// Offset just past the last member, once `tar` has stopped reading.
+    ///
+    /// `tar` stops after reading the first zero block, or at end of file when the
+    /// archive has no end-of-archive blocks at all.
+    fn end_of_archive(&self) -> u64 {
+        if self.at_eof {
+            self.position
+        } else {
+            self.position.saturating_sub(512)
+        }
+    }
+}
+
+impl<R: Read> Read for TrackedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.position += read as u64;
+        self.at_eof = read == 0 && !buf.is_empty();
+        Ok(read)
+    }
+}
@@ -0,0 +1,189 @@
+//! Checking every member of an archive against the policy without extracting (`audit`).
+
+use std::collections::HashSet;
+use std::fmt;
+
+use anyhow::{Context, Result};
+use camino::{Utf8Path, Utf8PathBuf};
+use serde::{Deserialize, Serialize};
+
+use super::raw::{RawMember, RawReader};
+use super::{
+    check_symlink_parents, enforce_link, open_archive_within, resolve_hardlink_source,
+    surface_input_limit, AuditOptions, EntryKind, VIRTUAL_ROOT,
+};
+use crate::names;
+use crate::policy::{PolicyError, SecurityPolicy, SpecialEntryAction, UsageTracker};
+
+/// One policy violation found in an archive.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct AuditFinding {
+    /// Member path as stored; bytes that are not valid UTF-8 appear as `\xHH`.
+    pub path: String,
+    /// Exact path bytes (base64) when the path is not valid UTF-8.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path_b64: Option<String>,
+    /// Offset of the member's first header block in the uncompressed stream.
+    pub offset: u64,
+    /// Name of the violated rule, see [`PolicyError::rule`].
+    pub rule: String,
+    pub detail: String,
+}
+
+impl fmt::Display for AuditFinding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}\t{}\t{}", self.path, self.rule, self.detail)
+    }
+}
+
+/// Check every member of `options.archive_path` as extraction would, collecting each
+/// violation instead of stopping at the first.
+///
+/// Only headers are read and nothing is written to disk. Each member's name is
+/// validated, and a member whose name is rejected is not checked further. Special
+/// entries are checked against their configured actions, and modes against the
+/// permission rules. Sizes count towards one [`UsageTracker`], and the file-count and
+/// total-size limits are reported once, at the member that crossed them. Link targets
+/// must stay inside the archive, and hard links must name an earlier member. No member
+/// may be written below an earlier symlink.
+///
+/// Errors other than policy violations, such as a corrupt archive, end the audit. So
+/// does an archive past the compression limits. Prints the report unless
+/// `options.quiet` is set and returns the findings in archive order.
+pub fn audit_archive(options: &AuditOptions, policy: &SecurityPolicy) -> Result<Vec<AuditFinding>> {
+    audit(options, policy).map_err(surface_input_limit)
+}
+
+fn audit(options: &AuditOptions, policy: &SecurityPolicy) -> Result<Vec<AuditFinding>> {
+    let reader = open_archive_within(&options.archive_path, policy.limits())?;
+    let mut members = RawReader::new(reader);
+    let mut audit = Audit {
+        policy,
+        usage: policy.usage(),
+        linkable: HashSet::new(),
+        symlinks: HashSet::new(),
+        exceeded: HashSet::new(),
+        findings: Vec::new(),
+    };
+    while let Some(member) = members
+        .next_member()
+        .with_context(|| format!("failed to read archive {}", options.archive_path))?
+    {
+        if !member.entry_type.is_pax_global_extensions() {
+            audit.member(&member)?;
+        }
+    }
+
+    if options.json && !options.quiet {
+        serde_json::to_writer_pretty(std::io::stdout(), &audit.findings)
+            .context("failed to render audit report")?;
+        println!();
+    } else if !options.quiet {
+        for finding in &audit.findings {
+            println!("{finding}");
+        }
+    }
+    Ok(audit.findings)
+}
+
+/// State of one audit, carried from member to member.
+struct Audit<'a> {
+    policy: &'a SecurityPolicy,
+    usage: UsageTracker,
+    /// Files and hard links seen so far, which later hard links may point at.
+    linkable: HashSet<Utf8PathBuf>,
+    /// Symlinks seen so far, which later members may not be written below.
+    symlinks: HashSet<Utf8PathBuf>,
+    /// Archive-wide limits already reported.
+    exceeded: HashSet<&'static str>,
+    findings: Vec<AuditFinding>,
+}
+
+impl Audit<'_> {
+    fn member(&mut self, member: &RawMember) -> Result<()> {
+        let root = Utf8Path::new(VIRTUAL_ROOT);
+        let validated = self
+            .policy
+            .normalize_and_validate_bytes(&names::to_bytes(&member.name), root);
+        let Some(validated) = self.check(member, validated.map_err(Into::into))? else {
+            return Ok(());
+        };
+        if let EntryKind::Special(kind) = member.kind {
+            let action = self.policy.check_special_entry(&validated.rel, kind);
+            // Skipped and rejected entries never count towards the limits.
+            if self.check(member, action.map_err(Into::into))? != Some(SpecialEntryAction::Create) {
+                return Ok(());
+            }
+        }
+        if let Some(mode) = member.mode.filter(|_| member.kind.has_own_mode()) {
+            let mode = self.policy.check_mode(&validated.rel, mode);
+            self.check(member, mode.map_err(Into::into))?;
+        }
+        let observed = self
+            .usage
+            .observe_sparse(&validated, member.size, member.stored);
+        self.check(member, observed.map_err(Into::into))?;
+        let below = check_symlink_parents(&self.symlinks, &validated.rel);
+        self.check(member, below.map_err(Into::into))?;
+
+        if let Some(target) = &member.link_target {
+            let target = names::from_bytes(&names::to_bytes(target), self.policy.byte_paths())
+                .map_err(PolicyError::InvalidName);
+            let Some(target) = self.check(member, target.map_err(Into::into))? else {
+                return Ok(());
+            };
+            match member.kind {
+                EntryKind::Symlink => {
+                    let linked = enforce_link(self.policy, root, &validated, &target);
+                    self.check(member, linked)?;
+                }
+                EntryKind::Hardlink => {
+                    let source = resolve_hardlink_source(
+                        self.policy,
+                        root,
+                        &validated,
+                        &target,
+                        &self.linkable,
+                    );
+                    self.check(member, source.map_err(Into::into))?;
+                }
+                _ => {}
+            }
+        }
+        if member.kind == EntryKind::Symlink {
+            self.symlinks.insert(validated.rel.clone());
+        } else {
+            self.symlinks.remove(&validated.rel);
+        }
+        if matches!(member.kind, EntryKind::File | EntryKind::Hardlink) {
+            self.linkable.insert(validated.rel);
+        }
+        Ok(())
+    }
+
+    /// Record a policy violation in `result` as a finding; other errors are returned.
+    fn check<T>(&mut self, member: &RawMember, result: Result<T>) -> Result<Option<T>> {
+        let err = match result {
+            Ok(value) => return Ok(Some(value)),
+            Err(err) => err,
+        };
+        let Some(violation) = err.downcast_ref::<PolicyError>() else {
+            return Err(err);
+        };
+        let rule = violation.rule();
+        let archive_wide = matches!(
+            violation,
+            PolicyError::FileCountExceeded { .. } | PolicyError::TotalBytesExceeded { .. }
+        );
+        if !archive_wide || self.exceeded.insert(rule) {
+            self.findings.push(AuditFinding {
+                path: names::escaped(&member.name),
+                path_b64: names::base64(&member.name),
+                offset: member.offset,
+                rule: rule.to_owned(),
+                detail: violation.to_string(),
+            });
+        }
+        Ok(None)
+    }
+}
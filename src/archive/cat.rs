@@ -0,0 +1,443 @@
+//! Writing the contents of archive members to stdout (`cat`).
+
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::io::{self, Read, Seek, Write};
+
+use anyhow::{anyhow, Context, Result};
+use camino::{Utf8Component, Utf8Path, Utf8PathBuf};
+use tar::Archive;
+use tempfile::SpooledTempFile;
+
+use super::header::classify_entry_type;
+use super::index::IndexedArchive;
+use super::pax::{self as pax_mod, PaxState};
+use super::raw::RawReader;
+use super::{
+    copy_hashing, entry_mtime, entry_sizes, is_stdio, map_policy_error_for_create, open_archive,
+    pax_sparse_layout, sparse, CatOptions, EntryKind, VIRTUAL_ROOT,
+};
+use crate::error::UserInputError;
+use crate::io::dec::InputBudget;
+use crate::manifest::{ManifestEntry, ManifestError, ManifestKind};
+use crate::names;
+use crate::policy::{PolicyError, SecurityPolicy, UsageTracker};
+
+/// Links followed while resolving one name, like the kernel's `ELOOP` limit.
+const MAX_LINK_HOPS: usize = 40;
+/// Member data held in memory while it is checked against a manifest; larger members
+/// are spooled to an anonymous temporary file.
+const SPOOL_IN_MEMORY: usize = 8 << 20;
+
+/// Write the contents of `options.members` to `out`, in archive order.
+///
+/// Names are resolved like paths inside the archive: symbolic links (in any component)
+/// and hard links are followed to the regular file they name, as long as every step
+/// stays inside the archive. Directories and other entries are refused. Every member
+/// that is written passes the same name and size checks as on extraction. With
+/// `options.manifest`, each member is checked against its SHA-256 before any of it is
+/// written.
+///
+/// Resolving links needs the member list first: it is read from `options.index` when
+/// given, and otherwise from an extra pass over the archive. An archive read from
+/// stdin gets a single pass, so its links cannot be followed.
+pub fn cat_members<W: Write>(
+    options: &CatOptions,
+    policy: &SecurityPolicy,
+    out: W,
+) -> Result<Vec<ManifestEntry>> {
+    if options.members.is_empty() {
+        return Err(UserInputError::new("cat needs at least one member name").into());
+    }
+    let root = Utf8Path::new(VIRTUAL_ROOT);
+    let operands = options
+        .members
+        .iter()
+        .map(|member| {
+            policy
+                .normalize_and_validate(Utf8Path::new(member), root)
+                .map(|validated| validated.rel)
+                .map_err(map_policy_error_for_create)
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let expected = options
+        .manifest
+        .as_deref()
+        .map(crate::manifest::read_manifest_json)
+        .transpose()?;
+    let indexed = options
+        .index
+        .as_deref()
+        .map(|index| IndexedArchive::open(&options.archive_path, index))
+        .transpose()?;
+
+    let wanted = if indexed.is_none() && is_stdio(&options.archive_path) {
+        Wanted::Names(operands.into_iter().map(|name| (name, false)).collect())
+    } else {
+        let catalog = match &indexed {
+            Some(indexed) => Catalog::from_index(indexed, policy)?,
+            None => Catalog::scan(&options.archive_path, policy)?,
+        };
+        let positions = operands
+            .iter()
+            .map(|operand| catalog.resolve(operand))
+            .collect::<Result<BTreeSet<_>>>()?;
+        Wanted::Positions(positions)
+    };
+
+    let mut writer = MemberWriter {
+        policy,
+        usage: policy.usage(),
+        expected: expected.as_deref(),
+        pax_state: PaxState::default(),
+        wanted,
+        out,
+        written: Vec::new(),
+    };
+    match &indexed {
+        Some(indexed) => {
+            for member in indexed.members()? {
+                if member.name.is_some() && !writer.wanted.has_position(member.offset) {
+                    continue;
+                }
+                let mut archive = indexed.archive_at(&member, &InputBudget::default())?;
+                let entry = archive
+                    .entries()?
+                    .next()
+                    .ok_or_else(|| anyhow!("no member at indexed offset"))??;
+                writer.entry(entry, member.offset)?;
+            }
+        }
+        None => {
+            let mut archive = Archive::new(open_archive(&options.archive_path)?);
+            let mut ordinal = 0;
+            for entry in archive.entries()? {
+                let entry = entry?;
+                let position = ordinal;
+                if !entry.header().entry_type().is_pax_global_extensions() {
+                    ordinal += 1;
+                }
+                writer.entry(entry, position)?;
+            }
+        }
+    }
+    writer.finish()
+}
+
+/// Which members to write: resolved positions, or (for stdin) names matched as read.
+enum Wanted {
+    /// Archive positions: member ordinals in a scan, offsets with an index.
+    Positions(BTreeSet<u64>),
+    /// Names and whether a member with that name was seen.
+    Names(HashMap<Utf8PathBuf, bool>),
+}
+
+impl Wanted {
+    fn has_position(&self, position: u64) -> bool {
+        match self {
+            Wanted::Positions(positions) => positions.contains(&position),
+            Wanted::Names(_) => true,
+        }
+    }
+}
+
+/// The member list, keyed by normalised name; later copies of a path replace earlier
+/// ones, as they would on extraction.
+struct Catalog {
+    members: HashMap<Utf8PathBuf, CatalogEntry>,
+}
+
+struct CatalogEntry {
+    kind: ManifestKind,
+    link_target: Option<Utf8PathBuf>,
+    position: u64,
+}
+
+impl Catalog {
+    fn scan(path: &Utf8Path, policy: &SecurityPolicy) -> Result<Self> {
+        let mut reader = RawReader::new(open_archive(path)?);
+        let mut members = HashMap::new();
+        let mut ordinal = 0;
+        while let Some(member) = reader
+            .next_member()
+            .with_context(|| format!("failed to read archive {path}"))?
+        {
+            if member.entry_type.is_pax_global_extensions() {
+                continue;
+            }
+            members.insert(
+                normalize(policy, &member.name)?,
+                CatalogEntry {
+                    kind: member.kind.manifest_kind(),
+                    link_target: member.link_target,
+                    position: ordinal,
+                },
+            );
+            ordinal += 1;
+        }
+        Ok(Self { members })
+    }
+
+    fn from_index(indexed: &IndexedArchive, policy: &SecurityPolicy) -> Result<Self> {
+        let mut members = HashMap::new();
+        for member in indexed.members()? {
+            let Some(name) = &member.name else {
+                continue;
+            };
+            // Links are followed on the index's word alone, so they are checked first.
+            if matches!(member.kind, ManifestKind::Symlink | ManifestKind::Hardlink) {
+                indexed.verify(&member)?;
+            }
+            members.insert(
+                normalize(policy, name)?,
+                CatalogEntry {
+                    kind: member.kind,
+                    link_target: member.link_target,
+                    position: member.offset,
+                },
+            );
+        }
+        Ok(Self { members })
+    }
+
+    /// Position of the regular file `operand` names, following links inside the archive.
+    fn resolve(&self, operand: &Utf8Path) -> Result<u64> {
+        let not_found = || UserInputError::new(format!("not found in archive: {operand}"));
+        let mut hops = 0;
+        let mut hop = || {
+            hops += 1;
+            if hops > MAX_LINK_HOPS {
+                Err(UserInputError::new(format!(
+                    "too many levels of links resolving {operand}"
+                )))
+            } else {
+                Ok(())
+            }
+        };
+
+        let mut pending: VecDeque<String> = components(operand);
+        let mut resolved = Utf8PathBuf::new();
+        while let Some(component) = pending.pop_front() {
+            match component.as_str() {
+                "" | "." => {}
+                ".." => {
+                    if !resolved.pop() {
+                        return Err(PolicyError::LinkOutsideRoot(operand.to_owned()).into());
+                    }
+                }
+                name => {
+                    let candidate = resolved.join(name);
+                    match self.members.get(&candidate) {
+                        Some(entry) if entry.kind == ManifestKind::Symlink => {
+                            hop()?;
+                            let target = entry
+                                .link_target
+                                .as_deref()
+                                .ok_or_else(|| anyhow!("symlink {candidate} has no target"))?;
+                            if target.is_absolute() {
+                                return Err(PolicyError::LinkOutsideRoot(target.to_owned()).into());
+                            }
+                            for component in components(target).into_iter().rev() {
+                                pending.push_front(component);
+                            }
+                        }
+                        _ => resolved = candidate,
+                    }
+                }
+            }
+        }
+
+        let mut entry = self.members.get(&resolved).ok_or_else(not_found)?;
+        // Hard links name their target from the archive root.
+        while entry.kind == ManifestKind::Hardlink {
+            hop()?;
+            let target = entry
+                .link_target
+                .as_deref()
+                .ok_or_else(|| anyhow!("hard link {resolved} has no target"))?;
+            resolved = normalize_link(target)?;
+            entry = self.members.get(&resolved).ok_or_else(not_found)?;
+        }
+        match entry.kind {
+            ManifestKind::File => Ok(entry.position),
+            ManifestKind::Directory => {
+                Err(UserInputError::new(format!("{operand} is a directory")).into())
+            }
+            _ => Err(UserInputError::new(format!("{operand} is not a regular file")).into()),
+        }
+    }
+}
+
+/// Streams the wanted members of an archive to the output.
+struct MemberWriter<'a, W> {
+    policy: &'a SecurityPolicy,
+    usage: UsageTracker,
+    expected: Option<&'a [ManifestEntry]>,
+    pax_state: PaxState,
+    wanted: Wanted,
+    out: W,
+    written: Vec<ManifestEntry>,
+}
+
+impl<W: Write> MemberWriter<'_, W> {
+    fn entry<R: Read>(&mut self, mut entry: tar::Entry<'_, R>, position: u64) -> Result<()> {
+        if entry.header().entry_type().is_pax_global_extensions() {
+            return self
+                .pax_state
+                .absorb_global(&mut entry)
+                .context("failed to parse global PAX header");
+        }
+        let header = entry.header().clone();
+        let kind = classify_entry_type(&header);
+        let raw_path = entry.path_bytes().into_owned();
+        let shown = names::escape_bytes(&raw_path);
+        let pax_meta = pax_mod::extract_pax_extensions(&mut entry, &self.pax_state)
+            .with_context(|| format!("failed to parse PAX records for {shown}"))?;
+        let pax_sparse = pax_sparse_layout(kind, &pax_meta, &shown)?;
+        let raw_path = match &pax_sparse {
+            Some(layout) => names::to_bytes(&layout.name).into_owned(),
+            None => raw_path,
+        };
+        let validated = self
+            .policy
+            .normalize_and_validate_bytes(&raw_path, Utf8Path::new(VIRTUAL_ROOT))
+            .map_err(map_policy_error_for_create)?;
+
+        match &mut self.wanted {
+            Wanted::Positions(positions) => {
+                if !positions.remove(&position) {
+                    return Ok(());
+                }
+                if kind != EntryKind::File {
+                    return Err(anyhow!(
+                        "{} is no longer a regular file; the archive changed while reading it",
+                        validated.rel
+                    ));
+                }
+            }
+            Wanted::Names(wanted) => {
+                let Some(seen) = wanted.get_mut(&validated.rel) else {
+                    return Ok(());
+                };
+                *seen = true;
+                match kind {
+                    EntryKind::File => {}
+                    EntryKind::Symlink | EntryKind::Hardlink => {
+                        return Err(UserInputError::new(format!(
+                            "{} is a link, which cannot be followed in an archive read from stdin",
+                            validated.rel
+                        ))
+                        .into());
+                    }
+                    _ => {
+                        return Err(UserInputError::new(format!(
+                            "{} is not a regular file",
+                            validated.rel
+                        ))
+                        .into());
+                    }
+                }
+            }
+        }
+
+        let (size, stored) = entry_sizes(&entry, &header, pax_sparse.as_ref());
+        self.usage
+            .observe_sparse(&validated, size, stored)
+            .map_err(map_policy_error_for_create)?;
+        let mtime = entry_mtime(&header, &pax_meta);
+        let mut data: Box<dyn Read + '_> = match &pax_sparse {
+            Some(layout) => {
+                let map = layout
+                    .read_map(&mut entry, stored)
+                    .with_context(|| format!("failed to read sparse map for {shown}"))?;
+                Box::new(sparse::ExpandReader::new(&mut entry, map))
+            }
+            None => Box::new(&mut entry),
+        };
+
+        let sha256 = match self.expected {
+            None => copy_hashing(&mut data, &mut self.out)
+                .with_context(|| format!("failed to write {}", validated.rel))?,
+            Some(expected) => {
+                let mut spool = SpooledTempFile::new(SPOOL_IN_MEMORY);
+                let sha256 = copy_hashing(&mut data, &mut spool)
+                    .with_context(|| format!("failed to read {}", validated.rel))?;
+                verify(expected, &validated.rel, &sha256)?;
+                spool.rewind()?;
+                io::copy(&mut spool, &mut self.out)
+                    .with_context(|| format!("failed to write {}", validated.rel))?;
+                sha256
+            }
+        };
+        self.written
+            .push(ManifestEntry::for_file(&validated.rel, size, sha256, mtime));
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<Vec<ManifestEntry>> {
+        self.out.flush()?;
+        match self.wanted {
+            Wanted::Positions(positions) if !positions.is_empty() => Err(anyhow!(
+                "archive ended before every member was read; it changed while reading it"
+            )),
+            Wanted::Names(wanted) => {
+                let mut missing: Vec<_> = wanted
+                    .iter()
+                    .filter(|(_, seen)| !**seen)
+                    .map(|(name, _)| name.as_str())
+                    .collect();
+                if missing.is_empty() {
+                    return Ok(self.written);
+                }
+                missing.sort_unstable();
+                Err(
+                    UserInputError::new(format!("not found in archive: {}", missing.join(", ")))
+                        .into(),
+                )
+            }
+            Wanted::Positions(_) => Ok(self.written),
+        }
+    }
+}
+
+/// Check `sha256` against the manifest entry for `rel`.
+fn verify(expected: &[ManifestEntry], rel: &Utf8Path, sha256: &str) -> Result<()> {
+    let path = names::escaped(rel);
+    let path_b64 = names::base64(rel);
+    let entry = expected
+        .iter()
+        .find(|entry| entry.path == path && entry.path_b64 == path_b64)
+        .ok_or_else(|| ManifestError::MissingEntry(path.clone()))?;
+    if entry.kind != ManifestKind::File || entry.sha256 != sha256 {
+        return Err(ManifestError::Mismatch {
+            path,
+            expected: entry.sha256.clone(),
+            actual: sha256.to_owned(),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+fn normalize(policy: &SecurityPolicy, name: &Utf8Path) -> Result<Utf8PathBuf> {
+    policy
+        .normalize_and_validate_bytes(&names::to_bytes(name), Utf8Path::new(VIRTUAL_ROOT))
+        .map(|validated| validated.rel)
+        .map_err(map_policy_error_for_create)
+}
+
+/// A hard link target as an archive-relative name; it must not leave the archive.
+fn normalize_link(target: &Utf8Path) -> Result<Utf8PathBuf> {
+    let mut resolved = Utf8PathBuf::new();
+    for component in target.components() {
+        match component {
+            Utf8Component::Normal(name) => resolved.push(name),
+            Utf8Component::CurDir => {}
+            _ => return Err(PolicyError::LinkOutsideRoot(target.to_owned()).into()),
+        }
+    }
+    Ok(resolved)
+}
+
+fn components(path: &Utf8Path) -> VecDeque<String> {
+    path.as_str().split('/').map(str::to_owned).collect()
+}
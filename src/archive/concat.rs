@@ -0,0 +1,173 @@
+//! Merging several archives into one (`concat`).
+
+use std::collections::HashSet;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use camino::{Utf8Path, Utf8PathBuf};
+
+use super::raw::RawReader;
+use super::{
+    enforce_link, is_stdio, map_policy_error_for_create, open_archive, report,
+    resolve_hardlink_source, stdout_archive, ConcatOptions, EntryKind, VIRTUAL_ROOT,
+};
+use crate::error::UserInputError;
+use crate::io::enc::{wrap_writer, CompressionWriter};
+use crate::names;
+use crate::policy::{PolicyError, SecurityPolicy, SpecialEntryAction};
+
+/// Stream the members of `options.inputs`, in order, into a new archive.
+///
+/// Inputs may use any supported codec; member data is copied without re-encoding.
+/// Long-name and PAX headers are rewritten to state the checked name once, so no
+/// reader can resolve a copied member differently. Every member is checked as
+/// extraction would check it: names and link targets against `policy`, special
+/// entries against its special-entry actions, and sizes against one
+/// [`UsageTracker`](crate::policy::UsageTracker) shared by all inputs. A path stored
+/// twice is rejected unless `options.allow_duplicates` is set; repeated directories
+/// are merged, keeping the first copy. Global PAX headers are dropped, because they
+/// would otherwise apply to the members of later inputs.
+///
+/// The output is staged next to its final location and only renamed into place once
+/// every member passed, so an input may also be the output. `-` reads one input from
+/// stdin or writes the output to stdout. Returns the merged member paths.
+pub fn concat_archives(
+    options: &ConcatOptions,
+    policy: &SecurityPolicy,
+) -> Result<Vec<Utf8PathBuf>> {
+    if options.inputs.is_empty() {
+        return Err(UserInputError::new("concat needs at least one input archive").into());
+    }
+    if options
+        .inputs
+        .iter()
+        .filter(|input| is_stdio(input))
+        .count()
+        > 1
+    {
+        return Err(UserInputError::new("stdin (`-`) can be merged only once").into());
+    }
+    if is_stdio(&options.archive_path) {
+        let writer = wrap_writer(stdout_archive()?, options.compression).with_context(|| {
+            format!("failed to initialise {:?} compressor", options.compression)
+        })?;
+        return merge_into(options, policy, writer);
+    }
+
+    let output = names::to_path(&options.archive_path);
+    let parent = match output.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let mut staging = tempfile::Builder::new();
+    staging.prefix(".safetar-concat-");
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        // Same mode `File::create` would use, after the umask.
+        staging.permissions(fs::Permissions::from_mode(0o666));
+    }
+    let staging = staging
+        .tempfile_in(parent)
+        .with_context(|| format!("failed to create a temporary file in {}", parent.display()))?;
+    let writer = wrap_writer(staging.as_file(), options.compression)
+        .with_context(|| format!("failed to initialise {:?} compressor", options.compression))?;
+    let merged = merge_into(options, policy, writer)?;
+
+    staging.as_file().sync_all()?;
+    staging
+        .persist(&output)
+        .map_err(|err| err.error)
+        .with_context(|| format!("failed to write archive {}", options.archive_path))?;
+    Ok(merged)
+}
+
+/// Copy every accepted member of `options.inputs` into `writer` and finish the archive.
+fn merge_into<W: Write>(
+    options: &ConcatOptions,
+    policy: &SecurityPolicy,
+    mut writer: CompressionWriter<W>,
+) -> Result<Vec<Utf8PathBuf>> {
+    let root = Utf8Path::new(VIRTUAL_ROOT);
+    let to_stdout = is_stdio(&options.archive_path);
+    let mut usage = policy.usage();
+    let mut paths = HashSet::new();
+    let mut directories = HashSet::new();
+    let mut linkable = HashSet::new();
+    let mut merged = Vec::new();
+    for input in &options.inputs {
+        let reader = open_archive(input)?;
+        let mut members = RawReader::new(reader);
+        while let Some(member) = members
+            .next_member()
+            .with_context(|| format!("failed to read archive {input}"))?
+        {
+            if member.entry_type.is_pax_global_extensions() {
+                if !options.quiet {
+                    eprintln!("safetar: dropping global PAX header from {input}");
+                }
+                continue;
+            }
+            let validated = policy
+                .normalize_and_validate_bytes(&names::to_bytes(&member.name), root)
+                .map_err(map_policy_error_for_create)?;
+            let shown = names::escaped(&validated.rel);
+            if let EntryKind::Special(kind) = member.kind {
+                let action = policy
+                    .check_special_entry(&validated.rel, kind)
+                    .map_err(map_policy_error_for_create)?;
+                if action == SpecialEntryAction::Skip {
+                    if !options.quiet {
+                        eprintln!("safetar: skipping {kind} {shown}");
+                    }
+                    continue;
+                }
+            }
+            if member.kind == EntryKind::Directory {
+                if !directories.insert(validated.rel.clone()) {
+                    continue;
+                }
+            } else if !paths.insert(validated.rel.clone()) && !options.allow_duplicates {
+                return Err(map_policy_error_for_create(PolicyError::DuplicatePath(
+                    validated.rel,
+                )));
+            }
+            usage
+                .observe_sparse(&validated, member.size, member.stored)
+                .map_err(map_policy_error_for_create)?;
+            if let Some(target) = &member.link_target {
+                let target = names::from_bytes(&names::to_bytes(target), policy.byte_paths())
+                    .map_err(|err| map_policy_error_for_create(PolicyError::InvalidName(err)))?;
+                match member.kind {
+                    EntryKind::Symlink => enforce_link(policy, root, &validated, &target)?,
+                    EntryKind::Hardlink => {
+                        resolve_hardlink_source(policy, root, &validated, &target, &linkable)
+                            .map_err(map_policy_error_for_create)?;
+                    }
+                    _ => {}
+                }
+            }
+            if matches!(member.kind, EntryKind::File | EntryKind::Hardlink) {
+                linkable.insert(validated.rel.clone());
+            }
+
+            if options.verbose && !options.quiet {
+                report(to_stdout, format_args!("adding {shown} from {input}"));
+            }
+            writer.write_all(&member.headers_named(&validated.rel)?)?;
+            members
+                .copy_data(&mut writer)
+                .with_context(|| format!("failed to copy {shown} from {input}"))?;
+            merged.push(validated.rel);
+        }
+    }
+
+    // End-of-archive marker: two zero blocks.
+    writer.write_all(&[0_u8; 1024])?;
+    writer
+        .finish()
+        .context("failed to finish compressed writer")?;
+    Ok(merged)
+}
@@ -0,0 +1,159 @@
+//! Removing members from an archive (`delete`).
+
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+
+use anyhow::{anyhow, Context, Result};
+use camino::{Utf8Path, Utf8PathBuf};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use tar::EntryType;
+
+use super::raw::RawReader;
+use super::{is_stdio, open_archive, report, stdout_archive, DeleteOptions};
+use crate::error::UserInputError;
+use crate::io::dec::wrap_reader;
+use crate::io::enc::{wrap_writer, CompressionWriter};
+use crate::names;
+
+/// Rewrite an archive without the members selected by `options.patterns`.
+///
+/// A pattern is a glob matched against member names; a member is also removed when
+/// a pattern matches one of its parent directories, so `delete docs` drops `docs/`
+/// and everything below it. The archive keeps its compression codec and is replaced
+/// atomically. Every pattern must match at least one member, otherwise the archive
+/// is left untouched. With `-` as the archive path, stdin is filtered to stdout
+/// instead. Returns the names of the removed members.
+pub fn delete_members(options: &DeleteOptions) -> Result<Vec<Utf8PathBuf>> {
+    let patterns = compile_patterns(&options.patterns)?;
+    if is_stdio(&options.archive_path) {
+        let reader = open_archive(&options.archive_path)?;
+        let codec = reader.codec();
+        let writer = wrap_writer(stdout_archive()?, codec)
+            .with_context(|| format!("failed to initialise {codec:?} compressor"))?;
+        return filter_members(options, &patterns, reader, writer);
+    }
+
+    let archive_path = fs::canonicalize(names::to_path(&options.archive_path))
+        .with_context(|| format!("failed to open archive {}", options.archive_path))?;
+    let archive_file = File::open(&archive_path)
+        .with_context(|| format!("failed to open archive {}", options.archive_path))?;
+    let permissions = archive_file.metadata()?.permissions();
+    let reader = wrap_reader(archive_file).context("failed to detect archive compression")?;
+    let codec = reader.codec();
+
+    let parent = archive_path
+        .parent()
+        .ok_or_else(|| anyhow!("archive {} has no parent directory", options.archive_path))?;
+    let staging = tempfile::Builder::new()
+        .prefix(".safetar-delete-")
+        .tempfile_in(parent)
+        .with_context(|| format!("failed to create a temporary file in {}", parent.display()))?;
+    let writer = wrap_writer(staging.as_file(), codec)
+        .with_context(|| format!("failed to initialise {codec:?} compressor"))?;
+    let removed = filter_members(options, &patterns, reader, writer)?;
+
+    staging.as_file().sync_all()?;
+    fs::set_permissions(staging.path(), permissions)?;
+    staging
+        .persist(&archive_path)
+        .map_err(|err| err.error)
+        .with_context(|| format!("failed to replace archive {}", options.archive_path))?;
+    Ok(removed)
+}
+
+/// Copy the members of `reader` that no pattern selects into `writer`, then finish
+/// the archive. Fails before finishing when a pattern matched nothing.
+fn filter_members<R: Read, W: Write>(
+    options: &DeleteOptions,
+    patterns: &GlobSet,
+    reader: R,
+    mut writer: CompressionWriter<W>,
+) -> Result<Vec<Utf8PathBuf>> {
+    let to_stdout = is_stdio(&options.archive_path);
+    let mut members = RawReader::new(reader);
+    let mut matched = vec![false; options.patterns.len()];
+    let mut removed = Vec::new();
+    let mut removed_files = HashSet::new();
+    while let Some(member) = members
+        .next_member()
+        .with_context(|| format!("failed to read archive {}", options.archive_path))?
+    {
+        let hits = selected_by(patterns, &member.name);
+        if !hits.is_empty() {
+            for index in hits {
+                matched[index] = true;
+            }
+            if options.verbose && !options.quiet {
+                report(
+                    to_stdout,
+                    format_args!("deleting {}", names::escaped(&member.name)),
+                );
+            }
+            removed_files.insert(member.name.clone());
+            removed.push(member.name);
+            continue;
+        }
+        if member.entry_type == EntryType::Link && !options.quiet {
+            if let Some(target) = member
+                .link_target
+                .as_ref()
+                .filter(|target| removed_files.contains(*target))
+            {
+                eprintln!(
+                    "safetar: hard link {} points at deleted member {}",
+                    names::escaped(&member.name),
+                    names::escaped(target)
+                );
+            }
+        }
+        writer.write_all(&member.header_blocks)?;
+        members
+            .copy_data(&mut writer)
+            .with_context(|| format!("failed to copy {}", names::escaped(&member.name)))?;
+    }
+
+    let unmatched: Vec<_> = options
+        .patterns
+        .iter()
+        .zip(&matched)
+        .filter(|(_, hit)| !**hit)
+        .map(|(pattern, _)| pattern.as_str())
+        .collect();
+    if !unmatched.is_empty() {
+        return Err(
+            UserInputError::new(format!("not found in archive: {}", unmatched.join(", "))).into(),
+        );
+    }
+
+    // End-of-archive marker: two zero blocks.
+    writer.write_all(&[0_u8; 1024])?;
+    writer
+        .finish()
+        .context("failed to finish compressed writer")?;
+    Ok(removed)
+}
+
+fn compile_patterns(patterns: &[String]) -> Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let trimmed = pattern.trim_end_matches('/');
+        let glob = Glob::new(if trimmed.is_empty() { pattern } else { trimmed })
+            .map_err(|err| UserInputError::new(format!("invalid pattern {pattern}: {err}")))?;
+        builder.add(glob);
+    }
+    Ok(builder.build()?)
+}
+
+/// Indices of the patterns matching `name` or one of its parent directories.
+fn selected_by(patterns: &GlobSet, name: &Utf8Path) -> Vec<usize> {
+    let trimmed = Utf8Path::new(name.as_str().trim_end_matches('/'));
+    let mut hits: Vec<usize> = trimmed
+        .ancestors()
+        .filter(|ancestor| !ancestor.as_str().is_empty())
+        .flat_map(|ancestor| patterns.matches(names::to_path(ancestor)))
+        .collect();
+    hits.sort_unstable();
+    hits.dedup();
+    hits
+}
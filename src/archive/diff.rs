@@ -0,0 +1,373 @@
+//! Comparing an archive with a directory tree (`diff`).
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::io;
+
+use anyhow::{anyhow, Context, Result};
+use camino::{Utf8Path, Utf8PathBuf};
+use serde::{Deserialize, Serialize};
+use tar::Archive;
+use walkdir::WalkDir;
+
+use super::{
+    map_policy_error_for_create, open_archive, resolve_destination, scan_members, special,
+    DiffOptions, EntryKind,
+};
+use crate::manifest::{self, ManifestEntry, ManifestItem, ManifestKind};
+use crate::names;
+use crate::policy::SecurityPolicy;
+
+/// One difference between an archive member and the file on disk.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct DiffEntry {
+    /// Member path; bytes that are not valid UTF-8 appear as `\xHH`.
+    pub path: String,
+    /// Exact path bytes (base64) when the path is not valid UTF-8.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path_b64: Option<String>,
+    pub kind: DiffKind,
+    /// Value recorded in the archive, when the difference has one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub archive: Option<String>,
+    /// Value found on disk, when the difference has one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub disk: Option<String>,
+}
+
+/// What differs between a member and the filesystem.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DiffKind {
+    /// The member does not exist on disk.
+    Missing,
+    /// The file on disk has a different type (e.g. a directory instead of a file).
+    Type,
+    Size,
+    /// SHA-256 of the contents differs.
+    Contents,
+    /// Permission bits differ.
+    Mode,
+    /// Modification time differs (whole seconds).
+    Mtime,
+    /// Symlink target differs, or a hard link no longer shares its target's inode.
+    LinkTarget,
+    /// The file exists on disk but not in the archive.
+    Extra,
+}
+
+impl fmt::Display for DiffEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let what = match self.kind {
+            DiffKind::Missing => "missing on disk",
+            DiffKind::Type => "type differs",
+            DiffKind::Size => "size differs",
+            DiffKind::Contents => "contents differ",
+            DiffKind::Mode => "mode differs",
+            DiffKind::Mtime => "mod time differs",
+            DiffKind::LinkTarget => "link target differs",
+            DiffKind::Extra => "not in archive",
+        };
+        write!(f, "{}: {what}", self.path)?;
+        if self.kind != DiffKind::Contents {
+            if let (Some(archive), Some(disk)) = (&self.archive, &self.disk) {
+                write!(f, " (archive {archive}, disk {disk})")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Compare the members of an archive with the tree under `options.directory`.
+///
+/// Member names are validated with `policy` before anything on disk is inspected, so a
+/// hostile archive cannot make `diff` read outside the directory. Contents are
+/// compared by SHA-256 only when sizes match; directories are compared by type and
+/// mode. When the archive stores a path more than once, the last copy is used, as it
+/// would be on extraction. Prints the report unless `options.quiet` is set and returns
+/// the differences found.
+pub fn diff_archive(options: &DiffOptions, policy: &SecurityPolicy) -> Result<Vec<DiffEntry>> {
+    let directory = resolve_destination(&options.directory)?;
+    if !directory.is_dir() {
+        return Err(anyhow!(
+            "{} is not a directory",
+            names::escaped(&options.directory)
+        ));
+    }
+    let root = names::from_path(&directory, policy.byte_paths())
+        .with_context(|| format!("unusable directory {}", directory.display()))?;
+
+    let reader = open_archive(&options.archive_path)?;
+    let mut archive = Archive::new(reader);
+    let mut members = BTreeMap::new();
+    scan_members(&mut archive, |member| {
+        let validated = policy
+            .normalize_and_validate_bytes(&names::to_bytes(&member.path), &root)
+            .map_err(map_policy_error_for_create)?;
+        if validated.rel.as_str().is_empty() {
+            return Ok(());
+        }
+        members.insert(
+            validated.rel.clone(),
+            Expected {
+                kind: member.kind,
+                abs: validated.abs,
+                link_target: member.link_target,
+                mode: member.mode,
+                manifest: member.manifest,
+            },
+        );
+        Ok(())
+    })?;
+
+    let mut differences = Vec::new();
+    let mut to_hash = Vec::new();
+    for (rel, expected) in &members {
+        let report = |kind, archive: Option<String>, disk: Option<String>| DiffEntry {
+            path: names::escaped(rel),
+            path_b64: names::base64(rel),
+            kind,
+            archive,
+            disk,
+        };
+        let metadata = match fs::symlink_metadata(names::to_path(&expected.abs)) {
+            Ok(metadata) => metadata,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                differences.push(report(DiffKind::Missing, None, None));
+                continue;
+            }
+            Err(err) => {
+                return Err(anyhow!(err).context(format!("failed to stat {}", expected.abs)));
+            }
+        };
+        let wanted = match expected.manifest.kind {
+            ManifestKind::Hardlink => ManifestKind::File,
+            ref kind => kind.clone(),
+        };
+        let found = disk_kind(&metadata);
+        if found != wanted {
+            differences.push(report(
+                DiffKind::Type,
+                Some(kind_name(&wanted).to_owned()),
+                Some(kind_name(&found).to_owned()),
+            ));
+            continue;
+        }
+
+        if expected.kind != EntryKind::Symlink {
+            if let (Some(archived), Some(actual)) = (expected.mode, permission_bits(&metadata)) {
+                if archived & 0o7777 != actual {
+                    differences.push(report(
+                        DiffKind::Mode,
+                        Some(format!("{:04o}", archived & 0o7777)),
+                        Some(format!("{actual:04o}")),
+                    ));
+                }
+            }
+        }
+
+        match expected.kind {
+            EntryKind::File => {
+                if expected.manifest.size != metadata.len() {
+                    differences.push(report(
+                        DiffKind::Size,
+                        Some(expected.manifest.size.to_string()),
+                        Some(metadata.len().to_string()),
+                    ));
+                } else {
+                    to_hash.push(ManifestItem {
+                        relative: rel.clone(),
+                        absolute: expected.abs.clone(),
+                        kind: ManifestKind::File,
+                        link_target: None,
+                        size: metadata.len(),
+                        mtime: metadata.modified().ok(),
+                        xattrs: None,
+                        outcome: None,
+                        backup: None,
+                    });
+                }
+                let disk_mtime = metadata
+                    .modified()
+                    .ok()
+                    .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+                    .map(|since| since.as_secs());
+                if let (Some(archived), Some(actual)) = (expected.manifest.mtime, disk_mtime) {
+                    if archived != actual {
+                        differences.push(report(
+                            DiffKind::Mtime,
+                            Some(archived.to_string()),
+                            Some(actual.to_string()),
+                        ));
+                    }
+                }
+            }
+            EntryKind::Symlink => {
+                let target = fs::read_link(names::to_path(&expected.abs))
+                    .with_context(|| format!("failed to read link {}", expected.abs))?;
+                let target = names::from_path(&target, true)?;
+                if expected.link_target.as_ref() != Some(&target) {
+                    differences.push(report(
+                        DiffKind::LinkTarget,
+                        expected.link_target.as_deref().map(names::escaped),
+                        Some(names::escaped(&target)),
+                    ));
+                }
+            }
+            EntryKind::Hardlink => {
+                let target = expected
+                    .link_target
+                    .as_ref()
+                    .ok_or_else(|| anyhow!("hard link {} has no target", expected.abs))?;
+                let source = root.join(target);
+                let linked = fs::metadata(names::to_path(&source))
+                    .is_ok_and(|source| same_file(&source, &metadata));
+                if !linked {
+                    differences.push(report(
+                        DiffKind::LinkTarget,
+                        Some(names::escaped(target)),
+                        None,
+                    ));
+                }
+            }
+            EntryKind::Directory | EntryKind::Special(_) => {}
+        }
+    }
+
+    // Hashed entries are keyed by the normalised path, which may differ from the name
+    // stored in the archive (`./a` versus `a`).
+    let archived: HashMap<_, _> = members
+        .iter()
+        .map(|(rel, expected)| {
+            let key = (names::escaped(rel), names::base64(rel));
+            (key, &expected.manifest.sha256)
+        })
+        .collect();
+    for actual in manifest::collect_manifest(&to_hash)? {
+        let key = (actual.path.clone(), actual.path_b64.clone());
+        let expected = archived
+            .get(&key)
+            .ok_or_else(|| anyhow!("no member for {}", actual.path))?;
+        if **expected != actual.sha256 {
+            differences.push(DiffEntry {
+                path: actual.path,
+                path_b64: actual.path_b64,
+                kind: DiffKind::Contents,
+                archive: Some((*expected).clone()),
+                disk: Some(actual.sha256),
+            });
+        }
+    }
+
+    if options.report_extra {
+        differences.extend(extra_files(&root, &members, policy)?);
+    }
+    differences.sort_by(|a, b| a.path.cmp(&b.path));
+
+    if options.json && !options.quiet {
+        serde_json::to_writer_pretty(std::io::stdout(), &differences)
+            .context("failed to render diff report")?;
+        println!();
+    } else if !options.quiet {
+        for difference in &differences {
+            println!("{difference}");
+        }
+    }
+    Ok(differences)
+}
+
+/// What the archive says about one path.
+struct Expected {
+    kind: EntryKind,
+    abs: Utf8PathBuf,
+    link_target: Option<Utf8PathBuf>,
+    mode: Option<u32>,
+    manifest: ManifestEntry,
+}
+
+/// Files under `root` that no member describes (parents of members are not extra).
+fn extra_files(
+    root: &Utf8Path,
+    members: &BTreeMap<Utf8PathBuf, Expected>,
+    policy: &SecurityPolicy,
+) -> Result<Vec<DiffEntry>> {
+    let covered: HashSet<&Utf8Path> = members.keys().flat_map(|rel| rel.ancestors()).collect();
+    let mut extra = Vec::new();
+    let mut walker = WalkDir::new(names::to_path(root)).min_depth(1).into_iter();
+    while let Some(entry) = walker.next() {
+        let entry = entry?;
+        let path = names::from_path(entry.path(), policy.byte_paths())
+            .with_context(|| format!("cannot compare {}", entry.path().display()))?;
+        let Ok(rel) = path.strip_prefix(root) else {
+            continue;
+        };
+        if covered.contains(rel) {
+            continue;
+        }
+        extra.push(DiffEntry {
+            path: names::escaped(rel),
+            path_b64: names::base64(rel),
+            kind: DiffKind::Extra,
+            archive: None,
+            disk: Some(kind_name(&disk_kind(&entry.metadata()?)).to_owned()),
+        });
+        // Everything below an unexpected directory is unexpected as well.
+        if entry.file_type().is_dir() {
+            walker.skip_current_dir();
+        }
+    }
+    Ok(extra)
+}
+
+fn disk_kind(metadata: &fs::Metadata) -> ManifestKind {
+    let file_type = metadata.file_type();
+    if file_type.is_file() {
+        ManifestKind::File
+    } else if file_type.is_dir() {
+        ManifestKind::Directory
+    } else if file_type.is_symlink() {
+        ManifestKind::Symlink
+    } else if let Some(kind) = special::special_kind_of(&file_type) {
+        special::manifest_kind(kind)
+    } else {
+        ManifestKind::Other
+    }
+}
+
+fn kind_name(kind: &ManifestKind) -> &'static str {
+    match kind {
+        ManifestKind::File => "file",
+        ManifestKind::Directory => "directory",
+        ManifestKind::Symlink => "symlink",
+        ManifestKind::Hardlink => "hardlink",
+        ManifestKind::CharDevice => "chardev",
+        ManifestKind::BlockDevice => "blockdev",
+        ManifestKind::Fifo => "fifo",
+        ManifestKind::Other => "other",
+    }
+}
+
+#[cfg(unix)]
+fn permission_bits(metadata: &fs::Metadata) -> Option<u32> {
+    use std::os::unix::fs::PermissionsExt;
+
+    Some(metadata.permissions().mode() & 0o7777)
+}
+
+#[cfg(not(unix))]
+fn permission_bits(_metadata: &fs::Metadata) -> Option<u32> {
+    None
+}
+
+#[cfg(unix)]
+fn same_file(a: &fs::Metadata, b: &fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    (a.dev(), a.ino()) == (b.dev(), b.ino())
+}
+
+#[cfg(not(unix))]
+fn same_file(_a: &fs::Metadata, _b: &fs::Metadata) -> bool {
+    true
+}
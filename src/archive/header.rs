@@ -1,8 +1,34 @@
 //! Helpers for working with tar headers.
 
+use anyhow::Result;
 use tar::{EntryType, Header};
 
+use super::owner::Owner;
+use super::pax::PaxTime;
 use super::EntryKind;
+use crate::error::UserInputError;
+use crate::policy::SpecialEntryKind;
+
+/// Largest size representable in the 11-digit octal ustar size field.
+const USTAR_MAX_SIZE: u64 = 0o77_777_777_777;
+/// Length of the ustar `name` and `linkname` fields.
+const USTAR_NAME_LEN: usize = 100;
+/// Largest id representable in the 7-digit octal ustar `uid` and `gid` fields.
+const USTAR_MAX_ID: u64 = 0o7_777_777;
+/// Length of the ustar `uname` and `gname` fields.
+const USTAR_OWNER_NAME_LEN: usize = 32;
+
+/// Header layout used when writing new archive members.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    /// Plain POSIX ustar; members that do not fit are rejected.
+    Ustar,
+    /// GNU headers with `././@LongLink` records and base-256 sizes.
+    #[default]
+    Gnu,
+    /// POSIX.1-2001 pax: ustar headers preceded by extended records when needed.
+    Pax,
+}
 
 /// Classify the entry type for convenience.
 #[must_use]
@@ -11,7 +37,167 @@ pub(crate) fn classify_entry_type(header: &Header) -> EntryKind {
         EntryType::Regular | EntryType::Continuous | EntryType::GNUSparse => EntryKind::File,
         EntryType::Directory => EntryKind::Directory,
         EntryType::Symlink => EntryKind::Symlink,
-        EntryType::Link => EntryKind::File,
-        _ => EntryKind::File,
+        EntryType::Link => EntryKind::Hardlink,
+        EntryType::Char => EntryKind::Special(SpecialEntryKind::CharDevice),
+        EntryType::Block => EntryKind::Special(SpecialEntryKind::BlockDevice),
+        EntryType::Fifo => EntryKind::Special(SpecialEntryKind::Fifo),
+        _ => EntryKind::Special(SpecialEntryKind::Unknown),
+    }
+}
+
+/// Create an empty header in the layout required by `format`.
+#[must_use]
+pub(crate) fn new_header(format: ArchiveFormat) -> Header {
+    match format {
+        ArchiveFormat::Gnu => Header::new_gnu(),
+        ArchiveFormat::Ustar | ArchiveFormat::Pax => Header::new_ustar(),
+    }
+}
+
+/// Compute the PAX records a member needs to be stored without loss.
+///
+/// Only values the ustar header cannot carry are emitted: names longer than the
+/// name field or containing non-ASCII bytes, sizes beyond 8 GiB, and timestamps with
+/// a sub-second component. Names that are not valid UTF-8 are stored verbatim, as GNU
+/// tar does (it warns about `hdrcharset`, so that record is not written).
+#[must_use]
+pub(crate) fn pax_records(
+    path: &[u8],
+    link_target: Option<&[u8]>,
+    size: u64,
+    mtime: Option<PaxTime>,
+) -> Vec<(&'static str, Vec<u8>)> {
+    let mut records = Vec::new();
+    if needs_pax_name(path) {
+        records.push(("path", path.to_vec()));
+    }
+    if let Some(target) = link_target {
+        if needs_pax_name(target) {
+            records.push(("linkpath", target.to_vec()));
+        }
+    }
+    if size > USTAR_MAX_SIZE {
+        records.push(("size", size.to_string().into_bytes()));
+    }
+    if let Some(mtime) = mtime {
+        if mtime.nanos != 0 || mtime.secs < 0 {
+            records.push(("mtime", mtime.to_string().into_bytes()));
+        }
+    }
+    records
+}
+
+/// Store `owner` in `header`, returning the PAX records for values it cannot hold.
+///
+/// Records are only produced for the pax format. GNU headers encode large ids in
+/// base-256 and plain ustar rejects them; names that do not fit either header are
+/// left out, since the ids still identify the owner.
+pub(crate) fn set_owner(
+    header: &mut Header,
+    owner: &Owner,
+    format: ArchiveFormat,
+) -> Result<Vec<(&'static str, Vec<u8>)>> {
+    let mut records = Vec::new();
+    for (key, id) in [("uid", owner.uid), ("gid", owner.gid)] {
+        if id > USTAR_MAX_ID {
+            match format {
+                ArchiveFormat::Ustar => {
+                    return Err(UserInputError::new(format!(
+                        "{key} {id} cannot be stored in ustar format; use --format pax"
+                    ))
+                    .into())
+                }
+                ArchiveFormat::Pax => records.push((key, id.to_string().into_bytes())),
+                ArchiveFormat::Gnu => {}
+            }
+        }
+    }
+    header.set_uid(owner.uid);
+    header.set_gid(owner.gid);
+    for (key, name) in [("uname", &owner.uname), ("gname", &owner.gname)] {
+        let Some(name) = name else {
+            continue;
+        };
+        if name.len() <= USTAR_OWNER_NAME_LEN && name.is_ascii() {
+            if key == "uname" {
+                header.set_username(name)?;
+            } else {
+                header.set_groupname(name)?;
+            }
+        } else if format == ArchiveFormat::Pax {
+            records.push((key, name.clone().into_bytes()));
+        }
+    }
+    Ok(records)
+}
+
+/// Whether `size` exceeds what a ustar header can represent.
+#[must_use]
+pub(crate) fn exceeds_ustar_size(size: u64) -> bool {
+    size > USTAR_MAX_SIZE
+}
+
+/// ASCII stand-in stored in the ustar name field when the real name lives in a PAX record.
+#[must_use]
+pub(crate) fn pax_fallback_name(path: &[u8]) -> String {
+    let ascii: String = String::from_utf8_lossy(path)
+        .chars()
+        .map(|c| if c.is_ascii() && c != '\0' { c } else { '_' })
+        .collect();
+    if ascii.len() <= USTAR_NAME_LEN {
+        return ascii;
+    }
+    // Keep the tail so the file name remains recognisable to pax-unaware readers.
+    ascii[ascii.len() - USTAR_NAME_LEN..]
+        .trim_start_matches('/')
+        .to_owned()
+}
+
+fn needs_pax_name(name: &[u8]) -> bool {
+    name.len() > USTAR_NAME_LEN || !name.is_ascii()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn short_ascii_member_needs_no_records() {
+        assert!(pax_records(b"dir/file.txt", None, 10, None).is_empty());
+    }
+
+    #[test]
+    fn records_cover_ustar_limits() {
+        let long = "a/".repeat(60) + "file";
+        let records = pax_records(
+            long.as_bytes(),
+            Some("zielort/dätei".as_bytes()),
+            USTAR_MAX_SIZE + 1,
+            Some(PaxTime {
+                secs: 5,
+                nanos: 500_000_000,
+            }),
+        );
+        let keys: Vec<_> = records.iter().map(|(key, _)| *key).collect();
+        assert_eq!(keys, ["path", "linkpath", "size", "mtime"]);
+        assert_eq!(records[3].1, b"5.5");
+    }
+
+    #[test]
+    fn fallback_name_fits_ustar() {
+        let long = format!("{}/näme.txt", "x".repeat(150));
+        let fallback = pax_fallback_name(long.as_bytes());
+        assert!(fallback.len() <= USTAR_NAME_LEN);
+        assert!(fallback.is_ascii());
+        assert!(fallback.ends_with("n_me.txt"));
+    }
+
+    #[test]
+    fn binary_names_are_stored_verbatim() {
+        let records = pax_records(b"caf\xe9.txt", None, 1, None);
+        let keys: Vec<_> = records.iter().map(|(key, _)| *key).collect();
+        assert_eq!(keys, ["path"]);
+        assert_eq!(records[0].1, b"caf\xe9.txt");
+        assert_eq!(pax_fallback_name(b"caf\xe9.txt"), "caf_.txt");
     }
 }
@@ -0,0 +1,288 @@
+//! Sidecar indexes of member offsets, for reaching members without a full scan (`index`).
+//!
+//! An index only says where members start. Whoever reads a member through it parses
+//! the headers found at that offset, checks that they give the indexed member's name,
+//! kind, and link target, and then applies the same policy checks as a sequential
+//! read. Links that are followed without being read are checked the same way, so a
+//! stale or tampered index can at worst make a read fail.
+
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Cursor, Read, Seek, SeekFrom, Write};
+
+use anyhow::{anyhow, Context, Result};
+use camino::{Utf8Path, Utf8PathBuf};
+use serde::{Deserialize, Serialize};
+use tar::Archive;
+
+use super::raw::{RawMember, RawReader};
+use super::{is_stdio, open_archive, IndexOptions};
+use crate::error::UserInputError;
+use crate::io::dec::InputBudget;
+use crate::io::seekable::SeekTable;
+use crate::io::Compression;
+use crate::manifest::ManifestKind;
+use crate::names;
+
+/// Current version of the index file format.
+pub const INDEX_VERSION: u32 = 1;
+
+/// Offsets of the members of one archive, written next to it as JSON.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ArchiveIndex {
+    pub version: u32,
+    /// `none` for plain tar, `zstd` for the zstd seekable format.
+    pub compression: String,
+    /// Size of the archive file when it was indexed; any other size means the index
+    /// is stale.
+    pub archive_size: u64,
+    /// Offsets of global PAX headers, which apply to every later member.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub globals: Vec<u64>,
+    pub members: Vec<IndexEntry>,
+}
+
+/// Where one member starts in the uncompressed tar stream.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct IndexEntry {
+    /// Member name as stored; bytes that are not valid UTF-8 appear as `\xHH`.
+    pub path: String,
+    /// Exact name bytes (base64) when the name is not valid UTF-8.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path_b64: Option<String>,
+    pub kind: ManifestKind,
+    pub size: u64,
+    /// Offset of the member's first header block, including extension headers.
+    pub offset: u64,
+    /// Link target of symbolic and hard links.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    /// Exact link target bytes (base64) when the target is not valid UTF-8.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target_b64: Option<String>,
+}
+
+/// Scan `options.archive_path` and write the offsets of its members to
+/// `options.output`.
+///
+/// Only uncompressed archives and zstd archives in the seekable format (written by
+/// `create --zstd --seekable`) can be indexed; other codecs cannot be entered in the
+/// middle of the stream.
+pub fn index_archive(options: &IndexOptions) -> Result<ArchiveIndex> {
+    let (archive_size, compression, _) = open_seekable(&options.archive_path)?;
+    let reader = open_archive(&options.archive_path)?;
+    let mut members = RawReader::new(reader);
+    let mut index = ArchiveIndex {
+        version: INDEX_VERSION,
+        compression: compression.to_string(),
+        archive_size,
+        globals: Vec::new(),
+        members: Vec::new(),
+    };
+    while let Some(member) = members
+        .next_member()
+        .with_context(|| format!("failed to read archive {}", options.archive_path))?
+    {
+        if member.entry_type.is_pax_global_extensions() {
+            index.globals.push(member.offset);
+            continue;
+        }
+        index.members.push(IndexEntry {
+            path: names::escaped(&member.name),
+            path_b64: names::base64(&member.name),
+            kind: member.kind.manifest_kind(),
+            size: member.size,
+            offset: member.offset,
+            target: member.link_target.as_deref().map(names::escaped),
+            target_b64: member.link_target.as_deref().and_then(names::base64),
+        });
+    }
+
+    let file = File::create(names::to_path(&options.output))
+        .with_context(|| format!("failed to create index {}", options.output))?;
+    let mut writer = BufWriter::new(file);
+    // Indexes of large archives are large too, so they are written compactly.
+    serde_json::to_writer(&mut writer, &index)
+        .with_context(|| format!("failed to encode index {}", options.output))?;
+    writer.flush().context("failed to flush index writer")?;
+    Ok(index)
+}
+
+/// Read an index written by [`index_archive`].
+pub fn read_index(path: &Utf8Path) -> Result<ArchiveIndex> {
+    let file =
+        File::open(names::to_path(path)).with_context(|| format!("failed to open index {path}"))?;
+    let index: ArchiveIndex = serde_json::from_reader(BufReader::new(file))
+        .with_context(|| format!("failed to decode index {path}"))?;
+    if index.version != INDEX_VERSION {
+        return Err(UserInputError::new(format!(
+            "index {path} has version {}, expected {INDEX_VERSION}",
+            index.version
+        ))
+        .into());
+    }
+    Ok(index)
+}
+
+/// Reader over the uncompressed stream, starting at a member's headers.
+pub(crate) type MemberReader = io::Chain<Cursor<Vec<u8>>, Box<dyn Read>>;
+
+/// One step of an indexed read: a global PAX header or a member.
+pub(crate) struct IndexedMember {
+    pub offset: u64,
+    /// Stored member name; `None` for global PAX headers.
+    pub name: Option<Utf8PathBuf>,
+    pub kind: ManifestKind,
+    pub link_target: Option<Utf8PathBuf>,
+}
+
+/// An archive opened together with its index.
+pub(crate) struct IndexedArchive {
+    path: Utf8PathBuf,
+    index: ArchiveIndex,
+    table: Option<SeekTable>,
+}
+
+impl IndexedArchive {
+    pub fn open(archive_path: &Utf8Path, index_path: &Utf8Path) -> Result<Self> {
+        let index = read_index(index_path)?;
+        let (archive_size, compression, table) = open_seekable(archive_path)?;
+        if archive_size != index.archive_size || compression.to_string() != index.compression {
+            return Err(UserInputError::new(format!(
+                "index {index_path} does not match {archive_path}; run `safetar index` again"
+            ))
+            .into());
+        }
+        Ok(Self {
+            path: archive_path.to_owned(),
+            index,
+            table,
+        })
+    }
+
+    /// Global headers and members in archive order.
+    pub fn members(&self) -> Result<Vec<IndexedMember>> {
+        let globals = self.index.globals.iter().map(|&offset| {
+            Ok(IndexedMember {
+                offset,
+                name: None,
+                kind: ManifestKind::Other,
+                link_target: None,
+            })
+        });
+        let members = self.index.members.iter().map(|entry| {
+            let link_target = entry
+                .target
+                .as_deref()
+                .map(|target| decode_name(target, entry.target_b64.as_deref()))
+                .transpose()?;
+            Ok(IndexedMember {
+                offset: entry.offset,
+                name: Some(decode_name(&entry.path, entry.path_b64.as_deref())?),
+                kind: entry.kind.clone(),
+                link_target,
+            })
+        });
+        let mut all = globals.chain(members).collect::<Result<Vec<_>>>()?;
+        all.sort_by_key(|member| member.offset);
+        Ok(all)
+    }
+
+    /// Check that the headers at `member`'s offset still describe it, for callers
+    /// that act on what the index says about a member without reading it.
+    pub fn verify(&self, member: &IndexedMember) -> Result<()> {
+        self.archive_at(member, &InputBudget::default()).map(drop)
+    }
+
+    /// An archive whose first entry is `member`, read from its offset.
+    ///
+    /// The headers there must give `member`'s name, kind, and link target; anything
+    /// else means the archive changed since it was indexed. Reads count against `budget`, which the caller
+    /// shares across every member it reads.
+    pub fn archive_at(
+        &self,
+        member: &IndexedMember,
+        budget: &InputBudget,
+    ) -> Result<Archive<MemberReader>> {
+        let mut file = File::open(names::to_path(&self.path))
+            .with_context(|| format!("failed to open archive {}", self.path))?;
+        let reader: Box<dyn Read> = match &self.table {
+            Some(table) => Box::new(table.reader_at(file, member.offset, budget)?),
+            None => {
+                file.seek(SeekFrom::Start(member.offset))?;
+                Box::new(budget.limited(BufReader::new(budget.counted(file))))
+            }
+        };
+        let mut headers = RawReader::new(reader);
+        let found = headers
+            .next_member()
+            .with_context(|| format!("failed to read archive {}", self.path))?
+            .filter(|found| describes(found, member));
+        let Some(found) = found else {
+            return Err(anyhow!(
+                "{} changed since it was indexed (no {} at offset {}); run `safetar index` again",
+                self.path,
+                member
+                    .name
+                    .as_deref()
+                    .map_or_else(|| "global header".to_owned(), names::escaped),
+                member.offset
+            ));
+        };
+        Ok(Archive::new(
+            Cursor::new(found.header_blocks).chain(headers.into_inner()),
+        ))
+    }
+}
+
+/// A name as written to the index: escaped text, plus base64 for raw bytes.
+fn decode_name(text: &str, base64: Option<&str>) -> Result<Utf8PathBuf> {
+    match base64 {
+        Some(encoded) => names::from_base64(encoded)
+            .ok_or_else(|| anyhow!("index has an invalid name for {text}")),
+        None => Ok(Utf8PathBuf::from(text)),
+    }
+}
+
+fn describes(found: &RawMember, member: &IndexedMember) -> bool {
+    let global = found.entry_type.is_pax_global_extensions();
+    match &member.name {
+        Some(name) => {
+            !global
+                && found.name == *name
+                && found.kind.manifest_kind() == member.kind
+                && found.link_target == member.link_target
+        }
+        None => global,
+    }
+}
+
+/// Size, codec, and (for zstd) seek table of an archive that supports random access.
+fn open_seekable(path: &Utf8Path) -> Result<(u64, Compression, Option<SeekTable>)> {
+    if is_stdio(path) {
+        return Err(UserInputError::new("indexes need an archive file, not stdin").into());
+    }
+    let mut file = File::open(names::to_path(path))
+        .with_context(|| format!("failed to open archive {path}"))?;
+    let size = file.metadata()?.len();
+    let mut magic = Vec::with_capacity(8);
+    (&mut file).take(8).read_to_end(&mut magic)?;
+    match Compression::detect(&magic) {
+        Compression::None => Ok((size, Compression::None, None)),
+        Compression::Zstd => {
+            let table = SeekTable::read(&mut file)
+                .with_context(|| format!("failed to read the seek table of {path}"))?
+                .ok_or_else(|| {
+                    UserInputError::new(format!(
+                        "{path} is not in the zstd seekable format; \
+                         recreate it with `safetar create --zstd --seekable`"
+                    ))
+                })?;
+            Ok((size, Compression::Zstd, Some(table)))
+        }
+        codec => Err(UserInputError::new(format!(
+            "{codec} archives cannot be indexed; use an uncompressed archive \
+             or `safetar create --zstd --seekable`"
+        ))
+        .into()),
+    }
+}
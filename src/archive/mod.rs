@@ -1,8 +1,9 @@
-//! Archive creation, extraction, and listing logic.
+//! Archive creation, extraction, listing, and rewriting logic.
 
 use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs::{self, File};
-use std::io::{self, Read};
+use std::io::{self, IsTerminal, Read};
 use std::time::Duration;
 
 use anyhow::{anyhow, Context, Result};
@@ -12,18 +13,64 @@ use sha2::{Digest, Sha256};
 use tar::{Archive, Builder, EntryType, HeaderMode};
 use walkdir::WalkDir;
 
-use self::pax as pax_mod;
+pub use self::append::{append_archive, AppendMode};
+pub use self::audit::{audit_archive, AuditFinding};
+pub use self::cat::cat_members;
+pub use self::concat::concat_archives;
+pub use self::delete::delete_members;
+pub use self::diff::{diff_archive, DiffEntry, DiffKind};
+pub use self::header::ArchiveFormat;
+use self::index::IndexedArchive;
+pub use self::index::{index_archive, read_index, ArchiveIndex, IndexEntry, INDEX_VERSION};
+use self::overwrite::Placement;
+pub use self::overwrite::{BackupMode, OverwriteMode};
+use self::owner::{Owner, Owners};
+use self::pax::{self as pax_mod, PaxMetadata, PaxState, PaxTime};
+use self::rename::Renamer;
+use self::rooted::{Location, Root};
+use self::select::Selector;
+use self::staging::Staging;
+use self::xattr::XattrSelection;
 use crate::archive::header::classify_entry_type;
 use crate::error::UserInputError;
-use crate::io::dec::wrap_reader;
-use crate::io::enc::wrap_writer;
+use crate::io::dec::{wrap_reader, CompressionReader, InputBudget};
+use crate::io::enc::{wrap_seekable_writer, wrap_writer};
 use crate::io::Compression;
-use crate::manifest::{self, ManifestEntry, ManifestItem, ManifestKind};
-use crate::policy::{LinkType, PolicyError, SecurityPolicy, UsageTracker};
+use crate::manifest::{self, ExtractOutcome, ManifestEntry, ManifestItem, ManifestKind};
+use crate::names;
+use crate::policy::{
+    LinkType, PolicyError, PolicyLimits, SecurityPolicy, SpecialEntryAction, SpecialEntryKind,
+    UsageTracker,
+};
 use indicatif::{ProgressBar, ProgressStyle};
+use path_clean::PathClean;
 
+mod append;
+mod audit;
+mod cat;
+mod concat;
+mod delete;
+mod diff;
 pub mod header;
+mod index;
+mod overwrite;
+mod owner;
 pub mod pax;
+mod raw;
+mod rename;
+mod rooted;
+mod select;
+mod sparse;
+mod special;
+mod staging;
+mod xattr;
+
+/// Stand-in extraction root for validating member names and link targets when no
+/// destination exists (`concat`, `cat`).
+///
+/// The NUL byte keeps every absolute link target outside this root, since no
+/// filesystem path can contain one.
+const VIRTUAL_ROOT: &str = "/\0archive";
 
 /// Options that steer archive creation.
 #[derive(Debug, Clone)]
@@ -32,14 +79,32 @@ pub struct CreateOptions {
     pub inputs: Vec<Utf8PathBuf>,
     pub work_dir: Option<Utf8PathBuf>,
     pub compression: Compression,
+    pub format: ArchiveFormat,
     pub verbose: bool,
     pub quiet: bool,
     pub print_plan: bool,
     pub excludes: Vec<String>,
     pub exclude_from: Vec<Utf8PathBuf>,
     pub manifest_out: Option<Utf8PathBuf>,
+    /// Record owners by id only, without user and group names.
     pub numeric_owner: bool,
+    /// Record every member as owned by root (0:0) instead of by its file's owner.
     pub no_same_owner: bool,
+    pub owners: OwnerMapping,
+    /// Archive devices and FIFOs instead of skipping them with a warning.
+    pub include_special: bool,
+    /// Store holes in sparse files as sparse entries instead of runs of zeros.
+    pub sparse: bool,
+    pub rewrite: NameRewrite,
+    /// Write zstd in the seekable format, so the archive can be indexed.
+    pub seekable: bool,
+    /// Record each member's modification time instead of a fixed one; the pax format
+    /// also keeps sub-second precision and access times.
+    pub preserve_times: bool,
+    /// Record extended attributes other than ACLs (pax format only).
+    pub xattrs: bool,
+    /// Record POSIX ACLs (pax format only).
+    pub acls: bool,
 }
 
 /// Options that steer archive extraction.
@@ -52,8 +117,36 @@ pub struct ExtractOptions {
     pub strict: bool,
     pub manifest: Option<Utf8PathBuf>,
     pub manifest_relaxed: bool,
+    /// Restore owners by id, ignoring the user and group names in the archive.
     pub numeric_owner: bool,
+    /// Leave extracted files owned by the extracting user; owners are otherwise
+    /// restored when running as root.
     pub no_same_owner: bool,
+    pub owners: OwnerMapping,
+    pub selection: MemberSelection,
+    pub rewrite: NameRewrite,
+    /// Index written by [`index_archive`]; selected members are read from their
+    /// offsets instead of scanning the whole archive.
+    pub index: Option<Utf8PathBuf>,
+    /// Leave modification times at the time of extraction instead of restoring the
+    /// archived ones (`tar -m`).
+    pub touch: bool,
+    /// Restore extended attributes other than ACLs that the policy allows.
+    pub xattrs: bool,
+    /// Restore POSIX ACLs.
+    pub acls: bool,
+    /// What to do when a member's path already exists.
+    pub overwrite: OverwriteMode,
+    /// Keep the files that extraction replaces, renamed.
+    pub backup: Option<BackupMode>,
+    /// Create symlinks only after every other member, instead of rejecting members
+    /// below a symlink the archive created earlier. Those members then land in a real
+    /// directory, and the symlink is left out.
+    pub delay_symlinks: bool,
+    /// Extract into a staging directory next to the destination and move the members
+    /// into place only once every check, the manifest included, has passed. Requires
+    /// [`OverwriteMode::Replace`] and no backups.
+    pub atomic: bool,
 }
 
 /// Options for listing archives.
@@ -63,6 +156,111 @@ pub struct ListOptions {
     pub verbose: bool,
     pub quiet: bool,
     pub json: bool,
+    pub selection: MemberSelection,
+    /// Only the compression limits apply to listing.
+    pub limits: PolicyLimits,
+}
+
+/// Which members `extract` and `list` act on; the default selects every member.
+#[derive(Debug, Clone, Default)]
+pub struct MemberSelection {
+    /// Member names (or globs, with `wildcards`); a directory selects its contents.
+    pub members: Vec<String>,
+    /// Files listing further members, one per line.
+    pub files_from: Vec<Utf8PathBuf>,
+    /// Treat `members` as glob patterns instead of literal names.
+    pub wildcards: bool,
+    pub excludes: Vec<String>,
+    pub exclude_from: Vec<Utf8PathBuf>,
+}
+
+/// How member names are rewritten before they are validated; the default keeps them.
+#[derive(Debug, Clone, Default)]
+pub struct NameRewrite {
+    /// Leading components to remove; members with nothing left are skipped.
+    pub strip_components: usize,
+    /// sed-style `s/regex/replacement/flags` expressions, applied in order.
+    pub transforms: Vec<String>,
+}
+
+/// How member owners are translated on create and extract; the default keeps them.
+#[derive(Debug, Clone, Default)]
+pub struct OwnerMapping {
+    /// File of `OLD NEW` lines translating user names or `+UID`s, as for GNU tar's
+    /// `--owner-map`.
+    pub owner_map: Option<Utf8PathBuf>,
+    /// Same as `owner_map`, for groups.
+    pub group_map: Option<Utf8PathBuf>,
+    /// Added to every uid after mapping, e.g. to move between user namespaces.
+    /// Shifted owners are numeric.
+    pub uid_shift: i64,
+    /// Added to every gid after mapping.
+    pub gid_shift: i64,
+}
+
+/// Options for deleting archive members.
+#[derive(Debug, Clone)]
+pub struct DeleteOptions {
+    pub archive_path: Utf8PathBuf,
+    /// Globs selecting the members to remove.
+    pub patterns: Vec<String>,
+    pub verbose: bool,
+    pub quiet: bool,
+}
+
+/// Options for merging archives into one.
+#[derive(Debug, Clone)]
+pub struct ConcatOptions {
+    /// Archive to write.
+    pub archive_path: Utf8PathBuf,
+    /// Archives to merge, in order; any supported codec.
+    pub inputs: Vec<Utf8PathBuf>,
+    pub compression: Compression,
+    /// Keep every copy of a path stored by more than one member.
+    pub allow_duplicates: bool,
+    pub verbose: bool,
+    pub quiet: bool,
+}
+
+/// Options for comparing an archive with a directory.
+#[derive(Debug, Clone)]
+pub struct DiffOptions {
+    pub archive_path: Utf8PathBuf,
+    /// Directory the members are compared against.
+    pub directory: Utf8PathBuf,
+    /// Also report files on disk that are not in the archive.
+    pub report_extra: bool,
+    pub quiet: bool,
+    pub json: bool,
+}
+
+/// Options for auditing an archive against the policy.
+#[derive(Debug, Clone)]
+pub struct AuditOptions {
+    pub archive_path: Utf8PathBuf,
+    pub quiet: bool,
+    pub json: bool,
+}
+
+/// Options for writing members to stdout.
+#[derive(Debug, Clone)]
+pub struct CatOptions {
+    pub archive_path: Utf8PathBuf,
+    /// Names of the members to write; links are followed inside the archive.
+    pub members: Vec<String>,
+    /// Manifest each member must match before it is written.
+    pub manifest: Option<Utf8PathBuf>,
+    /// Index written by [`index_archive`], used to find members without a scan.
+    pub index: Option<Utf8PathBuf>,
+}
+
+/// Options for indexing an archive.
+#[derive(Debug, Clone)]
+pub struct IndexOptions {
+    /// Uncompressed or seekable zstd archive to index.
+    pub archive_path: Utf8PathBuf,
+    /// Index file to write.
+    pub output: Utf8PathBuf,
 }
 
 /// Create an archive.
@@ -71,16 +269,68 @@ pub fn create_archive(
     policy: &SecurityPolicy,
 ) -> Result<Vec<ManifestEntry>> {
     let base = resolve_base(options.work_dir.as_ref())?;
-    let base_utf8 = Utf8PathBuf::from_path_buf(base.clone())
-        .map_err(|_| anyhow!("working directory not valid UTF-8: {}", base.display()))?;
-    let exclude_set = compile_excludes(&options.excludes, &options.exclude_from)?;
-    let mut usage = policy.usage();
+    let base_utf8 = names::from_path(&base, policy.byte_paths())
+        .with_context(|| format!("unusable working directory {}", base.display()))?;
+    let entries = collect_entries(options, policy, &base_utf8)?;
+    if options.print_plan && !options.quiet {
+        print_plan(&entries);
+    }
+
+    let manifest_inputs: Vec<_> = entries
+        .iter()
+        .map(|entry| entry.to_manifest_item())
+        .collect();
+    let manifest_entries = manifest::collect_manifest(&manifest_inputs)?;
 
-    let mut entries = Vec::new();
+    if options.print_plan {
+        return Ok(manifest_entries);
+    }
+
+    let output = create_output(&options.archive_path)?;
+    let writer = if options.seekable {
+        if options.compression != Compression::Zstd {
+            return Err(UserInputError::new("--seekable requires --zstd").into());
+        }
+        wrap_seekable_writer(output)
+    } else {
+        wrap_writer(output, options.compression)
+    }
+    .with_context(|| format!("failed to initialise {:?} compressor", options.compression))?;
+    let mut builder = Builder::new(writer);
+    write_entries(&mut builder, &entries, options, "create complete")?;
+
+    builder.finish().context("failed to finalise tar archive")?;
+    let writer = builder
+        .into_inner()
+        .context("failed to finalise tar builder")?;
+    writer
+        .finish()
+        .context("failed to finish compressed writer")?;
+
+    if let Some(manifest_path) = &options.manifest_out {
+        manifest::write_manifest_json(&manifest_entries, manifest_path)?;
+    }
+
+    Ok(manifest_entries)
+}
+
+/// Walk the create inputs, applying excludes and the security policy.
+fn collect_entries(
+    options: &CreateOptions,
+    policy: &SecurityPolicy,
+    base: &Utf8Path,
+) -> Result<Vec<ArchiveEntry>> {
+    let exclude_set = compile_excludes(&options.excludes, &options.exclude_from)?;
+    let mut state = WalkState {
+        usage: policy.usage(),
+        entries: Vec::new(),
+        hardlinks: HashMap::new(),
+        renamer: Renamer::new(&options.rewrite)?,
+    };
 
     for input in &options.inputs {
-        let abs_input = canonicalize_input(&base_utf8, input)?;
-        let metadata = fs::metadata(abs_input.as_std_path())
+        let abs_input = canonicalize_input(base, input, policy.byte_paths())?;
+        let metadata = fs::metadata(names::to_path(&abs_input))
             .with_context(|| format!("failed to stat input {}", abs_input))?;
         let trim_prefix = if metadata.is_dir() {
             Some(abs_input.clone())
@@ -88,32 +338,38 @@ pub fn create_archive(
             None
         };
         walk_input(
-            &base_utf8,
+            base,
             &abs_input,
             trim_prefix.as_ref().map(|p| p.as_ref()),
             &exclude_set,
+            options,
             policy,
-            &mut usage,
-            &mut entries,
+            &mut state,
         )?;
     }
+    Ok(state.entries)
+}
 
-    if options.print_plan && !options.quiet {
-        for entry in &entries {
-            println!("{}\t{}", entry.kind_label(), entry.relative);
-        }
-    }
-
-    let manifest_inputs: Vec<_> = entries
-        .iter()
-        .map(|entry| entry.to_manifest_item())
-        .collect();
-    let manifest_entries = manifest::collect_manifest(&manifest_inputs)?;
-
-    if options.print_plan {
-        return Ok(manifest_entries);
+fn print_plan(entries: &[ArchiveEntry]) {
+    for entry in entries {
+        println!(
+            "{}\t{}",
+            entry.kind_label(),
+            names::escaped(&entry.relative)
+        );
     }
+}
 
+/// Append `entries` to `builder`, reporting progress as `create` does.
+fn write_entries<W>(
+    builder: &mut Builder<W>,
+    entries: &[ArchiveEntry],
+    options: &CreateOptions,
+    done_message: &'static str,
+) -> Result<()>
+where
+    W: io::Write,
+{
     let progress = if options.verbose && !options.quiet {
         let pb = ProgressBar::new(entries.len() as u64);
         let style = ProgressStyle::default_bar()
@@ -125,72 +381,80 @@ pub fn create_archive(
         None
     };
 
-    let archive_file = File::create(&options.archive_path)
-        .with_context(|| format!("failed to create archive {}", options.archive_path))?;
-    let writer = wrap_writer(archive_file, options.compression)
-        .with_context(|| format!("failed to initialise {:?} compressor", options.compression))?;
-    let mut builder = Builder::new(writer);
-    builder.follow_symlinks(policy.follow_symlinks());
-    builder.mode(HeaderMode::Deterministic);
-
-    for entry in &entries {
+    if (options.xattrs || options.acls) && options.format != ArchiveFormat::Pax {
+        return Err(UserInputError::new(
+            "extended attributes and ACLs need PAX records; use --format pax",
+        )
+        .into());
+    }
+    // Without owners, members are stored as owned by root, as in reproducible builds.
+    let owners = (!options.no_same_owner)
+        .then(|| Owners::new(&options.owners, options.numeric_owner))
+        .transpose()?;
+    for entry in entries {
         if let Some(pb) = &progress {
             pb.inc(1);
-            pb.set_message(entry.relative.as_str().to_owned());
+            pb.set_message(names::escaped(&entry.relative));
         } else if options.verbose && !options.quiet {
-            println!("adding {} ({})", entry.relative, entry.kind_label());
-        }
-        match entry.kind {
-            EntryKind::Directory => builder
-                .append_dir(entry.relative.as_str(), entry.absolute.as_std_path())
-                .with_context(|| format!("failed to append directory {}", entry.relative))?,
-            EntryKind::File => {
-                let mut file = File::open(entry.absolute.as_std_path())
-                    .with_context(|| format!("failed to open {}", entry.absolute))?;
-                builder
-                    .append_file(entry.relative.as_str(), &mut file)
-                    .with_context(|| format!("failed to append file {}", entry.relative))?;
-            }
-            EntryKind::Symlink => append_symlink(&mut builder, entry)?,
+            report(
+                is_stdio(&options.archive_path),
+                format_args!(
+                    "adding {} ({})",
+                    names::escaped(&entry.relative),
+                    entry.kind_label()
+                ),
+            );
         }
+        append_entry(
+            builder,
+            entry,
+            options.format,
+            owners.as_ref(),
+            options.preserve_times,
+        )?;
     }
 
     if let Some(pb) = progress {
-        pb.finish_with_message("create complete");
-    }
-
-    builder.finish().context("failed to finalise tar archive")?;
-    let writer = builder
-        .into_inner()
-        .context("failed to finalise tar builder")?;
-    writer
-        .finish()
-        .context("failed to finish compressed writer")?;
-
-    if let Some(manifest_path) = &options.manifest_out {
-        manifest::write_manifest_json(&manifest_entries, manifest_path)?;
+        pb.finish_with_message(done_message);
     }
-
-    Ok(manifest_entries)
+    Ok(())
 }
 
 /// Extract an archive.
+///
+/// With `options.index`, only the selected members are read, each one from the offset
+/// the index records for it; they go through the same checks as in a full scan, and
+/// the compression limits apply to everything read across all of them.
 pub fn extract_archive(
     options: &ExtractOptions,
     policy: &SecurityPolicy,
 ) -> Result<Vec<ManifestEntry>> {
-    let archive_file = File::open(&options.archive_path)
-        .with_context(|| format!("failed to open archive {}", options.archive_path))?;
-    let reader = wrap_reader(archive_file).context("failed to detect archive compression")?;
-    let mut archive = Archive::new(reader);
+    extract(options, policy).map_err(surface_input_limit)
+}
+
+fn extract(options: &ExtractOptions, policy: &SecurityPolicy) -> Result<Vec<ManifestEntry>> {
+    let indexed = options
+        .index
+        .as_deref()
+        .map(|index| IndexedArchive::open(&options.archive_path, index))
+        .transpose()?;
 
     let destination = resolve_destination(&options.destination)?;
-    fs::create_dir_all(&destination)
-        .with_context(|| format!("failed to prepare destination {}", destination.display()))?;
-    let dest_utf8 = Utf8PathBuf::from_path_buf(destination.clone())
-        .map_err(|_| anyhow!("destination not valid UTF-8: {}", destination.display()))?;
-    let mut usage = policy.usage();
-    let mut manifest_items = Vec::new();
+    let staging = if options.atomic {
+        if options.overwrite != OverwriteMode::Replace || options.backup.is_some() {
+            return Err(UserInputError::new(
+                "--atomic replaces existing files; it cannot be combined with other overwrite modes or --backup",
+            )
+            .into());
+        }
+        Some(Staging::new(&destination)?)
+    } else {
+        fs::create_dir_all(&destination)
+            .with_context(|| format!("failed to prepare destination {}", destination.display()))?;
+        None
+    };
+    let dest_utf8 = names::from_path(&destination, policy.byte_paths())
+        .with_context(|| format!("unusable destination {}", destination.display()))?;
 
     let progress = if options.verbose && !options.quiet {
         let pb = ProgressBar::new_spinner();
@@ -200,221 +464,949 @@ pub fn extract_archive(
     } else {
         None
     };
+    let mut extraction = Extraction {
+        options,
+        policy,
+        // Paths are still checked against the destination; only the writes are staged.
+        root: Root::open(staging.as_ref().map_or(&*dest_utf8, Staging::root))?,
+        destination: dest_utf8.clone(),
+        usage: policy.usage(),
+        manifest_items: Vec::new(),
+        pax_state: PaxState::default(),
+        extracted_files: HashSet::new(),
+        selector: Selector::new(&options.selection)?,
+        renamer: Renamer::new(&options.rewrite)?,
+        owners: (!options.no_same_owner && owner::can_restore())
+            .then(|| Owners::new(&options.owners, options.numeric_owner))
+            .transpose()?,
+        xattrs: XattrSelection {
+            xattrs: options.xattrs,
+            acls: options.acls,
+        },
+        times: Vec::new(),
+        directory_modes: Vec::new(),
+        symlinks: HashSet::new(),
+        delayed_symlinks: Vec::new(),
+        progress,
+    };
 
-    for entry_result in archive.entries()? {
-        let mut entry = entry_result?;
+    match &indexed {
+        Some(indexed) => {
+            let limits = policy.limits();
+            let budget =
+                InputBudget::new(limits.max_compression_ratio, limits.max_compressed_input);
+            for member in indexed.members()? {
+                if let Some(name) = &member.name {
+                    if !extraction.selector.is_selected(name) {
+                        continue;
+                    }
+                }
+                let mut archive = indexed.archive_at(&member, &budget)?;
+                let entry = archive
+                    .entries()?
+                    .next()
+                    .ok_or_else(|| anyhow!("no member at indexed offset"))??;
+                extraction.entry(entry)?;
+            }
+        }
+        None => {
+            let reader = open_archive_within(&options.archive_path, policy.limits())?;
+            let mut archive = Archive::new(reader);
+            for entry_result in archive.entries()? {
+                extraction.entry(entry_result?)?;
+            }
+        }
+    }
+
+    // Every other member is in place, so none of them can have gone through these.
+    for link in std::mem::take(&mut extraction.delayed_symlinks) {
+        extraction.symlink(link, true)?;
+    }
+    if let Some(pb) = &extraction.progress {
+        pb.finish_with_message("extract complete");
+    }
+    extraction.selector.finish()?;
+
+    let Some(staging) = staging else {
+        let manifest_entries = manifest::collect_manifest(&extraction.manifest_items)?;
+        extraction.restore_deferred()?;
+        verify_extracted(options, &manifest_entries)?;
+        return Ok(manifest_entries);
+    };
+    for item in &mut extraction.manifest_items {
+        item.absolute = staging.root().join(&item.relative);
+    }
+    let mut manifest_entries = manifest::collect_manifest(&extraction.manifest_items)?;
+    verify_extracted(options, &manifest_entries)?;
+    let outcomes: HashMap<String, ExtractOutcome> = staging
+        .commit(&dest_utf8)?
+        .into_iter()
+        .map(|(rel, outcome)| (names::escaped(&rel), outcome))
+        .collect();
+    for entry in &mut manifest_entries {
+        if let Some(outcome) = outcomes.get(&entry.path) {
+            entry.outcome = Some(*outcome);
+        }
+    }
+    // Merging into existing directories changes their times, so they are only
+    // restored once everything is in place.
+    extraction.root = Root::open(&dest_utf8)?;
+    extraction.restore_deferred()?;
+    Ok(manifest_entries)
+}
+
+/// Check the extracted members against the manifest in `options.manifest`, if any.
+fn verify_extracted(options: &ExtractOptions, manifest_entries: &[ManifestEntry]) -> Result<()> {
+    if let Some(path) = &options.manifest {
+        let expected = manifest::read_manifest_json(path)?;
+        manifest::verify_manifest(&expected, manifest_entries, options.manifest_relaxed)?;
+    }
+    Ok(())
+}
+
+/// State of one extraction, carried from member to member.
+struct Extraction<'a> {
+    options: &'a ExtractOptions,
+    policy: &'a SecurityPolicy,
+    destination: Utf8PathBuf,
+    /// The destination held open; members are only ever reached through it.
+    root: Root,
+    usage: UsageTracker,
+    manifest_items: Vec<ManifestItem>,
+    pax_state: PaxState,
+    /// Files and hard links extracted so far, which later hard links may point at.
+    extracted_files: HashSet<Utf8PathBuf>,
+    selector: Selector,
+    renamer: Renamer,
+    /// Set when extracted members get the owners stored in the archive.
+    owners: Option<Owners>,
+    /// Extended attributes restored from the archive.
+    xattrs: XattrSelection,
+    /// Times of the members extracted so far, by relative path, restored once
+    /// extraction ends.
+    times: Vec<(Utf8PathBuf, MemberTimes)>,
+    /// Permissions of the directories extracted so far, applied once extraction ends.
+    directory_modes: Vec<(Utf8PathBuf, u32)>,
+    /// Symlinks extracted so far; no later member is written below one.
+    symlinks: HashSet<Utf8PathBuf>,
+    /// Symlinks left for the end with `options.delay_symlinks`.
+    delayed_symlinks: Vec<PendingSymlink>,
+    progress: Option<ProgressBar>,
+}
+
+/// A symlink member, read from the archive but not yet created.
+struct PendingSymlink {
+    validated: crate::policy::ValidatedPath,
+    target: Utf8PathBuf,
+    owner: Owner,
+    pax: PaxMetadata,
+    mtime: Option<std::time::SystemTime>,
+    times: Option<MemberTimes>,
+    xattrs: Option<BTreeMap<String, String>>,
+}
+
+/// Access and modification times to give an extracted member.
+#[derive(Debug, Clone, Copy)]
+struct MemberTimes {
+    accessed: std::time::SystemTime,
+    modified: std::time::SystemTime,
+}
+
+impl MemberTimes {
+    /// Times stored for a member; without a PAX `atime`, access matches modification.
+    fn of_entry(header: &tar::Header, pax: &PaxMetadata) -> Option<Self> {
+        let modified = entry_mtime(header, pax)?;
+        let accessed = pax.atime.map_or(modified, pax_mod::PaxTime::to_system_time);
+        Some(Self { accessed, modified })
+    }
+}
+
+impl Extraction<'_> {
+    /// Check and extract one archive entry.
+    fn entry<R: Read>(&mut self, mut entry: tar::Entry<'_, R>) -> Result<()> {
+        if entry.header().entry_type().is_pax_global_extensions() {
+            self.pax_state
+                .absorb_global(&mut entry)
+                .context("failed to parse global PAX header")?;
+            return Ok(());
+        }
         let header = entry.header().clone();
         let entry_type = classify_entry_type(&header);
-        let path = entry_path_utf8(&entry)?;
-        let validated = policy
-            .normalize_and_validate(path.as_ref(), &dest_utf8)
-            .map_err(|err| map_policy_error(err, options.strict))?;
-        usage
-            .observe(&validated, header.size().unwrap_or_default())
-            .map_err(|err| map_policy_error(err, options.strict))?;
+        let raw_path = entry.path_bytes().into_owned();
+        let shown = names::escape_bytes(&raw_path);
+        let pax_meta = pax_mod::extract_pax_extensions(&mut entry, &self.pax_state)
+            .with_context(|| format!("failed to parse PAX records for {shown}"))?;
+        let pax_sparse = pax_sparse_layout(entry_type, &pax_meta, &shown)?;
+        let raw_path = match &pax_sparse {
+            Some(layout) => names::to_bytes(&layout.name).into_owned(),
+            None => raw_path,
+        };
+        let (size, stored) = entry_sizes(&entry, &header, pax_sparse.as_ref());
+        let mtime = entry_mtime(&header, &pax_meta);
+        let times = MemberTimes::of_entry(&header, &pax_meta).filter(|_| !self.options.touch);
+        let owner = Owner::from_header(&header, &pax_meta);
+        let xattr_digests = (!self.xattrs.is_empty()).then(|| {
+            manifest::xattr_digests(
+                pax_meta
+                    .xattrs
+                    .iter()
+                    .map(|(name, value)| (name.as_str(), value.as_slice())),
+            )
+        });
+        let stored_name = names::from_bytes(&raw_path, true)
+            .map_err(|err| map_policy_error(PolicyError::InvalidName(err)))?;
+        // Unselected members are skipped before they are validated or count towards
+        // any quota. Like `tar`, operands name members as stored, before any rewriting.
+        if !self.selector.is_selected(&stored_name) {
+            return Ok(());
+        }
+        // Names are rewritten before validation, so a transform cannot add traversal.
+        let Some(renamed) = self.renamer.name(&stored_name) else {
+            return Ok(());
+        };
+        let validated = self
+            .policy
+            .normalize_and_validate_bytes(&names::to_bytes(&renamed), &self.destination)
+            .map_err(map_policy_error)?;
+        if let EntryKind::Special(kind) = entry_type {
+            let action = self
+                .policy
+                .check_special_entry(&validated.rel, kind)
+                .map_err(map_policy_error)?;
+            if action == SpecialEntryAction::Skip {
+                if !self.options.quiet {
+                    eprintln!(
+                        "safetar: skipping {kind} {}",
+                        names::escaped(&validated.rel)
+                    );
+                }
+                return Ok(());
+            }
+        }
+        let mode = header
+            .mode()
+            .ok()
+            .filter(|_| entry_type.has_own_mode())
+            .map(|mode| self.policy.check_mode(&validated.rel, mode))
+            .transpose()
+            .map_err(map_policy_error)?;
+        self.usage
+            .observe_sparse(&validated, size, stored)
+            .map_err(map_policy_error)?;
 
-        if let Some(pb) = &progress {
-            pb.set_message(format!("{entry_type:?} {}", validated.rel));
+        if let Some(pb) = &self.progress {
+            pb.set_message(format!("{entry_type:?} {}", names::escaped(&validated.rel)));
             pb.inc(1);
-        } else if options.verbose && !options.quiet {
-            println!("extracting {} ({entry_type:?})", validated.rel);
+        } else if self.options.verbose && !self.options.quiet {
+            println!(
+                "extracting {} ({entry_type:?})",
+                names::escaped(&validated.rel)
+            );
         }
 
         match entry_type {
             EntryKind::Directory => {
-                let target_path = validated.abs.as_std_path();
-                fs::create_dir_all(target_path)
-                    .with_context(|| format!("failed to create directory {}", validated.abs))?;
-                manifest_items.push(ManifestItem {
+                let location = self.locate(&validated.rel)?;
+                let Some(placement) = self.place(&location, entry_type, mtime, false)? else {
+                    return Ok(());
+                };
+                location
+                    .create_dir()
+                    .with_context(|| format!("failed to create directory {location}"))?;
+                self.restore_owner(&location, &owner)?;
+                self.restore_xattrs(&location, &pax_meta);
+                if let Some(mode) = mode {
+                    self.directory_modes.push((validated.rel.clone(), mode));
+                }
+                self.manifest_items.push(ManifestItem {
                     relative: validated.rel.clone(),
                     absolute: validated.abs.clone(),
                     kind: ManifestKind::Directory,
                     link_target: None,
                     size: 0,
-                    mtime: header
-                        .mtime()
-                        .ok()
-                        .map(|secs| std::time::UNIX_EPOCH + std::time::Duration::from_secs(secs)),
+                    mtime,
+                    xattrs: xattr_digests.clone(),
+                    outcome: Some(placement.outcome),
+                    backup: placement.backup,
                 });
             }
             EntryKind::File => {
-                ensure_parent_exists(&validated)?;
-                entry
-                    .unpack_in(dest_utf8.as_std_path())
-                    .with_context(|| format!("failed to extract {}", validated.rel))?;
-                manifest_items.push(ManifestItem {
+                let location = self.locate(&validated.rel)?;
+                let in_place = pax_sparse.is_none();
+                let Some(placement) = self.place(&location, entry_type, mtime, in_place)? else {
+                    return Ok(());
+                };
+                let mut file = match placement.outcome {
+                    ExtractOutcome::Overwritten => location.open_file(),
+                    _ => location.create_file(),
+                }
+                .with_context(|| format!("failed to create {location}"))?;
+                let written = match &pax_sparse {
+                    Some(layout) => {
+                        let map = layout
+                            .read_map(&mut entry, stored)
+                            .with_context(|| format!("failed to read sparse map for {shown}"))?;
+                        sparse::unpack(&mut entry, &map, &mut file)
+                    }
+                    // `tar` expands GNU sparse members; their runs of zeros become holes again.
+                    None if header.entry_type().is_gnu_sparse() => {
+                        sparse::copy_with_holes(&mut entry, &mut file)
+                    }
+                    None => io::copy(&mut entry, &mut file).map(drop),
+                };
+                written.with_context(|| format!("failed to extract {location}"))?;
+                drop(file);
+                self.restore_owner(&location, &owner)?;
+                self.restore_mode(&location, ManifestKind::File, mode)?;
+                self.restore_xattrs(&location, &pax_meta);
+                self.extracted_files.insert(validated.rel.clone());
+                self.manifest_items.push(ManifestItem {
                     relative: validated.rel.clone(),
                     absolute: validated.abs.clone(),
                     kind: ManifestKind::File,
                     link_target: None,
-                    size: header.size().unwrap_or_default(),
-                    mtime: header
-                        .mtime()
-                        .ok()
-                        .map(|secs| std::time::UNIX_EPOCH + std::time::Duration::from_secs(secs)),
+                    size,
+                    mtime,
+                    xattrs: xattr_digests.clone(),
+                    outcome: Some(placement.outcome),
+                    backup: placement.backup,
                 });
             }
-            EntryKind::Symlink => {
-                ensure_parent_exists(&validated)?;
-                let link_target = entry.link_name().with_context(|| {
-                    format!("failed to read symlink target for {}", validated.rel)
-                })?;
-                let target_utf8 = link_target
-                    .clone()
-                    .map(|cow| {
-                        let owned = cow.into_owned();
-                        Utf8PathBuf::from_path_buf(owned)
-                            .map_err(|_| anyhow!("symlink target not UTF-8: {}", validated.rel))
-                    })
-                    .transpose()?;
-                if let Some(target) = &target_utf8 {
-                    enforce_link(policy, &dest_utf8, &validated, target)?;
-                }
-                entry
-                    .unpack_in(dest_utf8.as_std_path())
-                    .with_context(|| format!("failed to extract {}", validated.rel))?;
-                manifest_items.push(ManifestItem {
+            EntryKind::Hardlink => {
+                let target = entry
+                    .link_name_bytes()
+                    .ok_or_else(|| anyhow!("hard link {} has no target", validated.rel))?;
+                let target = names::from_bytes(&target, self.policy.byte_paths())
+                    .map_err(|err| map_policy_error(PolicyError::InvalidName(err)))?;
+                let Some(target) = self.renamer.hardlink_target(&target) else {
+                    if !self.options.quiet {
+                        eprintln!(
+                            "safetar: skipping hard link {}: --strip-components removes its target",
+                            names::escaped(&validated.rel)
+                        );
+                    }
+                    return Ok(());
+                };
+                let source = resolve_hardlink_source(
+                    self.policy,
+                    &self.destination,
+                    &validated,
+                    &target,
+                    &self.extracted_files,
+                )
+                .map_err(map_policy_error)?;
+                let source = match source.strip_prefix(&self.destination) {
+                    Ok(rel) => self.root.locate(rel, false)?,
+                    Err(_) => Location::outside(&source)?,
+                };
+                let location = self.locate(&validated.rel)?;
+                let Some(placement) = self.place(&location, entry_type, mtime, false)? else {
+                    return Ok(());
+                };
+                location
+                    .hard_link(&source)
+                    .with_context(|| format!("failed to link {location} to {target}"))?;
+                self.extracted_files.insert(validated.rel.clone());
+                self.manifest_items.push(ManifestItem {
                     relative: validated.rel.clone(),
                     absolute: validated.abs.clone(),
-                    kind: ManifestKind::Symlink,
-                    link_target: target_utf8.clone(),
+                    kind: ManifestKind::Hardlink,
+                    link_target: Some(target),
                     size: 0,
-                    mtime: header
-                        .mtime()
-                        .ok()
-                        .map(|secs| std::time::UNIX_EPOCH + std::time::Duration::from_secs(secs)),
+                    mtime,
+                    xattrs: xattr_digests.clone(),
+                    outcome: Some(placement.outcome),
+                    backup: placement.backup,
                 });
             }
+            EntryKind::Special(kind) => {
+                let location = self.locate(&validated.rel)?;
+                let Some(placement) = self.place(&location, entry_type, mtime, false)? else {
+                    return Ok(());
+                };
+                special::create_special_node(&location, &header, kind, mode)?;
+                self.restore_owner(&location, &owner)?;
+                self.restore_mode(&location, special::manifest_kind(kind), mode)?;
+                self.restore_xattrs(&location, &pax_meta);
+                self.manifest_items.push(ManifestItem {
+                    relative: validated.rel.clone(),
+                    absolute: validated.abs.clone(),
+                    kind: special::manifest_kind(kind),
+                    link_target: None,
+                    size: 0,
+                    mtime,
+                    xattrs: xattr_digests.clone(),
+                    outcome: Some(placement.outcome),
+                    backup: placement.backup,
+                });
+            }
+            EntryKind::Symlink => {
+                let target = entry
+                    .link_name_bytes()
+                    .filter(|target| !target.is_empty())
+                    .ok_or_else(|| anyhow!("symlink {} has no target", validated.rel))?;
+                let target = names::from_bytes(&target, self.policy.byte_paths())
+                    .map_err(|err| map_policy_error(PolicyError::InvalidName(err)))?;
+                let target = self.renamer.symlink_target(&target);
+                enforce_link(self.policy, &self.destination, &validated, &target)?;
+                let link = PendingSymlink {
+                    validated,
+                    target,
+                    owner,
+                    pax: pax_meta,
+                    mtime,
+                    times,
+                    xattrs: xattr_digests,
+                };
+                if self.options.delay_symlinks {
+                    self.delayed_symlinks.push(link);
+                    return Ok(());
+                }
+                return self.symlink(link, false);
+            }
         }
+        // Hard links share the times of their source.
+        if let Some(times) = times.filter(|_| entry_type != EntryKind::Hardlink) {
+            self.times.push((validated.rel, times));
+        }
+        Ok(())
     }
 
-    if let Some(pb) = progress {
-        pb.finish_with_message("extract complete");
+    /// Restore the times and directory modes held back until every member is in place.
+    ///
+    /// Writing directory contents and hashing files both change times, so they are
+    /// restored only now. Directory modes come last, children first, so that
+    /// read-only directories could still be filled.
+    fn restore_deferred(&self) -> Result<()> {
+        for (rel, times) in &self.times {
+            let location = self.root.locate(rel, false)?;
+            location
+                .set_times(times.accessed, times.modified)
+                .with_context(|| format!("failed to set times for {location}"))?;
+        }
+        for (rel, mode) in self.directory_modes.iter().rev() {
+            set_mode(
+                &self.root.locate(rel, false)?,
+                *mode,
+                ManifestKind::Directory,
+            )?;
+        }
+        Ok(())
     }
 
-    let manifest_entries = manifest::collect_manifest(&manifest_items)?;
-    if let Some(path) = &options.manifest {
-        let expected = manifest::read_manifest_json(path)?;
-        manifest::verify_manifest(&expected, &manifest_entries, options.manifest_relaxed)?;
+    /// Create the symlink `link` and restore what the archive stores for it.
+    ///
+    /// A `delayed` symlink whose path now holds a directory, usually because members
+    /// were extracted below it, is left out with a warning.
+    fn symlink(&mut self, link: PendingSymlink, delayed: bool) -> Result<()> {
+        let PendingSymlink {
+            validated,
+            target,
+            owner,
+            pax,
+            mtime,
+            times,
+            xattrs,
+        } = link;
+        let location = self.locate(&validated.rel)?;
+        if delayed {
+            let existing = location
+                .stat()
+                .with_context(|| format!("failed to inspect {location}"))?;
+            if existing.is_some_and(|existing| existing.kind == ManifestKind::Directory) {
+                if !self.options.quiet {
+                    eprintln!(
+                        "safetar: not creating symlink {location}: a directory took its place"
+                    );
+                }
+                return Ok(());
+            }
+        }
+        let Some(placement) = self.place(&location, EntryKind::Symlink, mtime, false)? else {
+            return Ok(());
+        };
+        location
+            .symlink(&target)
+            .with_context(|| format!("failed to symlink {location} to {target}"))?;
+        self.restore_owner(&location, &owner)?;
+        self.restore_xattrs(&location, &pax);
+        self.symlinks.insert(validated.rel.clone());
+        self.manifest_items.push(ManifestItem {
+            relative: validated.rel.clone(),
+            absolute: validated.abs,
+            kind: ManifestKind::Symlink,
+            link_target: Some(target),
+            size: 0,
+            mtime,
+            xattrs,
+            outcome: Some(placement.outcome),
+            backup: placement.backup,
+        });
+        if let Some(times) = times {
+            self.times.push((validated.rel, times));
+        }
+        Ok(())
     }
-    Ok(manifest_entries)
-}
 
-/// List archive contents.
-pub fn list_archive(options: &ListOptions) -> Result<Vec<ManifestEntry>> {
-    let archive_file = File::open(&options.archive_path)
-        .with_context(|| format!("failed to open archive {}", options.archive_path))?;
-    let reader = wrap_reader(archive_file).context("failed to detect archive compression")?;
-    let mut archive = Archive::new(reader);
-    let mut manifest_entries = Vec::new();
+    /// Find where the member at `rel` goes, creating its missing parents.
+    ///
+    /// Members below a symlink, whether extracted earlier or already in the
+    /// destination, are rejected.
+    fn locate(&self, rel: &Utf8Path) -> Result<Location> {
+        check_symlink_parents(&self.symlinks, rel).map_err(map_policy_error)?;
+        self.root.locate(rel, true)
+    }
 
-    for entry_result in archive.entries()? {
-        let mut entry = entry_result?;
-        let header = entry.header().clone();
-        let entry_type = classify_entry_type(&header);
-        let path = entry_path_utf8(&entry)?;
-        if options.verbose && !options.quiet {
-            let size = header.size().unwrap_or_default();
-            let pax_meta = pax_mod::extract_pax_extensions(&header);
-            if pax_meta.is_empty() {
-                println!("{:?}\t{}\t{}", entry_type, size, path);
-            } else {
-                println!("{:?}\t{}\t{}\t{:?}", entry_type, size, path, pax_meta);
+    /// Clear the way for a member at `location` as `options.overwrite` asks.
+    ///
+    /// Returns `None`, after recording what is there, when the member is skipped.
+    fn place(
+        &mut self,
+        location: &Location,
+        kind: EntryKind,
+        mtime: Option<std::time::SystemTime>,
+        in_place: bool,
+    ) -> Result<Option<Placement>> {
+        let placement = overwrite::make_room(
+            location,
+            kind,
+            mtime,
+            self.options.overwrite,
+            self.options.backup,
+            in_place,
+        )?;
+        if placement.outcome != ExtractOutcome::Skipped {
+            // Whatever was there, a symlink included, is gone.
+            self.symlinks.remove(location.rel());
+            return Ok(Some(placement));
+        }
+        let existing = location
+            .stat()
+            .with_context(|| format!("failed to inspect {location}"))?
+            .ok_or_else(|| anyhow!("{location} disappeared during extraction"))?;
+        let link_target = match existing.kind {
+            ManifestKind::Symlink => {
+                let target = location
+                    .read_link()
+                    .with_context(|| format!("failed to read symlink {location}"))?;
+                Some(names::from_bytes(&target, true)?)
             }
-        } else if !options.quiet {
-            println!("{}", path);
+            _ => None,
+        };
+        let rel = location.rel().to_owned();
+        // Later hard links may point at the file that was kept.
+        if existing.kind == ManifestKind::File
+            && matches!(kind, EntryKind::File | EntryKind::Hardlink)
+        {
+            self.extracted_files.insert(rel.clone());
         }
+        self.manifest_items.push(ManifestItem {
+            absolute: self.destination.join(&rel),
+            relative: rel,
+            size: if existing.kind == ManifestKind::File {
+                existing.len
+            } else {
+                0
+            },
+            kind: existing.kind,
+            link_target,
+            mtime: existing.modified,
+            xattrs: None,
+            outcome: Some(ExtractOutcome::Skipped),
+            backup: None,
+        });
+        Ok(None)
+    }
 
-        let mtime = header
-            .mtime()
-            .ok()
-            .map(|secs| std::time::UNIX_EPOCH + std::time::Duration::from_secs(secs));
+    /// Give an extracted member the permissions the policy allows.
+    ///
+    /// This runs after [`Self::restore_owner`], since changing the owner clears the
+    /// setuid and setgid bits.
+    fn restore_mode(
+        &self,
+        location: &Location,
+        kind: ManifestKind,
+        mode: Option<u32>,
+    ) -> Result<()> {
+        match mode {
+            Some(mode) => set_mode(location, mode, kind),
+            None => Ok(()),
+        }
+    }
 
-        let manifest_entry = match entry_type {
-            EntryKind::File => {
-                let hash = hash_entry_data(&mut entry)?;
-                ManifestEntry {
-                    path: path.to_string(),
-                    size: header.size().unwrap_or_default(),
-                    sha256: hash,
-                    kind: ManifestKind::File,
-                    target: None,
-                    mtime: mtime.map(|t| {
-                        t.duration_since(std::time::UNIX_EPOCH)
-                            .unwrap_or_default()
-                            .as_secs()
-                    }),
+    /// Give an extracted member the selected extended attributes the policy allows.
+    ///
+    /// Like `tar`, this only warns when an attribute cannot be set, for example on a
+    /// file system without xattr support. It runs after [`Self::restore_owner`], since
+    /// changing the owner drops file capabilities.
+    fn restore_xattrs(&self, location: &Location, pax: &PaxMetadata) {
+        for (name, value) in &pax.xattrs {
+            if !self.xattrs.includes(name) {
+                continue;
+            }
+            if !self.policy.allows_xattr(name) {
+                if !self.options.quiet {
+                    eprintln!(
+                        "safetar: not restoring xattr {name} of {location}: blocked by policy"
+                    );
                 }
+                continue;
             }
-            EntryKind::Directory => ManifestEntry::for_directory(&path, mtime),
-            EntryKind::Symlink => {
-                let target = entry
-                    .link_name()
-                    .with_context(|| format!("failed to read symlink target for {path}"))?;
-                let target_str = target
-                    .clone()
-                    .map(|cow| {
-                        let owned = cow.into_owned();
-                        Utf8PathBuf::from_path_buf(owned)
-                            .map_err(|_| anyhow!("symlink target not UTF-8: {path}"))
-                            .map(|p| p.to_string())
-                    })
-                    .transpose()?;
-                ManifestEntry {
-                    path: path.to_string(),
-                    size: 0,
-                    sha256: target_str
-                        .as_ref()
-                        .map(|s| digest_bytes_prefilled(s.as_bytes()))
-                        .unwrap_or_else(|| digest_bytes_prefilled(&[])),
-                    kind: ManifestKind::Symlink,
-                    target: target_str,
-                    mtime: None,
+            if let Err(err) = xattr::write(location, name, value) {
+                if !self.options.quiet {
+                    eprintln!("safetar: {err:#}");
                 }
             }
-        };
-        manifest_entries.push(manifest_entry);
+        }
+    }
+
+    /// Give an extracted member its stored owner, when owners are restored.
+    fn restore_owner(&self, location: &Location, owner: &Owner) -> Result<()> {
+        match &self.owners {
+            Some(owners) => owners.restore(location, owner),
+            None => Ok(()),
+        }
     }
+}
+
+/// List archive contents.
+pub fn list_archive(options: &ListOptions) -> Result<Vec<ManifestEntry>> {
+    list(options).map_err(surface_input_limit)
+}
+
+fn list(options: &ListOptions) -> Result<Vec<ManifestEntry>> {
+    let reader = open_archive_within(&options.archive_path, options.limits)?;
+    let mut archive = Archive::new(reader);
+    let mut manifest_entries = Vec::new();
+    let mut selector = Selector::new(&options.selection)?;
+
+    scan_members(&mut archive, |member| {
+        if !selector.is_selected(&member.path) {
+            return Ok(());
+        }
+        let shown = names::escaped(&member.path);
+        if options.verbose && !options.quiet {
+            match &member.manifest.pax {
+                Some(pax_meta) => {
+                    println!(
+                        "{:?}\t{}\t{}\t{}",
+                        member.kind, member.size, shown, pax_meta
+                    )
+                }
+                None => println!("{:?}\t{}\t{}", member.kind, member.size, shown),
+            }
+        } else if !options.quiet {
+            println!("{}", shown);
+        }
+        manifest_entries.push(member.manifest);
+        Ok(())
+    })?;
 
     if options.json && !options.quiet {
         serde_json::to_writer_pretty(std::io::stdout(), &manifest_entries)
             .context("failed to render manifest")?;
         println!();
     }
+    selector.finish()?;
 
     Ok(manifest_entries)
 }
 
-fn append_symlink<W>(builder: &mut Builder<W>, entry: &ArchiveEntry) -> Result<()>
+/// A member read back from an archive, described the way `list` reports it.
+struct ScannedMember {
+    kind: EntryKind,
+    path: Utf8PathBuf,
+    link_target: Option<Utf8PathBuf>,
+    size: u64,
+    stored: u64,
+    mode: Option<u32>,
+    manifest: ManifestEntry,
+}
+
+/// Read every member of `archive`, hashing file data, and pass each one to `visit`.
+///
+/// Raw names are always accepted here; callers that act on members apply the policy.
+fn scan_members<R: Read>(
+    archive: &mut Archive<R>,
+    mut visit: impl FnMut(ScannedMember) -> Result<()>,
+) -> Result<()> {
+    let mut pax_state = PaxState::default();
+
+    for entry_result in archive.entries()? {
+        let mut entry = entry_result?;
+        if entry.header().entry_type().is_pax_global_extensions() {
+            pax_state
+                .absorb_global(&mut entry)
+                .context("failed to parse global PAX header")?;
+            continue;
+        }
+        let header = entry.header().clone();
+        let entry_type = classify_entry_type(&header);
+        let path = names::from_bytes(&entry.path_bytes(), true)?;
+        let shown = names::escaped(&path);
+        let pax_meta = pax_mod::extract_pax_extensions(&mut entry, &pax_state)
+            .with_context(|| format!("failed to parse PAX records for {shown}"))?;
+        let pax_sparse = pax_sparse_layout(entry_type, &pax_meta, &shown)?;
+        let (path, shown) = match &pax_sparse {
+            Some(layout) => (layout.name.clone(), names::escaped(&layout.name)),
+            None => (path, shown),
+        };
+        let (size, stored) = entry_sizes(&entry, &header, pax_sparse.as_ref());
+        let mtime = entry_mtime(&header, &pax_meta);
+        let link_target = match entry_type {
+            EntryKind::Symlink | EntryKind::Hardlink => entry
+                .link_name_bytes()
+                .map(|target| names::from_bytes(&target, true))
+                .transpose()?,
+            EntryKind::File | EntryKind::Directory | EntryKind::Special(_) => None,
+        };
+
+        let mut manifest_entry = match entry_type {
+            EntryKind::File => {
+                let hash = match &pax_sparse {
+                    Some(layout) => {
+                        let map = layout
+                            .read_map(&mut entry, stored)
+                            .with_context(|| format!("failed to read sparse map for {shown}"))?;
+                        hash_entry_data(&mut sparse::ExpandReader::new(&mut entry, map))?
+                    }
+                    None => hash_entry_data(&mut entry)?,
+                };
+                ManifestEntry::for_file(&path, size, hash, mtime)
+            }
+            EntryKind::Directory => ManifestEntry::for_directory(&path, mtime),
+            EntryKind::Hardlink => {
+                let target = link_target
+                    .as_ref()
+                    .ok_or_else(|| anyhow!("hard link {shown} has no target"))?;
+                ManifestEntry::for_hardlink(&path, target, mtime)
+            }
+            EntryKind::Special(kind) => ManifestEntry::for_special(
+                &path,
+                special::manifest_kind(kind),
+                special::header_device_numbers(&header),
+                mtime,
+            ),
+            EntryKind::Symlink => match &link_target {
+                Some(target) => ManifestEntry::for_symlink(&path, target),
+                None => {
+                    let mut manifest_entry = ManifestEntry::for_symlink(&path, Utf8Path::new(""));
+                    manifest_entry.target = None;
+                    manifest_entry
+                }
+            },
+        };
+        if !pax_meta.is_empty() {
+            manifest_entry.pax = Some(pax_meta);
+        }
+        visit(ScannedMember {
+            kind: entry_type,
+            path,
+            link_target,
+            size,
+            stored,
+            mode: header.mode().ok(),
+            manifest: manifest_entry,
+        })?;
+    }
+    Ok(())
+}
+
+fn append_entry<W>(
+    builder: &mut Builder<W>,
+    entry: &ArchiveEntry,
+    format: ArchiveFormat,
+    owners: Option<&Owners>,
+    preserve_times: bool,
+) -> Result<()>
 where
     W: io::Write,
 {
-    let target = entry
-        .link_target
-        .as_ref()
-        .ok_or_else(|| anyhow!("missing symlink target for {}", entry.relative))?;
-    let mut header = tar::Header::new_gnu();
-    header.set_path(entry.relative.as_str())?;
-    header.set_size(0);
-    header.set_entry_type(EntryType::Symlink);
-    header.set_mode(0o777);
-    header.set_link_name(target.as_str())?;
-    header.set_mtime(
+    let mut header = header::new_header(format);
+    let metadata = match entry.kind {
+        EntryKind::Directory | EntryKind::File => {
+            let metadata = fs::metadata(names::to_path(&entry.absolute))
+                .with_context(|| format!("failed to stat {}", entry.absolute))?;
+            header.set_metadata_in_mode(&metadata, HeaderMode::Deterministic);
+            metadata
+        }
+        EntryKind::Hardlink => {
+            let metadata = fs::metadata(names::to_path(&entry.absolute))
+                .with_context(|| format!("failed to stat {}", entry.absolute))?;
+            header.set_metadata_in_mode(&metadata, HeaderMode::Deterministic);
+            header.set_entry_type(EntryType::Link);
+            header.set_size(0);
+            metadata
+        }
+        EntryKind::Special(_) => {
+            let metadata = fs::symlink_metadata(names::to_path(&entry.absolute))
+                .with_context(|| format!("failed to stat {}", entry.absolute))?;
+            header.set_metadata_in_mode(&metadata, HeaderMode::Deterministic);
+            special::set_device_numbers(&mut header, &metadata).with_context(|| {
+                format!("failed to record device numbers for {}", entry.relative)
+            })?;
+            metadata
+        }
+        EntryKind::Symlink => {
+            let metadata = fs::symlink_metadata(names::to_path(&entry.absolute))
+                .with_context(|| format!("failed to stat {}", entry.absolute))?;
+            header.set_size(0);
+            header.set_entry_type(EntryType::Symlink);
+            header.set_mode(0o777);
+            header.set_uid(0);
+            header.set_gid(0);
+            metadata
+        }
+    };
+    // Symlinks always keep their own mtime; other members get the deterministic one
+    // unless times are preserved.
+    let mtime = (preserve_times || entry.kind == EntryKind::Symlink).then(|| {
         entry
             .mtime
-            .map(|time| {
-                time.duration_since(std::time::UNIX_EPOCH)
-                    .unwrap_or_default()
-                    .as_secs()
-            })
-            .unwrap_or_default(),
-    );
-    header.set_uid(0);
-    header.set_gid(0);
-    header.set_cksum();
-    builder
-        .append(&header, io::empty())
-        .with_context(|| format!("failed to append symlink {}", entry.relative))?;
+            .map(PaxTime::from_system_time)
+            .unwrap_or(PaxTime { secs: 0, nanos: 0 })
+    });
+    if let Some(mtime) = mtime {
+        header.set_mtime(u64::try_from(mtime.secs).unwrap_or_default());
+    }
+    let atime = entry
+        .atime
+        .filter(|_| preserve_times)
+        .map(PaxTime::from_system_time);
+    let mut owner_records = match owners {
+        Some(owners) => {
+            let owner = owners.recorded(&metadata)?;
+            header::set_owner(&mut header, &owner, format)
+                .with_context(|| format!("failed to record the owner of {}", entry.relative))?
+        }
+        None => Vec::new(),
+    };
+
+    let link_target = match entry.kind {
+        EntryKind::Symlink | EntryKind::Hardlink => Some(
+            entry
+                .link_target
+                .as_ref()
+                .ok_or_else(|| anyhow!("missing link target for {}", entry.relative))?,
+        ),
+        EntryKind::File | EntryKind::Directory | EntryKind::Special(_) => None,
+    };
+    let mut member_name = Cow::Borrowed(entry.relative.as_path());
+    let mut sparse_records = Vec::new();
+    let mut data: Box<dyn Read> = match entry.kind {
+        EntryKind::File => {
+            let file = File::open(names::to_path(&entry.absolute))
+                .with_context(|| format!("failed to open {}", entry.absolute))?;
+            match (&entry.sparse, format) {
+                (Some(map), ArchiveFormat::Gnu) => {
+                    let extensions = sparse::fill_gnu_header(&mut header, map)?;
+                    Box::new(
+                        io::Cursor::new(extensions).chain(sparse::SegmentReader::new(file, map)),
+                    )
+                }
+                (Some(map), ArchiveFormat::Pax) => {
+                    let map_block = sparse::pax_map_block(map);
+                    header.set_size(map_block.len() as u64 + map.stored_size());
+                    sparse_records = sparse::pax_records(&entry.relative, map);
+                    member_name = Cow::Owned(sparse::pax_placeholder_name(&entry.relative));
+                    Box::new(
+                        io::Cursor::new(map_block).chain(sparse::SegmentReader::new(file, map)),
+                    )
+                }
+                // Ustar has no sparse representation; store the holes as zeros.
+                _ => Box::new(file),
+            }
+        }
+        EntryKind::Directory | EntryKind::Symlink | EntryKind::Hardlink | EntryKind::Special(_) => {
+            Box::new(io::empty())
+        }
+    };
+    let size = header.entry_size().unwrap_or_default();
+
+    match format {
+        ArchiveFormat::Gnu => match link_target {
+            Some(target) => builder.append_link(
+                &mut header,
+                names::to_path(&entry.relative),
+                names::to_path(target),
+            ),
+            None => builder.append_data(&mut header, names::to_path(&entry.relative), &mut data),
+        },
+        ArchiveFormat::Ustar => {
+            if header::exceeds_ustar_size(size) {
+                return Err(UserInputError::new(format!(
+                    "{} is too large for the ustar format; use --format pax",
+                    names::escaped(&entry.relative)
+                ))
+                .into());
+            }
+            header.set_path(names::to_path(&entry.relative)).map_err(|err| {
+                UserInputError::new(format!(
+                    "{} cannot be stored in ustar format ({err}); use --format pax",
+                    names::escaped(&entry.relative)
+                ))
+            })?;
+            if let Some(target) = link_target {
+                header.set_link_name(names::to_path(target)).map_err(|err| {
+                    UserInputError::new(format!(
+                        "link target of {} cannot be stored in ustar format ({err}); use --format pax",
+                        names::escaped(&entry.relative)
+                    ))
+                })?;
+            }
+            header.set_cksum();
+            builder.append(&header, &mut data)
+        }
+        ArchiveFormat::Pax => {
+            let target_bytes = link_target.map(|target| names::to_bytes(target));
+            let mut records = header::pax_records(
+                &names::to_bytes(&member_name),
+                target_bytes.as_deref(),
+                size,
+                mtime,
+            );
+            if let Some(atime) = atime {
+                records.push(("atime", atime.to_string().into_bytes()));
+            }
+            records.append(&mut sparse_records);
+            records.append(&mut owner_records);
+            let xattr_keys: Vec<String> = entry
+                .xattrs
+                .iter()
+                .flatten()
+                .map(|(name, _)| format!("{}{name}", pax_mod::SCHILY_XATTR_PREFIX))
+                .collect();
+            let xattr_records = xattr_keys
+                .iter()
+                .zip(entry.xattrs.iter().flatten())
+                .map(|(key, (_, value))| (key.as_str(), value.as_slice()));
+            builder
+                .append_pax_extensions(
+                    records
+                        .iter()
+                        .map(|(key, value)| (*key, value.as_slice()))
+                        .chain(xattr_records),
+                )
+                .with_context(|| format!("failed to write PAX records for {}", entry.relative))?;
+            header.set_path(header::pax_fallback_name(&names::to_bytes(&member_name)))?;
+            if let Some(target) = &target_bytes {
+                header.set_link_name(header::pax_fallback_name(target))?;
+            }
+            if header::exceeds_ustar_size(size) {
+                // The real size travels in the `size` record; readers ignore the header field.
+                header.set_size(0);
+            }
+            header.set_cksum();
+            builder.append(&header, &mut data)
+        }
+    }
+    .with_context(|| {
+        format!(
+            "failed to append {} {}",
+            entry.kind_label(),
+            names::escaped(&entry.relative)
+        )
+    })?;
     Ok(())
 }
 
@@ -423,16 +1415,16 @@ fn walk_input(
     input: &Utf8Path,
     trim_prefix: Option<&Utf8Path>,
     excludes: &Option<GlobSet>,
+    options: &CreateOptions,
     policy: &SecurityPolicy,
-    usage: &mut UsageTracker,
-    entries: &mut Vec<ArchiveEntry>,
+    state: &mut WalkState,
 ) -> Result<()> {
-    let walkdir = WalkDir::new(input.as_std_path()).follow_links(policy.follow_symlinks());
+    let walkdir = WalkDir::new(names::to_path(input)).follow_links(policy.follow_symlinks());
     let mut iter = walkdir.into_iter();
     while let Some(entry_result) = iter.next() {
         let entry = entry_result?;
-        let abs_path = Utf8PathBuf::from_path_buf(entry.path().to_path_buf())
-            .map_err(|_| anyhow!("path not valid UTF-8: {}", entry.path().display()))?;
+        let abs_path = names::from_path(entry.path(), policy.byte_paths())
+            .with_context(|| format!("cannot archive {}", entry.path().display()))?;
         let allowed_root = trim_prefix.unwrap_or(base);
         let rel = abs_path
             .strip_prefix(allowed_root)
@@ -455,12 +1447,34 @@ fn walk_input(
 
         let metadata = entry.metadata()?;
         let kind = if metadata.is_file() {
-            EntryKind::File
+            if file_identity(&metadata).is_some_and(|id| state.hardlinks.contains_key(&id)) {
+                EntryKind::Hardlink
+            } else {
+                EntryKind::File
+            }
         } else if metadata.is_dir() {
             EntryKind::Directory
         } else if metadata.file_type().is_symlink() {
             EntryKind::Symlink
+        } else if let Some(special) = special::special_kind_of(&metadata.file_type()) {
+            if !options.include_special {
+                if !options.quiet {
+                    eprintln!(
+                        "safetar: skipping {special} {rel} (use --include-special to archive it)"
+                    );
+                }
+                continue;
+            }
+            EntryKind::Special(special)
         } else {
+            if !options.quiet {
+                let what = if special::is_socket(&metadata.file_type()) {
+                    "socket"
+                } else {
+                    "unsupported file type"
+                };
+                eprintln!("safetar: skipping {what} {rel}");
+            }
             continue;
         };
         let size = if kind == EntryKind::File {
@@ -469,10 +1483,12 @@ fn walk_input(
             0
         };
 
-        let link_target = if kind == EntryKind::Symlink {
+        let link_target = if kind == EntryKind::Hardlink {
+            file_identity(&metadata).and_then(|id| state.hardlinks.get(&id).cloned())
+        } else if kind == EntryKind::Symlink {
             let target = fs::read_link(entry.path())?;
-            let target = Utf8PathBuf::from_path_buf(target)
-                .map_err(|_| anyhow!("symlink target not UTF-8: {}", entry.path().display()))?;
+            let target = names::from_path(&target, policy.byte_paths())
+                .with_context(|| format!("bad symlink target for {}", entry.path().display()))?;
             let resolved = if target.is_absolute() {
                 target.clone()
             } else if let Some(parent) = abs_path.parent() {
@@ -504,18 +1520,72 @@ fn walk_input(
         let validated = policy
             .normalize_and_validate(stored_rel.as_ref(), allowed_root)
             .map_err(map_policy_error_for_create)?;
+        // The rewritten name is validated on its own, so a transform cannot add traversal.
+        let Some(renamed) = state.renamer.name(&stored_rel) else {
+            continue;
+        };
+        let stored = policy
+            .normalize_and_validate(&renamed, allowed_root)
+            .map_err(map_policy_error_for_create)?;
+        let link_target = match (kind, link_target) {
+            (EntryKind::Symlink, Some(target)) => {
+                let renamed_target = state.renamer.symlink_target(&target);
+                if renamed_target != target {
+                    let resolved = match stored.abs.parent() {
+                        Some(parent) => parent.join(&renamed_target),
+                        None => allowed_root.join(&renamed_target),
+                    };
+                    policy
+                        .enforce_link_policy(resolved.as_ref(), allowed_root, LinkType::Symlink)
+                        .map_err(map_policy_error_for_create)?;
+                }
+                Some(renamed_target)
+            }
+            (_, target) => target,
+        };
+
+        let sparse_map = if options.sparse && kind == EntryKind::File && size > 0 {
+            let file =
+                File::open(entry.path()).with_context(|| format!("failed to open {abs_path}"))?;
+            sparse::detect(&file, size)
+                .with_context(|| format!("failed to scan {abs_path} for holes"))?
+        } else {
+            None
+        };
+        let stored_size = sparse_map
+            .as_ref()
+            .map_or(size, sparse::SparseMap::stored_size);
 
-        usage
-            .observe(&validated, size)
+        state
+            .usage
+            .observe_sparse(&stored, size, stored_size)
             .map_err(map_policy_error_for_create)?;
 
-        entries.push(ArchiveEntry {
+        if kind == EntryKind::File {
+            if let Some(id) = file_identity(&metadata) {
+                state.hardlinks.insert(id, stored.rel.clone());
+            }
+        }
+
+        let selection = XattrSelection {
+            xattrs: options.xattrs,
+            acls: options.acls,
+        };
+        // Hard links share the attributes of the member they point at.
+        let xattrs = (!selection.is_empty() && kind != EntryKind::Hardlink)
+            .then(|| xattr::read(&validated.abs, selection))
+            .transpose()?;
+
+        state.entries.push(ArchiveEntry {
             absolute: validated.abs,
-            relative: stored_rel,
+            relative: stored.rel,
             kind,
             size,
             link_target,
             mtime: metadata.modified().ok(),
+            atime: metadata.accessed().ok(),
+            xattrs,
+            sparse: sparse_map,
         });
     }
     Ok(())
@@ -548,50 +1618,134 @@ fn compile_excludes(patterns: &[String], files: &[Utf8PathBuf]) -> Result<Option
 
 fn is_excluded(set: &Option<GlobSet>, rel: &Utf8Path) -> bool {
     set.as_ref()
-        .map(|set| set.is_match(rel.as_str()))
+        .map(|set| set.is_match(names::to_path(rel)))
         .unwrap_or(false)
 }
 
 fn resolve_base(dir: Option<&Utf8PathBuf>) -> Result<std::path::PathBuf> {
     let base = match dir {
-        Some(dir) => dir.clone(),
-        None => Utf8PathBuf::from_path_buf(std::env::current_dir()?)
-            .map_err(|_| anyhow!("current directory not valid UTF-8"))?,
+        Some(dir) => names::to_path(dir).into_owned(),
+        None => std::env::current_dir()?,
     };
-    let canonical = fs::canonicalize(base.as_std_path())
-        .with_context(|| format!("failed to canonicalize {}", base))?;
+    let canonical = fs::canonicalize(&base)
+        .with_context(|| format!("failed to canonicalize {}", base.display()))?;
     Ok(canonical)
 }
 
-fn canonicalize_input(base: &Utf8Path, input: &Utf8Path) -> Result<Utf8PathBuf> {
+fn canonicalize_input(base: &Utf8Path, input: &Utf8Path, allow_raw: bool) -> Result<Utf8PathBuf> {
     let joined = if input.is_absolute() {
         input.to_owned()
     } else {
         base.join(input)
     };
-    let canonical = match fs::canonicalize(joined.as_std_path()) {
+    let canonical = match fs::canonicalize(names::to_path(&joined)) {
         Ok(path) => path,
         Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
-            return Err(
-                UserInputError::new(format!("input path does not exist: {}", joined)).into(),
-            );
+            return Err(UserInputError::new(format!(
+                "input path does not exist: {}",
+                names::escaped(&joined)
+            ))
+            .into());
         }
         Err(err) => {
             return Err(anyhow!(err).context(format!("failed to canonicalize {joined}")));
         }
     };
-    Utf8PathBuf::from_path_buf(canonical).map_err(|_| anyhow!("path not UTF-8: {joined}"))
+    names::from_path(&canonical, allow_raw).map_err(|err| anyhow!(err))
+}
+
+/// Whether `path` is `-`, which stands for stdin when reading an archive and for
+/// stdout when writing one.
+pub(crate) fn is_stdio(path: &Utf8Path) -> bool {
+    path.as_str() == "-"
+}
+
+/// Print a status line: to stdout, or to stderr while stdout carries the archive.
+pub(crate) fn report(archive_on_stdout: bool, message: impl std::fmt::Display) {
+    if archive_on_stdout {
+        eprintln!("{message}");
+    } else {
+        println!("{message}");
+    }
+}
+
+/// Open the archive at `path` (stdin for `-`) and detect its compression.
+fn open_archive(path: &Utf8Path) -> Result<CompressionReader> {
+    let reader = if is_stdio(path) {
+        wrap_reader(io::stdin())
+    } else {
+        let file = File::open(names::to_path(path))
+            .with_context(|| format!("failed to open archive {path}"))?;
+        wrap_reader(file)
+    };
+    reader.context("failed to detect archive compression")
+}
+
+/// Open an archive like [`open_archive`], failing reads past the compression limits.
+fn open_archive_within(path: &Utf8Path, limits: PolicyLimits) -> Result<CompressionReader> {
+    Ok(open_archive(path)?.with_limits(limits.max_compression_ratio, limits.max_compressed_input))
+}
+
+/// Turn a compression limit violation, which the archive reader can only report as an
+/// I/O error, back into the [`PolicyError`] it carries.
+fn surface_input_limit(err: anyhow::Error) -> anyhow::Error {
+    let violation = err.chain().find_map(|cause| {
+        match *cause
+            .downcast_ref::<io::Error>()?
+            .get_ref()?
+            .downcast_ref::<PolicyError>()?
+        {
+            PolicyError::CompressionRatioExceeded {
+                limit,
+                compressed,
+                decoded,
+            } => Some(PolicyError::CompressionRatioExceeded {
+                limit,
+                compressed,
+                decoded,
+            }),
+            PolicyError::CompressedInputExceeded { limit, actual } => {
+                Some(PolicyError::CompressedInputExceeded { limit, actual })
+            }
+            _ => None,
+        }
+    });
+    match violation {
+        Some(violation) => anyhow!(violation),
+        None => err,
+    }
+}
+
+/// Create the archive file at `path`, or write to stdout for `-`.
+fn create_output(path: &Utf8Path) -> Result<Box<dyn io::Write>> {
+    if is_stdio(path) {
+        Ok(Box::new(stdout_archive()?))
+    } else {
+        let file = File::create(names::to_path(path))
+            .with_context(|| format!("failed to create archive {path}"))?;
+        Ok(Box::new(file))
+    }
+}
+
+/// Stdout as an archive destination; a terminal is refused, as `tar` does.
+fn stdout_archive() -> Result<io::Stdout> {
+    let stdout = io::stdout();
+    if stdout.is_terminal() {
+        return Err(UserInputError::new("refusing to write archive data to a terminal").into());
+    }
+    Ok(stdout)
 }
 
 fn resolve_destination(dir: &Utf8Path) -> Result<std::path::PathBuf> {
-    match fs::canonicalize(dir.as_std_path()) {
+    let fs_dir = names::to_path(dir);
+    match fs::canonicalize(&fs_dir) {
         Ok(path) => Ok(path),
         Err(err) if err.kind() == io::ErrorKind::NotFound => {
             if dir.is_absolute() {
-                Ok(dir.as_std_path().to_path_buf())
+                Ok(fs_dir.into_owned())
             } else {
                 let mut cwd = std::env::current_dir()?;
-                cwd.push(dir.as_std_path());
+                cwd.push(fs_dir);
                 Ok(cwd)
             }
         }
@@ -599,24 +1753,105 @@ fn resolve_destination(dir: &Utf8Path) -> Result<std::path::PathBuf> {
     }
 }
 
-fn ensure_parent_exists(validated: &crate::policy::ValidatedPath) -> Result<()> {
-    if let Some(parent) = validated.abs.parent() {
-        fs::create_dir_all(parent)
-            .with_context(|| format!("failed to create parent {}", parent))?;
+/// Set the permission bits of the member of kind `kind` at `location`.
+fn set_mode(location: &Location, mode: u32, kind: ManifestKind) -> Result<()> {
+    location
+        .set_mode(mode, kind)
+        .with_context(|| format!("failed to set permissions on {location}"))
+}
+
+/// Reject a member at `rel` below one of the `symlinks` extracted before it.
+fn check_symlink_parents(
+    symlinks: &HashSet<Utf8PathBuf>,
+    rel: &Utf8Path,
+) -> Result<(), PolicyError> {
+    match rel
+        .ancestors()
+        .skip(1)
+        .find(|ancestor| symlinks.contains(*ancestor))
+    {
+        Some(link) => Err(PolicyError::SymlinkInPath {
+            path: rel.to_owned(),
+            link: link.to_owned(),
+        }),
+        None => Ok(()),
     }
-    Ok(())
 }
 
-fn entry_path_utf8(
-    entry: &tar::Entry<'_, crate::io::dec::CompressionReader>,
-) -> Result<Utf8PathBuf> {
-    let path = entry.path()?;
-    match path {
-        Cow::Borrowed(path) => Utf8PathBuf::from_path_buf(path.to_path_buf())
-            .map_err(|_| anyhow!("entry path not UTF-8: {}", path.display())),
-        Cow::Owned(path) => {
-            Utf8PathBuf::from_path_buf(path).map_err(|_| anyhow!("entry path not UTF-8"))
+/// Resolve the on-disk source for a hard link, requiring it to be an earlier member.
+fn resolve_hardlink_source(
+    policy: &SecurityPolicy,
+    root: &Utf8Path,
+    validated: &crate::policy::ValidatedPath,
+    target: &Utf8Path,
+    extracted: &HashSet<Utf8PathBuf>,
+) -> Result<Utf8PathBuf, PolicyError> {
+    policy.enforce_link_policy(target, root, LinkType::Hardlink)?;
+    // Hard link names are relative to the archive root, not to the link's parent.
+    let joined = if target.is_absolute() {
+        target.to_owned()
+    } else {
+        root.join(target)
+    };
+    let cleaned = Utf8PathBuf::from_path_buf(joined.as_std_path().to_path_buf().clean())
+        .map_err(|_| PolicyError::LinkOutsideRoot(target.to_owned()))?;
+    match cleaned.strip_prefix(root) {
+        Ok(rel) if extracted.contains(rel) => Ok(cleaned),
+        Ok(_) => Err(PolicyError::HardlinkTargetMissing {
+            path: validated.rel.clone(),
+            target: target.to_owned(),
+        }),
+        // Only reachable when the policy explicitly allows links outside the root.
+        Err(_) => Ok(cleaned),
+    }
+}
+
+#[cfg(unix)]
+fn file_identity(metadata: &fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+
+    (metadata.nlink() > 1).then(|| (metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn file_identity(_metadata: &fs::Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+fn entry_mtime(header: &tar::Header, pax: &PaxMetadata) -> Option<std::time::SystemTime> {
+    pax.mtime.map(pax_mod::PaxTime::to_system_time).or_else(|| {
+        header
+            .mtime()
+            .ok()
+            .map(|secs| std::time::UNIX_EPOCH + Duration::from_secs(secs))
+    })
+}
+
+/// PAX 1.0 sparse layout of a regular file member, if it has one.
+fn pax_sparse_layout(
+    kind: EntryKind,
+    pax: &PaxMetadata,
+    shown: &str,
+) -> Result<Option<sparse::PaxSparse>> {
+    if kind != EntryKind::File {
+        return Ok(None);
+    }
+    sparse::PaxSparse::from_pax(pax).with_context(|| format!("invalid sparse records for {shown}"))
+}
+
+/// Logical and stored size of a member.
+fn entry_sizes<R: Read>(
+    entry: &tar::Entry<'_, R>,
+    header: &tar::Header,
+    pax_sparse: Option<&sparse::PaxSparse>,
+) -> (u64, u64) {
+    match pax_sparse {
+        Some(layout) => (layout.real_size, entry.size()),
+        // `tar` reports the expanded size of GNU sparse members; the header holds the stored one.
+        None if header.entry_type().is_gnu_sparse() => {
+            (entry.size(), header.entry_size().unwrap_or_default())
         }
+        None => (entry.size(), entry.size()),
     }
 }
 
@@ -632,25 +1867,33 @@ fn enforce_link(
             .map_err(map_policy_error_for_create)?;
     } else if let Some(parent) = validated.abs.parent() {
         let resolved = parent.join(target);
+        // Report the target as stored; the joined path may start at a virtual root.
         policy
             .enforce_link_policy(resolved.as_ref(), root, LinkType::Symlink)
-            .map_err(map_policy_error_for_create)?;
+            .map_err(|_| {
+                map_policy_error_for_create(PolicyError::LinkOutsideRoot(target.to_owned()))
+            })?;
     }
     Ok(())
 }
 
-fn map_policy_error(err: PolicyError, strict: bool) -> anyhow::Error {
-    if strict {
-        anyhow!(err)
-    } else {
-        anyhow!(err)
-    }
+fn map_policy_error(err: PolicyError) -> anyhow::Error {
+    anyhow!(err)
 }
 
 fn map_policy_error_for_create(err: PolicyError) -> anyhow::Error {
     anyhow!(err)
 }
 
+/// Accumulated state while walking create inputs.
+struct WalkState {
+    usage: UsageTracker,
+    entries: Vec<ArchiveEntry>,
+    /// First stored path for each multiply-linked (device, inode) pair.
+    hardlinks: HashMap<(u64, u64), Utf8PathBuf>,
+    renamer: Renamer,
+}
+
 #[derive(Debug, Clone)]
 struct ArchiveEntry {
     absolute: Utf8PathBuf,
@@ -659,6 +1902,12 @@ struct ArchiveEntry {
     size: u64,
     link_target: Option<Utf8PathBuf>,
     mtime: Option<std::time::SystemTime>,
+    /// Access time when the input was found, before archiving reads it.
+    atime: Option<std::time::SystemTime>,
+    /// Extended attributes to record, when they are tracked.
+    xattrs: Option<Vec<(String, Vec<u8>)>>,
+    /// Data runs of a file with holes, when sparse archiving is enabled.
+    sparse: Option<sparse::SparseMap>,
 }
 
 impl ArchiveEntry {
@@ -667,21 +1916,38 @@ impl ArchiveEntry {
             EntryKind::File => "file",
             EntryKind::Directory => "dir",
             EntryKind::Symlink => "symlink",
+            EntryKind::Hardlink => "hardlink",
+            EntryKind::Special(SpecialEntryKind::CharDevice) => "chardev",
+            EntryKind::Special(SpecialEntryKind::BlockDevice) => "blockdev",
+            EntryKind::Special(SpecialEntryKind::Fifo) => "fifo",
+            EntryKind::Special(SpecialEntryKind::Unknown) => "other",
         }
     }
 
+    /// Bytes the member occupies in the archive (less than `size` for sparse files).
+    fn stored_size(&self) -> u64 {
+        self.sparse
+            .as_ref()
+            .map_or(self.size, sparse::SparseMap::stored_size)
+    }
+
     fn to_manifest_item(&self) -> ManifestItem {
         ManifestItem {
             relative: self.relative.clone(),
             absolute: self.absolute.clone(),
-            kind: match self.kind {
-                EntryKind::File => ManifestKind::File,
-                EntryKind::Directory => ManifestKind::Directory,
-                EntryKind::Symlink => ManifestKind::Symlink,
-            },
+            kind: self.kind.manifest_kind(),
             link_target: self.link_target.clone(),
             size: self.size,
             mtime: self.mtime,
+            xattrs: self.xattrs.as_ref().map(|xattrs| {
+                manifest::xattr_digests(
+                    xattrs
+                        .iter()
+                        .map(|(name, value)| (name.as_str(), value.as_slice())),
+                )
+            }),
+            outcome: None,
+            backup: None,
         }
     }
 }
@@ -691,23 +1957,42 @@ pub(crate) enum EntryKind {
     File,
     Directory,
     Symlink,
+    Hardlink,
+    Special(SpecialEntryKind),
 }
 
-fn hash_entry_data<R: Read>(entry: &mut tar::Entry<'_, R>) -> Result<String> {
+impl EntryKind {
+    fn manifest_kind(self) -> ManifestKind {
+        match self {
+            EntryKind::File => ManifestKind::File,
+            EntryKind::Directory => ManifestKind::Directory,
+            EntryKind::Symlink => ManifestKind::Symlink,
+            EntryKind::Hardlink => ManifestKind::Hardlink,
+            EntryKind::Special(kind) => special::manifest_kind(kind),
+        }
+    }
+
+    /// Whether the member's mode applies to what is extracted; links have none of their own.
+    fn has_own_mode(self) -> bool {
+        !matches!(self, EntryKind::Symlink | EntryKind::Hardlink)
+    }
+}
+
+fn hash_entry_data<R: Read>(reader: &mut R) -> Result<String> {
+    copy_hashing(reader, &mut io::sink())
+}
+
+/// Copy `reader` into `out`, returning the SHA-256 of the bytes copied.
+fn copy_hashing<R: Read, W: io::Write>(reader: &mut R, out: &mut W) -> Result<String> {
     let mut hasher = Sha256::new();
     let mut buffer = [0_u8; 64 * 1024];
     loop {
-        let read = entry.read(&mut buffer)?;
+        let read = reader.read(&mut buffer)?;
         if read == 0 {
             break;
         }
         hasher.update(&buffer[..read]);
+        out.write_all(&buffer[..read])?;
     }
     Ok(hex::encode(hasher.finalize()))
 }
-
-fn digest_bytes_prefilled(bytes: &[u8]) -> String {
-    let mut hasher = Sha256::new();
-    hasher.update(bytes);
-    hex::encode(hasher.finalize())
-}
@@ -0,0 +1,188 @@
+//! What extraction does when a member's path already exists (`--keep-old-files`,
+//! `--skip-old-files`, `--overwrite`, `--unlink-first`, `--keep-newer-files`,
+//! `--backup`).
+
+use std::time::SystemTime;
+
+use anyhow::{anyhow, Context, Result};
+use camino::Utf8PathBuf;
+
+use super::rooted::Location;
+use super::EntryKind;
+use crate::manifest::{ExtractOutcome, ManifestKind};
+use crate::names;
+
+/// What [`extract_archive`](super::extract_archive) does when a member's path already
+/// exists.
+///
+/// An existing directory is always kept for a directory member, and its contents merged
+/// with the archive's; the modes decide what happens to everything else.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OverwriteMode {
+    /// Remove an existing file, symlink, or special file and extract the member in its
+    /// place. A directory in the way of another kind of member is an error.
+    #[default]
+    Replace,
+    /// Fail when a member's path exists (`--keep-old-files`).
+    KeepOld,
+    /// Leave existing files alone and skip their members (`--skip-old-files`).
+    SkipOld,
+    /// Write file members into existing regular files in place, so other hard links
+    /// to them see the new contents (`--overwrite`). Anything else is replaced.
+    Overwrite,
+    /// Like `Replace`, but an empty directory in the way is removed as well
+    /// (`--unlink-first`).
+    UnlinkFirst,
+    /// Skip members older than the existing file and replace the rest
+    /// (`--keep-newer-files`).
+    KeepNewer,
+}
+
+/// How a file replaced by extraction is kept (`--backup`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackupMode {
+    /// Rename it to `NAME~`, replacing an earlier backup.
+    Simple,
+    /// Rename it to `NAME.~N~`, numbering after the highest existing backup.
+    Numbered,
+}
+
+/// How the path of a member was prepared for it.
+#[derive(Debug, Clone)]
+pub(crate) struct Placement {
+    pub outcome: ExtractOutcome,
+    /// Where the file that was in the way went, relative to the destination.
+    pub backup: Option<Utf8PathBuf>,
+}
+
+impl Placement {
+    fn new(outcome: ExtractOutcome) -> Self {
+        Self {
+            outcome,
+            backup: None,
+        }
+    }
+}
+
+/// Clear the way for a member of kind `kind` at `location`.
+///
+/// `mtime` is the member's stored modification time and `in_place` whether it could be
+/// written into an existing regular file. With [`ExtractOutcome::Skipped`] the member
+/// must not be extracted; with [`ExtractOutcome::Overwritten`] the file is still there.
+pub(crate) fn make_room(
+    location: &Location,
+    kind: EntryKind,
+    mtime: Option<SystemTime>,
+    mode: OverwriteMode,
+    backup: Option<BackupMode>,
+    in_place: bool,
+) -> Result<Placement> {
+    let Some(existing) = location
+        .stat()
+        .with_context(|| format!("failed to inspect {location}"))?
+    else {
+        return Ok(Placement::new(ExtractOutcome::Created));
+    };
+    let is_dir = existing.kind == ManifestKind::Directory;
+    if is_dir && kind == EntryKind::Directory {
+        return Ok(Placement::new(ExtractOutcome::Merged));
+    }
+    match mode {
+        OverwriteMode::KeepOld => {
+            return Err(anyhow!(
+                "refusing to overwrite existing {location} (--keep-old-files)"
+            ))
+        }
+        OverwriteMode::SkipOld => return Ok(Placement::new(ExtractOutcome::Skipped)),
+        OverwriteMode::KeepNewer => {
+            if let (Some(current), Some(archived)) = (existing.modified, mtime) {
+                if current > archived {
+                    return Ok(Placement::new(ExtractOutcome::Skipped));
+                }
+            }
+        }
+        OverwriteMode::Replace | OverwriteMode::Overwrite | OverwriteMode::UnlinkFirst => {}
+    }
+
+    if is_dir {
+        if mode != OverwriteMode::UnlinkFirst {
+            return Err(anyhow!(
+                "cannot extract {location} over a directory (--unlink-first removes empty ones)"
+            ));
+        }
+        location
+            .remove_dir()
+            .with_context(|| format!("failed to remove directory {location}"))?;
+        return Ok(Placement::new(ExtractOutcome::Replaced));
+    }
+    if let Some(backup) = backup {
+        return Ok(Placement {
+            outcome: ExtractOutcome::Replaced,
+            backup: Some(back_up(location, backup)?),
+        });
+    }
+    if mode == OverwriteMode::Overwrite && in_place && existing.kind == ManifestKind::File {
+        return Ok(Placement::new(ExtractOutcome::Overwritten));
+    }
+    location
+        .remove_file()
+        .with_context(|| format!("failed to replace {location}"))?;
+    Ok(Placement::new(ExtractOutcome::Replaced))
+}
+
+/// Rename the file at `location` out of the way, returning its new relative path.
+fn back_up(location: &Location, mode: BackupMode) -> Result<Utf8PathBuf> {
+    let name = location
+        .rel()
+        .file_name()
+        .ok_or_else(|| anyhow!("cannot back up {location}"))?;
+    let backup_name = match mode {
+        BackupMode::Simple => format!("{name}~"),
+        BackupMode::Numbered => {
+            let siblings = location
+                .sibling_names()
+                .with_context(|| format!("failed to list the directory of {location}"))?;
+            format!("{name}.~{}~", next_backup_number(&siblings, name))
+        }
+    };
+    let backup = location.rel().with_file_name(&backup_name);
+    location.rename(&backup_name).with_context(|| {
+        format!(
+            "failed to back up {location} as {}",
+            names::escaped(&backup)
+        )
+    })?;
+    Ok(backup)
+}
+
+/// One more than the highest `N` among the `NAME.~N~` files in `siblings`.
+fn next_backup_number(siblings: &[Vec<u8>], name: &str) -> u64 {
+    let prefix = format!("{name}.~");
+    siblings
+        .iter()
+        .filter_map(|sibling| names::from_bytes(sibling, true).ok())
+        .filter_map(|sibling| {
+            sibling
+                .as_str()
+                .strip_prefix(&prefix)?
+                .strip_suffix('~')?
+                .parse::<u64>()
+                .ok()
+        })
+        .max()
+        .unwrap_or(0)
+        + 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn numbered_backups_count_past_the_highest() {
+        assert_eq!(next_backup_number(&[], "data.txt"), 1);
+        let siblings = ["data.txt.~1~", "data.txt.~7~", "data.txt.~x~", "other.~9~"]
+            .map(|name| name.as_bytes().to_vec());
+        assert_eq!(next_backup_number(&siblings, "data.txt"), 8);
+    }
+}
@@ -0,0 +1,362 @@
+//! Member owners: what `create` records and what `extract` restores.
+//!
+//! Names are resolved with the system's `/etc/passwd` and `/etc/group`. Map files use
+//! GNU tar's `--owner-map` syntax: one `OLD NEW` pair per line, where `OLD` is a name
+//! or `+ID` and `NEW` is `NAME:ID`, `NAME`, or `+ID`. An id shift moves every id by a
+//! fixed offset after mapping, as between a user namespace and its host; names mean
+//! nothing across that boundary, so shifted owners are always numeric.
+
+use std::collections::HashMap;
+use std::fs;
+
+use anyhow::{anyhow, Context, Result};
+use camino::Utf8Path;
+
+use super::pax::PaxMetadata;
+use super::rooted::Location;
+use super::OwnerMapping;
+use crate::error::UserInputError;
+use crate::names;
+
+const PASSWD: &str = "/etc/passwd";
+const GROUP: &str = "/etc/group";
+
+/// Numeric ids and, when known, names of a member's owner.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct Owner {
+    pub uid: u64,
+    pub gid: u64,
+    pub uname: Option<String>,
+    pub gname: Option<String>,
+}
+
+impl Owner {
+    /// Owner stored for a member, with PAX records taking precedence over the header.
+    pub fn from_header(header: &tar::Header, pax: &PaxMetadata) -> Self {
+        let name = |bytes: Option<&[u8]>| {
+            bytes
+                .filter(|bytes| !bytes.is_empty())
+                .map(|bytes| String::from_utf8_lossy(bytes).into_owned())
+        };
+        Self {
+            uid: pax.uid.or_else(|| header.uid().ok()).unwrap_or_default(),
+            gid: pax.gid.or_else(|| header.gid().ok()).unwrap_or_default(),
+            uname: pax.uname.clone().or_else(|| name(header.username_bytes())),
+            gname: pax.gname.clone().or_else(|| name(header.groupname_bytes())),
+        }
+    }
+}
+
+/// Owner translation for one operation: maps, shifts, and the account databases.
+pub(crate) struct Owners {
+    numeric: bool,
+    users: Side,
+    groups: Side,
+}
+
+impl Owners {
+    pub fn new(mapping: &OwnerMapping, numeric: bool) -> Result<Self> {
+        Ok(Self {
+            numeric: numeric || mapping.uid_shift != 0 || mapping.gid_shift != 0,
+            users: Side::new(
+                "user",
+                PASSWD,
+                mapping.owner_map.as_deref(),
+                mapping.uid_shift,
+            )?,
+            groups: Side::new(
+                "group",
+                GROUP,
+                mapping.group_map.as_deref(),
+                mapping.gid_shift,
+            )?,
+        })
+    }
+
+    /// Owner to record for a file on disk.
+    pub fn recorded(&self, metadata: &fs::Metadata) -> Result<Owner> {
+        let (uid, gid) = file_owner(metadata);
+        let (uid, uname) = self.users.recorded(uid)?;
+        let (gid, gname) = self.groups.recorded(gid)?;
+        Ok(Owner {
+            uid,
+            gid,
+            uname: uname.filter(|_| !self.numeric),
+            gname: gname.filter(|_| !self.numeric),
+        })
+    }
+
+    /// Local uid and gid for a member stored with `owner`; names win over ids unless
+    /// owners are numeric.
+    pub fn restored(&self, owner: &Owner) -> Result<(u32, u32)> {
+        Ok((
+            self.users
+                .restored(owner.uid, owner.uname.clone(), self.numeric)?,
+            self.groups
+                .restored(owner.gid, owner.gname.clone(), self.numeric)?,
+        ))
+    }
+
+    /// Give the member extracted at `location` (not following a symlink there) the
+    /// owner stored as `owner`.
+    pub fn restore(&self, location: &Location, owner: &Owner) -> Result<()> {
+        let (uid, gid) = self.restored(owner)?;
+        location
+            .set_owner(uid, gid)
+            .with_context(|| format!("failed to change the owner of {location} to {uid}:{gid}"))
+    }
+}
+
+/// Whether extraction may give files away to other owners.
+#[cfg(unix)]
+pub(crate) fn can_restore() -> bool {
+    rustix::process::geteuid().is_root()
+}
+
+#[cfg(not(unix))]
+pub(crate) fn can_restore() -> bool {
+    false
+}
+
+#[cfg(unix)]
+fn file_owner(metadata: &fs::Metadata) -> (u64, u64) {
+    use std::os::unix::fs::MetadataExt;
+
+    (u64::from(metadata.uid()), u64::from(metadata.gid()))
+}
+
+#[cfg(not(unix))]
+fn file_owner(_metadata: &fs::Metadata) -> (u64, u64) {
+    (0, 0)
+}
+
+/// Users or groups: one map file, one shift, and the matching account database.
+struct Side {
+    what: &'static str,
+    map: IdMap,
+    shift: i64,
+    accounts: Accounts,
+}
+
+impl Side {
+    fn new(what: &'static str, database: &str, map: Option<&Utf8Path>, shift: i64) -> Result<Self> {
+        Ok(Self {
+            what,
+            map: map.map(IdMap::read).transpose()?.unwrap_or_default(),
+            shift,
+            accounts: Accounts::read(database)?,
+        })
+    }
+
+    fn recorded(&self, id: u64) -> Result<(u64, Option<String>)> {
+        let name = self.accounts.names.get(&id).cloned();
+        let (id, name) = self.mapped(id, name)?;
+        Ok((self.shifted(id)?, name))
+    }
+
+    fn restored(&self, id: u64, name: Option<String>, numeric: bool) -> Result<u32> {
+        let (id, name) = self.mapped(id, name)?;
+        let local = name
+            .filter(|_| !numeric)
+            .and_then(|name| self.accounts.ids.get(&name).copied());
+        let id = self.shifted(local.unwrap_or(id))?;
+        // `-1` means "unchanged" to chown, so it cannot be restored either.
+        u32::try_from(id)
+            .ok()
+            .filter(|&id| id != u32::MAX)
+            .ok_or_else(|| anyhow!("{} id {id} cannot be restored", self.what))
+    }
+
+    fn mapped(&self, id: u64, name: Option<String>) -> Result<(u64, Option<String>)> {
+        let Some(target) = self.map.get(name.as_deref(), id) else {
+            return Ok((id, name));
+        };
+        let id = match (target.id, &target.name) {
+            (Some(id), _) => id,
+            (None, Some(name)) => *self.accounts.ids.get(name).ok_or_else(|| {
+                UserInputError::new(format!("unknown {} {name} in {} map", self.what, self.what))
+            })?,
+            (None, None) => id,
+        };
+        Ok((id, target.name.clone()))
+    }
+
+    fn shifted(&self, id: u64) -> Result<u64> {
+        id.checked_add_signed(self.shift)
+            .filter(|&shifted| shifted < u64::from(u32::MAX))
+            .ok_or_else(|| {
+                UserInputError::new(format!(
+                    "{} id {id} shifted by {} is out of range",
+                    self.what, self.shift
+                ))
+                .into()
+            })
+    }
+}
+
+/// Owner translations read from a map file.
+#[derive(Debug, Default)]
+struct IdMap {
+    by_name: HashMap<String, MapTarget>,
+    by_id: HashMap<u64, MapTarget>,
+}
+
+/// Right-hand side of a map line; at least one of the two is set.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct MapTarget {
+    name: Option<String>,
+    id: Option<u64>,
+}
+
+impl IdMap {
+    fn read(path: &Utf8Path) -> Result<Self> {
+        let text = fs::read_to_string(names::to_path(path))
+            .with_context(|| format!("failed to read map file {path}"))?;
+        Self::parse(&text).map_err(|(line, message)| {
+            UserInputError::new(format!("{path}:{line}: {message}")).into()
+        })
+    }
+
+    /// Parse map file text; errors carry the 1-based line number.
+    fn parse(text: &str) -> Result<Self, (usize, String)> {
+        let mut map = Self::default();
+        for (index, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let fail = |message: &str| (index + 1, format!("{message}: {line}"));
+            let mut fields = line.split_whitespace();
+            let (Some(old), Some(new), None) = (fields.next(), fields.next(), fields.next()) else {
+                return Err(fail("expected `OLD NEW`"));
+            };
+            let target = match new.split_once(':') {
+                Some((name, id)) if !name.is_empty() => MapTarget {
+                    name: Some(name.to_owned()),
+                    id: Some(id.parse().map_err(|_| fail("invalid id"))?),
+                },
+                Some(_) => return Err(fail("missing name before `:`")),
+                None => match new.strip_prefix('+') {
+                    Some(id) => MapTarget {
+                        name: None,
+                        id: Some(id.parse().map_err(|_| fail("invalid id"))?),
+                    },
+                    None => MapTarget {
+                        name: Some(new.to_owned()),
+                        id: None,
+                    },
+                },
+            };
+            match old.strip_prefix('+') {
+                Some(id) => {
+                    let id = id.parse().map_err(|_| fail("invalid id"))?;
+                    map.by_id.insert(id, target);
+                }
+                None => {
+                    map.by_name.insert(old.to_owned(), target);
+                }
+            }
+        }
+        Ok(map)
+    }
+
+    /// Translation for an owner, matched by name before id.
+    fn get(&self, name: Option<&str>, id: u64) -> Option<&MapTarget> {
+        name.and_then(|name| self.by_name.get(name))
+            .or_else(|| self.by_id.get(&id))
+    }
+}
+
+/// Names and ids from `/etc/passwd` or `/etc/group`.
+#[derive(Debug, Default)]
+struct Accounts {
+    ids: HashMap<String, u64>,
+    names: HashMap<u64, String>,
+}
+
+impl Accounts {
+    /// Read `path`; a system without the file simply has no names.
+    fn read(path: &str) -> Result<Self> {
+        match fs::read(path) {
+            Ok(data) => Ok(Self::parse(&String::from_utf8_lossy(&data))),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err).with_context(|| format!("failed to read {path}")),
+        }
+    }
+
+    fn parse(text: &str) -> Self {
+        let mut accounts = Self::default();
+        for line in text.lines() {
+            let mut fields = line.split(':');
+            let (Some(name), Some(_), Some(id)) = (fields.next(), fields.next(), fields.next())
+            else {
+                continue;
+            };
+            let Ok(id) = id.parse() else {
+                continue;
+            };
+            // Like getpwnam and getpwuid, the first entry for a name or id wins.
+            accounts.ids.entry(name.to_owned()).or_insert(id);
+            accounts.names.entry(id).or_insert_with(|| name.to_owned());
+        }
+        accounts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_gnu_map_syntax() {
+        let map = IdMap::parse("# comment\n+0 builder:1000\nstaff +50  # trailing\nold new\n")
+            .expect("valid map");
+        assert_eq!(
+            map.get(None, 0),
+            Some(&MapTarget {
+                name: Some("builder".into()),
+                id: Some(1000)
+            })
+        );
+        assert_eq!(map.get(Some("staff"), 7).and_then(|t| t.id), Some(50));
+        assert_eq!(
+            map.get(Some("old"), 1).and_then(|t| t.name.as_deref()),
+            Some("new")
+        );
+        assert_eq!(map.get(Some("other"), 1), None);
+
+        assert_eq!(IdMap::parse("\nroot\n").unwrap_err().0, 2);
+        assert!(IdMap::parse("root :5").is_err());
+        assert!(IdMap::parse("+x root").is_err());
+    }
+
+    #[test]
+    fn maps_and_shifts_owners() {
+        const PASSWD: &str = "root:x:0:0::/root:/bin/sh\napp:x:1000:1000::/:/bin/sh\n";
+        let side = |map: &str, shift| Side {
+            what: "user",
+            map: IdMap::parse(map).unwrap(),
+            shift,
+            accounts: Accounts::parse(PASSWD),
+        };
+
+        let plain = side("", 0);
+        assert_eq!(plain.recorded(1000).unwrap(), (1000, Some("app".into())));
+        // The name wins over the stored id unless owners are numeric.
+        assert_eq!(plain.restored(5, Some("app".into()), false).unwrap(), 1000);
+        assert_eq!(plain.restored(5, Some("app".into()), true).unwrap(), 5);
+        assert_eq!(plain.restored(5, Some("ghost".into()), false).unwrap(), 5);
+
+        let mapped = side("app root\n+7 +8\n", 0);
+        assert_eq!(mapped.recorded(1000).unwrap(), (0, Some("root".into())));
+        assert_eq!(mapped.restored(7, None, false).unwrap(), 8);
+
+        let shifted = side("", 100_000);
+        assert_eq!(
+            shifted.restored(0, Some("root".into()), true).unwrap(),
+            100_000
+        );
+        let unshifted = side("", -100_000);
+        assert!(unshifted.recorded(5).is_err());
+    }
+}
@@ -1,13 +1,432 @@
-//! Placeholder for future PAX extension support.
+//! PAX extended header parsing.
 
 use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{self, Read};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use tar::Header;
+use camino::{Utf8Path, Utf8PathBuf};
+use serde::{Deserialize, Serialize};
+use tar::{Entry, PaxExtensions};
 
-/// Extract known PAX key/value pairs from the header if present.
-///
-/// The current implementation does not parse extended headers; it returns an empty map so that
-/// call sites can remain future-proof.
-pub fn extract_pax_extensions(_header: &Header) -> BTreeMap<String, String> {
-    BTreeMap::new()
+use crate::names;
+
+pub(crate) const SCHILY_XATTR_PREFIX: &str = "SCHILY.xattr.";
+
+/// Timestamp stored in a PAX record, preserving sub-second precision.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PaxTime {
+    pub secs: i64,
+    pub nanos: u32,
+}
+
+impl PaxTime {
+    /// Parse a PAX decimal timestamp such as `1700000000.123456789` or `-1.5`.
+    #[must_use]
+    pub fn parse(value: &str) -> Option<Self> {
+        let (negative, digits) = match value.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, value),
+        };
+        let (whole, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+        if whole.is_empty() || !whole.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        if !fraction.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        let whole: i64 = whole.parse().ok()?;
+        let mut nanos = 0u32;
+        for (idx, digit) in fraction.bytes().take(9).enumerate() {
+            nanos += u32::from(digit - b'0') * 10u32.pow(8 - idx as u32);
+        }
+        if !negative {
+            return Some(Self { secs: whole, nanos });
+        }
+        // Normalise negative values so `nanos` always counts forward from `secs`.
+        if nanos == 0 {
+            Some(Self {
+                secs: whole.checked_neg()?,
+                nanos: 0,
+            })
+        } else {
+            Some(Self {
+                secs: whole.checked_neg()?.checked_sub(1)?,
+                nanos: 1_000_000_000 - nanos,
+            })
+        }
+    }
+
+    /// Build a timestamp from a [`SystemTime`].
+    #[must_use]
+    pub fn from_system_time(time: SystemTime) -> Self {
+        match time.duration_since(UNIX_EPOCH) {
+            Ok(after) => Self {
+                secs: i64::try_from(after.as_secs()).unwrap_or(i64::MAX),
+                nanos: after.subsec_nanos(),
+            },
+            Err(err) => {
+                let before = err.duration();
+                let secs = i64::try_from(before.as_secs()).unwrap_or(i64::MAX);
+                if before.subsec_nanos() == 0 {
+                    Self {
+                        secs: -secs,
+                        nanos: 0,
+                    }
+                } else {
+                    Self {
+                        secs: -secs - 1,
+                        nanos: 1_000_000_000 - before.subsec_nanos(),
+                    }
+                }
+            }
+        }
+    }
+
+    /// Convert into a [`SystemTime`].
+    #[must_use]
+    pub fn to_system_time(self) -> SystemTime {
+        if self.secs >= 0 {
+            UNIX_EPOCH + Duration::new(self.secs.unsigned_abs(), self.nanos)
+        } else {
+            UNIX_EPOCH - Duration::from_secs(self.secs.unsigned_abs())
+                + Duration::from_nanos(u64::from(self.nanos))
+        }
+    }
+}
+
+impl fmt::Display for PaxTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (secs, nanos) = if self.secs < 0 && self.nanos > 0 {
+            write!(f, "-")?;
+            ((self.secs + 1).unsigned_abs(), 1_000_000_000 - self.nanos)
+        } else {
+            if self.secs < 0 {
+                write!(f, "-")?;
+            }
+            (self.secs.unsigned_abs(), self.nanos)
+        };
+        if nanos == 0 {
+            write!(f, "{secs}")
+        } else {
+            let fraction = format!("{nanos:09}");
+            write!(f, "{secs}.{}", fraction.trim_end_matches('0'))
+        }
+    }
+}
+
+/// Well-known PAX values describing a single archive member.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PaxMetadata {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub linkpath: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mtime: Option<PaxTime>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub atime: Option<PaxTime>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ctime: Option<PaxTime>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uid: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gid: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uname: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gname: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+    /// `SCHILY.xattr.*` records keyed by attribute name (values hex-encoded in JSON).
+    #[serde(
+        default,
+        skip_serializing_if = "BTreeMap::is_empty",
+        with = "hex_values"
+    )]
+    pub xattrs: BTreeMap<String, Vec<u8>>,
+    /// Records safetar does not interpret, kept for display.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub other: BTreeMap<String, String>,
+}
+
+impl PaxMetadata {
+    /// Parse the payload of a PAX extended header (`x` or `g` entry).
+    pub fn from_records(data: &[u8]) -> io::Result<Self> {
+        let mut meta = Self::default();
+        meta.apply_records(data)?;
+        Ok(meta)
+    }
+
+    /// Apply the records in `data`, overriding any values already present.
+    pub fn apply_records(&mut self, data: &[u8]) -> io::Result<()> {
+        self.apply_extensions(PaxExtensions::new(data))
+    }
+
+    fn apply_extensions(&mut self, records: PaxExtensions<'_>) -> io::Result<()> {
+        for record in records {
+            let record = record?;
+            let key = record
+                .key()
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "PAX key not UTF-8"))?;
+            self.apply(key, record.value_bytes());
+        }
+        Ok(())
+    }
+
+    /// Whether no PAX values were recorded.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Layer the `local` records of one member over `self` (typically the active global
+    /// records).
+    ///
+    /// The records are applied to a copy in order, so an empty local value unsets the
+    /// global one just as it would an earlier value in the same header.
+    pub fn merged_with(&self, local: PaxExtensions<'_>) -> io::Result<Self> {
+        let mut merged = self.clone();
+        merged.apply_extensions(local)?;
+        Ok(merged)
+    }
+
+    fn apply(&mut self, key: &str, value: &[u8]) {
+        let text = String::from_utf8_lossy(value);
+        // An empty value deletes the keyword per POSIX; treat it as "unset".
+        let present = !value.is_empty();
+        match key {
+            "path" => self.path = present.then(|| text.into_owned()),
+            "linkpath" => self.linkpath = present.then(|| text.into_owned()),
+            "size" => self.size = text.parse().ok(),
+            "mtime" => self.mtime = PaxTime::parse(&text),
+            "atime" => self.atime = PaxTime::parse(&text),
+            "ctime" => self.ctime = PaxTime::parse(&text),
+            "uid" => self.uid = text.parse().ok(),
+            "gid" => self.gid = text.parse().ok(),
+            "uname" => self.uname = present.then(|| text.into_owned()),
+            "gname" => self.gname = present.then(|| text.into_owned()),
+            "comment" => self.comment = present.then(|| text.into_owned()),
+            _ => {
+                if let Some(name) = key.strip_prefix(SCHILY_XATTR_PREFIX) {
+                    self.xattrs.insert(name.to_owned(), value.to_vec());
+                } else if present {
+                    // Keep raw bytes recoverable (e.g. `GNU.sparse.name` of a non-UTF-8 file).
+                    let value = names::from_bytes(value, true)
+                        .map_or_else(|_| text.into_owned(), Utf8PathBuf::into_string);
+                    self.other.insert(key.to_owned(), value);
+                } else {
+                    self.other.remove(key);
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for PaxMetadata {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut fields: Vec<String> = Vec::new();
+        if let Some(path) = &self.path {
+            fields.push(format!("path={path}"));
+        }
+        if let Some(linkpath) = &self.linkpath {
+            fields.push(format!("linkpath={linkpath}"));
+        }
+        if let Some(size) = self.size {
+            fields.push(format!("size={size}"));
+        }
+        for (label, time) in [
+            ("mtime", self.mtime),
+            ("atime", self.atime),
+            ("ctime", self.ctime),
+        ] {
+            if let Some(time) = time {
+                fields.push(format!("{label}={time}"));
+            }
+        }
+        if let Some(uid) = self.uid {
+            fields.push(format!("uid={uid}"));
+        }
+        if let Some(gid) = self.gid {
+            fields.push(format!("gid={gid}"));
+        }
+        if let Some(uname) = &self.uname {
+            fields.push(format!("uname={uname}"));
+        }
+        if let Some(gname) = &self.gname {
+            fields.push(format!("gname={gname}"));
+        }
+        if let Some(comment) = &self.comment {
+            fields.push(format!("comment={comment}"));
+        }
+        for (name, value) in &self.xattrs {
+            fields.push(format!("xattr.{name}={}", hex::encode(value)));
+        }
+        for (key, value) in &self.other {
+            fields.push(format!("{key}={}", names::escaped(Utf8Path::new(value))));
+        }
+        write!(f, "{}", fields.join(" "))
+    }
+}
+
+/// Global PAX records accumulated while streaming through an archive.
+#[derive(Debug, Clone, Default)]
+pub struct PaxState {
+    global: PaxMetadata,
+}
+
+impl PaxState {
+    /// Fold the records of a `g` (global extended header) entry into the active state.
+    pub fn absorb_global<R: Read>(&mut self, entry: &mut Entry<'_, R>) -> io::Result<()> {
+        let mut data = Vec::new();
+        entry.read_to_end(&mut data)?;
+        self.global.apply_records(&data)
+    }
+
+    /// Global records currently in effect.
+    #[must_use]
+    pub fn global(&self) -> &PaxMetadata {
+        &self.global
+    }
+}
+
+/// Extract the PAX values applying to `entry`, layering local records over `state`.
+pub fn extract_pax_extensions<R: Read>(
+    entry: &mut Entry<'_, R>,
+    state: &PaxState,
+) -> io::Result<PaxMetadata> {
+    match entry.pax_extensions()? {
+        Some(records) => state.global.merged_with(records),
+        None => Ok(state.global.clone()),
+    }
+}
+
+mod hex_values {
+    use std::collections::BTreeMap;
+
+    use serde::de::Error as _;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S>(map: &BTreeMap<String, Vec<u8>>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_map(map.iter().map(|(k, v)| (k, hex::encode(v))))
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<BTreeMap<String, Vec<u8>>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let encoded = BTreeMap::<String, String>::deserialize(deserializer)?;
+        encoded
+            .into_iter()
+            .map(|(k, v)| {
+                hex::decode(v)
+                    .map(|bytes| (k, bytes))
+                    .map_err(D::Error::custom)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(key: &str, value: &str) -> Vec<u8> {
+        let rest = key.len() + value.len() + 3;
+        let mut len = rest + 1;
+        while len.to_string().len() + rest > len {
+            len += 1;
+        }
+        format!("{len} {key}={value}\n").into_bytes()
+    }
+
+    #[test]
+    fn parses_fractional_times() {
+        let time = PaxTime::parse("1700000000.123456789").expect("time");
+        assert_eq!(time.secs, 1_700_000_000);
+        assert_eq!(time.nanos, 123_456_789);
+        assert_eq!(time.to_string(), "1700000000.123456789");
+
+        let short = PaxTime::parse("12.5").expect("time");
+        assert_eq!(short.nanos, 500_000_000);
+        assert_eq!(short.to_string(), "12.5");
+
+        let negative = PaxTime::parse("-1.25").expect("time");
+        assert_eq!((negative.secs, negative.nanos), (-2, 750_000_000));
+        assert_eq!(negative.to_string(), "-1.25");
+
+        assert!(PaxTime::parse("abc").is_none());
+        assert!(PaxTime::parse(".5").is_none());
+    }
+
+    #[test]
+    fn system_time_roundtrip() {
+        let time = PaxTime {
+            secs: 1_600_000_000,
+            nanos: 42,
+        };
+        assert_eq!(PaxTime::from_system_time(time.to_system_time()), time);
+    }
+
+    #[test]
+    fn parses_known_records() {
+        let mut data = Vec::new();
+        data.extend(record("path", "very/long/name.txt"));
+        data.extend(record("mtime", "1.000000001"));
+        data.extend(record("uid", "1000"));
+        data.extend(record("uname", "builder"));
+        data.extend(record("SCHILY.xattr.user.tag", "blue"));
+        data.extend(record("LIBARCHIVE.creationtime", "5"));
+        let meta = PaxMetadata::from_records(&data).expect("records");
+        assert_eq!(meta.path.as_deref(), Some("very/long/name.txt"));
+        assert_eq!(meta.mtime, Some(PaxTime { secs: 1, nanos: 1 }));
+        assert_eq!(meta.uid, Some(1000));
+        assert_eq!(meta.uname.as_deref(), Some("builder"));
+        assert_eq!(
+            meta.xattrs.get("user.tag").map(Vec::as_slice),
+            Some(&b"blue"[..])
+        );
+        assert_eq!(
+            meta.other
+                .get("LIBARCHIVE.creationtime")
+                .map(String::as_str),
+            Some("5")
+        );
+    }
+
+    #[test]
+    fn local_records_override_global() {
+        let global =
+            PaxMetadata::from_records(&[record("uname", "root"), record("gid", "7")].concat())
+                .expect("global");
+        let local = record("uname", "alice");
+        let merged = global
+            .merged_with(PaxExtensions::new(&local))
+            .expect("local");
+        assert_eq!(merged.uname.as_deref(), Some("alice"));
+        assert_eq!(merged.gid, Some(7));
+    }
+
+    #[test]
+    fn empty_local_records_clear_global() {
+        let global =
+            PaxMetadata::from_records(&[record("uname", "root"), record("gid", "7")].concat())
+                .expect("global");
+        let local = record("uname", "");
+        let merged = global
+            .merged_with(PaxExtensions::new(&local))
+            .expect("local");
+        assert_eq!(merged.uname, None);
+        assert_eq!(merged.gid, Some(7));
+    }
+
+    #[test]
+    fn rejects_malformed_records() {
+        assert!(PaxMetadata::from_records(b"99 path=x\n").is_err());
+    }
 }
@@ -0,0 +1,505 @@
+//! Block-level reading of tar streams, for rewriting archives without re-encoding members.
+//!
+//! Each member is returned with all of its header blocks (GNU long-name records, PAX
+//! extended headers, the main header, and GNU sparse extension blocks), so it can be
+//! copied verbatim into another archive, or re-emitted under a checked name.
+
+use std::borrow::Cow;
+use std::io::{self, Read, Write};
+
+use camino::{Utf8Path, Utf8PathBuf};
+use tar::{Builder, EntryType, Header, PaxExtensions};
+
+use super::header::classify_entry_type;
+use super::pax::PaxMetadata;
+use super::sparse::PaxSparse;
+use super::EntryKind;
+use crate::names;
+
+const BLOCK_SIZE: u64 = 512;
+/// Largest extension header (long name or PAX records) accepted.
+const MAX_EXTENSION_SIZE: u64 = 16 << 20;
+/// Offset of the `isextended` flag in a GNU sparse extension block.
+const EXT_SPARSE_FLAG: usize = 504;
+
+/// One archive member and the header blocks that describe it.
+#[derive(Debug, Clone)]
+pub(crate) struct RawMember {
+    /// Member name as `tar` resolves it from the headers (empty for global headers).
+    pub name: Utf8PathBuf,
+    /// Link target for symbolic and hard links.
+    pub link_target: Option<Utf8PathBuf>,
+    pub entry_type: EntryType,
+    pub kind: EntryKind,
+    /// Permission bits from the header, when they parse.
+    pub mode: Option<u32>,
+    /// Logical size; the expanded size for sparse files.
+    pub size: u64,
+    /// Bytes of member data stored in the archive, before block padding.
+    pub stored: u64,
+    /// Every header block of the member, in archive order.
+    pub header_blocks: Vec<u8>,
+    /// Where the member's own header starts in `header_blocks`, after any extension
+    /// headers.
+    pub header_start: usize,
+    /// Records of the member's local PAX header.
+    pub pax: Option<Vec<u8>>,
+    /// Offset of the first header block in the uncompressed stream.
+    pub offset: u64,
+}
+
+impl RawMember {
+    /// Header blocks that store the member as `name`, for copying it into another
+    /// archive.
+    ///
+    /// Members without extension headers are copied as they are. Otherwise long-name
+    /// and PAX headers are replaced by one PAX header that states the name, link
+    /// target, and size once, so readers with different precedence rules cannot
+    /// disagree about the copy. The member's own header is renamed to match, and its
+    /// other PAX records are kept.
+    pub fn headers_named(&self, name: &Utf8Path) -> io::Result<Vec<u8>> {
+        if self.header_start == 0 {
+            return Ok(self.header_blocks.clone());
+        }
+        let own = &self.header_blocks[self.header_start..];
+        let mut header = Header::from_byte_slice(&own[..BLOCK_SIZE as usize]).clone();
+        let sparse = pax_sparse(self.pax.as_deref(), self.kind).is_some();
+        let name = names::to_bytes(name);
+        let link = self.link_target.as_deref().map(names::to_bytes);
+        let stored = self.stored.to_string();
+
+        let kept = PaxExtensions::new(self.pax.as_deref().unwrap_or_default())
+            .filter_map(Result::ok)
+            .filter_map(|record| Some((record.key().ok()?, record.value_bytes())))
+            .filter(|(key, _)| !matches!(*key, "path" | "linkpath" | "size"));
+        // PAX 1.0 sparse members are named by `GNU.sparse.name`, which is kept.
+        let path = (!sparse).then_some(("path", &*name));
+        let linkpath = link.as_deref().map(|link| ("linkpath", link));
+        // Readers honour the PAX size only when the header field is zero.
+        let size =
+            (header.entry_size()? == 0 && self.stored > 0).then_some(("size", stored.as_bytes()));
+        let mut builder = Builder::new(Vec::new());
+        builder.append_pax_extensions(kept.chain(path).chain(linkpath).chain(size))?;
+        let mut blocks = std::mem::take(builder.get_mut());
+
+        if !sparse {
+            set_field(&mut header.as_old_mut().name, &name);
+            if let Some(ustar) = header.as_ustar_mut() {
+                ustar.prefix.fill(0);
+            }
+        }
+        if let Some(link) = &link {
+            set_field(&mut header.as_old_mut().linkname, link);
+        }
+        header.set_cksum();
+        blocks.extend_from_slice(header.as_bytes());
+        blocks.extend_from_slice(&own[BLOCK_SIZE as usize..]);
+        Ok(blocks)
+    }
+}
+
+/// Reader yielding [`RawMember`]s from an uncompressed tar stream.
+pub(crate) struct RawReader<R> {
+    inner: R,
+    /// Data of the last member not yet consumed.
+    unread: u64,
+    /// Bytes consumed from `inner`.
+    position: u64,
+}
+
+impl<R: Read> RawReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            unread: 0,
+            position: 0,
+        }
+    }
+
+    /// Read the headers of the next member, skipping any unread data of the previous
+    /// one. Returns `None` at the end-of-archive blocks or at end of file.
+    ///
+    /// Names, link targets, and sizes are resolved exactly as the `tar` crate resolves
+    /// them for extraction, so every command sees the same member.
+    pub fn next_member(&mut self) -> io::Result<Option<RawMember>> {
+        self.skip_data()?;
+        let offset = self.position;
+        let mut header_blocks = Vec::new();
+        let mut long_name: Option<Vec<u8>> = None;
+        let mut long_link: Option<Vec<u8>> = None;
+        let mut pax: Option<Vec<u8>> = None;
+
+        loop {
+            let Some(block) = self.read_block()? else {
+                return if header_blocks.is_empty() {
+                    Ok(None)
+                } else {
+                    Err(invalid(
+                        "extension headers without a member at end of archive",
+                    ))
+                };
+            };
+            if block.iter().all(|&byte| byte == 0) {
+                return if header_blocks.is_empty() {
+                    Ok(None)
+                } else {
+                    Err(invalid(
+                        "extension headers without a member at end of archive",
+                    ))
+                };
+            }
+            let header = Header::from_byte_slice(&block);
+            verify_checksum(header)?;
+            header_blocks.extend_from_slice(&block);
+            let entry_type = header.entry_type();
+            let mut size = header.entry_size()?;
+
+            // Like `tar`, only GNU and ustar headers can carry extensions.
+            let recognized = header.as_gnu().is_some() || header.as_ustar().is_some();
+            if recognized
+                && (entry_type.is_gnu_longname()
+                    || entry_type.is_gnu_longlink()
+                    || entry_type.is_pax_local_extensions())
+            {
+                let data = self.read_extension(size)?;
+                header_blocks.extend_from_slice(&data);
+                let payload = data[..size as usize].to_vec();
+                let slot = if entry_type.is_gnu_longname() {
+                    &mut long_name
+                } else if entry_type.is_gnu_longlink() {
+                    &mut long_link
+                } else {
+                    &mut pax
+                };
+                if slot.replace(payload).is_some() {
+                    return Err(invalid("two extension headers of one kind for a member"));
+                }
+                continue;
+            }
+
+            if entry_type.is_gnu_sparse() && header.as_gnu().is_some_and(|gnu| gnu.is_extended()) {
+                loop {
+                    let ext = self
+                        .read_block()?
+                        .ok_or_else(|| invalid("truncated sparse extension header"))?;
+                    header_blocks.extend_from_slice(&ext);
+                    if ext[EXT_SPARSE_FLAG] == 0 {
+                        break;
+                    }
+                }
+            }
+            let header_start = header_blocks.len() - block.len();
+            let records = pax.as_deref();
+            // Readers honour the PAX size only when the header field is zero.
+            if size == 0 {
+                size = records.and_then(pax_size).unwrap_or(0);
+            }
+            let kind = classify_entry_type(header);
+            let pax_sparse = pax_sparse(records, kind);
+
+            let (name, link_target) = if entry_type.is_pax_global_extensions() {
+                (Utf8PathBuf::new(), None)
+            } else {
+                let name = match &pax_sparse {
+                    Some(layout) => layout.name.clone(),
+                    None => {
+                        let bytes = long_name
+                            .as_deref()
+                            .map(trim_trailing_nul)
+                            .or_else(|| records.and_then(|records| pax_value(records, b"path")))
+                            .map_or_else(|| header.path_bytes(), Cow::Borrowed);
+                        names::from_bytes(&bytes, true).map_err(|err| invalid(&err.to_string()))?
+                    }
+                };
+                let link_bytes = long_link
+                    .as_deref()
+                    .map(trim_trailing_nul)
+                    .or_else(|| records.and_then(|records| pax_value(records, b"linkpath")))
+                    .map(Cow::Borrowed)
+                    .or_else(|| header.link_name_bytes());
+                let link_target = link_bytes
+                    .filter(|_| matches!(entry_type, EntryType::Link | EntryType::Symlink))
+                    .map(|bytes| names::from_bytes(&bytes, true))
+                    .transpose()
+                    .map_err(|err| invalid(&err.to_string()))?;
+                (name, link_target)
+            };
+            let logical = match header.as_gnu().filter(|_| entry_type.is_gnu_sparse()) {
+                Some(gnu) => gnu.real_size()?,
+                None => pax_sparse.map_or(size, |layout| layout.real_size),
+            };
+            self.unread = padded(size)?;
+            return Ok(Some(RawMember {
+                name,
+                link_target,
+                entry_type,
+                kind,
+                mode: header.mode().ok(),
+                size: logical,
+                stored: size,
+                header_blocks,
+                header_start,
+                pax,
+                offset,
+            }));
+        }
+    }
+
+    /// The underlying reader, positioned at the data of the member last returned.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// Copy the padded data of the member last returned by [`Self::next_member`].
+    pub fn copy_data<W: Write>(&mut self, out: &mut W) -> io::Result<()> {
+        let expected = self.unread;
+        let copied = io::copy(&mut (&mut self.inner).take(expected), out)?;
+        self.unread = 0;
+        self.position += copied;
+        if copied != expected {
+            return Err(invalid("archive ends inside member data"));
+        }
+        Ok(())
+    }
+
+    fn skip_data(&mut self) -> io::Result<()> {
+        if self.unread > 0 {
+            self.copy_data(&mut io::sink())?;
+        }
+        Ok(())
+    }
+
+    fn read_block(&mut self) -> io::Result<Option<[u8; 512]>> {
+        let mut block = [0_u8; 512];
+        let mut filled = 0;
+        while filled < block.len() {
+            match self.inner.read(&mut block[filled..]) {
+                Ok(0) => break,
+                Ok(read) => filled += read,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+        self.position += filled as u64;
+        match filled {
+            0 => Ok(None),
+            512 => Ok(Some(block)),
+            _ => Err(invalid("archive ends inside a header block")),
+        }
+    }
+
+    fn read_extension(&mut self, size: u64) -> io::Result<Vec<u8>> {
+        if size > MAX_EXTENSION_SIZE {
+            return Err(invalid("extension header is too large"));
+        }
+        let mut data = vec![0_u8; padded(size)? as usize];
+        self.inner.read_exact(&mut data).map_err(|err| {
+            if err.kind() == io::ErrorKind::UnexpectedEof {
+                invalid("archive ends inside an extension header")
+            } else {
+                err
+            }
+        })?;
+        self.position += data.len() as u64;
+        Ok(data)
+    }
+}
+
+fn verify_checksum(header: &Header) -> io::Result<()> {
+    let bytes = header.as_bytes();
+    let sum = bytes[..148]
+        .iter()
+        .chain(&bytes[156..])
+        .fold(8 * u32::from(b' '), |sum, &byte| sum + u32::from(byte));
+    if header.cksum()? != sum {
+        return Err(invalid("archive header checksum mismatch"));
+    }
+    Ok(())
+}
+
+fn padded(size: u64) -> io::Result<u64> {
+    size.checked_next_multiple_of(BLOCK_SIZE)
+        .ok_or_else(|| invalid("member size overflows"))
+}
+
+/// The layout of a PAX 1.0 sparse file, whose records carry its real name and size.
+fn pax_sparse(records: Option<&[u8]>, kind: EntryKind) -> Option<PaxSparse> {
+    records
+        .filter(|_| kind == EntryKind::File)
+        .and_then(|records| PaxMetadata::from_records(records).ok())
+        .and_then(|meta| PaxSparse::from_pax(&meta).ok().flatten())
+}
+
+/// Store `value` in a fixed-size header field, leaving the field empty when the value
+/// does not fit; the PAX header holds the full value.
+fn set_field(field: &mut [u8], value: &[u8]) {
+    field.fill(0);
+    if let Some(slot) = field.get_mut(..value.len()) {
+        slot.copy_from_slice(value);
+    }
+}
+
+/// The first well-formed PAX record named `key`, skipping malformed ones as `tar` does.
+fn pax_value<'a>(records: &'a [u8], key: &[u8]) -> Option<&'a [u8]> {
+    PaxExtensions::new(records)
+        .filter_map(Result::ok)
+        .find(|record| record.key_bytes() == key)
+        .map(|record| record.value_bytes())
+}
+
+/// The PAX `size`, read the way `tar` reads it: from the first `size` record, giving up
+/// at the first malformed record.
+fn pax_size(records: &[u8]) -> Option<u64> {
+    for record in PaxExtensions::new(records) {
+        let record = record.ok()?;
+        if record.key_bytes() == b"size" {
+            return std::str::from_utf8(record.value_bytes()).ok()?.parse().ok();
+        }
+    }
+    None
+}
+
+/// Long names keep everything up to one trailing NUL, as in `tar`.
+fn trim_trailing_nul(bytes: &[u8]) -> &[u8] {
+    bytes.strip_suffix(&[0]).unwrap_or(bytes)
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_archive() -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        let long_name = format!("{}/file.txt", "d".repeat(120));
+        for (name, data) in [("short.txt", &b"one"[..]), (long_name.as_str(), b"two")] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            builder.append_data(&mut header, name, data).unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    #[test]
+    fn members_keep_their_extension_headers() {
+        let archive = sample_archive();
+        let mut reader = RawReader::new(archive.as_slice());
+        let first = reader.next_member().unwrap().unwrap();
+        assert_eq!(first.name, "short.txt");
+        assert_eq!(first.header_blocks.len(), 512);
+        assert_eq!((first.size, first.stored), (3, 3));
+
+        let second = reader.next_member().unwrap().unwrap();
+        assert!(second.name.as_str().ends_with("/file.txt"));
+        // Long-name header, its data block, and the member header.
+        assert_eq!(second.header_blocks.len(), 3 * 512);
+        assert_eq!(second.offset, 2 * 512);
+        let mut data = Vec::new();
+        reader.copy_data(&mut data).unwrap();
+        assert_eq!(&data[..3], b"two");
+        assert!(reader.next_member().unwrap().is_none());
+    }
+
+    fn record(key: &str, value: &str) -> Vec<u8> {
+        let rest = key.len() + value.len() + 3;
+        let mut len = rest + 1;
+        while len.to_string().len() + rest > len {
+            len += 1;
+        }
+        format!("{len} {key}={value}\n").into_bytes()
+    }
+
+    /// One member named `header.txt` in its header, after the given extension headers.
+    fn with_extensions(extensions: &[(EntryType, &[u8])]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for &(entry_type, data) in extensions {
+            let mut header = tar::Header::new_gnu();
+            header.set_entry_type(entry_type);
+            header.set_path("././@LongLink").unwrap();
+            header.set_size(data.len() as u64);
+            header.set_cksum();
+            builder.append(&header, data).unwrap();
+        }
+        let mut header = tar::Header::new_gnu();
+        header.set_size(4);
+        header.set_mode(0o644);
+        builder
+            .append_data(&mut header, "header.txt", &b"data"[..])
+            .unwrap();
+        builder.into_inner().unwrap()
+    }
+
+    /// The name the `tar` crate gives the only member of `archive`.
+    fn tar_name(archive: &[u8]) -> String {
+        let mut archive = tar::Archive::new(archive);
+        let entry = archive.entries().unwrap().next().unwrap().unwrap();
+        String::from_utf8(entry.path_bytes().into_owned()).unwrap()
+    }
+
+    #[test]
+    fn long_names_beat_pax_paths() {
+        let archive = with_extensions(&[
+            (EntryType::GNULongName, b"../../escape.txt\0"),
+            (EntryType::XHeader, &record("path", "ok.txt")),
+        ]);
+        let member = RawReader::new(archive.as_slice())
+            .next_member()
+            .unwrap()
+            .unwrap();
+        assert_eq!(member.name, "../../escape.txt");
+        assert_eq!(member.name, tar_name(&archive));
+    }
+
+    #[test]
+    fn first_pax_path_wins_and_malformed_records_are_skipped() {
+        let records = [
+            &b"99 path=bad.txt\n"[..],
+            &record("path", "first.txt"),
+            &record("path", "second.txt"),
+        ]
+        .concat();
+        let archive = with_extensions(&[(EntryType::XHeader, &records)]);
+        let member = RawReader::new(archive.as_slice())
+            .next_member()
+            .unwrap()
+            .unwrap();
+        assert_eq!(member.name, "first.txt");
+        assert_eq!(member.name, tar_name(&archive));
+    }
+
+    #[test]
+    fn renamed_headers_state_one_name() {
+        let long = format!("{}/file.txt", "d".repeat(120));
+        let records = [record("path", "decoy.txt"), record("mtime", "5")].concat();
+        let archive = with_extensions(&[
+            (EntryType::GNULongName, format!("{long}\0").as_bytes()),
+            (EntryType::XHeader, &records),
+        ]);
+        let mut reader = RawReader::new(archive.as_slice());
+        let member = reader.next_member().unwrap().unwrap();
+        let mut copy = member.headers_named(&member.name).unwrap();
+        reader.copy_data(&mut copy).unwrap();
+
+        let copied = RawReader::new(copy.as_slice())
+            .next_member()
+            .unwrap()
+            .unwrap();
+        assert_eq!(copied.name, long);
+        assert_eq!(tar_name(&copy), long);
+        let records = copied.pax.unwrap();
+        assert_eq!(pax_value(&records, b"mtime"), Some(&b"5"[..]));
+        assert!(pax_value(&records, b"path").is_some_and(|path| path == long.as_bytes()));
+        assert!(!records.windows(5).any(|window| window == b"decoy"));
+    }
+
+    #[test]
+    fn corrupt_headers_are_rejected() {
+        let mut archive = sample_archive();
+        archive[0] ^= 0xFF;
+        assert!(RawReader::new(archive.as_slice()).next_member().is_err());
+    }
+}
@@ -0,0 +1,338 @@
+//! Rewriting member names with `--strip-components` and sed-style `--transform`.
+//!
+//! Rewriting only produces candidate names; callers still run every result through
+//! [`SecurityPolicy::normalize_and_validate`](crate::policy::SecurityPolicy::normalize_and_validate),
+//! so an expression that introduces `..` or an absolute path is rejected like any
+//! hostile member name.
+
+use anyhow::Result;
+use camino::{Utf8Path, Utf8PathBuf};
+use regex::{Regex, RegexBuilder};
+
+use super::NameRewrite;
+use crate::error::UserInputError;
+
+/// Compiled form of a [`NameRewrite`].
+pub(crate) struct Renamer {
+    strip: usize,
+    rules: Vec<Rule>,
+}
+
+/// What a name being rewritten belongs to (GNU tar's `r`, `s`, and `h` flags).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Scope {
+    Name,
+    Symlink,
+    Hardlink,
+}
+
+/// One `s/regex/replacement/flags` expression.
+#[derive(Debug)]
+struct Rule {
+    regex: Regex,
+    /// Replacement in `regex` syntax (`${1}`, `$$`).
+    replacement: String,
+    global: bool,
+    /// 1-based index of the first match to replace.
+    occurrence: usize,
+    names: bool,
+    symlinks: bool,
+    hardlinks: bool,
+}
+
+impl Renamer {
+    pub fn new(rewrite: &NameRewrite) -> Result<Self> {
+        let mut rules = Vec::new();
+        for transform in &rewrite.transforms {
+            parse_expressions(transform, &mut rules).map_err(|message| {
+                UserInputError::new(format!("invalid transform {transform:?}: {message}"))
+            })?;
+        }
+        Ok(Self {
+            strip: rewrite.strip_components,
+            rules,
+        })
+    }
+
+    /// The rewritten member name, or `None` when stripping leaves nothing of it.
+    pub fn name(&self, name: &Utf8Path) -> Option<Utf8PathBuf> {
+        let stripped = self.strip_components(name)?;
+        non_empty(self.apply(stripped, Scope::Name))
+    }
+
+    /// The rewritten hard link target. Targets name other members, so they are
+    /// stripped and transformed exactly like member names.
+    pub fn hardlink_target(&self, target: &Utf8Path) -> Option<Utf8PathBuf> {
+        let stripped = self.strip_components(target)?;
+        non_empty(self.apply(stripped, Scope::Hardlink))
+    }
+
+    /// The rewritten symlink target. Symlink targets are resolved relative to the
+    /// link, so they are transformed but never stripped.
+    pub fn symlink_target(&self, target: &Utf8Path) -> Utf8PathBuf {
+        Utf8PathBuf::from(self.apply(target.as_str(), Scope::Symlink))
+    }
+
+    fn strip_components<'a>(&self, name: &'a Utf8Path) -> Option<&'a str> {
+        if self.strip == 0 {
+            return Some(name.as_str());
+        }
+        // Like GNU tar, repeated slashes separate one component and `.` counts.
+        let mut rest = name.as_str();
+        for _ in 0..self.strip {
+            rest = rest.trim_start_matches('/');
+            let (_, tail) = rest.split_once('/')?;
+            rest = tail;
+        }
+        let rest = rest.trim_start_matches('/');
+        (!rest.is_empty()).then_some(rest)
+    }
+
+    fn apply(&self, name: &str, scope: Scope) -> String {
+        let mut name = name.to_owned();
+        for rule in &self.rules {
+            let applies = match scope {
+                Scope::Name => rule.names,
+                Scope::Symlink => rule.symlinks,
+                Scope::Hardlink => rule.hardlinks,
+            };
+            if applies {
+                name = rule.replace(&name);
+            }
+        }
+        name
+    }
+}
+
+impl Rule {
+    fn replace(&self, name: &str) -> String {
+        let mut out = String::with_capacity(name.len());
+        let mut last = 0;
+        for (index, captures) in self.regex.captures_iter(name).enumerate() {
+            if index + 1 < self.occurrence {
+                continue;
+            }
+            let whole = captures.get(0).expect("group 0 always matches");
+            out.push_str(&name[last..whole.start()]);
+            captures.expand(&self.replacement, &mut out);
+            last = whole.end();
+            if !self.global {
+                break;
+            }
+        }
+        out.push_str(&name[last..]);
+        out
+    }
+}
+
+fn non_empty(name: String) -> Option<Utf8PathBuf> {
+    (!name.is_empty()).then(|| Utf8PathBuf::from(name))
+}
+
+/// Parse one `--transform` argument: `;`-separated `s` expressions.
+fn parse_expressions(text: &str, rules: &mut Vec<Rule>) -> Result<(), String> {
+    let mut chars = text.chars().peekable();
+    loop {
+        match chars.next() {
+            Some('s') => {}
+            Some(other) => return Err(format!("expected `s`, found `{other}`")),
+            None => return Err("empty expression".to_owned()),
+        }
+        let delimiter = chars.next().ok_or_else(|| "missing delimiter".to_owned())?;
+        let pattern = take_field(&mut chars, delimiter)?;
+        let replacement = take_field(&mut chars, delimiter)?;
+
+        let replacement = convert_replacement(&replacement, delimiter)?;
+
+        let (mut global, mut ignore_case, mut extended) = (false, false, false);
+        let (mut names, mut symlinks, mut hardlinks) = (true, true, true);
+        let mut number = String::new();
+        while let Some(flag) = chars.next_if(|&flag| flag != ';') {
+            match flag {
+                'g' => global = true,
+                'i' => ignore_case = true,
+                'x' => extended = true,
+                'r' | 'R' => names = flag == 'r',
+                's' | 'S' => symlinks = flag == 's',
+                'h' | 'H' => hardlinks = flag == 'h',
+                digit if digit.is_ascii_digit() => number.push(digit),
+                other => return Err(format!("unknown flag `{other}`")),
+            }
+        }
+        let occurrence = if number.is_empty() {
+            1
+        } else {
+            number
+                .parse()
+                .ok()
+                .filter(|&occurrence| occurrence > 0)
+                .ok_or_else(|| format!("invalid occurrence {number}"))?
+        };
+        let pattern = if extended {
+            pattern
+        } else {
+            basic_to_extended(&pattern)
+        };
+        let regex = RegexBuilder::new(&pattern)
+            .case_insensitive(ignore_case)
+            .build()
+            .map_err(|err| err.to_string())?;
+        rules.push(Rule {
+            regex,
+            replacement,
+            global,
+            occurrence,
+            names,
+            symlinks,
+            hardlinks,
+        });
+
+        // Flags stop at `;` or at the end; a trailing `;` ends the list too.
+        if chars.next().is_none() || chars.peek().is_none() {
+            return Ok(());
+        }
+    }
+}
+
+/// Read up to the next unescaped `delimiter`, turning `\<delimiter>` into the
+/// delimiter itself and keeping other escapes.
+fn take_field(
+    chars: &mut std::iter::Peekable<std::str::Chars<'_>>,
+    delimiter: char,
+) -> Result<String, String> {
+    let mut field = String::new();
+    while let Some(c) = chars.next() {
+        if c == delimiter {
+            return Ok(field);
+        }
+        if c == '\\' {
+            match chars.next() {
+                Some(next) if next == delimiter => field.push(next),
+                Some(next) => {
+                    field.push('\\');
+                    field.push(next);
+                }
+                None => return Err("trailing backslash".to_owned()),
+            }
+        } else {
+            field.push(c);
+        }
+    }
+    Err(format!("unterminated expression (missing `{delimiter}`)"))
+}
+
+/// Translate a POSIX basic regular expression (sed's default) to `regex` syntax:
+/// `\(`, `\)`, `\{`, `\}`, `\|`, `\+`, and `\?` are operators, and their bare forms
+/// are literals.
+fn basic_to_extended(pattern: &str) -> String {
+    const SWAPPED: &[char] = &['(', ')', '{', '}', '|', '+', '?'];
+    let mut out = String::with_capacity(pattern.len());
+    let mut chars = pattern.chars().peekable();
+    let mut in_bracket = false;
+    while let Some(c) = chars.next() {
+        if in_bracket {
+            out.push(c);
+            if c == ']' {
+                in_bracket = false;
+            }
+            continue;
+        }
+        match c {
+            '\\' => match chars.next() {
+                Some(next) if SWAPPED.contains(&next) => out.push(next),
+                Some(next) => {
+                    out.push('\\');
+                    out.push(next);
+                }
+                None => out.push_str("\\\\"),
+            },
+            '[' => {
+                in_bracket = true;
+                out.push('[');
+                // A leading `]` (after an optional `^`) is a literal member.
+                if let Some(next) = chars.next_if(|&next| next == '^') {
+                    out.push(next);
+                }
+                if chars.next_if(|&next| next == ']').is_some() {
+                    out.push_str("\\]");
+                }
+            }
+            c if SWAPPED.contains(&c) => {
+                out.push('\\');
+                out.push(c);
+            }
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Translate a sed replacement (`&`, `\1`…`\9`) to `regex` expansion syntax.
+fn convert_replacement(replacement: &str, delimiter: char) -> Result<String, String> {
+    let mut out = String::with_capacity(replacement.len());
+    let mut chars = replacement.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '&' => out.push_str("${0}"),
+            '$' => out.push_str("$$"),
+            '\\' => match chars.next() {
+                Some(digit @ '0'..='9') => {
+                    out.push_str("${");
+                    out.push(digit);
+                    out.push('}');
+                }
+                Some('n') => out.push('\n'),
+                Some(other) if other == delimiter || other == '&' || other == '\\' => {
+                    out.push(other);
+                }
+                Some(other) => return Err(format!("unsupported escape `\\{other}`")),
+                None => return Err("trailing backslash".to_owned()),
+            },
+            c => out.push(c),
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn renamer(strip: usize, transforms: &[&str]) -> Renamer {
+        Renamer::new(&NameRewrite {
+            strip_components: strip,
+            transforms: transforms.iter().map(|t| t.to_string()).collect(),
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn strip_components_counts_like_gnu_tar() {
+        let renamer = renamer(1, &[]);
+        assert_eq!(
+            renamer.name(Utf8Path::new("pkg-1.0//src/lib.rs")).unwrap(),
+            "src/lib.rs"
+        );
+        assert_eq!(renamer.name(Utf8Path::new("./pkg/a")).unwrap(), "pkg/a");
+        assert!(renamer.name(Utf8Path::new("pkg-1.0/")).is_none());
+        assert!(renamer.hardlink_target(Utf8Path::new("top")).is_none());
+        assert_eq!(renamer.symlink_target(Utf8Path::new("../a")), "../a");
+    }
+
+    #[test]
+    fn transforms_use_sed_syntax() {
+        let renamer = renamer(0, &[r"s,^\(pkg\)-[0-9.]*/,\1/,", "s/a/A/2g;s|x+|(&)|xS"]);
+        assert_eq!(
+            renamer.name(Utf8Path::new("pkg-1.2.3/banana")).unwrap(),
+            "pkg/banAnA"
+        );
+        assert_eq!(renamer.name(Utf8Path::new("xx")).unwrap(), "(xx)");
+        // `S` keeps symlink targets out of the second expression.
+        assert_eq!(renamer.symlink_target(Utf8Path::new("xx")), "xx");
+        assert!(Renamer::new(&NameRewrite {
+            strip_components: 0,
+            transforms: vec!["s/unterminated".to_owned()],
+        })
+        .is_err());
+    }
+}
@@ -0,0 +1,650 @@
+//! Extraction anchored at directory descriptors.
+//!
+//! A member's path is never resolved again as a string once it has been validated:
+//! [`Root::locate`] walks it one component at a time from a descriptor of the
+//! destination, opening every directory with `O_NOFOLLOW`, and whatever is then done to
+//! the member is relative to the descriptor of its parent. Another process that swaps a
+//! directory for a symlink while extraction runs cannot redirect a write outside the
+//! destination; the walk fails instead.
+
+use std::fmt;
+use std::time::SystemTime;
+
+use anyhow::{anyhow, Result};
+use camino::{Utf8Component, Utf8Path, Utf8PathBuf};
+
+use crate::manifest::ManifestKind;
+use crate::names;
+
+/// What [`Location::stat`] found at a location.
+#[derive(Debug, Clone)]
+pub(crate) struct Existing {
+    /// File type; hard links show up as [`ManifestKind::File`].
+    pub kind: ManifestKind,
+    pub len: u64,
+    pub modified: Option<SystemTime>,
+}
+
+/// Where a member goes: a directory held open and a name inside it.
+pub(crate) struct Location {
+    /// Path relative to the destination, for messages and backups; empty for the
+    /// destination itself.
+    rel: Utf8PathBuf,
+    #[cfg(unix)]
+    dir: std::os::fd::OwnedFd,
+    #[cfg(unix)]
+    name: std::ffi::OsString,
+    #[cfg(not(unix))]
+    path: std::path::PathBuf,
+}
+
+impl Location {
+    #[must_use]
+    pub fn rel(&self) -> &Utf8Path {
+        &self.rel
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.rel.as_str().is_empty() {
+            f.write_str(".")
+        } else {
+            f.write_str(&names::escaped(&self.rel))
+        }
+    }
+}
+
+/// The destination of an extraction, held open.
+pub(crate) struct Root {
+    #[cfg(unix)]
+    dir: std::os::fd::OwnedFd,
+    #[cfg(not(unix))]
+    path: std::path::PathBuf,
+}
+
+/// Parent components of `rel` and its last component, `.` for an empty path.
+fn split(rel: &Utf8Path) -> Result<(Vec<&str>, &str)> {
+    let mut components = rel
+        .components()
+        .map(|component| match component {
+            Utf8Component::Normal(name) => Ok(name),
+            other => Err(anyhow!("unexpected component {other} in {rel}")),
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let name = components.pop().unwrap_or(".");
+    Ok((components, name))
+}
+
+#[cfg(unix)]
+mod sys {
+    use std::ffi::{OsStr, OsString};
+    use std::fs::File;
+    use std::io;
+    use std::os::fd::OwnedFd;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    use anyhow::{anyhow, Context, Result};
+    use camino::Utf8Path;
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    use rustix::fs::chmodat;
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    use rustix::fs::{chmod, fchmod, fstat};
+    use rustix::fs::{
+        chownat, linkat, mkdirat, mknodat, openat, readlinkat, renameat, statat, symlinkat,
+        unlinkat, utimensat, AtFlags, Dir, FileType, Gid, Mode, OFlags, RawMode, Timespec,
+        Timestamps, Uid, CWD,
+    };
+    use rustix::io::Errno;
+
+    use super::{split, Existing, Location, Root};
+    use crate::archive::pax::PaxTime;
+    use crate::manifest::ManifestKind;
+    use crate::names;
+    use crate::policy::PolicyError;
+
+    const DIRECTORY: OFlags = OFlags::RDONLY
+        .union(OFlags::DIRECTORY)
+        .union(OFlags::NOFOLLOW)
+        .union(OFlags::CLOEXEC);
+
+    fn os_name(component: &str) -> OsString {
+        names::to_path(Utf8Path::new(component))
+            .into_owned()
+            .into_os_string()
+    }
+
+    fn mode(mode: u32) -> Mode {
+        Mode::from_raw_mode(mode as RawMode)
+    }
+
+    fn kind_of(raw_mode: RawMode) -> ManifestKind {
+        match FileType::from_raw_mode(raw_mode) {
+            FileType::RegularFile => ManifestKind::File,
+            FileType::Directory => ManifestKind::Directory,
+            FileType::Symlink => ManifestKind::Symlink,
+            FileType::CharacterDevice => ManifestKind::CharDevice,
+            FileType::BlockDevice => ManifestKind::BlockDevice,
+            FileType::Fifo => ManifestKind::Fifo,
+            _ => ManifestKind::Other,
+        }
+    }
+
+    /// Fail when something other than the expected kind of node was found, for
+    /// example because it was swapped for a symlink.
+    fn check_kind(found: ManifestKind, expected: ManifestKind) -> io::Result<()> {
+        if found == expected {
+            Ok(())
+        } else {
+            Err(io::Error::other(format!(
+                "expected {expected:?}, found {found:?}"
+            )))
+        }
+    }
+
+    /// Open the directory `name` in `dir` without following a symlink there, creating
+    /// it first when `create` is set and it does not exist.
+    fn enter(dir: &OwnedFd, name: &OsStr, create: bool) -> rustix::io::Result<OwnedFd> {
+        match openat(dir, name, DIRECTORY, Mode::empty()) {
+            Err(Errno::NOENT) if create => match mkdirat(dir, name, mode(0o777)) {
+                Ok(()) | Err(Errno::EXIST) => openat(dir, name, DIRECTORY, Mode::empty()),
+                Err(err) => Err(err),
+            },
+            opened => opened,
+        }
+    }
+
+    impl Root {
+        /// Open the destination directory, which must already exist.
+        pub fn open(path: &Utf8Path) -> Result<Self> {
+            let dir = openat(
+                CWD,
+                names::to_path(path).as_ref(),
+                OFlags::RDONLY | OFlags::DIRECTORY | OFlags::CLOEXEC,
+                Mode::empty(),
+            )
+            .with_context(|| format!("failed to open destination {path}"))?;
+            Ok(Self { dir })
+        }
+
+        /// Find `rel` below the destination, creating missing parent directories when
+        /// `create` is set. A parent that is a symlink is a [`PolicyError::SymlinkInPath`],
+        /// one that is not a directory an error.
+        pub fn locate(&self, rel: &Utf8Path, create: bool) -> Result<Location> {
+            let (parents, name) = split(rel)?;
+            let mut dir = openat(&self.dir, ".", DIRECTORY, Mode::empty())
+                .context("failed to reopen the destination")?;
+            for (depth, component) in parents.iter().enumerate() {
+                let component = os_name(component);
+                let shown = || names::escaped(Utf8Path::new(&parents[..=depth].join("/")));
+                dir = match enter(&dir, &component, create) {
+                    Ok(opened) => opened,
+                    Err(Errno::LOOP | Errno::NOTDIR) => {
+                        if let Ok(stat) = statat(&dir, &component, AtFlags::SYMLINK_NOFOLLOW) {
+                            if FileType::from_raw_mode(stat.st_mode).is_symlink() {
+                                return Err(PolicyError::SymlinkInPath {
+                                    path: rel.to_owned(),
+                                    link: parents[..=depth].iter().collect(),
+                                }
+                                .into());
+                            }
+                        }
+                        return Err(anyhow!(
+                            "refusing to extract {}: {} is not a directory",
+                            names::escaped(rel),
+                            shown()
+                        ));
+                    }
+                    Err(err) => {
+                        return Err(io::Error::from(err))
+                            .with_context(|| format!("failed to open parent {}", shown()))
+                    }
+                };
+            }
+            Ok(Location {
+                rel: rel.to_owned(),
+                dir,
+                name: os_name(name),
+            })
+        }
+    }
+
+    impl Location {
+        /// Location of `path` outside any destination, reached by following its
+        /// parents; only for hard links the policy lets leave the destination.
+        pub fn outside(path: &Utf8Path) -> Result<Self> {
+            let name = path
+                .file_name()
+                .ok_or_else(|| anyhow!("{path} has no file name"))?;
+            let parent = path.parent().unwrap_or(Utf8Path::new("."));
+            let dir = openat(
+                CWD,
+                names::to_path(parent).as_ref(),
+                OFlags::RDONLY | OFlags::DIRECTORY | OFlags::CLOEXEC,
+                Mode::empty(),
+            )
+            .with_context(|| format!("failed to open {parent}"))?;
+            Ok(Self {
+                rel: path.to_owned(),
+                dir,
+                name: os_name(name),
+            })
+        }
+
+        /// What is at the location, without following a symlink there.
+        pub fn stat(&self) -> io::Result<Option<Existing>> {
+            let stat = match statat(&self.dir, &self.name, AtFlags::SYMLINK_NOFOLLOW) {
+                Ok(stat) => stat,
+                Err(Errno::NOENT) => return Ok(None),
+                Err(err) => return Err(err.into()),
+            };
+            let kind = kind_of(stat.st_mode);
+            let modified = u64::try_from(stat.st_mtime)
+                .ok()
+                .map(|secs| UNIX_EPOCH + Duration::new(secs, stat.st_mtime_nsec as u32));
+            Ok(Some(Existing {
+                kind,
+                len: u64::try_from(stat.st_size).unwrap_or(0),
+                modified,
+            }))
+        }
+
+        /// Create a directory, or accept one that is already there.
+        pub fn create_dir(&self) -> io::Result<()> {
+            match mkdirat(&self.dir, &self.name, mode(0o777)) {
+                Ok(()) => Ok(()),
+                Err(Errno::EXIST)
+                    if self
+                        .stat()?
+                        .is_some_and(|existing| existing.kind == ManifestKind::Directory) =>
+                {
+                    Ok(())
+                }
+                Err(err) => Err(err.into()),
+            }
+        }
+
+        /// Create a new regular file, failing if anything is there.
+        pub fn create_file(&self) -> io::Result<File> {
+            let flags =
+                OFlags::WRONLY | OFlags::CREATE | OFlags::EXCL | OFlags::NOFOLLOW | OFlags::CLOEXEC;
+            Ok(openat(&self.dir, &self.name, flags, mode(0o600))?.into())
+        }
+
+        /// Open the regular file already there for rewriting.
+        pub fn open_file(&self) -> io::Result<File> {
+            let flags = OFlags::WRONLY | OFlags::TRUNC | OFlags::NOFOLLOW | OFlags::CLOEXEC;
+            Ok(openat(&self.dir, &self.name, flags, Mode::empty())?.into())
+        }
+
+        pub fn symlink(&self, target: &Utf8Path) -> io::Result<()> {
+            Ok(symlinkat(
+                names::to_path(target).as_ref(),
+                &self.dir,
+                &self.name,
+            )?)
+        }
+
+        /// Create a hard link here to `source`, not following a symlink there.
+        pub fn hard_link(&self, source: &Location) -> io::Result<()> {
+            Ok(linkat(
+                &source.dir,
+                &source.name,
+                &self.dir,
+                &self.name,
+                AtFlags::empty(),
+            )?)
+        }
+
+        pub fn mknod(&self, file_type: FileType, permissions: u32, device: u64) -> io::Result<()> {
+            Ok(mknodat(
+                &self.dir,
+                &self.name,
+                file_type,
+                mode(permissions),
+                device,
+            )?)
+        }
+
+        pub fn remove_file(&self) -> io::Result<()> {
+            Ok(unlinkat(&self.dir, &self.name, AtFlags::empty())?)
+        }
+
+        /// Remove the empty directory here.
+        pub fn remove_dir(&self) -> io::Result<()> {
+            Ok(unlinkat(&self.dir, &self.name, AtFlags::REMOVEDIR)?)
+        }
+
+        /// Rename what is here to `name`, in the same directory.
+        pub fn rename(&self, name: &str) -> io::Result<()> {
+            Ok(renameat(&self.dir, &self.name, &self.dir, os_name(name))?)
+        }
+
+        /// Move what is here to `target`, replacing a file there.
+        pub fn move_to(&self, target: &Location) -> io::Result<()> {
+            Ok(renameat(&self.dir, &self.name, &target.dir, &target.name)?)
+        }
+
+        /// Names of the other entries in the same directory.
+        pub fn sibling_names(&self) -> io::Result<Vec<Vec<u8>>> {
+            let mut siblings = Vec::new();
+            for entry in Dir::read_from(&self.dir)? {
+                let name = entry?.file_name().to_bytes().to_vec();
+                if name != b"." && name != b".." {
+                    siblings.push(name);
+                }
+            }
+            Ok(siblings)
+        }
+
+        pub fn read_link(&self) -> io::Result<Vec<u8>> {
+            Ok(readlinkat(&self.dir, &self.name, Vec::new())?.into_bytes())
+        }
+
+        /// Change the owner, not following a symlink here.
+        pub fn set_owner(&self, uid: u32, gid: u32) -> io::Result<()> {
+            Ok(chownat(
+                &self.dir,
+                &self.name,
+                Some(Uid::from_raw(uid)),
+                Some(Gid::from_raw(gid)),
+                AtFlags::SYMLINK_NOFOLLOW,
+            )?)
+        }
+
+        /// Set the permission bits of what is here, which must be of kind `expected`.
+        ///
+        /// The node is opened with `O_PATH | O_NOFOLLOW`, which neither follows a
+        /// symlink nor has the side effects of opening a device or FIFO, and its type
+        /// is checked on that descriptor before it is changed. `fchmod` does not accept
+        /// such descriptors, so the change goes through `/proc/self/fd`; without
+        /// `/proc` it fails rather than fall back to a lookup by name.
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        pub fn set_mode(&self, permissions: u32, expected: ManifestKind) -> io::Result<()> {
+            use std::os::fd::AsRawFd;
+
+            let flags = OFlags::PATH | OFlags::NOFOLLOW | OFlags::CLOEXEC;
+            let fd = openat(&self.dir, &self.name, flags, Mode::empty())?;
+            check_kind(kind_of(fstat(&fd)?.st_mode), expected)?;
+            match fchmod(&fd, mode(permissions)) {
+                Err(Errno::BADF) => Ok(chmod(
+                    format!("/proc/self/fd/{}", fd.as_raw_fd()),
+                    mode(permissions),
+                )?),
+                changed => Ok(changed?),
+            }
+        }
+
+        /// Set the permission bits of what is here, which must be of kind `expected`.
+        ///
+        /// Without `O_PATH`, the type is checked first and the change never follows a
+        /// symlink; where that is not supported, it fails.
+        #[cfg(not(any(target_os = "linux", target_os = "android")))]
+        pub fn set_mode(&self, permissions: u32, expected: ManifestKind) -> io::Result<()> {
+            let stat = statat(&self.dir, &self.name, AtFlags::SYMLINK_NOFOLLOW)?;
+            check_kind(kind_of(stat.st_mode), expected)?;
+            Ok(chmodat(
+                &self.dir,
+                &self.name,
+                mode(permissions),
+                AtFlags::SYMLINK_NOFOLLOW,
+            )?)
+        }
+
+        /// Set the access and modification times, not following a symlink here.
+        pub fn set_times(&self, accessed: SystemTime, modified: SystemTime) -> io::Result<()> {
+            let timespec = |time| {
+                let PaxTime { secs, nanos } = PaxTime::from_system_time(time);
+                Timespec {
+                    tv_sec: secs as _,
+                    tv_nsec: nanos as _,
+                }
+            };
+            let timestamps = Timestamps {
+                last_access: timespec(accessed),
+                last_modification: timespec(modified),
+            };
+            Ok(utimensat(
+                &self.dir,
+                &self.name,
+                &timestamps,
+                AtFlags::SYMLINK_NOFOLLOW,
+            )?)
+        }
+
+        /// A path that reaches this location through the held directory, for calls
+        /// that have no descriptor-relative form.
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        pub fn proc_path(&self) -> std::path::PathBuf {
+            use std::os::fd::AsRawFd;
+
+            std::path::Path::new(&format!("/proc/self/fd/{}", self.dir.as_raw_fd()))
+                .join(&self.name)
+        }
+    }
+}
+
+#[cfg(not(unix))]
+mod sys {
+    use std::fs::{self, File};
+    use std::io;
+    use std::path::PathBuf;
+    use std::time::SystemTime;
+
+    use anyhow::{anyhow, Context, Result};
+    use camino::Utf8Path;
+
+    use super::{split, Existing, Location, Root};
+    use crate::manifest::ManifestKind;
+    use crate::names;
+    use crate::policy::PolicyError;
+
+    impl Root {
+        pub fn open(path: &Utf8Path) -> Result<Self> {
+            let path = names::to_path(path).into_owned();
+            if !fs::metadata(&path)
+                .with_context(|| format!("failed to open destination {}", path.display()))?
+                .is_dir()
+            {
+                return Err(anyhow!("destination {} is not a directory", path.display()));
+            }
+            Ok(Self { path })
+        }
+
+        /// Without descriptor-relative calls, each parent is checked before use.
+        pub fn locate(&self, rel: &Utf8Path, create: bool) -> Result<Location> {
+            let (parents, name) = split(rel)?;
+            let mut path = self.path.clone();
+            for (depth, component) in parents.iter().enumerate() {
+                path.push(names::to_path(Utf8Path::new(component)));
+                match fs::symlink_metadata(&path) {
+                    Ok(metadata) if metadata.is_dir() => {}
+                    Ok(metadata) if metadata.is_symlink() => {
+                        return Err(PolicyError::SymlinkInPath {
+                            path: rel.to_owned(),
+                            link: parents[..=depth].iter().collect(),
+                        }
+                        .into())
+                    }
+                    Ok(_) => {
+                        return Err(anyhow!(
+                            "refusing to extract {}: {} is not a directory",
+                            names::escaped(rel),
+                            path.display()
+                        ))
+                    }
+                    Err(err) if err.kind() == io::ErrorKind::NotFound && create => {
+                        fs::create_dir(&path).with_context(|| {
+                            format!("failed to create parent {}", path.display())
+                        })?;
+                    }
+                    Err(err) => {
+                        return Err(err)
+                            .with_context(|| format!("failed to open parent {}", path.display()))
+                    }
+                }
+            }
+            path.push(names::to_path(Utf8Path::new(name)));
+            Ok(Location {
+                rel: rel.to_owned(),
+                path,
+            })
+        }
+    }
+
+    fn unsupported(what: &str) -> io::Error {
+        io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("{what} is not supported on this platform"),
+        )
+    }
+
+    impl Location {
+        pub fn outside(path: &Utf8Path) -> Result<Self> {
+            Ok(Self {
+                rel: path.to_owned(),
+                path: names::to_path(path).into_owned(),
+            })
+        }
+
+        pub fn stat(&self) -> io::Result<Option<Existing>> {
+            let metadata = match fs::symlink_metadata(&self.path) {
+                Ok(metadata) => metadata,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+                Err(err) => return Err(err),
+            };
+            let kind = if metadata.is_file() {
+                ManifestKind::File
+            } else if metadata.is_dir() {
+                ManifestKind::Directory
+            } else if metadata.is_symlink() {
+                ManifestKind::Symlink
+            } else {
+                ManifestKind::Other
+            };
+            Ok(Some(Existing {
+                kind,
+                len: metadata.len(),
+                modified: metadata.modified().ok(),
+            }))
+        }
+
+        pub fn create_dir(&self) -> io::Result<()> {
+            match fs::create_dir(&self.path) {
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists && self.path.is_dir() => {
+                    Ok(())
+                }
+                result => result,
+            }
+        }
+
+        pub fn create_file(&self) -> io::Result<File> {
+            File::options()
+                .write(true)
+                .create_new(true)
+                .open(&self.path)
+        }
+
+        pub fn open_file(&self) -> io::Result<File> {
+            File::options().write(true).truncate(true).open(&self.path)
+        }
+
+        pub fn symlink(&self, _target: &Utf8Path) -> io::Result<()> {
+            Err(unsupported("creating symlinks"))
+        }
+
+        pub fn hard_link(&self, source: &Location) -> io::Result<()> {
+            fs::hard_link(&source.path, &self.path)
+        }
+
+        pub fn remove_file(&self) -> io::Result<()> {
+            fs::remove_file(&self.path)
+        }
+
+        pub fn remove_dir(&self) -> io::Result<()> {
+            fs::remove_dir(&self.path)
+        }
+
+        pub fn rename(&self, name: &str) -> io::Result<()> {
+            fs::rename(
+                &self.path,
+                self.path
+                    .with_file_name(names::to_path(Utf8Path::new(name))),
+            )
+        }
+
+        pub fn move_to(&self, target: &Location) -> io::Result<()> {
+            fs::rename(&self.path, &target.path)
+        }
+
+        pub fn sibling_names(&self) -> io::Result<Vec<Vec<u8>>> {
+            let parent = self.path.parent().map_or_else(PathBuf::new, PathBuf::from);
+            fs::read_dir(parent)?
+                .map(|entry| Ok(entry?.file_name().to_string_lossy().as_bytes().to_vec()))
+                .collect()
+        }
+
+        pub fn read_link(&self) -> io::Result<Vec<u8>> {
+            let target = fs::read_link(&self.path)?;
+            Ok(target.to_string_lossy().as_bytes().to_vec())
+        }
+
+        pub fn set_owner(&self, _uid: u32, _gid: u32) -> io::Result<()> {
+            Ok(())
+        }
+
+        pub fn set_mode(&self, _permissions: u32, _expected: ManifestKind) -> io::Result<()> {
+            Ok(())
+        }
+
+        pub fn set_times(&self, accessed: SystemTime, modified: SystemTime) -> io::Result<()> {
+            File::options().write(true).open(&self.path)?.set_times(
+                fs::FileTimes::new()
+                    .set_accessed(accessed)
+                    .set_modified(modified),
+            )
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+
+    use rustix::fs::FileType;
+
+    use super::*;
+
+    #[test]
+    fn set_mode_refuses_a_node_swapped_for_a_symlink() {
+        let tmp = tempfile::tempdir().expect("tempdir");
+        let dir = Utf8PathBuf::from_path_buf(tmp.path().to_path_buf()).expect("utf8");
+        let victim = dir.join("victim");
+        fs::write(&victim, b"secret").expect("write victim");
+        fs::set_permissions(&victim, fs::Permissions::from_mode(0o600)).expect("chmod victim");
+        let mode_of = |name: &str| {
+            fs::symlink_metadata(dir.join(name))
+                .expect("metadata")
+                .permissions()
+                .mode()
+                & 0o7777
+        };
+
+        let root = Root::open(&dir).expect("root");
+        let location = root.locate(Utf8Path::new("pipe"), true).expect("locate");
+        location.mknod(FileType::Fifo, 0o600, 0).expect("mkfifo");
+        location
+            .set_mode(0o640, ManifestKind::Fifo)
+            .expect("set mode");
+        assert_eq!(mode_of("pipe"), 0o640);
+
+        // Another process swaps the node for a symlink before the mode is set.
+        location.remove_file().expect("remove fifo");
+        std::os::unix::fs::symlink(&victim, dir.join("pipe")).expect("plant symlink");
+        location
+            .set_mode(0o666, ManifestKind::Fifo)
+            .expect_err("the symlink is not followed");
+        assert_eq!(mode_of("victim"), 0o600);
+    }
+}
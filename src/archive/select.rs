@@ -0,0 +1,164 @@
+//! Choosing which archive members `extract` and `list` act on.
+
+use std::fs;
+
+use anyhow::{Context, Result};
+use camino::{Utf8Component, Utf8Path, Utf8PathBuf};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+
+use super::{compile_excludes, is_excluded, MemberSelection};
+use crate::error::UserInputError;
+use crate::names;
+
+/// Compiled form of a [`MemberSelection`].
+pub(crate) struct Selector {
+    /// Operands as given, for reporting the ones that matched nothing.
+    operands: Vec<String>,
+    matcher: Operands,
+    matched: Vec<bool>,
+    excludes: Option<GlobSet>,
+}
+
+enum Operands {
+    /// Member names, matched exactly (GNU tar's `--no-wildcards` default).
+    Literal(Vec<Utf8PathBuf>),
+    /// Globs (`--wildcards`).
+    Globs(GlobSet),
+}
+
+impl Selector {
+    pub fn new(selection: &MemberSelection) -> Result<Self> {
+        let mut operands = selection.members.clone();
+        for file in &selection.files_from {
+            let content = fs::read_to_string(names::to_path(file))
+                .with_context(|| format!("failed to read member list {file}"))?;
+            operands.extend(
+                content
+                    .lines()
+                    .filter(|line| !line.trim().is_empty())
+                    .map(str::to_owned),
+            );
+        }
+        let matcher = if selection.wildcards {
+            let mut builder = GlobSetBuilder::new();
+            for operand in &operands {
+                let trimmed = operand.trim_end_matches('/');
+                let glob = Glob::new(if trimmed.is_empty() { operand } else { trimmed }).map_err(
+                    |err| UserInputError::new(format!("invalid pattern {operand}: {err}")),
+                )?;
+                builder.add(glob);
+            }
+            Operands::Globs(builder.build()?)
+        } else {
+            Operands::Literal(
+                operands
+                    .iter()
+                    .map(|operand| member_key(Utf8Path::new(operand)))
+                    .collect(),
+            )
+        };
+        Ok(Self {
+            matched: vec![false; operands.len()],
+            operands,
+            matcher,
+            excludes: compile_excludes(&selection.excludes, &selection.exclude_from)?,
+        })
+    }
+
+    /// Whether the member `name` is selected. A member is selected when an operand
+    /// names it or one of its parent directories (or when there are no operands), and
+    /// no exclude pattern matches it or one of its parents.
+    pub fn is_selected(&mut self, name: &Utf8Path) -> bool {
+        let key = member_key(name);
+        let mut ancestors = key
+            .ancestors()
+            .filter(|ancestor| !ancestor.as_str().is_empty());
+        if ancestors.any(|ancestor| is_excluded(&self.excludes, ancestor)) {
+            return false;
+        }
+        if self.operands.is_empty() {
+            return true;
+        }
+        let mut selected = false;
+        for ancestor in key.ancestors() {
+            let hits = match &self.matcher {
+                Operands::Literal(operands) => operands
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, operand)| operand.as_path() == ancestor)
+                    .map(|(index, _)| index)
+                    .collect(),
+                Operands::Globs(globs) if !ancestor.as_str().is_empty() => {
+                    globs.matches(names::to_path(ancestor))
+                }
+                Operands::Globs(_) => Vec::new(),
+            };
+            for index in hits {
+                self.matched[index] = true;
+                selected = true;
+            }
+        }
+        selected
+    }
+
+    /// Fail when an operand matched no member, like `tar` does.
+    pub fn finish(&self) -> Result<()> {
+        let unmatched: Vec<_> = self
+            .operands
+            .iter()
+            .zip(&self.matched)
+            .filter(|(_, hit)| !**hit)
+            .map(|(operand, _)| operand.as_str())
+            .collect();
+        if unmatched.is_empty() {
+            Ok(())
+        } else {
+            Err(
+                UserInputError::new(format!("not found in archive: {}", unmatched.join(", ")))
+                    .into(),
+            )
+        }
+    }
+}
+
+/// `name` without `.` components or a trailing slash, so `./docs/` matches `docs`.
+fn member_key(name: &Utf8Path) -> Utf8PathBuf {
+    name.components()
+        .filter(|component| *component != Utf8Component::CurDir)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn selection(members: &[&str], wildcards: bool, excludes: &[&str]) -> MemberSelection {
+        MemberSelection {
+            members: members.iter().map(|member| member.to_string()).collect(),
+            wildcards,
+            excludes: excludes.iter().map(|pattern| pattern.to_string()).collect(),
+            ..MemberSelection::default()
+        }
+    }
+
+    #[test]
+    fn literal_operands_select_directories_and_contents() {
+        let mut selector = Selector::new(&selection(&["./docs/"], false, &[])).unwrap();
+        assert!(selector.is_selected(Utf8Path::new("docs")));
+        assert!(selector.is_selected(Utf8Path::new("./docs/guide.md")));
+        assert!(!selector.is_selected(Utf8Path::new("docs.md")));
+        // Without --wildcards, glob characters are literal.
+        let mut selector = Selector::new(&selection(&["*.md"], false, &[])).unwrap();
+        assert!(!selector.is_selected(Utf8Path::new("a.md")));
+        assert!(selector.finish().is_err());
+    }
+
+    #[test]
+    fn wildcards_and_excludes_combine() {
+        let mut selector = Selector::new(&selection(&["src/*.rs"], true, &["*/gen"])).unwrap();
+        assert!(selector.is_selected(Utf8Path::new("src/lib.rs")));
+        assert!(!selector.is_selected(Utf8Path::new("src/gen/out.rs")));
+        assert!(!selector.is_selected(Utf8Path::new("README.md")));
+        assert!(selector.finish().is_ok());
+    }
+}
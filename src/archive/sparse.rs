@@ -0,0 +1,465 @@
+//! Sparse file detection and the GNU / PAX 1.0 sparse encodings.
+
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use camino::{Utf8Path, Utf8PathBuf};
+use tar::{GnuExtSparseHeader, Header};
+
+use super::pax::PaxMetadata;
+use crate::names;
+
+const BLOCK_SIZE: u64 = 512;
+/// Map entries stored inline in the GNU header before extension blocks are needed.
+const GNU_INLINE_SLOTS: usize = 4;
+/// Map entries per GNU sparse extension block.
+const GNU_EXT_SLOTS: usize = 21;
+/// Map entries accepted from a PAX 1.0 sparse map.
+const MAX_MAP_ENTRIES: u64 = 1 << 20;
+
+/// One run of data inside a sparse file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct SparseSegment {
+    pub offset: u64,
+    pub length: u64,
+}
+
+/// Data runs of a sparse file; everything outside them is a hole.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct SparseMap {
+    pub segments: Vec<SparseSegment>,
+    pub real_size: u64,
+}
+
+impl SparseMap {
+    /// Bytes of file data that are actually stored.
+    #[must_use]
+    pub fn stored_size(&self) -> u64 {
+        self.segments.iter().map(|segment| segment.length).sum()
+    }
+
+    /// Segments as written to a map, terminated by an empty run at the logical size
+    /// when the file ends in a hole.
+    fn encoded_segments(&self) -> Vec<SparseSegment> {
+        let mut segments = self.segments.clone();
+        let end = segments.last().map_or(0, |s| s.offset + s.length);
+        if end < self.real_size || segments.is_empty() {
+            segments.push(SparseSegment {
+                offset: self.real_size,
+                length: 0,
+            });
+        }
+        segments
+    }
+}
+
+/// Locate the data runs of `file` with `SEEK_DATA`/`SEEK_HOLE`.
+///
+/// Returns `None` when the file has no holes or the filesystem cannot report them.
+/// Runs are widened to 512-byte boundaries as both sparse encodings require.
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
+pub(crate) fn detect(file: &File, len: u64) -> io::Result<Option<SparseMap>> {
+    use rustix::fs::{seek, SeekFrom as RawSeek};
+    use rustix::io::Errno;
+
+    let mut segments: Vec<SparseSegment> = Vec::new();
+    let mut pos = 0;
+    while pos < len {
+        let start = match seek(file, RawSeek::Data(pos)) {
+            Ok(start) => start,
+            Err(Errno::NXIO) => break,
+            Err(Errno::INVAL) if pos == 0 => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        if start >= len {
+            break;
+        }
+        let end = seek(file, RawSeek::Hole(start))?.min(len);
+        let start = start - start % BLOCK_SIZE;
+        let end = end.div_ceil(BLOCK_SIZE).saturating_mul(BLOCK_SIZE).min(len);
+        match segments.last_mut() {
+            Some(last) if start <= last.offset + last.length => {
+                last.length = end - last.offset;
+            }
+            _ => segments.push(SparseSegment {
+                offset: start,
+                length: end - start,
+            }),
+        }
+        pos = end;
+    }
+    let map = SparseMap {
+        segments,
+        real_size: len,
+    };
+    Ok((map.stored_size() < len).then_some(map))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "freebsd")))]
+pub(crate) fn detect(_file: &File, _len: u64) -> io::Result<Option<SparseMap>> {
+    Ok(None)
+}
+
+/// Turn `header` into a GNU sparse (`S`) header for `map`.
+///
+/// Returns the extension blocks that must follow the header ahead of the data.
+pub(crate) fn fill_gnu_header(header: &mut Header, map: &SparseMap) -> io::Result<Vec<u8>> {
+    let segments = map.encoded_segments();
+    header.set_entry_type(tar::EntryType::GNUSparse);
+    header.set_size(map.stored_size());
+    let gnu = header
+        .as_gnu_mut()
+        .ok_or_else(|| io::Error::other("sparse members require a GNU header"))?;
+    gnu.set_real_size(map.real_size);
+    let (inline, rest) = segments.split_at(segments.len().min(GNU_INLINE_SLOTS));
+    for (slot, segment) in gnu.sparse.iter_mut().zip(inline) {
+        slot.set_offset(segment.offset);
+        slot.set_length(segment.length);
+    }
+    gnu.set_is_extended(!rest.is_empty());
+
+    let mut blocks = Vec::new();
+    let mut chunks = rest.chunks(GNU_EXT_SLOTS).peekable();
+    while let Some(chunk) = chunks.next() {
+        let mut ext = GnuExtSparseHeader::new();
+        for (slot, segment) in ext.sparse.iter_mut().zip(chunk) {
+            slot.set_offset(segment.offset);
+            slot.set_length(segment.length);
+        }
+        ext.set_is_extended(chunks.peek().is_some());
+        blocks.extend_from_slice(ext.as_bytes());
+    }
+    Ok(blocks)
+}
+
+/// Placeholder member name GNU tar uses for PAX 1.0 sparse members.
+#[must_use]
+pub(crate) fn pax_placeholder_name(path: &Utf8Path) -> Utf8PathBuf {
+    let file_name = path.file_name().unwrap_or(path.as_str());
+    match path.parent().filter(|parent| !parent.as_str().is_empty()) {
+        Some(parent) => parent.join("GNUSparseFile.0").join(file_name),
+        None => Utf8Path::new("GNUSparseFile.0").join(file_name),
+    }
+}
+
+/// PAX records announcing a 1.0 sparse member.
+#[must_use]
+pub(crate) fn pax_records(path: &Utf8Path, map: &SparseMap) -> Vec<(&'static str, Vec<u8>)> {
+    vec![
+        ("GNU.sparse.major", b"1".to_vec()),
+        ("GNU.sparse.minor", b"0".to_vec()),
+        ("GNU.sparse.name", names::to_bytes(path).into_owned()),
+        (
+            "GNU.sparse.realsize",
+            map.real_size.to_string().into_bytes(),
+        ),
+    ]
+}
+
+/// The decimal map that prefixes PAX 1.0 sparse data, padded to a block boundary.
+#[must_use]
+pub(crate) fn pax_map_block(map: &SparseMap) -> Vec<u8> {
+    let segments = map.encoded_segments();
+    let mut block = format!("{}\n", segments.len()).into_bytes();
+    for segment in &segments {
+        block.extend_from_slice(format!("{}\n{}\n", segment.offset, segment.length).as_bytes());
+    }
+    let padded = (block.len() as u64).div_ceil(BLOCK_SIZE) * BLOCK_SIZE;
+    block.resize(padded as usize, 0);
+    block
+}
+
+/// Sparse layout announced by GNU PAX 1.0 records.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct PaxSparse {
+    /// Real member name (encoded per [`crate::names`]); the header carries a
+    /// `GNUSparseFile.0` placeholder.
+    pub name: Utf8PathBuf,
+    pub real_size: u64,
+}
+
+impl PaxSparse {
+    /// Recognise a PAX 1.0 sparse member from its records.
+    ///
+    /// Older 0.x encodings are rejected rather than extracted as garbage.
+    pub fn from_pax(meta: &PaxMetadata) -> io::Result<Option<Self>> {
+        if !meta.other.keys().any(|key| key.starts_with("GNU.sparse.")) {
+            return Ok(None);
+        }
+        let field = |key: &str| meta.other.get(key).map(String::as_str);
+        if field("GNU.sparse.major") != Some("1") || field("GNU.sparse.minor") != Some("0") {
+            return Err(invalid("unsupported GNU sparse format version"));
+        }
+        let name = field("GNU.sparse.name").ok_or_else(|| invalid("sparse member has no name"))?;
+        let real_size = field("GNU.sparse.realsize")
+            .and_then(|size| size.parse().ok())
+            .ok_or_else(|| invalid("sparse member has no valid real size"))?;
+        Ok(Some(Self {
+            name: Utf8PathBuf::from(name),
+            real_size,
+        }))
+    }
+
+    /// Consume the map at the start of the member data.
+    ///
+    /// `stored` is the member's size in the archive; the map must account for all of
+    /// it and stay within the logical size.
+    pub fn read_map<R: Read>(&self, reader: &mut R, stored: u64) -> io::Result<SparseMap> {
+        let mut consumed = 0;
+        let count = read_decimal(reader, &mut consumed)?;
+        if count > MAX_MAP_ENTRIES || count.saturating_mul(4) > stored {
+            return Err(invalid("sparse map is too large"));
+        }
+        let mut segments = Vec::new();
+        let mut end = 0;
+        for _ in 0..count {
+            let offset = read_decimal(reader, &mut consumed)?;
+            let length = read_decimal(reader, &mut consumed)?;
+            let segment_end = offset
+                .checked_add(length)
+                .filter(|&segment_end| offset >= end && segment_end <= self.real_size)
+                .ok_or_else(|| invalid("sparse map runs overlap or exceed the real size"))?;
+            end = segment_end;
+            if length > 0 {
+                segments.push(SparseSegment { offset, length });
+            }
+        }
+        let padding = (BLOCK_SIZE - consumed % BLOCK_SIZE) % BLOCK_SIZE;
+        io::copy(&mut reader.take(padding), &mut io::sink())?;
+        let map = SparseMap {
+            segments,
+            real_size: self.real_size,
+        };
+        if consumed + padding + map.stored_size() != stored {
+            return Err(invalid("sparse map does not match the stored data"));
+        }
+        Ok(map)
+    }
+}
+
+/// Reads the stored runs of a file on disk back to back.
+pub(crate) struct SegmentReader {
+    file: File,
+    segments: std::vec::IntoIter<SparseSegment>,
+    remaining: u64,
+}
+
+impl SegmentReader {
+    pub fn new(file: File, map: &SparseMap) -> Self {
+        Self {
+            file,
+            segments: map.segments.clone().into_iter(),
+            remaining: 0,
+        }
+    }
+}
+
+impl Read for SegmentReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.remaining == 0 {
+            let Some(segment) = self.segments.next() else {
+                return Ok(0);
+            };
+            self.file.seek(SeekFrom::Start(segment.offset))?;
+            self.remaining = segment.length;
+        }
+        let max = usize::try_from(self.remaining).map_or(buf.len(), |r| r.min(buf.len()));
+        let read = self.file.read(&mut buf[..max])?;
+        if read == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "file shrank while being archived",
+            ));
+        }
+        self.remaining -= read as u64;
+        Ok(read)
+    }
+}
+
+/// Expands stored runs into the logical file contents, filling holes with zeros.
+pub(crate) struct ExpandReader<R> {
+    inner: R,
+    segments: std::vec::IntoIter<SparseSegment>,
+    current: Option<SparseSegment>,
+    pos: u64,
+    real_size: u64,
+}
+
+impl<R: Read> ExpandReader<R> {
+    pub fn new(inner: R, map: SparseMap) -> Self {
+        let mut segments = map.segments.into_iter();
+        Self {
+            current: segments.next(),
+            inner,
+            segments,
+            pos: 0,
+            real_size: map.real_size,
+        }
+    }
+}
+
+impl<R: Read> Read for ExpandReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while let Some(segment) = self.current {
+            if self.pos < segment.offset + segment.length {
+                break;
+            }
+            self.current = self.segments.next();
+        }
+        let (limit, from_data) = match self.current {
+            Some(segment) if self.pos >= segment.offset => {
+                (segment.offset + segment.length - self.pos, true)
+            }
+            Some(segment) => (segment.offset - self.pos, false),
+            None => (self.real_size.saturating_sub(self.pos), false),
+        };
+        let max = usize::try_from(limit).map_or(buf.len(), |l| l.min(buf.len()));
+        let read = if from_data {
+            let read = self.inner.read(&mut buf[..max])?;
+            if read == 0 && max > 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "sparse member data is truncated",
+                ));
+            }
+            read
+        } else {
+            buf[..max].fill(0);
+            max
+        };
+        self.pos += read as u64;
+        Ok(read)
+    }
+}
+
+/// Write a sparse member to the new, empty `file`, leaving holes where the map has no
+/// data.
+pub(crate) fn unpack<R: Read>(reader: &mut R, map: &SparseMap, file: &mut File) -> io::Result<()> {
+    for segment in &map.segments {
+        file.seek(SeekFrom::Start(segment.offset))?;
+        let copied = io::copy(&mut reader.take(segment.length), file)?;
+        if copied != segment.length {
+            return Err(invalid("sparse member data is truncated"));
+        }
+    }
+    file.set_len(map.real_size)?;
+    file.flush()?;
+    Ok(())
+}
+
+/// Copy `reader` into `file`, seeking over runs of zeros so they stay holes.
+pub(crate) fn copy_with_holes<R: Read>(reader: &mut R, file: &mut File) -> io::Result<()> {
+    let mut buffer = vec![0; 64 * 1024];
+    let mut len = 0;
+    loop {
+        let read = match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+        let chunk = &buffer[..read];
+        if chunk.iter().all(|&byte| byte == 0) {
+            file.seek(SeekFrom::Current(read as i64))?;
+        } else {
+            file.write_all(chunk)?;
+        }
+        len += read as u64;
+    }
+    // A trailing hole is only a seek so far.
+    file.set_len(len)
+}
+
+fn read_decimal<R: Read>(reader: &mut R, consumed: &mut u64) -> io::Result<u64> {
+    let mut digits = String::new();
+    let mut byte = [0_u8; 1];
+    loop {
+        reader.read_exact(&mut byte)?;
+        *consumed += 1;
+        match byte[0] {
+            b'\n' => break,
+            b @ b'0'..=b'9' if digits.len() < 20 => digits.push(char::from(b)),
+            _ => return Err(invalid("malformed sparse map")),
+        }
+    }
+    digits.parse().map_err(|_| invalid("malformed sparse map"))
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_map() -> SparseMap {
+        SparseMap {
+            segments: vec![
+                SparseSegment {
+                    offset: 0,
+                    length: 512,
+                },
+                SparseSegment {
+                    offset: 4096,
+                    length: 10,
+                },
+            ],
+            real_size: 8192,
+        }
+    }
+
+    #[test]
+    fn pax_map_roundtrips() {
+        let map = sample_map();
+        let block = pax_map_block(&map);
+        assert_eq!(block.len(), 512);
+        assert!(block.starts_with(b"3\n0\n512\n4096\n10\n8192\n0\n"));
+        let sparse = PaxSparse {
+            name: Utf8PathBuf::from("disk.img"),
+            real_size: 8192,
+        };
+        let stored = block.len() as u64 + map.stored_size();
+        let parsed = sparse.read_map(&mut block.as_slice(), stored).unwrap();
+        assert_eq!(parsed, map);
+    }
+
+    #[test]
+    fn pax_map_rejects_runs_past_real_size() {
+        let sparse = PaxSparse {
+            name: Utf8PathBuf::from("disk.img"),
+            real_size: 100,
+        };
+        let mut block = b"1\n0\n200\n".to_vec();
+        block.resize(512, 0);
+        assert!(sparse.read_map(&mut block.as_slice(), 712).is_err());
+    }
+
+    #[test]
+    fn expand_reader_fills_holes() {
+        let map = SparseMap {
+            segments: vec![SparseSegment {
+                offset: 2,
+                length: 3,
+            }],
+            real_size: 8,
+        };
+        let mut out = Vec::new();
+        ExpandReader::new(&b"abc"[..], map)
+            .read_to_end(&mut out)
+            .unwrap();
+        assert_eq!(out, b"\0\0abc\0\0\0");
+    }
+
+    #[test]
+    fn placeholder_keeps_directory() {
+        assert_eq!(
+            pax_placeholder_name(Utf8Path::new("vm/disk.img")),
+            "vm/GNUSparseFile.0/disk.img"
+        );
+        assert_eq!(
+            pax_placeholder_name(Utf8Path::new("disk.img")),
+            "GNUSparseFile.0/disk.img"
+        );
+    }
+}
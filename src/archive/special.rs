@@ -0,0 +1,134 @@
+//! Device, FIFO, and other non-regular entries.
+
+use std::fs;
+
+use anyhow::{anyhow, Context, Result};
+use tar::Header;
+
+use super::rooted::Location;
+use crate::manifest::ManifestKind;
+use crate::policy::SpecialEntryKind;
+
+/// Map a filesystem object to the special entry kind tar can represent.
+///
+/// Returns `None` for regular files, directories, symlinks, and sockets (tar has
+/// no socket entry type).
+#[cfg(unix)]
+#[must_use]
+pub(crate) fn special_kind_of(file_type: &fs::FileType) -> Option<SpecialEntryKind> {
+    use std::os::unix::fs::FileTypeExt;
+
+    if file_type.is_char_device() {
+        Some(SpecialEntryKind::CharDevice)
+    } else if file_type.is_block_device() {
+        Some(SpecialEntryKind::BlockDevice)
+    } else if file_type.is_fifo() {
+        Some(SpecialEntryKind::Fifo)
+    } else {
+        None
+    }
+}
+
+#[cfg(not(unix))]
+#[must_use]
+pub(crate) fn special_kind_of(_file_type: &fs::FileType) -> Option<SpecialEntryKind> {
+    None
+}
+
+/// Whether `file_type` is a socket, which cannot be stored in a tar archive.
+#[cfg(unix)]
+#[must_use]
+pub(crate) fn is_socket(file_type: &fs::FileType) -> bool {
+    use std::os::unix::fs::FileTypeExt;
+
+    file_type.is_socket()
+}
+
+#[cfg(not(unix))]
+#[must_use]
+pub(crate) fn is_socket(_file_type: &fs::FileType) -> bool {
+    false
+}
+
+/// Copy the device numbers of `metadata` into `header`.
+#[cfg(unix)]
+pub(crate) fn set_device_numbers(header: &mut Header, metadata: &fs::Metadata) -> Result<()> {
+    use std::os::unix::fs::MetadataExt;
+
+    let rdev = metadata.rdev();
+    header.set_device_major(rustix::fs::major(rdev))?;
+    header.set_device_minor(rustix::fs::minor(rdev))?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub(crate) fn set_device_numbers(_header: &mut Header, _metadata: &fs::Metadata) -> Result<()> {
+    Ok(())
+}
+
+/// Device numbers recorded in `header`, if any.
+#[must_use]
+pub(crate) fn header_device_numbers(header: &Header) -> Option<(u32, u32)> {
+    let major = header.device_major().ok().flatten()?;
+    let minor = header.device_minor().ok().flatten()?;
+    Some((major, minor))
+}
+
+/// Manifest kind used for a special entry.
+#[must_use]
+pub(crate) fn manifest_kind(kind: SpecialEntryKind) -> ManifestKind {
+    match kind {
+        SpecialEntryKind::CharDevice => ManifestKind::CharDevice,
+        SpecialEntryKind::BlockDevice => ManifestKind::BlockDevice,
+        SpecialEntryKind::Fifo => ManifestKind::Fifo,
+        SpecialEntryKind::Unknown => ManifestKind::Other,
+    }
+}
+
+/// Create the node described by `header` at `location`, with the permission bits of
+/// `mode` as already checked against the policy.
+///
+/// FIFOs can always be created; device nodes require an effective uid of 0. Special
+/// bits are left for the caller to restore after the owner.
+#[cfg(unix)]
+pub(crate) fn create_special_node(
+    location: &Location,
+    header: &Header,
+    kind: SpecialEntryKind,
+    mode: Option<u32>,
+) -> Result<()> {
+    use rustix::fs::{makedev, FileType};
+
+    let file_type = match kind {
+        SpecialEntryKind::CharDevice => FileType::CharacterDevice,
+        SpecialEntryKind::BlockDevice => FileType::BlockDevice,
+        SpecialEntryKind::Fifo => FileType::Fifo,
+        SpecialEntryKind::Unknown => {
+            return Err(anyhow!("cannot create {kind} {location}"));
+        }
+    };
+    let device = if file_type == FileType::Fifo {
+        0
+    } else {
+        if !rustix::process::geteuid().is_root() {
+            return Err(anyhow!("creating {kind} {location} requires root"));
+        }
+        let (major, minor) = header_device_numbers(header)
+            .ok_or_else(|| anyhow!("{kind} {location} has no device numbers"))?;
+        makedev(major, minor)
+    };
+    location
+        .mknod(file_type, mode.unwrap_or(0o600) & 0o777, device)
+        .with_context(|| format!("failed to create {kind} {location}"))?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub(crate) fn create_special_node(
+    location: &Location,
+    _header: &Header,
+    kind: SpecialEntryKind,
+    _mode: Option<u32>,
+) -> Result<()> {
+    Err(anyhow!("cannot create {kind} {location} on this platform"))
+}
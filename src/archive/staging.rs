@@ -0,0 +1,161 @@
+//! Atomic extraction (`--atomic`).
+//!
+//! Members are extracted into a private staging directory next to the destination
+//! and only moved into place once the whole archive has passed every check. A new
+//! destination appears with a single rename; an existing one is merged into entry by
+//! entry, each with a rename. Until then nothing in the destination changes, and the
+//! staging directory is removed whenever extraction fails.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+use camino::{Utf8Path, Utf8PathBuf};
+use tempfile::TempDir;
+
+use super::rooted::{Location, Root};
+use crate::manifest::{ExtractOutcome, ManifestKind};
+use crate::names;
+
+/// A staging directory for one extraction, removed when dropped.
+pub(crate) struct Staging {
+    /// Private to the extracting user, so nobody else can meddle with `root`.
+    _dir: TempDir,
+    /// What becomes the destination, created with the usual permissions.
+    root: Utf8PathBuf,
+}
+
+impl Staging {
+    /// Create a staging directory in the same directory as `destination`, so that
+    /// members can be renamed into place.
+    pub fn new(destination: &Path) -> Result<Self> {
+        match fs::metadata(destination) {
+            Ok(metadata) if !metadata.is_dir() => {
+                return Err(anyhow!(
+                    "destination {} is not a directory",
+                    destination.display()
+                ))
+            }
+            Ok(_) => {}
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => {
+                return Err(err).with_context(|| {
+                    format!("failed to inspect destination {}", destination.display())
+                })
+            }
+        }
+        let parent = destination
+            .parent()
+            .ok_or_else(|| anyhow!("destination {} has no parent", destination.display()))?;
+        fs::create_dir_all(parent)
+            .with_context(|| format!("failed to prepare {}", parent.display()))?;
+        let name = destination
+            .file_name()
+            .map_or_else(|| "destination".into(), |name| name.to_string_lossy());
+        let dir = tempfile::Builder::new()
+            .prefix(&format!(".{name}.safetar-"))
+            .tempdir_in(parent)
+            .with_context(|| {
+                format!(
+                    "failed to create a staging directory in {}",
+                    parent.display()
+                )
+            })?;
+        let root = dir.path().join("root");
+        fs::create_dir(&root)
+            .with_context(|| format!("failed to create staging directory {}", root.display()))?;
+        let root = names::from_path(&root, true)
+            .with_context(|| format!("unusable staging directory {}", root.display()))?;
+        Ok(Self { _dir: dir, root })
+    }
+
+    /// Where members are extracted.
+    pub fn root(&self) -> &Utf8Path {
+        &self.root
+    }
+
+    /// Move everything staged into `destination`.
+    ///
+    /// Every conflict is checked before the first move, so a directory in the way of
+    /// another kind of member fails the commit with the destination untouched. Returns
+    /// the outcomes of the staged paths that already existed there.
+    pub fn commit(self, destination: &Utf8Path) -> Result<HashMap<Utf8PathBuf, ExtractOutcome>> {
+        let target = names::to_path(destination);
+        if let Err(err) = fs::symlink_metadata(&target) {
+            if err.kind() == io::ErrorKind::NotFound {
+                fs::rename(names::to_path(&self.root), &target)
+                    .with_context(|| format!("failed to move extracted files to {destination}"))?;
+                return Ok(HashMap::new());
+            }
+            return Err(err)
+                .with_context(|| format!("failed to inspect destination {destination}"));
+        }
+        let root = Root::open(destination)?;
+        let mut outcomes = HashMap::new();
+        merge(&self.root, &root, Utf8Path::new(""), false, &mut outcomes)?;
+        merge(
+            &self.root,
+            &root,
+            Utf8Path::new(""),
+            true,
+            &mut HashMap::new(),
+        )?;
+        Ok(outcomes)
+    }
+}
+
+/// Move the contents of the staged directory `rel` into the same directory of `root`,
+/// merging directories that exist in both.
+///
+/// Without `apply` nothing moves; conflicts are only checked and the outcomes of
+/// existing paths collected.
+fn merge(
+    staged: &Utf8Path,
+    root: &Root,
+    rel: &Utf8Path,
+    apply: bool,
+    outcomes: &mut HashMap<Utf8PathBuf, ExtractOutcome>,
+) -> Result<()> {
+    let dir = names::to_path(&staged.join(rel)).into_owned();
+    let mut entries = fs::read_dir(&dir)
+        .and_then(|entries| entries.collect::<io::Result<Vec<_>>>())
+        .with_context(|| format!("failed to read staging directory {}", dir.display()))?;
+    entries.sort_by_key(fs::DirEntry::file_name);
+    for entry in entries {
+        let name = names::from_path(Path::new(&entry.file_name()), true)?;
+        let rel = rel.join(name);
+        let staged_dir = entry.file_type()?.is_dir();
+        let target = root.locate(&rel, false)?;
+        let existing = target
+            .stat()
+            .with_context(|| format!("failed to inspect {target}"))?;
+        match existing.map(|existing| existing.kind) {
+            None => {}
+            Some(ManifestKind::Directory) if staged_dir => {
+                outcomes.insert(rel.clone(), ExtractOutcome::Merged);
+                merge(staged, root, &rel, apply, outcomes)?;
+                continue;
+            }
+            Some(ManifestKind::Directory) => {
+                return Err(anyhow!("cannot extract {target} over a directory"));
+            }
+            Some(_) => {
+                outcomes.insert(rel.clone(), ExtractOutcome::Replaced);
+                // Only a file can be renamed over a file.
+                if apply && staged_dir {
+                    target
+                        .remove_file()
+                        .with_context(|| format!("failed to replace {target}"))?;
+                }
+            }
+        }
+        if apply {
+            Location::outside(&staged.join(&rel))?
+                .move_to(&target)
+                .with_context(|| format!("failed to move {target} into place"))?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,184 @@
+//! Extended attributes and POSIX ACLs (`--xattrs`, `--acls`).
+//!
+//! Both travel as `SCHILY.xattr.NAME` PAX records, as GNU tar and bsdtar write them.
+//! ACLs are the `system.posix_acl_access` and `system.posix_acl_default` attributes,
+//! kept in the kernel's binary form, so they round-trip between Linux systems.
+
+use anyhow::{anyhow, Context, Result};
+use camino::Utf8Path;
+
+use super::rooted::Location;
+
+/// Attributes that hold POSIX ACLs.
+const ACL_NAMES: [&str; 2] = ["system.posix_acl_access", "system.posix_acl_default"];
+
+/// Which extended attributes an operation carries.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct XattrSelection {
+    /// Attributes other than ACLs.
+    pub xattrs: bool,
+    pub acls: bool,
+}
+
+impl XattrSelection {
+    #[must_use]
+    pub fn is_empty(self) -> bool {
+        !self.xattrs && !self.acls
+    }
+
+    /// Whether the attribute `name` is carried.
+    #[must_use]
+    pub fn includes(self, name: &str) -> bool {
+        if ACL_NAMES.contains(&name) {
+            self.acls
+        } else {
+            self.xattrs
+        }
+    }
+}
+
+/// Read the selected attributes of `path`, without following a symlink there.
+///
+/// Names that are not valid UTF-8 cannot be stored as PAX keywords and are skipped
+/// with a warning.
+pub(crate) fn read(path: &Utf8Path, selection: XattrSelection) -> Result<Vec<(String, Vec<u8>)>> {
+    let mut xattrs = Vec::new();
+    for name in sys::list(path).with_context(|| format!("failed to list xattrs of {path}"))? {
+        let Ok(name) = String::from_utf8(name) else {
+            eprintln!("safetar: skipping xattr of {path} whose name is not valid UTF-8");
+            continue;
+        };
+        if !selection.includes(&name) {
+            continue;
+        }
+        // The attribute may have been removed since it was listed.
+        if let Some(value) = sys::get(path, &name)
+            .with_context(|| format!("failed to read xattr {name} of {path}"))?
+        {
+            xattrs.push((name, value));
+        }
+    }
+    Ok(xattrs)
+}
+
+/// Set the attribute `name` of the member extracted at `location`, without following
+/// a symlink there.
+pub(crate) fn write(location: &Location, name: &str, value: &[u8]) -> Result<()> {
+    sys::set(location, name, value)
+        .map_err(|err| anyhow!("failed to set xattr {name} on {location}: {err}"))
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod sys {
+    use std::io;
+
+    use camino::Utf8Path;
+    use rustix::fs::{lgetxattr, llistxattr, lsetxattr, XattrFlags};
+    use rustix::io::Errno;
+
+    use crate::archive::rooted::Location;
+    use crate::names;
+
+    pub(super) fn list(path: &Utf8Path) -> io::Result<Vec<Vec<u8>>> {
+        let path = names::to_path(path);
+        let list = loop {
+            let size = match llistxattr(path.as_ref(), &mut [0_u8; 0][..]) {
+                Ok(size) => size,
+                Err(Errno::NOTSUP) => return Ok(Vec::new()),
+                Err(err) => return Err(err.into()),
+            };
+            let mut list = vec![0; size];
+            match llistxattr(path.as_ref(), &mut list[..]) {
+                Ok(size) => {
+                    list.truncate(size);
+                    break list;
+                }
+                // Attributes were added in between; ask for the size again.
+                Err(Errno::RANGE) => continue,
+                Err(err) => return Err(err.into()),
+            }
+        };
+        Ok(list
+            .split(|&byte| byte == 0)
+            .filter(|name| !name.is_empty())
+            .map(<[u8]>::to_vec)
+            .collect())
+    }
+
+    pub(super) fn get(path: &Utf8Path, name: &str) -> io::Result<Option<Vec<u8>>> {
+        let path = names::to_path(path);
+        loop {
+            let size = match lgetxattr(path.as_ref(), name, &mut [0_u8; 0][..]) {
+                Ok(size) => size,
+                Err(Errno::NODATA) => return Ok(None),
+                Err(err) => return Err(err.into()),
+            };
+            let mut value = vec![0; size];
+            match lgetxattr(path.as_ref(), name, &mut value[..]) {
+                Ok(size) => {
+                    value.truncate(size);
+                    return Ok(Some(value));
+                }
+                Err(Errno::RANGE) => continue,
+                Err(Errno::NODATA) => return Ok(None),
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+
+    /// Attributes have no descriptor-relative call, so the member is reached through
+    /// the directory its location holds open.
+    pub(super) fn set(location: &Location, name: &str, value: &[u8]) -> io::Result<()> {
+        lsetxattr(location.proc_path(), name, value, XattrFlags::empty()).map_err(Into::into)
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+mod sys {
+    use std::io;
+
+    use camino::Utf8Path;
+
+    use crate::archive::rooted::Location;
+
+    fn unsupported() -> io::Error {
+        io::Error::new(
+            io::ErrorKind::Unsupported,
+            "extended attributes are not supported on this platform",
+        )
+    }
+
+    pub(super) fn list(_path: &Utf8Path) -> io::Result<Vec<Vec<u8>>> {
+        Err(unsupported())
+    }
+
+    pub(super) fn get(_path: &Utf8Path, _name: &str) -> io::Result<Option<Vec<u8>>> {
+        Err(unsupported())
+    }
+
+    pub(super) fn set(_location: &Location, _name: &str, _value: &[u8]) -> io::Result<()> {
+        Err(unsupported())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn selection_separates_acls_from_other_attributes() {
+        let acls = XattrSelection {
+            xattrs: false,
+            acls: true,
+        };
+        assert!(acls.includes("system.posix_acl_access"));
+        assert!(!acls.includes("user.mime_type"));
+        let xattrs = XattrSelection {
+            xattrs: true,
+            acls: false,
+        };
+        assert!(xattrs.includes("security.capability"));
+        assert!(!xattrs.includes("system.posix_acl_default"));
+        assert!(XattrSelection::default().is_empty());
+    }
+}
@@ -2,9 +2,9 @@
 use std::path::PathBuf;
 
 use camino::Utf8PathBuf;
-use clap::{Args, Parser, Subcommand, ValueHint};
+use clap::{Args, Parser, Subcommand, ValueEnum, ValueHint};
 
-const CLI_EXAMPLES: &str = "Examples:\n  safetar create -f backup.tar ./src\n  safetar extract -f backup.tar -C ./restore --strict\n  safetar list -f backup.tar --json\n";
+const CLI_EXAMPLES: &str = "Examples:\n  safetar create -f backup.tar ./src\n  safetar extract -f backup.tar -C ./restore --strict\n  safetar extract -f backup.tar --wildcards 'docs/*.md'\n  safetar list -f backup.tar --json\n  curl -sL https://example.org/pkg.tar.gz | safetar extract -f - -C ./pkg\n  safetar append -f backup.tar ./notes.txt\n  safetar diff -f backup.tar -C ./restore\n  safetar audit -f vendor-drop.tar.gz --json\n  safetar cat -f backup.tar etc/app.conf\n  safetar index -f backup.tar && safetar extract -f backup.tar --index backup.tar.idx etc/app.conf\n";
 
 /// Top-level CLI parser.
 #[derive(Debug, Parser)]
@@ -34,6 +34,26 @@ pub enum Commands {
     /// List archive contents.
     #[command(alias = "t")]
     List(ListArgs),
+    /// Append files to the end of an uncompressed archive.
+    #[command(alias = "r")]
+    Append(AppendArgs),
+    /// Append only files that are newer than their archived copies.
+    #[command(alias = "u")]
+    Update(AppendArgs),
+    /// Remove members from an archive, keeping its compression.
+    Delete(DeleteArgs),
+    /// Merge several archives into one, re-checking every member.
+    #[command(alias = "catenate")]
+    Concat(ConcatArgs),
+    /// Compare an archive with the files in a directory.
+    #[command(alias = "d")]
+    Diff(DiffArgs),
+    /// Record member offsets so single members can be extracted without a full scan.
+    Index(IndexArgs),
+    /// Write the contents of members to stdout.
+    Cat(CatArgs),
+    /// Report every policy violation in an archive without extracting it.
+    Audit(AuditArgs),
 }
 
 /// Compression flags shared by multiple subcommands.
@@ -69,6 +89,120 @@ pub enum CompressionChoice {
     Auto,
 }
 
+/// Header format selected with `--format`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum FormatChoice {
+    /// POSIX ustar (fails on long names and files over 8 GiB).
+    Ustar,
+    /// GNU tar headers.
+    #[default]
+    Gnu,
+    /// POSIX pax with extended records where ustar falls short.
+    Pax,
+}
+
+/// Handling of device, FIFO, and unknown entries on extract.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum SpecialAction {
+    /// Abort with a policy error.
+    #[default]
+    Reject,
+    /// Skip the entry with a warning.
+    Skip,
+    /// Create the node (devices require root).
+    Create,
+}
+
+/// Handling of setuid, setgid, sticky, and world-writable bits on extract.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum PermissionAction {
+    /// Clear the bit.
+    #[default]
+    Strip,
+    /// Abort with a policy error.
+    Reject,
+    /// Extract the member with the bit set.
+    Keep,
+}
+
+/// Permission rules shared by extract/audit.
+#[derive(Debug, Args, Clone, Default)]
+pub struct ModeArgs {
+    /// How to handle setuid bits.
+    #[arg(long = "setuid", value_enum, default_value_t = PermissionAction::Strip)]
+    pub setuid: PermissionAction,
+    /// How to handle setgid bits.
+    #[arg(long = "setgid", value_enum, default_value_t = PermissionAction::Strip)]
+    pub setgid: PermissionAction,
+    /// How to handle sticky bits.
+    #[arg(long = "sticky", value_enum, default_value_t = PermissionAction::Strip)]
+    pub sticky: PermissionAction,
+    /// How to handle world-writable files and directories.
+    #[arg(long = "world-writable", value_enum, default_value_t = PermissionAction::Strip)]
+    pub world_writable: PermissionAction,
+    /// Clear these permission bits (octal, like `022`) from every extracted member.
+    #[arg(long = "umask", value_name = "OCTAL", value_parser = parse_umask, default_value = "000")]
+    pub umask: u32,
+}
+
+fn parse_umask(value: &str) -> Result<u32, String> {
+    u32::from_str_radix(value, 8)
+        .ok()
+        .filter(|&umask| umask <= 0o7777)
+        .ok_or_else(|| format!("`{value}` is not an octal mode between 0 and 7777"))
+}
+
+/// Extended attribute restoration for extract.
+#[derive(Debug, Args, Clone, Default)]
+pub struct XattrArgs {
+    /// Restore extended attributes other than ACLs.
+    #[arg(long = "xattrs")]
+    pub xattrs: bool,
+    /// Restore POSIX ACLs.
+    #[arg(long = "acls")]
+    pub acls: bool,
+    /// Only restore attributes matching PATTERN (a name, or a prefix ending in `*`).
+    #[arg(long = "xattrs-include", value_name = "PATTERN")]
+    pub xattrs_include: Vec<String>,
+    /// Never restore attributes matching PATTERN.
+    #[arg(long = "xattrs-exclude", value_name = "PATTERN")]
+    pub xattrs_exclude: Vec<String>,
+    /// Also restore `security.capability` and `trusted.*`, which are blocked by
+    /// default; only for archives you built yourself.
+    #[arg(long = "trust-xattrs")]
+    pub trust_xattrs: bool,
+}
+
+/// What extract does with files that already exist; at most one may be given.
+#[derive(Debug, Args, Clone, Default)]
+#[group(multiple = false)]
+pub struct OverwriteArgs {
+    /// Fail when a member's path already exists.
+    #[arg(short = 'k', long = "keep-old-files")]
+    pub keep_old_files: bool,
+    /// Leave existing files alone and skip their members.
+    #[arg(long = "skip-old-files")]
+    pub skip_old_files: bool,
+    /// Write into existing regular files in place, keeping their other hard links.
+    #[arg(long = "overwrite")]
+    pub overwrite: bool,
+    /// Remove existing files, and empty directories, in the way of members.
+    #[arg(short = 'U', long = "unlink-first")]
+    pub unlink_first: bool,
+    /// Skip members older than the existing file.
+    #[arg(long = "keep-newer-files")]
+    pub keep_newer_files: bool,
+}
+
+/// How replaced files are kept on extract.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum BackupControl {
+    /// Rename to `NAME~`.
+    Simple,
+    /// Rename to `NAME.~N~`.
+    Numbered,
+}
+
 /// Resource limit overrides shared by create/extract.
 #[derive(Debug, Args, Clone, Default)]
 pub struct LimitArgs {
@@ -84,17 +218,78 @@ pub struct LimitArgs {
     /// Maximum directory depth relative to the root.
     #[arg(long = "max-depth")]
     pub max_depth: Option<u32>,
+    /// Maximum bytes decoded per byte read from a compressed archive (default 1000).
+    #[arg(long = "max-compression-ratio")]
+    pub max_compression_ratio: Option<u64>,
+    /// Maximum bytes read from the archive file itself.
+    #[arg(long = "max-compressed-input")]
+    pub max_compressed_input: Option<u64>,
+}
+
+/// Member selection shared by extract/list.
+#[derive(Debug, Args, Clone, Default)]
+pub struct SelectionArgs {
+    /// Treat member operands as glob patterns.
+    #[arg(long = "wildcards")]
+    pub wildcards: bool,
+    /// Skip members matching these glob patterns.
+    #[arg(long = "exclude")]
+    pub exclude: Vec<String>,
+    /// Read exclude patterns from these files.
+    #[arg(long = "exclude-from", value_hint = ValueHint::FilePath)]
+    pub exclude_from: Vec<PathBuf>,
+    /// Read member names from these files, one per line.
+    #[arg(short = 'T', long = "files-from", value_hint = ValueHint::FilePath)]
+    pub files_from: Vec<PathBuf>,
+    /// Members to act on (directories include their contents; default: all).
+    #[arg(value_name = "MEMBER")]
+    pub members: Vec<String>,
+}
+
+/// Member name rewriting shared by create/append/extract.
+#[derive(Debug, Args, Clone, Default)]
+pub struct RewriteArgs {
+    /// Remove this many leading components from member names.
+    #[arg(long = "strip-components", value_name = "N", default_value_t = 0)]
+    pub strip_components: usize,
+    /// Rewrite member names with a sed-style expression, e.g. `s,^pkg-1.0/,pkg/,`.
+    #[arg(long = "transform", visible_alias = "xform", value_name = "EXPR")]
+    pub transform: Vec<String>,
+}
+
+/// Owner translation shared by create/append/extract.
+#[derive(Debug, Args, Clone, Default)]
+pub struct OwnerArgs {
+    /// Translate users with a file of `OLD NEW` lines (`name` or `+uid`, then
+    /// `name:uid`, `name`, or `+uid`).
+    #[arg(long = "owner-map", value_name = "FILE", value_hint = ValueHint::FilePath)]
+    pub owner_map: Option<Utf8PathBuf>,
+    /// Translate groups with a file in the `--owner-map` format.
+    #[arg(long = "group-map", value_name = "FILE", value_hint = ValueHint::FilePath)]
+    pub group_map: Option<Utf8PathBuf>,
+    /// Add N to every uid after mapping, as between user namespaces (implies
+    /// --numeric-owner).
+    #[arg(
+        long = "uid-shift",
+        value_name = "N",
+        allow_negative_numbers = true,
+        default_value_t = 0
+    )]
+    pub uid_shift: i64,
+    /// Add N to every gid after mapping (default: the --uid-shift value).
+    #[arg(long = "gid-shift", value_name = "N", allow_negative_numbers = true)]
+    pub gid_shift: Option<i64>,
 }
 
 /// Arguments for the `create` subcommand.
 #[derive(Debug, Args)]
 pub struct CreateArgs {
-    /// Archive path to write.
+    /// Archive path to write (`-` for stdout).
     #[arg(short = 'f', long = "file", value_hint = ValueHint::FilePath)]
-    pub file: Utf8PathBuf,
+    pub file: PathBuf,
     /// Change to this directory before resolving inputs.
     #[arg(short = 'C', long = "directory", value_hint = ValueHint::DirPath)]
-    pub directory: Option<Utf8PathBuf>,
+    pub directory: Option<PathBuf>,
     /// Emit verbose progress.
     #[arg(short = 'v', long = "verbose")]
     pub verbose: bool,
@@ -110,6 +305,12 @@ pub struct CreateArgs {
     /// Enable zstd compression.
     #[arg(long = "zstd")]
     pub zstd: bool,
+    /// Write zstd in the seekable format so `safetar index` can index it.
+    #[arg(long = "seekable", requires = "zstd")]
+    pub seekable: bool,
+    /// Header format for new members.
+    #[arg(long = "format", value_enum, default_value_t = FormatChoice::Gnu)]
+    pub format: FormatChoice,
     /// Exclude entries matching these glob patterns.
     #[arg(long = "exclude")]
     pub exclude: Vec<String>,
@@ -119,21 +320,46 @@ pub struct CreateArgs {
     /// Write a manifest JSON file describing archive contents.
     #[arg(long = "manifest-out", value_hint = ValueHint::FilePath)]
     pub manifest_out: Option<Utf8PathBuf>,
-    /// Track numeric owner values.
+    /// Record owners by id only, without user and group names.
     #[arg(long = "numeric-owner")]
     pub numeric_owner: bool,
-    /// Do not attempt to restore owners.
+    /// Record every member as owned by root (0:0), as for reproducible builds.
     #[arg(long = "no-same-owner")]
     pub no_same_owner: bool,
+    /// Translate recorded owners.
+    #[command(flatten)]
+    pub owners: OwnerArgs,
+    /// Record modification times instead of a fixed one (`--format pax` also keeps
+    /// sub-second precision and access times).
+    #[arg(long = "preserve-times")]
+    pub preserve_times: bool,
+    /// Record extended attributes other than ACLs (needs `--format pax`).
+    #[arg(long = "xattrs")]
+    pub xattrs: bool,
+    /// Record POSIX ACLs (needs `--format pax`).
+    #[arg(long = "acls")]
+    pub acls: bool,
+    /// Archive device nodes and FIFOs instead of skipping them.
+    #[arg(long = "include-special")]
+    pub include_special: bool,
+    /// Detect holes and store sparse files efficiently (not available with ustar).
+    #[arg(short = 'S', long = "sparse")]
+    pub sparse: bool,
+    /// Accept file names that are not valid UTF-8 and store their raw bytes.
+    #[arg(long = "byte-paths")]
+    pub byte_paths: bool,
     /// Preview entries without writing the archive.
     #[arg(long = "print-plan")]
     pub print_plan: bool,
+    /// Rewrite stored member names.
+    #[command(flatten)]
+    pub rewrite: RewriteArgs,
     /// Override resource limits.
     #[command(flatten)]
     pub limits: LimitArgs,
     /// Inputs to archive.
     #[arg(value_name = "PATH", required = true, value_hint = ValueHint::AnyPath)]
-    pub inputs: Vec<Utf8PathBuf>,
+    pub inputs: Vec<PathBuf>,
 }
 
 impl CreateArgs {
@@ -148,15 +374,249 @@ impl CreateArgs {
     }
 }
 
+/// Arguments for the `append` and `update` subcommands.
+#[derive(Debug, Args)]
+pub struct AppendArgs {
+    /// Uncompressed archive to extend (created if missing).
+    #[arg(short = 'f', long = "file", value_hint = ValueHint::FilePath)]
+    pub file: PathBuf,
+    /// Change to this directory before resolving inputs.
+    #[arg(short = 'C', long = "directory", value_hint = ValueHint::DirPath)]
+    pub directory: Option<PathBuf>,
+    /// Emit verbose progress.
+    #[arg(short = 'v', long = "verbose")]
+    pub verbose: bool,
+    /// Reduce output to errors only.
+    #[arg(long = "quiet")]
+    pub quiet: bool,
+    /// Header format for new members.
+    #[arg(long = "format", value_enum, default_value_t = FormatChoice::Gnu)]
+    pub format: FormatChoice,
+    /// Exclude entries matching these glob patterns.
+    #[arg(long = "exclude")]
+    pub exclude: Vec<String>,
+    /// Read exclude patterns from these files.
+    #[arg(long = "exclude-from", value_hint = ValueHint::FilePath)]
+    pub exclude_from: Vec<PathBuf>,
+    /// Rewrite this manifest JSON file to describe the whole archive.
+    #[arg(long = "manifest-out", value_hint = ValueHint::FilePath)]
+    pub manifest_out: Option<Utf8PathBuf>,
+    /// Record owners by id only, without user and group names.
+    #[arg(long = "numeric-owner")]
+    pub numeric_owner: bool,
+    /// Record every member as owned by root (0:0), as for reproducible builds.
+    #[arg(long = "no-same-owner")]
+    pub no_same_owner: bool,
+    /// Translate recorded owners.
+    #[command(flatten)]
+    pub owners: OwnerArgs,
+    /// Record modification times instead of a fixed one (`--format pax` also keeps
+    /// sub-second precision and access times).
+    #[arg(long = "preserve-times")]
+    pub preserve_times: bool,
+    /// Record extended attributes other than ACLs (needs `--format pax`).
+    #[arg(long = "xattrs")]
+    pub xattrs: bool,
+    /// Record POSIX ACLs (needs `--format pax`).
+    #[arg(long = "acls")]
+    pub acls: bool,
+    /// Archive device nodes and FIFOs instead of skipping them.
+    #[arg(long = "include-special")]
+    pub include_special: bool,
+    /// Detect holes and store sparse files efficiently (not available with ustar).
+    #[arg(short = 'S', long = "sparse")]
+    pub sparse: bool,
+    /// Accept file names that are not valid UTF-8 and store their raw bytes.
+    #[arg(long = "byte-paths")]
+    pub byte_paths: bool,
+    /// Preview the entries that would be added without touching the archive.
+    #[arg(long = "print-plan")]
+    pub print_plan: bool,
+    /// Rewrite stored member names.
+    #[command(flatten)]
+    pub rewrite: RewriteArgs,
+    /// Override resource limits (applied to existing and new members together).
+    #[command(flatten)]
+    pub limits: LimitArgs,
+    /// Inputs to add.
+    #[arg(value_name = "PATH", required = true, value_hint = ValueHint::AnyPath)]
+    pub inputs: Vec<PathBuf>,
+}
+
+/// Arguments for the `delete` subcommand.
+#[derive(Debug, Args)]
+pub struct DeleteArgs {
+    /// Archive to rewrite in place (`-` filters stdin to stdout).
+    #[arg(short = 'f', long = "file", value_hint = ValueHint::FilePath)]
+    pub file: PathBuf,
+    /// Report each deleted member.
+    #[arg(short = 'v', long = "verbose")]
+    pub verbose: bool,
+    /// Reduce output to errors only.
+    #[arg(long = "quiet")]
+    pub quiet: bool,
+    /// Member names or globs to delete (directories take their contents with them).
+    #[arg(value_name = "PATTERN", required = true)]
+    pub patterns: Vec<String>,
+}
+
+/// Arguments for the `concat` subcommand.
+#[derive(Debug, Args)]
+pub struct ConcatArgs {
+    /// Archive to write (`-` for stdout).
+    #[arg(short = 'f', long = "file", value_hint = ValueHint::FilePath)]
+    pub file: PathBuf,
+    /// Report each merged member.
+    #[arg(short = 'v', long = "verbose")]
+    pub verbose: bool,
+    /// Reduce output to errors only.
+    #[arg(long = "quiet")]
+    pub quiet: bool,
+    /// Enable gzip compression.
+    #[arg(short = 'z', long = "gzip")]
+    pub gzip: bool,
+    /// Enable xz compression.
+    #[arg(short = 'J', long = "xz")]
+    pub xz: bool,
+    /// Enable zstd compression.
+    #[arg(long = "zstd")]
+    pub zstd: bool,
+    /// Keep members whose path is already stored by an earlier member.
+    #[arg(long = "allow-duplicates")]
+    pub allow_duplicates: bool,
+    /// How to handle FIFO entries.
+    #[arg(long = "fifos", value_enum, default_value_t = SpecialAction::Reject)]
+    pub fifos: SpecialAction,
+    /// How to handle character and block device entries.
+    #[arg(long = "devices", value_enum, default_value_t = SpecialAction::Reject)]
+    pub devices: SpecialAction,
+    /// How to handle entry types safetar does not understand (`create` keeps them).
+    #[arg(long = "unknown-entries", value_enum, default_value_t = SpecialAction::Reject)]
+    pub unknown_entries: SpecialAction,
+    /// Accept member names that are not valid UTF-8.
+    #[arg(long = "byte-paths")]
+    pub byte_paths: bool,
+    /// Override resource limits (applied to all inputs together).
+    #[command(flatten)]
+    pub limits: LimitArgs,
+    /// Archives to merge, in order (`-` for stdin).
+    #[arg(value_name = "ARCHIVE", required = true, value_hint = ValueHint::FilePath)]
+    pub inputs: Vec<PathBuf>,
+}
+
+impl ConcatArgs {
+    /// Collect compression flags into a helper struct.
+    #[must_use]
+    pub fn compression_flags(&self) -> CompressionFlags {
+        CompressionFlags {
+            gzip: self.gzip,
+            xz: self.xz,
+            zstd: self.zstd,
+        }
+    }
+}
+
+/// Arguments for the `diff` subcommand.
+#[derive(Debug, Args)]
+pub struct DiffArgs {
+    /// Archive to compare (`-` for stdin).
+    #[arg(short = 'f', long = "file", value_hint = ValueHint::FilePath)]
+    pub file: PathBuf,
+    /// Directory to compare against (default: current directory).
+    #[arg(short = 'C', long = "directory", value_hint = ValueHint::DirPath)]
+    pub directory: Option<PathBuf>,
+    /// Also report files on disk that are not in the archive.
+    #[arg(long = "extra")]
+    pub extra: bool,
+    /// Emit the differences as JSON.
+    #[arg(long = "json")]
+    pub json: bool,
+    /// Print nothing; only the exit status reports differences.
+    #[arg(long = "quiet")]
+    pub quiet: bool,
+    /// Accept member names that are not valid UTF-8.
+    #[arg(long = "byte-paths")]
+    pub byte_paths: bool,
+}
+
+/// Arguments for the `cat` subcommand.
+#[derive(Debug, Args)]
+pub struct CatArgs {
+    /// Archive to read (`-` for stdin).
+    #[arg(short = 'f', long = "file", value_hint = ValueHint::FilePath)]
+    pub file: PathBuf,
+    /// Manifest each member must match before it is written.
+    #[arg(long = "manifest", value_hint = ValueHint::FilePath)]
+    pub manifest: Option<Utf8PathBuf>,
+    /// Find members at the offsets recorded by `safetar index`.
+    #[arg(long = "index", value_hint = ValueHint::FilePath)]
+    pub index: Option<PathBuf>,
+    /// Accept member names that are not valid UTF-8.
+    #[arg(long = "byte-paths")]
+    pub byte_paths: bool,
+    /// Override resource limits.
+    #[command(flatten)]
+    pub limits: LimitArgs,
+    /// Members to write, in archive order (links inside the archive are followed).
+    #[arg(value_name = "MEMBER", required = true)]
+    pub members: Vec<String>,
+}
+
+/// Arguments for the `audit` subcommand.
+#[derive(Debug, Args)]
+pub struct AuditArgs {
+    /// Archive to check (`-` for stdin).
+    #[arg(short = 'f', long = "file", value_hint = ValueHint::FilePath)]
+    pub file: PathBuf,
+    /// Emit the findings as JSON.
+    #[arg(long = "json")]
+    pub json: bool,
+    /// Print nothing; only the exit status reports violations.
+    #[arg(long = "quiet")]
+    pub quiet: bool,
+    /// How extraction would handle FIFO entries.
+    #[arg(long = "fifos", value_enum, default_value_t = SpecialAction::Reject)]
+    pub fifos: SpecialAction,
+    /// How extraction would handle character and block device entries.
+    #[arg(long = "devices", value_enum, default_value_t = SpecialAction::Reject)]
+    pub devices: SpecialAction,
+    /// How extraction would handle entry types safetar does not understand.
+    #[arg(long = "unknown-entries", value_enum, default_value_t = SpecialAction::Reject)]
+    pub unknown_entries: SpecialAction,
+    /// Permission rules extraction would apply (`reject` turns bits into findings).
+    #[command(flatten)]
+    pub modes: ModeArgs,
+    /// Accept member names that are not valid UTF-8.
+    #[arg(long = "byte-paths")]
+    pub byte_paths: bool,
+    /// Override resource limits.
+    #[command(flatten)]
+    pub limits: LimitArgs,
+}
+
+/// Arguments for the `index` subcommand.
+#[derive(Debug, Args)]
+pub struct IndexArgs {
+    /// Uncompressed or seekable zstd archive to index.
+    #[arg(short = 'f', long = "file", value_hint = ValueHint::FilePath)]
+    pub file: PathBuf,
+    /// Index file to write (default: the archive path with `.idx` appended).
+    #[arg(short = 'o', long = "output", value_hint = ValueHint::FilePath)]
+    pub output: Option<PathBuf>,
+    /// Report the number of indexed members.
+    #[arg(short = 'v', long = "verbose")]
+    pub verbose: bool,
+}
+
 /// Arguments for the `extract` subcommand.
 #[derive(Debug, Args)]
 pub struct ExtractArgs {
-    /// Archive to extract.
+    /// Archive to extract (`-` for stdin).
     #[arg(short = 'f', long = "file", value_hint = ValueHint::FilePath)]
-    pub file: Utf8PathBuf,
+    pub file: PathBuf,
     /// Destination directory (default: current directory).
     #[arg(short = 'C', long = "directory", value_hint = ValueHint::DirPath)]
-    pub directory: Option<Utf8PathBuf>,
+    pub directory: Option<PathBuf>,
     /// Emit verbose progress.
     #[arg(short = 'v', long = "verbose")]
     pub verbose: bool,
@@ -172,23 +632,70 @@ pub struct ExtractArgs {
     /// Allow additional files when verifying manifests.
     #[arg(long = "manifest-relaxed")]
     pub manifest_relaxed: bool,
-    /// Assume numeric owner values from the archive.
+    /// Restore owners by id, ignoring the user and group names in the archive.
     #[arg(long = "numeric-owner")]
     pub numeric_owner: bool,
-    /// Do not attempt to restore owners.
+    /// Do not restore owners (they are restored by default when running as root).
     #[arg(long = "no-same-owner")]
     pub no_same_owner: bool,
+    /// Translate owners before restoring them.
+    #[command(flatten)]
+    pub owners: OwnerArgs,
+    /// Do not restore modification times; extracted members get the current time.
+    #[arg(short = 'm', long = "touch")]
+    pub touch: bool,
+    /// Which extended attributes to restore.
+    #[command(flatten)]
+    pub xattrs: XattrArgs,
+    /// What to do with files that already exist.
+    #[command(flatten)]
+    pub overwrite: OverwriteArgs,
+    /// Rename files before replacing them instead of removing them.
+    #[arg(long = "backup", value_enum, value_name = "CONTROL")]
+    pub backup: Option<BackupControl>,
+    /// Create symlinks after every other member, instead of rejecting members below
+    /// a symlink extracted earlier.
+    #[arg(long = "delay-symlinks")]
+    pub delay_symlinks: bool,
+    /// Extract into a staging directory and move everything into place only once
+    /// every check has passed; nothing is left behind on failure.
+    #[arg(long = "atomic")]
+    pub atomic: bool,
+    /// How to handle FIFO entries.
+    #[arg(long = "fifos", value_enum, default_value_t = SpecialAction::Reject)]
+    pub fifos: SpecialAction,
+    /// How to handle character and block device entries.
+    #[arg(long = "devices", value_enum, default_value_t = SpecialAction::Reject)]
+    pub devices: SpecialAction,
+    /// How to handle entry types safetar does not understand (`create` is treated as `skip`).
+    #[arg(long = "unknown-entries", value_enum, default_value_t = SpecialAction::Reject)]
+    pub unknown_entries: SpecialAction,
+    /// Permissions given to extracted members.
+    #[command(flatten)]
+    pub modes: ModeArgs,
+    /// Accept member names that are not valid UTF-8 and extract them byte for byte.
+    #[arg(long = "byte-paths")]
+    pub byte_paths: bool,
+    /// Read selected members at the offsets recorded by `safetar index`.
+    #[arg(long = "index", value_hint = ValueHint::FilePath)]
+    pub index: Option<PathBuf>,
     /// Override resource limits.
     #[command(flatten)]
     pub limits: LimitArgs,
+    /// Rewrite member names before extracting them.
+    #[command(flatten)]
+    pub rewrite: RewriteArgs,
+    /// Members to extract.
+    #[command(flatten)]
+    pub selection: SelectionArgs,
 }
 
 /// Arguments for the `list` subcommand.
 #[derive(Debug, Args)]
 pub struct ListArgs {
-    /// Archive to inspect.
+    /// Archive to inspect (`-` for stdin).
     #[arg(short = 'f', long = "file", value_hint = ValueHint::FilePath)]
-    pub file: Utf8PathBuf,
+    pub file: PathBuf,
     /// Emit verbose metadata.
     #[arg(short = 'v', long = "verbose")]
     pub verbose: bool,
@@ -198,4 +705,10 @@ pub struct ListArgs {
     /// Emit machine-readable JSON.
     #[arg(long = "json")]
     pub json: bool,
+    /// Members to list.
+    #[command(flatten)]
+    pub selection: SelectionArgs,
+    /// Override resource limits (only the compression limits apply).
+    #[command(flatten)]
+    pub limits: LimitArgs,
 }
@@ -1,25 +1,33 @@
 //! Compression-aware reader helpers.
 
-use std::io::{self, BufRead, BufReader, Read};
+use std::io::{self, BufReader, Read};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 
 use flate2::read::MultiGzDecoder;
 use xz2::read::XzDecoder;
 use zstd::stream::read::Decoder as ZstdDecoder;
 
 use super::Compression;
+use crate::policy::{PolicyError, RATIO_GRACE_BYTES};
+
+/// Bytes needed to recognise every supported codec.
+const MAGIC_LEN: u64 = 8;
 
 /// Wrap a reader with auto-detected compression.
+///
+/// The magic bytes are read with as many reads as it takes, so detection also works
+/// on pipes and sockets that deliver the first bytes in small pieces.
 pub fn wrap_reader<R>(reader: R) -> io::Result<CompressionReader>
 where
     R: Read + Send + 'static,
 {
-    let mut buf = BufReader::new(reader);
-    let header = {
-        let available = buf.fill_buf()?;
-        let len = available.len().min(8);
-        available[..len].to_vec()
-    };
+    let budget = InputBudget::default();
+    let mut reader = budget.counted(reader);
+    let mut header = Vec::with_capacity(MAGIC_LEN as usize);
+    (&mut reader).take(MAGIC_LEN).read_to_end(&mut header)?;
     let codec = Compression::detect(&header);
+    let buf = BufReader::new(io::Cursor::new(header).chain(reader));
     let inner: Box<dyn Read + Send> = match codec {
         Compression::None => Box::new(buf),
         Compression::Gzip => Box::new(MultiGzDecoder::new(buf)),
@@ -29,13 +37,118 @@ where
             Box::new(decoder)
         }
     };
-    Ok(CompressionReader { codec, inner })
+    Ok(CompressionReader {
+        codec,
+        inner: budget.limited(inner),
+    })
+}
+
+/// Compression limits over everything read from one archive.
+///
+/// Clones share the byte counts, so an archive read in several pieces, such as the
+/// members of an indexed archive, is held to the same limits as one read at once.
+/// The default budget is unlimited.
+#[derive(Debug, Clone)]
+pub struct InputBudget {
+    consumed: Arc<AtomicU64>,
+    decoded: Arc<AtomicU64>,
+    max_ratio: u64,
+    max_input: u64,
+}
+
+impl Default for InputBudget {
+    fn default() -> Self {
+        Self::new(u64::MAX, u64::MAX)
+    }
+}
+
+impl InputBudget {
+    /// Fail reads once more than `max_input` bytes have been read from the archive as
+    /// stored, or once more than `max_ratio` bytes have been decoded per byte read.
+    ///
+    /// The errors carry a [`PolicyError`]; the ratio is only checked past
+    /// [`RATIO_GRACE_BYTES`] of decoded output.
+    #[must_use]
+    pub fn new(max_ratio: u64, max_input: u64) -> Self {
+        Self {
+            consumed: Arc::new(AtomicU64::new(0)),
+            decoded: Arc::new(AtomicU64::new(0)),
+            max_ratio,
+            max_input,
+        }
+    }
+
+    /// Count the bytes read from `raw`, the archive as stored.
+    pub fn counted<R: Read>(&self, raw: R) -> Counted<R> {
+        Counted {
+            inner: raw,
+            consumed: Arc::clone(&self.consumed),
+        }
+    }
+
+    /// Count the bytes read from `decoded`, failing reads past the limits.
+    pub fn limited<R: Read>(&self, decoded: R) -> Limited<R> {
+        Limited {
+            inner: decoded,
+            budget: self.clone(),
+        }
+    }
+
+    fn check(&self) -> Result<(), PolicyError> {
+        let consumed = self.consumed.load(Ordering::Relaxed);
+        let decoded = self.decoded.load(Ordering::Relaxed);
+        if consumed > self.max_input {
+            return Err(PolicyError::CompressedInputExceeded {
+                limit: self.max_input,
+                actual: consumed,
+            });
+        }
+        if decoded > RATIO_GRACE_BYTES && decoded > consumed.saturating_mul(self.max_ratio) {
+            return Err(PolicyError::CompressionRatioExceeded {
+                limit: self.max_ratio,
+                compressed: consumed,
+                decoded,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Reader that counts the bytes taken from the reader it wraps.
+pub struct Counted<R> {
+    inner: R,
+    consumed: Arc<AtomicU64>,
+}
+
+impl<R: Read> Read for Counted<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.consumed.fetch_add(read as u64, Ordering::Relaxed);
+        Ok(read)
+    }
+}
+
+/// Reader of decoded bytes that checks an [`InputBudget`] after every read.
+pub struct Limited<R> {
+    inner: R,
+    budget: InputBudget,
+}
+
+impl<R: Read> Read for Limited<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.budget
+            .decoded
+            .fetch_add(read as u64, Ordering::Relaxed);
+        self.budget.check().map_err(io::Error::other)?;
+        Ok(read)
+    }
 }
 
 /// Reader that decodes according to a detected compression codec.
 pub struct CompressionReader {
     codec: Compression,
-    inner: Box<dyn Read + Send>,
+    inner: Limited<Box<dyn Read + Send>>,
 }
 
 impl CompressionReader {
@@ -44,10 +157,92 @@ impl CompressionReader {
     pub fn codec(&self) -> Compression {
         self.codec
     }
+
+    /// Hold the reader to the limits of [`InputBudget::new`].
+    #[must_use]
+    pub fn with_limits(mut self, max_ratio: u64, max_input: u64) -> Self {
+        self.inner.budget.max_ratio = max_ratio;
+        self.inner.budget.max_input = max_input;
+        self
+    }
 }
 
 impl Read for CompressionReader {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        self.inner.as_mut().read(buf)
+        self.inner.read(buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    /// Reader that hands out one byte per call, like a slow pipe.
+    struct Trickle(io::Cursor<Vec<u8>>);
+
+    impl Read for Trickle {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let len = buf.len().min(1);
+            self.0.read(&mut buf[..len])
+        }
+    }
+
+    #[test]
+    fn detects_codec_from_short_reads() {
+        let mut encoder = xz2::write::XzEncoder::new(Vec::new(), 6);
+        encoder.write_all(b"payload").unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let mut reader = wrap_reader(Trickle(io::Cursor::new(compressed))).unwrap();
+        assert_eq!(reader.codec(), Compression::Xz);
+        let mut decoded = Vec::new();
+        reader.read_to_end(&mut decoded).unwrap();
+        assert_eq!(decoded, b"payload");
+
+        let mut reader = wrap_reader(Trickle(io::Cursor::new(b"abc".to_vec()))).unwrap();
+        assert_eq!(reader.codec(), Compression::None);
+        let mut plain = Vec::new();
+        reader.read_to_end(&mut plain).unwrap();
+        assert_eq!(plain, b"abc");
+    }
+
+    #[test]
+    fn limits_stop_decoding_bombs() {
+        let zeros = vec![0_u8; 8 << 20];
+        let compressed = zstd::encode_all(&zeros[..], 19).unwrap();
+        let decode = |max_ratio, max_input| {
+            let reader = wrap_reader(io::Cursor::new(compressed.clone())).unwrap();
+            let mut decoded = Vec::new();
+            reader
+                .with_limits(max_ratio, max_input)
+                .read_to_end(&mut decoded)
+                .map(|_| decoded.len())
+        };
+        assert_eq!(decode(u64::MAX, u64::MAX).unwrap(), zeros.len());
+
+        let err = decode(100, u64::MAX).expect_err("ratio is far above 100");
+        let violation = err
+            .get_ref()
+            .and_then(|err| err.downcast_ref::<PolicyError>());
+        assert!(
+            matches!(
+                violation,
+                Some(PolicyError::CompressionRatioExceeded { limit: 100, .. })
+            ),
+            "{err}"
+        );
+        let err = decode(u64::MAX, 16).expect_err("input is over 16 bytes");
+        let violation = err
+            .get_ref()
+            .and_then(|err| err.downcast_ref::<PolicyError>());
+        assert!(
+            matches!(
+                violation,
+                Some(PolicyError::CompressedInputExceeded { limit: 16, .. })
+            ),
+            "{err}"
+        );
     }
 }
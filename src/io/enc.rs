@@ -7,6 +7,7 @@ use flate2::Compression as GzipLevel;
 use xz2::write::XzEncoder;
 use zstd::stream::write::Encoder as ZstdEncoder;
 
+use super::seekable::SeekableEncoder;
 use super::Compression;
 
 /// Wrap a writer with the requested compression codec.
@@ -29,6 +30,18 @@ where
     Ok(CompressionWriter { codec, inner })
 }
 
+/// Wrap a writer with zstd in the seekable format, so the archive can be indexed.
+pub fn wrap_seekable_writer<W>(writer: W) -> io::Result<CompressionWriter<W>>
+where
+    W: Write,
+{
+    let encoder = SeekableEncoder::new(BufWriter::new(writer), 3)?;
+    Ok(CompressionWriter {
+        codec: Compression::Zstd,
+        inner: CompressionWriterInner::SeekableZstd(encoder),
+    })
+}
+
 /// Writer with codec-aware finalisation.
 pub struct CompressionWriter<W: Write> {
     codec: Compression,
@@ -52,6 +65,10 @@ impl<W: Write> CompressionWriter<W> {
                 let mut writer = inner.finish().map_err(io::Error::other)?;
                 writer.flush()
             }
+            CompressionWriterInner::SeekableZstd(inner) => {
+                let mut writer = inner.finish()?;
+                writer.flush()
+            }
         }
     }
 
@@ -69,6 +86,7 @@ impl<W: Write> Write for CompressionWriter<W> {
             CompressionWriterInner::Gzip(inner) => inner.write(buf),
             CompressionWriterInner::Xz(inner) => inner.write(buf),
             CompressionWriterInner::Zstd(inner) => inner.write(buf),
+            CompressionWriterInner::SeekableZstd(inner) => inner.write(buf),
         }
     }
 
@@ -78,6 +96,7 @@ impl<W: Write> Write for CompressionWriter<W> {
             CompressionWriterInner::Gzip(inner) => inner.flush(),
             CompressionWriterInner::Xz(inner) => inner.flush(),
             CompressionWriterInner::Zstd(inner) => inner.flush(),
+            CompressionWriterInner::SeekableZstd(inner) => inner.flush(),
         }
     }
 }
@@ -87,4 +106,5 @@ enum CompressionWriterInner<W: Write> {
     Gzip(GzEncoder<BufWriter<W>>),
     Xz(XzEncoder<BufWriter<W>>),
     Zstd(ZstdEncoder<'static, BufWriter<W>>),
+    SeekableZstd(SeekableEncoder<BufWriter<W>>),
 }
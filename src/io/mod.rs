@@ -4,6 +4,7 @@ use std::fmt;
 
 pub mod dec;
 pub mod enc;
+pub mod seekable;
 
 /// Compression codecs supported by safetar.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
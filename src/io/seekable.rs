@@ -0,0 +1,241 @@
+//! The zstd seekable format: independent frames followed by a seek table.
+//!
+//! Ordinary zstd decoders read such a file as one stream and skip the seek table,
+//! which lives in a skippable frame. Readers that know the format use the table to
+//! start decoding at the frame that holds a given uncompressed offset.
+
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+
+use zstd::bulk::Compressor;
+use zstd::stream::read::Decoder as ZstdDecoder;
+
+use super::dec::InputBudget;
+
+/// Uncompressed bytes per frame; seeking decodes at most this much to reach an offset.
+pub const FRAME_SIZE: usize = 1 << 20;
+const SKIPPABLE_MAGIC: u32 = 0x184D_2A5E;
+const SEEKABLE_MAGIC: u32 = 0x8F92_EAB1;
+/// Number of frames, descriptor byte, and seekable magic.
+const FOOTER_LEN: u64 = 9;
+/// Descriptor bit announcing a checksum after each table entry.
+const CHECKSUM_FLAG: u8 = 0x80;
+
+/// Encoder writing one zstd frame per [`FRAME_SIZE`] bytes and a seek table on finish.
+pub struct SeekableEncoder<W: Write> {
+    inner: W,
+    compressor: Compressor<'static>,
+    pending: Vec<u8>,
+    /// Compressed and uncompressed size of every frame written so far.
+    frames: Vec<(u32, u32)>,
+}
+
+impl<W: Write> SeekableEncoder<W> {
+    pub fn new(inner: W, level: i32) -> io::Result<Self> {
+        Ok(Self {
+            inner,
+            compressor: Compressor::new(level)?,
+            pending: Vec::with_capacity(FRAME_SIZE),
+            frames: Vec::new(),
+        })
+    }
+
+    /// Write the last frame and the seek table, returning the inner writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.flush_frame()?;
+        let entries = u32::try_from(self.frames.len())
+            .map_err(|_| io::Error::other("too many frames for a seek table"))?;
+        let mut table = Vec::with_capacity(self.frames.len() * 8 + 17);
+        table.extend_from_slice(&SKIPPABLE_MAGIC.to_le_bytes());
+        let body_len = entries * 8 + FOOTER_LEN as u32;
+        table.extend_from_slice(&body_len.to_le_bytes());
+        for (compressed, decompressed) in &self.frames {
+            table.extend_from_slice(&compressed.to_le_bytes());
+            table.extend_from_slice(&decompressed.to_le_bytes());
+        }
+        table.extend_from_slice(&entries.to_le_bytes());
+        table.push(0);
+        table.extend_from_slice(&SEEKABLE_MAGIC.to_le_bytes());
+        self.inner.write_all(&table)?;
+        Ok(self.inner)
+    }
+
+    fn flush_frame(&mut self) -> io::Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let frame = self.compressor.compress(&self.pending)?;
+        self.inner.write_all(&frame)?;
+        let compressed = u32::try_from(frame.len())
+            .map_err(|_| io::Error::other("compressed frame exceeds 4 GiB"))?;
+        self.frames.push((compressed, self.pending.len() as u32));
+        self.pending.clear();
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for SeekableEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let take = buf.len().min(FRAME_SIZE - self.pending.len());
+        self.pending.extend_from_slice(&buf[..take]);
+        if self.pending.len() == FRAME_SIZE {
+            self.flush_frame()?;
+        }
+        Ok(take)
+    }
+
+    /// Frames are only cut at [`FRAME_SIZE`], so flushing passes through to the inner
+    /// writer without ending the current frame.
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Frame layout read from the seek table at the end of a seekable file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SeekTable {
+    frames: Vec<Frame>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Frame {
+    compressed_offset: u64,
+    decompressed_offset: u64,
+    decompressed_size: u64,
+}
+
+impl SeekTable {
+    /// Read the seek table of `file`, or `None` when the file does not end in one.
+    pub fn read<R: Read + Seek>(file: &mut R) -> io::Result<Option<Self>> {
+        let len = file.seek(SeekFrom::End(0))?;
+        if len < FOOTER_LEN + 8 {
+            return Ok(None);
+        }
+        let mut footer = [0_u8; FOOTER_LEN as usize];
+        file.seek(SeekFrom::Start(len - FOOTER_LEN))?;
+        file.read_exact(&mut footer)?;
+        if le_u32(&footer[5..9]) != SEEKABLE_MAGIC {
+            return Ok(None);
+        }
+        let count = u64::from(le_u32(&footer[0..4]));
+        let entry_len = if footer[4] & CHECKSUM_FLAG == 0 {
+            8
+        } else {
+            12
+        };
+        let body_len = count * entry_len + FOOTER_LEN;
+        let table_start = (len - 8)
+            .checked_sub(body_len)
+            .ok_or_else(|| invalid("seek table is larger than the file"))?;
+
+        let mut table = vec![0_u8; (body_len + 8) as usize];
+        file.seek(SeekFrom::Start(table_start))?;
+        file.read_exact(&mut table)?;
+        if le_u32(&table[0..4]) != SKIPPABLE_MAGIC || u64::from(le_u32(&table[4..8])) != body_len {
+            return Err(invalid("malformed seek table frame"));
+        }
+
+        let mut frames = Vec::with_capacity(count as usize);
+        let (mut compressed_offset, mut decompressed_offset) = (0_u64, 0_u64);
+        for entry in table[8..]
+            .chunks_exact(entry_len as usize)
+            .take(count as usize)
+        {
+            let compressed = u64::from(le_u32(&entry[0..4]));
+            let decompressed = u64::from(le_u32(&entry[4..8]));
+            frames.push(Frame {
+                compressed_offset,
+                decompressed_offset,
+                decompressed_size: decompressed,
+            });
+            compressed_offset += compressed;
+            decompressed_offset += decompressed;
+        }
+        if compressed_offset != table_start {
+            return Err(invalid("seek table does not describe the file"));
+        }
+        Ok(Some(Self { frames }))
+    }
+
+    /// Total uncompressed size.
+    #[must_use]
+    pub fn decompressed_len(&self) -> u64 {
+        self.frames.last().map_or(0, |frame| {
+            frame.decompressed_offset + frame.decompressed_size
+        })
+    }
+
+    /// Decode `file` starting at uncompressed `offset`, reading through the end.
+    ///
+    /// Everything read and decoded counts against `budget`, including the part of the
+    /// first frame before `offset`.
+    pub fn reader_at<R>(
+        &self,
+        mut file: R,
+        offset: u64,
+        budget: &InputBudget,
+    ) -> io::Result<impl Read>
+    where
+        R: Read + Seek,
+    {
+        let index = self
+            .frames
+            .partition_point(|frame| frame.decompressed_offset + frame.decompressed_size <= offset);
+        let frame = self
+            .frames
+            .get(index)
+            .ok_or_else(|| invalid("offset is past the end of the archive"))?;
+        file.seek(SeekFrom::Start(frame.compressed_offset))?;
+        let decoder = ZstdDecoder::with_buffer(BufReader::new(budget.counted(file)))?;
+        let mut decoder = budget.limited(decoder);
+        let skip = offset - frame.decompressed_offset;
+        let skipped = io::copy(&mut (&mut decoder).take(skip), &mut io::sink())?;
+        if skipped != skip {
+            return Err(invalid("archive ends before the requested offset"));
+        }
+        Ok(decoder)
+    }
+}
+
+fn le_u32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes(bytes.try_into().expect("four bytes"))
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn seeks_into_later_frames() {
+        let data: Vec<u8> = (0..FRAME_SIZE * 2 + 100).map(|i| (i % 251) as u8).collect();
+        let mut encoder = SeekableEncoder::new(Vec::new(), 3).unwrap();
+        encoder.write_all(&data).unwrap();
+        let file = encoder.finish().unwrap();
+
+        // Plain decoders see one stream and skip the seek table.
+        assert_eq!(zstd::decode_all(file.as_slice()).unwrap(), data);
+
+        let mut cursor = Cursor::new(file);
+        let table = SeekTable::read(&mut cursor).unwrap().unwrap();
+        assert_eq!(table.decompressed_len(), data.len() as u64);
+        let offset = FRAME_SIZE + 12_345;
+        let mut tail = Vec::new();
+        table
+            .reader_at(cursor, offset as u64, &InputBudget::default())
+            .unwrap()
+            .read_to_end(&mut tail)
+            .unwrap();
+        assert_eq!(tail, &data[offset..]);
+    }
+
+    #[test]
+    fn plain_zstd_has_no_table() {
+        let file = zstd::encode_all(&b"not seekable"[..], 3).unwrap();
+        assert_eq!(SeekTable::read(&mut Cursor::new(file)).unwrap(), None);
+    }
+}
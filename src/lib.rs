@@ -5,17 +5,33 @@ pub mod cli;
 pub mod error;
 pub mod io;
 pub mod manifest;
+pub mod names;
 pub mod policy;
 
+use std::path::{Path, PathBuf};
+
 use anyhow::{anyhow, Result};
 use camino::Utf8PathBuf;
 
 use crate::archive::{
-    create_archive, extract_archive, list_archive, CreateOptions, ExtractOptions, ListOptions,
+    append_archive, audit_archive, cat_members, concat_archives, create_archive, delete_members,
+    diff_archive, extract_archive, index_archive, is_stdio, list_archive, report, AppendMode,
+    ArchiveFormat, AuditOptions, BackupMode, CatOptions, ConcatOptions, CreateOptions,
+    DeleteOptions, DiffOptions, ExtractOptions, IndexOptions, ListOptions, MemberSelection,
+    NameRewrite, OverwriteMode, OwnerMapping,
+};
+use crate::cli::{
+    AppendArgs, AuditArgs, BackupControl, CatArgs, Cli, Commands, ConcatArgs, CreateArgs,
+    DeleteArgs, DiffArgs, ExtractArgs, IndexArgs, LimitArgs, ListArgs, ModeArgs, OverwriteArgs,
+    OwnerArgs, PermissionAction, RewriteArgs, SelectionArgs, SpecialAction, XattrArgs,
 };
-use crate::cli::{Cli, Commands, CreateArgs, ExtractArgs, ListArgs};
+use crate::error::UserInputError;
 use crate::io::Compression;
-use crate::policy::SecurityPolicy;
+use crate::manifest::ExtractOutcome;
+use crate::policy::{
+    ModeAction, ModeRules, PolicyError, SecurityPolicy, SpecialEntryAction, SpecialEntryKind,
+    XattrRules,
+};
 
 /// Execute the command represented by the parsed CLI input.
 pub fn run(cli: Cli) -> Result<()> {
@@ -23,29 +39,36 @@ pub fn run(cli: Cli) -> Result<()> {
         Commands::Create(args) => handle_create(args),
         Commands::Extract(args) => handle_extract(args),
         Commands::List(args) => handle_list(args),
+        Commands::Append(args) => handle_append(args, AppendMode::Append),
+        Commands::Update(args) => handle_append(args, AppendMode::Update),
+        Commands::Delete(args) => handle_delete(args),
+        Commands::Concat(args) => handle_concat(args),
+        Commands::Diff(args) => handle_diff(args),
+        Commands::Index(args) => handle_index(args),
+        Commands::Cat(args) => handle_cat(args),
+        Commands::Audit(args) => handle_audit(args),
     }
 }
 
 fn handle_create(args: CreateArgs) -> Result<()> {
     let compression = choose_compression(args.compression_flags());
-    let policy = base_policy(
-        args.limits.max_files,
-        args.limits.max_total_bytes,
-        args.limits.max_single_file,
-        args.limits.max_depth,
-    );
-    let exclude_from = args
-        .exclude_from
-        .into_iter()
-        .map(|path| {
-            Utf8PathBuf::from_path_buf(path).map_err(|_| anyhow!("exclude path must be UTF-8"))
-        })
+    let policy = base_policy(&args.limits).with_byte_paths(args.byte_paths);
+    let exclude_from = utf8_exclude_files(args.exclude_from)?;
+    let inputs = args
+        .inputs
+        .iter()
+        .map(|input| encode_path(input, args.byte_paths))
         .collect::<Result<Vec<_>>>()?;
     let options = CreateOptions {
-        archive_path: args.file,
-        inputs: args.inputs,
-        work_dir: args.directory,
+        archive_path: encode_path(&args.file, args.byte_paths)?,
+        inputs,
+        work_dir: args
+            .directory
+            .as_deref()
+            .map(|dir| encode_path(dir, args.byte_paths))
+            .transpose()?,
         compression,
+        format: choose_format(args.format),
         verbose: args.verbose,
         quiet: args.quiet,
         print_plan: args.print_plan,
@@ -54,8 +77,64 @@ fn handle_create(args: CreateArgs) -> Result<()> {
         manifest_out: args.manifest_out,
         numeric_owner: args.numeric_owner,
         no_same_owner: args.no_same_owner,
+        owners: owner_mapping(args.owners),
+        include_special: args.include_special,
+        sparse: args.sparse,
+        rewrite: name_rewrite(args.rewrite),
+        seekable: args.seekable,
+        preserve_times: args.preserve_times,
+        xattrs: args.xattrs,
+        acls: args.acls,
     };
     let manifest = create_archive(&options, &policy)?;
+    if options.verbose && !options.quiet {
+        let to_stdout = is_stdio(&options.archive_path);
+        for entry in manifest {
+            report(
+                to_stdout,
+                format_args!("added {} ({} bytes)", entry.path, entry.size),
+            );
+        }
+    }
+    Ok(())
+}
+
+fn handle_append(args: AppendArgs, mode: AppendMode) -> Result<()> {
+    let policy = base_policy(&args.limits).with_byte_paths(args.byte_paths);
+    let exclude_from = utf8_exclude_files(args.exclude_from)?;
+    let inputs = args
+        .inputs
+        .iter()
+        .map(|input| encode_path(input, args.byte_paths))
+        .collect::<Result<Vec<_>>>()?;
+    let options = CreateOptions {
+        archive_path: encode_path(&args.file, args.byte_paths)?,
+        inputs,
+        work_dir: args
+            .directory
+            .as_deref()
+            .map(|dir| encode_path(dir, args.byte_paths))
+            .transpose()?,
+        compression: Compression::None,
+        format: choose_format(args.format),
+        verbose: args.verbose,
+        quiet: args.quiet,
+        print_plan: args.print_plan,
+        excludes: args.exclude,
+        exclude_from,
+        manifest_out: args.manifest_out,
+        numeric_owner: args.numeric_owner,
+        no_same_owner: args.no_same_owner,
+        owners: owner_mapping(args.owners),
+        include_special: args.include_special,
+        sparse: args.sparse,
+        rewrite: name_rewrite(args.rewrite),
+        seekable: false,
+        preserve_times: args.preserve_times,
+        xattrs: args.xattrs,
+        acls: args.acls,
+    };
+    let manifest = append_archive(&options, mode, &policy)?;
     if options.verbose && !options.quiet {
         for entry in manifest {
             println!("added {} ({} bytes)", entry.path, entry.size);
@@ -64,16 +143,157 @@ fn handle_create(args: CreateArgs) -> Result<()> {
     Ok(())
 }
 
+fn handle_delete(args: DeleteArgs) -> Result<()> {
+    let options = DeleteOptions {
+        archive_path: encode_path(&args.file, true)?,
+        patterns: args.patterns,
+        verbose: args.verbose,
+        quiet: args.quiet,
+    };
+    let removed = delete_members(&options)?;
+    if options.verbose && !options.quiet {
+        report(
+            is_stdio(&options.archive_path),
+            format_args!("deleted {} members", removed.len()),
+        );
+    }
+    Ok(())
+}
+
+fn handle_concat(args: ConcatArgs) -> Result<()> {
+    let compression = choose_compression(args.compression_flags());
+    let policy = with_special_actions(
+        base_policy(&args.limits),
+        args.fifos,
+        args.devices,
+        args.unknown_entries,
+    )
+    .with_byte_paths(args.byte_paths);
+    let inputs = args
+        .inputs
+        .iter()
+        .map(|input| encode_path(input, true))
+        .collect::<Result<Vec<_>>>()?;
+    let options = ConcatOptions {
+        archive_path: encode_path(&args.file, true)?,
+        inputs,
+        compression,
+        allow_duplicates: args.allow_duplicates,
+        verbose: args.verbose,
+        quiet: args.quiet,
+    };
+    let merged = concat_archives(&options, &policy)?;
+    if options.verbose && !options.quiet {
+        report(
+            is_stdio(&options.archive_path),
+            format_args!("merged {} members", merged.len()),
+        );
+    }
+    Ok(())
+}
+
+fn handle_diff(args: DiffArgs) -> Result<()> {
+    let policy = SecurityPolicy::new().with_byte_paths(args.byte_paths);
+    let directory = match &args.directory {
+        Some(dir) => encode_path(dir, args.byte_paths)?,
+        None => Utf8PathBuf::from("."),
+    };
+    let options = DiffOptions {
+        archive_path: encode_path(&args.file, true)?,
+        directory,
+        report_extra: args.extra,
+        quiet: args.quiet,
+        json: args.json,
+    };
+    let differences = diff_archive(&options, &policy)?;
+    if !differences.is_empty() {
+        // Like `tar --diff`, differences are reported through the exit status.
+        return Err(anyhow!(
+            "archive differs from {} ({} differences)",
+            options.directory,
+            differences.len()
+        ));
+    }
+    Ok(())
+}
+
+fn handle_audit(args: AuditArgs) -> Result<()> {
+    let policy = with_special_actions(
+        base_policy(&args.limits),
+        args.fifos,
+        args.devices,
+        args.unknown_entries,
+    )
+    .with_byte_paths(args.byte_paths)
+    .with_mode_rules(mode_rules(&args.modes));
+    let options = AuditOptions {
+        archive_path: encode_path(&args.file, true)?,
+        quiet: args.quiet,
+        json: args.json,
+    };
+    let findings = audit_archive(&options, &policy)?;
+    if !findings.is_empty() {
+        return Err(PolicyError::Violations {
+            count: findings.len(),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+fn handle_cat(args: CatArgs) -> Result<()> {
+    let policy = base_policy(&args.limits).with_byte_paths(args.byte_paths);
+    let options = CatOptions {
+        archive_path: encode_path(&args.file, true)?,
+        members: args.members,
+        manifest: args.manifest,
+        index: args
+            .index
+            .as_deref()
+            .map(|index| encode_path(index, true))
+            .transpose()?,
+    };
+    cat_members(&options, &policy, std::io::stdout().lock())?;
+    Ok(())
+}
+
+fn handle_index(args: IndexArgs) -> Result<()> {
+    let archive_path = encode_path(&args.file, true)?;
+    let output = match &args.output {
+        Some(output) => encode_path(output, true)?,
+        None => Utf8PathBuf::from(format!("{archive_path}.idx")),
+    };
+    let options = IndexOptions {
+        archive_path,
+        output,
+    };
+    let index = index_archive(&options)?;
+    if args.verbose {
+        println!(
+            "indexed {} members into {}",
+            index.members.len(),
+            options.output
+        );
+    }
+    Ok(())
+}
+
 fn handle_extract(args: ExtractArgs) -> Result<()> {
-    let policy = base_policy(
-        args.limits.max_files,
-        args.limits.max_total_bytes,
-        args.limits.max_single_file,
-        args.limits.max_depth,
-    );
-    let dest = args.directory.unwrap_or_else(|| Utf8PathBuf::from("."));
+    let policy = with_special_actions(
+        base_policy(&args.limits),
+        args.fifos,
+        args.devices,
+        args.unknown_entries,
+    )
+    .with_byte_paths(args.byte_paths)
+    .with_mode_rules(mode_rules(&args.modes))
+    .with_xattr_rules(xattr_rules(&args.xattrs));
+    let dest = match &args.directory {
+        Some(dir) => encode_path(dir, args.byte_paths)?,
+        None => Utf8PathBuf::from("."),
+    };
     let options = ExtractOptions {
-        archive_path: args.file,
+        archive_path: encode_path(&args.file, args.byte_paths)?,
         destination: dest,
         verbose: args.verbose,
         quiet: args.quiet,
@@ -82,11 +302,42 @@ fn handle_extract(args: ExtractArgs) -> Result<()> {
         manifest_relaxed: args.manifest_relaxed,
         numeric_owner: args.numeric_owner,
         no_same_owner: args.no_same_owner,
+        owners: owner_mapping(args.owners),
+        selection: member_selection(args.selection, args.byte_paths)?,
+        rewrite: name_rewrite(args.rewrite),
+        index: args
+            .index
+            .as_deref()
+            .map(|index| encode_path(index, true))
+            .transpose()?,
+        touch: args.touch,
+        xattrs: args.xattrs.xattrs,
+        acls: args.xattrs.acls,
+        overwrite: overwrite_mode(&args.overwrite),
+        backup: args.backup.map(|backup| match backup {
+            BackupControl::Simple => BackupMode::Simple,
+            BackupControl::Numbered => BackupMode::Numbered,
+        }),
+        delay_symlinks: args.delay_symlinks,
+        atomic: args.atomic,
     };
     let manifest = extract_archive(&options, &policy)?;
     if options.verbose && !options.quiet {
         for entry in manifest {
-            println!("extracted {} ({} bytes)", entry.path, entry.size);
+            let outcome = match entry.outcome {
+                Some(ExtractOutcome::Skipped) => "kept existing",
+                Some(ExtractOutcome::Replaced) => "replaced",
+                Some(ExtractOutcome::Overwritten) => "overwrote",
+                Some(ExtractOutcome::Merged) => "merged",
+                Some(ExtractOutcome::Created) | None => "extracted",
+            };
+            match &entry.backup {
+                Some(backup) => println!(
+                    "{outcome} {} ({} bytes, backup {backup})",
+                    entry.path, entry.size
+                ),
+                None => println!("{outcome} {} ({} bytes)", entry.path, entry.size),
+            }
         }
     }
     Ok(())
@@ -94,10 +345,12 @@ fn handle_extract(args: ExtractArgs) -> Result<()> {
 
 fn handle_list(args: ListArgs) -> Result<()> {
     let options = ListOptions {
-        archive_path: args.file,
+        archive_path: encode_path(&args.file, true)?,
         verbose: args.verbose,
         quiet: args.quiet,
         json: args.json,
+        selection: member_selection(args.selection, true)?,
+        limits: base_policy(&args.limits).limits(),
     };
     let manifest = list_archive(&options)?;
     if options.verbose && !options.json && !options.quiet {
@@ -106,6 +359,50 @@ fn handle_list(args: ListArgs) -> Result<()> {
     Ok(())
 }
 
+fn member_selection(args: SelectionArgs, byte_paths: bool) -> Result<MemberSelection> {
+    Ok(MemberSelection {
+        members: args.members,
+        files_from: args
+            .files_from
+            .iter()
+            .map(|path| encode_path(path, byte_paths))
+            .collect::<Result<Vec<_>>>()?,
+        wildcards: args.wildcards,
+        excludes: args.exclude,
+        exclude_from: utf8_exclude_files(args.exclude_from)?,
+    })
+}
+
+fn owner_mapping(args: OwnerArgs) -> OwnerMapping {
+    OwnerMapping {
+        owner_map: args.owner_map,
+        group_map: args.group_map,
+        uid_shift: args.uid_shift,
+        gid_shift: args.gid_shift.unwrap_or(args.uid_shift),
+    }
+}
+
+fn name_rewrite(args: RewriteArgs) -> NameRewrite {
+    NameRewrite {
+        strip_components: args.strip_components,
+        transforms: args.transform,
+    }
+}
+
+fn utf8_exclude_files(files: Vec<PathBuf>) -> Result<Vec<Utf8PathBuf>> {
+    files
+        .into_iter()
+        .map(|path| {
+            Utf8PathBuf::from_path_buf(path).map_err(|_| anyhow!("exclude path must be UTF-8"))
+        })
+        .collect()
+}
+
+/// Encode a command-line path, rejecting raw names unless `allow_raw` is set.
+fn encode_path(path: &Path, allow_raw: bool) -> Result<Utf8PathBuf> {
+    names::from_path(path, allow_raw).map_err(|err| UserInputError::new(err.to_string()).into())
+}
+
 fn choose_compression(flags: crate::cli::CompressionFlags) -> Compression {
     match flags.resolve() {
         crate::cli::CompressionChoice::None => Compression::None,
@@ -116,15 +413,86 @@ fn choose_compression(flags: crate::cli::CompressionFlags) -> Compression {
     }
 }
 
-fn base_policy(
-    max_files: Option<u64>,
-    max_total_bytes: Option<u64>,
-    max_single_file: Option<u64>,
-    max_depth: Option<u32>,
+fn choose_format(choice: crate::cli::FormatChoice) -> ArchiveFormat {
+    match choice {
+        crate::cli::FormatChoice::Ustar => ArchiveFormat::Ustar,
+        crate::cli::FormatChoice::Gnu => ArchiveFormat::Gnu,
+        crate::cli::FormatChoice::Pax => ArchiveFormat::Pax,
+    }
+}
+
+fn choose_special_action(action: SpecialAction) -> SpecialEntryAction {
+    match action {
+        SpecialAction::Reject => SpecialEntryAction::Reject,
+        SpecialAction::Skip => SpecialEntryAction::Skip,
+        SpecialAction::Create => SpecialEntryAction::Create,
+    }
+}
+
+fn with_special_actions(
+    policy: SecurityPolicy,
+    fifos: SpecialAction,
+    devices: SpecialAction,
+    unknown_entries: SpecialAction,
 ) -> SecurityPolicy {
+    let devices = choose_special_action(devices);
+    policy
+        .with_special_entry_action(SpecialEntryKind::Fifo, choose_special_action(fifos))
+        .with_special_entry_action(SpecialEntryKind::CharDevice, devices)
+        .with_special_entry_action(SpecialEntryKind::BlockDevice, devices)
+        .with_special_entry_action(
+            SpecialEntryKind::Unknown,
+            choose_special_action(unknown_entries),
+        )
+}
+
+fn mode_rules(args: &ModeArgs) -> ModeRules {
+    let action = |action| match action {
+        PermissionAction::Strip => ModeAction::Strip,
+        PermissionAction::Reject => ModeAction::Reject,
+        PermissionAction::Keep => ModeAction::Keep,
+    };
+    ModeRules {
+        setuid: action(args.setuid),
+        setgid: action(args.setgid),
+        sticky: action(args.sticky),
+        world_writable: action(args.world_writable),
+        umask: args.umask,
+    }
+}
+
+fn overwrite_mode(args: &OverwriteArgs) -> OverwriteMode {
+    if args.keep_old_files {
+        OverwriteMode::KeepOld
+    } else if args.skip_old_files {
+        OverwriteMode::SkipOld
+    } else if args.overwrite {
+        OverwriteMode::Overwrite
+    } else if args.unlink_first {
+        OverwriteMode::UnlinkFirst
+    } else if args.keep_newer_files {
+        OverwriteMode::KeepNewer
+    } else {
+        OverwriteMode::Replace
+    }
+}
+
+fn xattr_rules(args: &XattrArgs) -> XattrRules {
+    let mut rules = XattrRules::default();
+    if args.trust_xattrs {
+        rules.deny.clear();
+    }
+    rules.allow.extend(args.xattrs_include.iter().cloned());
+    rules.deny.extend(args.xattrs_exclude.iter().cloned());
+    rules
+}
+
+fn base_policy(limits: &LimitArgs) -> SecurityPolicy {
     SecurityPolicy::new()
-        .with_max_files(max_files)
-        .with_max_total_bytes(max_total_bytes)
-        .with_max_single_file(max_single_file)
-        .with_max_depth(max_depth)
+        .with_max_files(limits.max_files)
+        .with_max_total_bytes(limits.max_total_bytes)
+        .with_max_single_file(limits.max_single_file)
+        .with_max_depth(limits.max_depth)
+        .with_max_compression_ratio(limits.max_compression_ratio)
+        .with_max_compressed_input(limits.max_compressed_input)
 }
@@ -1,6 +1,6 @@
 //! Manifest collection and verification helpers.
 
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fs::File;
 use std::io::{BufReader, BufWriter, Read, Write};
 use std::time::SystemTime;
@@ -12,39 +12,138 @@ use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 use thiserror::Error;
 
+use crate::archive::pax::PaxMetadata;
+use crate::names;
+
 /// Entry describing a filesystem object stored in the manifest.
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct ManifestEntry {
+    /// Member path; bytes that are not valid UTF-8 appear as `\xHH`.
     pub path: String,
+    /// Exact path bytes (base64) when the path is not valid UTF-8.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path_b64: Option<String>,
     pub size: u64,
     pub sha256: String,
     pub kind: ManifestKind,
     pub target: Option<String>,
+    /// Exact link target bytes (base64) when the target is not valid UTF-8.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target_b64: Option<String>,
     pub mtime: Option<u64>,
+    /// SHA-256 of each extended attribute value, by name, when attributes are tracked.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub xattrs: Option<BTreeMap<String, String>>,
+    /// PAX extended header values recorded for the member, when listing an archive.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pax: Option<PaxMetadata>,
+    /// What extraction did at the member's path, when extracting.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub outcome: Option<ExtractOutcome>,
+    /// Where extraction moved the file it replaced, with `--backup`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backup: Option<String>,
 }
 
 impl ManifestEntry {
+    #[must_use]
+    pub fn for_file(path: &Utf8Path, size: u64, sha256: String, mtime: Option<SystemTime>) -> Self {
+        Self {
+            path: names::escaped(path),
+            path_b64: names::base64(path),
+            size,
+            sha256,
+            kind: ManifestKind::File,
+            target: None,
+            target_b64: None,
+            mtime: mtime.and_then(to_unix_secs),
+            xattrs: None,
+            pax: None,
+            outcome: None,
+            backup: None,
+        }
+    }
+
     #[must_use]
     pub fn for_directory(path: &Utf8Path, mtime: Option<SystemTime>) -> Self {
         Self {
-            path: path.to_string(),
+            path: names::escaped(path),
+            path_b64: names::base64(path),
             size: 0,
             sha256: digest_bytes(&[]),
             kind: ManifestKind::Directory,
             target: None,
+            target_b64: None,
             mtime: mtime.and_then(to_unix_secs),
+            xattrs: None,
+            pax: None,
+            outcome: None,
+            backup: None,
         }
     }
 
     #[must_use]
     pub fn for_symlink(path: &Utf8Path, target: &Utf8Path) -> Self {
         Self {
-            path: path.to_string(),
+            path: names::escaped(path),
+            path_b64: names::base64(path),
             size: 0,
-            sha256: digest_bytes(target.as_str().as_bytes()),
+            sha256: digest_bytes(&names::to_bytes(target)),
             kind: ManifestKind::Symlink,
-            target: Some(target.to_string()),
+            target: Some(names::escaped(target)),
+            target_b64: names::base64(target),
             mtime: None,
+            xattrs: None,
+            pax: None,
+            outcome: None,
+            backup: None,
+        }
+    }
+
+    /// Hard links are identified by their archive-relative target.
+    #[must_use]
+    pub fn for_hardlink(path: &Utf8Path, target: &Utf8Path, mtime: Option<SystemTime>) -> Self {
+        Self {
+            path: names::escaped(path),
+            path_b64: names::base64(path),
+            size: 0,
+            sha256: digest_bytes(&names::to_bytes(target)),
+            kind: ManifestKind::Hardlink,
+            target: Some(names::escaped(target)),
+            target_b64: names::base64(target),
+            mtime: mtime.and_then(to_unix_secs),
+            xattrs: None,
+            pax: None,
+            outcome: None,
+            backup: None,
+        }
+    }
+
+    /// Device nodes are identified by their major/minor numbers; other special entries
+    /// by their path alone.
+    #[must_use]
+    pub fn for_special(
+        path: &Utf8Path,
+        kind: ManifestKind,
+        device: Option<(u32, u32)>,
+        mtime: Option<SystemTime>,
+    ) -> Self {
+        let identity = device
+            .map(|(major, minor)| format!("{major}:{minor}"))
+            .unwrap_or_default();
+        Self {
+            path: names::escaped(path),
+            path_b64: names::base64(path),
+            size: 0,
+            sha256: digest_bytes(identity.as_bytes()),
+            kind,
+            target: None,
+            target_b64: None,
+            mtime: mtime.and_then(to_unix_secs),
+            xattrs: None,
+            pax: None,
+            outcome: None,
+            backup: None,
         }
     }
 }
@@ -58,6 +157,29 @@ pub struct ManifestItem {
     pub link_target: Option<Utf8PathBuf>,
     pub size: u64,
     pub mtime: Option<SystemTime>,
+    /// Digests of the member's extended attributes, when they are tracked.
+    pub xattrs: Option<BTreeMap<String, String>>,
+    /// What extraction did at the member's path.
+    pub outcome: Option<ExtractOutcome>,
+    /// Where extraction moved the file it replaced, relative to the destination.
+    pub backup: Option<Utf8PathBuf>,
+}
+
+/// What extraction did at a member's path.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ExtractOutcome {
+    /// Nothing was there.
+    Created,
+    /// An existing file, or empty directory, was removed or backed up first.
+    Replaced,
+    /// An existing regular file was written in place.
+    Overwritten,
+    /// An existing directory was kept and the member's contents merged into it.
+    Merged,
+    /// The member was not extracted because its path exists; the entry describes the
+    /// existing file.
+    Skipped,
 }
 
 /// Types of entries captured in the manifest.
@@ -66,6 +188,12 @@ pub enum ManifestKind {
     File,
     Directory,
     Symlink,
+    Hardlink,
+    CharDevice,
+    BlockDevice,
+    Fifo,
+    /// Entry types without a dedicated kind (volume labels and the like).
+    Other,
 }
 
 /// Collect manifest entries for the provided items (hashed in parallel).
@@ -75,35 +203,58 @@ pub fn collect_manifest(items: &[ManifestItem]) -> Result<Vec<ManifestEntry>> {
         .map(|item| match item.kind {
             ManifestKind::File => {
                 let hash = hash_file(&item.absolute)?;
-                Ok(ManifestEntry {
-                    path: item.relative.to_string(),
-                    size: item.size,
-                    sha256: hash,
-                    kind: ManifestKind::File,
-                    target: None,
-                    mtime: item.mtime.and_then(to_unix_secs),
-                })
+                Ok(ManifestEntry::for_file(
+                    &item.relative,
+                    item.size,
+                    hash,
+                    item.mtime,
+                ))
             }
             ManifestKind::Directory => Ok(ManifestEntry::for_directory(&item.relative, item.mtime)),
             ManifestKind::Symlink => {
-                let target = item
-                    .link_target
-                    .as_ref()
-                    .map(|p| p.to_string())
-                    .unwrap_or_default();
-                Ok(ManifestEntry {
-                    path: item.relative.to_string(),
-                    size: 0,
-                    sha256: digest_bytes(target.as_bytes()),
-                    kind: ManifestKind::Symlink,
-                    target: item.link_target.as_ref().map(|p| p.to_string()),
-                    mtime: item.mtime.and_then(to_unix_secs),
-                })
+                let target = item.link_target.clone().unwrap_or_default();
+                let mut entry = ManifestEntry::for_symlink(&item.relative, &target);
+                if item.link_target.is_none() {
+                    entry.target = None;
+                }
+                entry.mtime = item.mtime.and_then(to_unix_secs);
+                Ok(entry)
+            }
+            ManifestKind::Hardlink => {
+                let target = item.link_target.clone().unwrap_or_default();
+                Ok(ManifestEntry::for_hardlink(
+                    &item.relative,
+                    &target,
+                    item.mtime,
+                ))
+            }
+            ManifestKind::CharDevice
+            | ManifestKind::BlockDevice
+            | ManifestKind::Fifo
+            | ManifestKind::Other => {
+                let device = match item.kind {
+                    ManifestKind::CharDevice | ManifestKind::BlockDevice => {
+                        device_numbers(&item.absolute)?
+                    }
+                    _ => None,
+                };
+                Ok(ManifestEntry::for_special(
+                    &item.relative,
+                    item.kind.clone(),
+                    device,
+                    item.mtime,
+                ))
             }
         })
         .collect();
 
     let mut entries = entries?;
+    // `collect` keeps the items' order, so entries still line up with them here.
+    for (entry, item) in entries.iter_mut().zip(items) {
+        entry.xattrs.clone_from(&item.xattrs);
+        entry.outcome = item.outcome;
+        entry.backup = item.backup.as_deref().map(names::escaped);
+    }
     entries.sort_by(|a, b| a.path.cmp(&b.path));
     Ok(entries)
 }
@@ -135,24 +286,38 @@ pub fn verify_manifest(
     let expected_map = as_map(expected);
     let actual_map = as_map(actual);
 
-    for (path, entry) in &expected_map {
-        let Some(actual_entry) = actual_map.get(path) else {
-            return Err(ManifestError::MissingEntry(path.clone()).into());
+    for (key, entry) in &expected_map {
+        let Some(actual_entry) = actual_map.get(key) else {
+            return Err(ManifestError::MissingEntry(entry.path.clone()).into());
         };
         if entry.sha256 != actual_entry.sha256 || entry.kind != actual_entry.kind {
             return Err(ManifestError::Mismatch {
-                path: path.clone(),
+                path: entry.path.clone(),
                 expected: entry.sha256.clone(),
                 actual: actual_entry.sha256.clone(),
             }
             .into());
         }
+        // Attributes are only compared when both sides tracked them.
+        if let (Some(expected), Some(actual)) = (&entry.xattrs, &actual_entry.xattrs) {
+            let names: BTreeSet<&String> = expected.keys().chain(actual.keys()).collect();
+            if let Some(name) = names
+                .into_iter()
+                .find(|name| expected.get(*name) != actual.get(*name))
+            {
+                return Err(ManifestError::XattrMismatch {
+                    path: entry.path.clone(),
+                    name: name.clone(),
+                }
+                .into());
+            }
+        }
     }
 
     if !relaxed {
-        for path in actual_map.keys() {
-            if !expected_map.contains_key(path) {
-                return Err(ManifestError::UnexpectedEntry(path.clone()).into());
+        for (key, entry) in &actual_map {
+            if !expected_map.contains_key(key) {
+                return Err(ManifestError::UnexpectedEntry(entry.path.clone()).into());
             }
         }
     }
@@ -161,7 +326,8 @@ pub fn verify_manifest(
 }
 
 fn hash_file(path: &Utf8Path) -> Result<String> {
-    let mut file = File::open(path).with_context(|| format!("failed to open {path}"))?;
+    let mut file =
+        File::open(names::to_path(path)).with_context(|| format!("failed to open {path}"))?;
     let mut hasher = Sha256::new();
     let mut buffer = [0_u8; 64 * 1024];
     loop {
@@ -174,6 +340,32 @@ fn hash_file(path: &Utf8Path) -> Result<String> {
     Ok(hex::encode(hasher.finalize()))
 }
 
+#[cfg(unix)]
+fn device_numbers(path: &Utf8Path) -> Result<Option<(u32, u32)>> {
+    use std::os::unix::fs::MetadataExt;
+
+    let metadata = std::fs::symlink_metadata(names::to_path(path))
+        .with_context(|| format!("failed to stat {path}"))?;
+    let rdev = metadata.rdev();
+    Ok(Some((rustix::fs::major(rdev), rustix::fs::minor(rdev))))
+}
+
+#[cfg(not(unix))]
+fn device_numbers(_path: &Utf8Path) -> Result<Option<(u32, u32)>> {
+    Ok(None)
+}
+
+/// Digest each extended attribute value, keyed by attribute name.
+#[must_use]
+pub fn xattr_digests<'a>(
+    xattrs: impl IntoIterator<Item = (&'a str, &'a [u8])>,
+) -> BTreeMap<String, String> {
+    xattrs
+        .into_iter()
+        .map(|(name, value)| (name.to_owned(), digest_bytes(value)))
+        .collect()
+}
+
 fn digest_bytes(bytes: &[u8]) -> String {
     let mut hasher = Sha256::new();
     hasher.update(bytes);
@@ -186,11 +378,12 @@ fn to_unix_secs(time: SystemTime) -> Option<u64> {
         .map(|duration| duration.as_secs())
 }
 
-fn as_map(entries: &[ManifestEntry]) -> BTreeMap<String, ManifestEntry> {
+/// Entries keyed by exact path bytes, so distinct raw names never collide.
+fn as_map(entries: &[ManifestEntry]) -> BTreeMap<(String, Option<String>), ManifestEntry> {
     entries
         .iter()
         .cloned()
-        .map(|entry| (entry.path.clone(), entry))
+        .map(|entry| ((entry.path.clone(), entry.path_b64.clone()), entry))
         .collect()
 }
 
@@ -207,4 +400,6 @@ pub enum ManifestError {
     },
     #[error("manifest contains unexpected entry: {0}")]
     UnexpectedEntry(String),
+    #[error("manifest xattr mismatch for {path}: {name}")]
+    XattrMismatch { path: String, name: String },
 }
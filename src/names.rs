@@ -0,0 +1,197 @@
+//! Lossless handling of member and file names that are not valid UTF-8.
+//!
+//! safetar works with [`Utf8PathBuf`] throughout. Raw names are carried inside it by
+//! mapping each byte that is not part of a valid UTF-8 sequence (always `0x80..=0xFF`)
+//! to a code point in `U+10FF80..=U+10FFFF`, a private-use range no real file name
+//! uses. The mapping never touches `/` or `.`, so path validation sees exactly the
+//! components of the raw name. Names are decoded back to bytes wherever they meet the
+//! filesystem or a tar header.
+
+use std::borrow::Cow;
+use std::path::Path;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine as _;
+use camino::{Utf8Path, Utf8PathBuf};
+use thiserror::Error;
+
+/// First code point of the range standing in for raw bytes (`0x80` maps here).
+const RAW_BASE: u32 = 0x10_FF00;
+const RAW_FIRST: char = '\u{10FF80}';
+const RAW_LAST: char = '\u{10FFFF}';
+
+/// Problems turning a raw name into a [`Utf8PathBuf`].
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum NameError {
+    #[error("name is not valid UTF-8: {0} (use --byte-paths to accept raw names)")]
+    NotUtf8(String),
+    #[error("name uses code points reserved for raw bytes: {0}")]
+    Reserved(String),
+}
+
+/// Encode raw name bytes, accepting invalid UTF-8 only when `allow_raw` is set.
+pub fn from_bytes(bytes: &[u8], allow_raw: bool) -> Result<Utf8PathBuf, NameError> {
+    if let Ok(text) = std::str::from_utf8(bytes) {
+        if text.chars().any(is_raw_char) {
+            return Err(NameError::Reserved(text.escape_default().to_string()));
+        }
+        return Ok(Utf8PathBuf::from(text));
+    }
+    let mut encoded = String::with_capacity(bytes.len() + 8);
+    for chunk in bytes.utf8_chunks() {
+        if chunk.valid().chars().any(is_raw_char) {
+            return Err(NameError::Reserved(escape_bytes(bytes)));
+        }
+        encoded.push_str(chunk.valid());
+        encoded.extend(chunk.invalid().iter().map(|&byte| raw_char(byte)));
+    }
+    if !allow_raw {
+        return Err(NameError::NotUtf8(escape_bytes(bytes)));
+    }
+    Ok(Utf8PathBuf::from(encoded))
+}
+
+/// Encode a filesystem path, accepting invalid UTF-8 only when `allow_raw` is set.
+pub fn from_path(path: &Path, allow_raw: bool) -> Result<Utf8PathBuf, NameError> {
+    from_bytes(&path_bytes(path)?, allow_raw)
+}
+
+/// Raw bytes of an encoded name.
+#[must_use]
+pub fn to_bytes(name: &Utf8Path) -> Cow<'_, [u8]> {
+    if !is_raw(name) {
+        return Cow::Borrowed(name.as_str().as_bytes());
+    }
+    let mut bytes = Vec::with_capacity(name.as_str().len());
+    let mut buf = [0_u8; 4];
+    for c in name.as_str().chars() {
+        if is_raw_char(c) {
+            bytes.push((u32::from(c) - RAW_BASE) as u8);
+        } else {
+            bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+        }
+    }
+    Cow::Owned(bytes)
+}
+
+/// Filesystem path for an encoded name.
+#[cfg(unix)]
+#[must_use]
+pub fn to_path(name: &Utf8Path) -> Cow<'_, Path> {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    match to_bytes(name) {
+        Cow::Borrowed(_) => Cow::Borrowed(name.as_std_path()),
+        Cow::Owned(bytes) => Cow::Owned(OsStr::from_bytes(&bytes).into()),
+    }
+}
+
+#[cfg(not(unix))]
+#[must_use]
+pub fn to_path(name: &Utf8Path) -> Cow<'_, Path> {
+    Cow::Borrowed(name.as_std_path())
+}
+
+/// Whether `name` carries bytes that are not valid UTF-8.
+#[must_use]
+pub fn is_raw(name: &Utf8Path) -> bool {
+    name.as_str().chars().any(is_raw_char)
+}
+
+/// Printable form of `name`, with raw bytes shown as `\xHH`.
+#[must_use]
+pub fn escaped(name: &Utf8Path) -> String {
+    if !is_raw(name) {
+        return name.to_string();
+    }
+    escape_bytes(&to_bytes(name))
+}
+
+/// Base64 of the raw bytes of `name`, or `None` when it is valid UTF-8.
+#[must_use]
+pub fn base64(name: &Utf8Path) -> Option<String> {
+    is_raw(name).then(|| STANDARD.encode(to_bytes(name)))
+}
+
+/// Decode a name written by [`base64`], or `None` when `encoded` is not valid base64.
+#[must_use]
+pub fn from_base64(encoded: &str) -> Option<Utf8PathBuf> {
+    let bytes = STANDARD.decode(encoded).ok()?;
+    from_bytes(&bytes, true).ok()
+}
+
+fn is_raw_char(c: char) -> bool {
+    (RAW_FIRST..=RAW_LAST).contains(&c)
+}
+
+fn raw_char(byte: u8) -> char {
+    char::from_u32(RAW_BASE + u32::from(byte)).unwrap_or(char::REPLACEMENT_CHARACTER)
+}
+
+/// Printable form of raw name bytes, with invalid UTF-8 shown as `\xHH`.
+#[must_use]
+pub fn escape_bytes(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len());
+    for chunk in bytes.utf8_chunks() {
+        out.push_str(chunk.valid());
+        for byte in chunk.invalid() {
+            out.push_str(&format!("\\x{byte:02x}"));
+        }
+    }
+    out
+}
+
+#[cfg(unix)]
+fn path_bytes(path: &Path) -> Result<Cow<'_, [u8]>, NameError> {
+    use std::os::unix::ffi::OsStrExt;
+
+    Ok(Cow::Borrowed(path.as_os_str().as_bytes()))
+}
+
+#[cfg(not(unix))]
+fn path_bytes(path: &Path) -> Result<Cow<'_, [u8]>, NameError> {
+    path.to_str()
+        .map(|text| Cow::Borrowed(text.as_bytes()))
+        .ok_or_else(|| NameError::NotUtf8(path.display().to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn raw_bytes_roundtrip() {
+        let raw = b"dir/caf\xe9/\xff\xfe.txt";
+        let name = from_bytes(raw, true).unwrap();
+        assert!(is_raw(&name));
+        assert_eq!(to_bytes(&name).as_ref(), raw);
+        assert_eq!(escaped(&name), "dir/caf\\xe9/\\xff\\xfe.txt");
+        assert_eq!(name.components().count(), 3);
+        assert_eq!(base64(&name).as_deref(), Some("ZGlyL2NhZukv//4udHh0"));
+    }
+
+    #[test]
+    fn utf8_names_pass_through() {
+        let name = from_bytes("größe.txt".as_bytes(), false).unwrap();
+        assert_eq!(name, "größe.txt");
+        assert!(!is_raw(&name));
+        assert_eq!(base64(&name), None);
+    }
+
+    #[test]
+    fn raw_names_require_opt_in() {
+        assert!(matches!(
+            from_bytes(b"caf\xe9", false),
+            Err(NameError::NotUtf8(shown)) if shown == "caf\\xe9"
+        ));
+    }
+
+    #[test]
+    fn reserved_code_points_are_rejected() {
+        assert!(matches!(
+            from_bytes("a\u{10FFAA}".as_bytes(), true),
+            Err(NameError::Reserved(_))
+        ));
+    }
+}
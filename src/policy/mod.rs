@@ -4,6 +4,8 @@ use path_clean::PathClean;
 use std::fmt;
 use thiserror::Error;
 
+use crate::names;
+
 /// Resource limits enforced by [`SecurityPolicy`].
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct PolicyLimits {
@@ -11,8 +13,17 @@ pub struct PolicyLimits {
     pub max_total_bytes: u64,
     pub max_single_file: u64,
     pub max_depth: u32,
+    /// Decoded archive bytes allowed per byte read from the archive file, checked
+    /// once the first [`RATIO_GRACE_BYTES`] have been decoded.
+    pub max_compression_ratio: u64,
+    /// Bytes that may be read from the archive file (`u64::MAX` for no limit).
+    pub max_compressed_input: u64,
 }
 
+/// Decoded bytes below which [`PolicyLimits::max_compression_ratio`] is not checked, so
+/// small archives whose block padding compresses to almost nothing still pass.
+pub const RATIO_GRACE_BYTES: u64 = 1 << 20;
+
 impl Default for PolicyLimits {
     fn default() -> Self {
         Self {
@@ -20,6 +31,8 @@ impl Default for PolicyLimits {
             max_total_bytes: 8u64 << 30,
             max_single_file: 2u64 << 30,
             max_depth: 64,
+            max_compression_ratio: 1_000,
+            max_compressed_input: u64::MAX,
         }
     }
 }
@@ -31,6 +44,140 @@ pub enum LinkType {
     Hardlink,
 }
 
+/// Archive members that are neither files, directories, nor links.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpecialEntryKind {
+    CharDevice,
+    BlockDevice,
+    Fifo,
+    /// Entry types safetar does not understand (volume labels, multi-volume parts, ...).
+    Unknown,
+}
+
+impl fmt::Display for SpecialEntryKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::CharDevice => write!(f, "character device"),
+            Self::BlockDevice => write!(f, "block device"),
+            Self::Fifo => write!(f, "fifo"),
+            Self::Unknown => write!(f, "unsupported entry"),
+        }
+    }
+}
+
+/// What extraction does with a special entry.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SpecialEntryAction {
+    /// Fail with [`PolicyError::SpecialEntryRejected`].
+    #[default]
+    Reject,
+    /// Leave the entry out and emit a warning.
+    Skip,
+    /// Create the node (FIFOs always, devices only when running as root).
+    ///
+    /// [`SpecialEntryKind::Unknown`] entries cannot be created and are skipped instead.
+    Create,
+}
+
+/// Per-kind handling of special entries.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SpecialEntryRules {
+    pub char_device: SpecialEntryAction,
+    pub block_device: SpecialEntryAction,
+    pub fifo: SpecialEntryAction,
+    pub unknown: SpecialEntryAction,
+}
+
+/// Permission bits that can let an extracted file act with someone else's privileges.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModeBit {
+    Setuid,
+    Setgid,
+    Sticky,
+}
+
+impl ModeBit {
+    /// The bit in a `st_mode` value.
+    #[must_use]
+    pub fn mask(self) -> u32 {
+        match self {
+            Self::Setuid => 0o4000,
+            Self::Setgid => 0o2000,
+            Self::Sticky => 0o1000,
+        }
+    }
+}
+
+impl fmt::Display for ModeBit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Setuid => write!(f, "setuid"),
+            Self::Setgid => write!(f, "setgid"),
+            Self::Sticky => write!(f, "sticky"),
+        }
+    }
+}
+
+/// What extraction does with a permission bit an archive member carries.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ModeAction {
+    /// Clear the bit and extract the member.
+    #[default]
+    Strip,
+    /// Fail with [`PolicyError::ModeBitRejected`] or [`PolicyError::WorldWritable`].
+    Reject,
+    /// Extract the member with the bit set.
+    Keep,
+}
+
+/// Handling of member permission bits on extract.
+///
+/// The default strips setuid, setgid, sticky, and world-writable bits and applies no
+/// further umask.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ModeRules {
+    pub setuid: ModeAction,
+    pub setgid: ModeAction,
+    pub sticky: ModeAction,
+    pub world_writable: ModeAction,
+    /// Bits cleared from every mode after the rules above.
+    pub umask: u32,
+}
+
+/// Extended attributes that are never restored unless the denylist is replaced: file
+/// capabilities grant privileges, and `trusted.*` is reserved for privileged daemons.
+pub const DEFAULT_XATTR_DENY: [&str; 2] = ["security.capability", "trusted.*"];
+
+/// Which extended attributes extraction restores.
+///
+/// Patterns are exact names or prefixes ending in `*`, like `user.*`. A name is
+/// restored when it matches the allowlist (an empty one allows every name) and
+/// nothing on the denylist.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct XattrRules {
+    pub allow: Vec<String>,
+    pub deny: Vec<String>,
+}
+
+impl Default for XattrRules {
+    fn default() -> Self {
+        Self {
+            allow: Vec::new(),
+            deny: DEFAULT_XATTR_DENY.map(str::to_owned).to_vec(),
+        }
+    }
+}
+
+impl XattrRules {
+    fn allows(&self, name: &str) -> bool {
+        let matches = |pattern: &String| match pattern.strip_suffix('*') {
+            Some(prefix) => name.starts_with(prefix),
+            None => name == pattern,
+        };
+        (self.allow.is_empty() || self.allow.iter().any(matches)) && !self.deny.iter().any(matches)
+    }
+}
+
 /// Security policy configuration.
 #[derive(Debug, Clone, Default)]
 pub struct SecurityPolicy {
@@ -40,6 +187,10 @@ pub struct SecurityPolicy {
     follow_symlinks: bool,
     allow_symlink_outside_root: bool,
     allow_hardlink_outside_root: bool,
+    special_entries: SpecialEntryRules,
+    modes: ModeRules,
+    xattrs: XattrRules,
+    byte_paths: bool,
 }
 
 impl SecurityPolicy {
@@ -98,6 +249,152 @@ impl SecurityPolicy {
         self
     }
 
+    /// Adjust the maximum compression ratio.
+    #[must_use]
+    pub fn with_max_compression_ratio(mut self, value: Option<u64>) -> Self {
+        if let Some(value) = value {
+            self.limits.max_compression_ratio = value;
+        }
+        self
+    }
+
+    /// Adjust the maximum bytes read from the archive file.
+    #[must_use]
+    pub fn with_max_compressed_input(mut self, value: Option<u64>) -> Self {
+        if let Some(value) = value {
+            self.limits.max_compressed_input = value;
+        }
+        self
+    }
+
+    /// Accept names that are not valid UTF-8, carrying their raw bytes (see [`crate::names`]).
+    #[must_use]
+    pub fn with_byte_paths(mut self, enabled: bool) -> Self {
+        self.byte_paths = enabled;
+        self
+    }
+
+    /// Whether names that are not valid UTF-8 are accepted.
+    #[must_use]
+    pub fn byte_paths(&self) -> bool {
+        self.byte_paths
+    }
+
+    /// Choose how extraction treats one kind of special entry.
+    #[must_use]
+    pub fn with_special_entry_action(
+        mut self,
+        kind: SpecialEntryKind,
+        action: SpecialEntryAction,
+    ) -> Self {
+        let slot = match kind {
+            SpecialEntryKind::CharDevice => &mut self.special_entries.char_device,
+            SpecialEntryKind::BlockDevice => &mut self.special_entries.block_device,
+            SpecialEntryKind::Fifo => &mut self.special_entries.fifo,
+            SpecialEntryKind::Unknown => &mut self.special_entries.unknown,
+        };
+        *slot = action;
+        self
+    }
+
+    /// Decide what to do with a special entry at `path`.
+    pub fn check_special_entry(
+        &self,
+        path: &Utf8Path,
+        kind: SpecialEntryKind,
+    ) -> Result<SpecialEntryAction, PolicyError> {
+        let action = match kind {
+            SpecialEntryKind::CharDevice => self.special_entries.char_device,
+            SpecialEntryKind::BlockDevice => self.special_entries.block_device,
+            SpecialEntryKind::Fifo => self.special_entries.fifo,
+            SpecialEntryKind::Unknown => match self.special_entries.unknown {
+                SpecialEntryAction::Create => SpecialEntryAction::Skip,
+                other => other,
+            },
+        };
+        match action {
+            SpecialEntryAction::Reject => Err(PolicyError::SpecialEntryRejected {
+                path: path.to_owned(),
+                kind,
+            }),
+            other => Ok(other),
+        }
+    }
+
+    /// Choose how extraction treats permission bits.
+    #[must_use]
+    pub fn with_mode_rules(mut self, rules: ModeRules) -> Self {
+        self.modes = rules;
+        self
+    }
+
+    /// Return the active permission rules.
+    #[must_use]
+    pub fn mode_rules(&self) -> ModeRules {
+        self.modes
+    }
+
+    /// Choose which extended attributes extraction restores.
+    #[must_use]
+    pub fn with_xattr_rules(mut self, rules: XattrRules) -> Self {
+        self.xattrs = rules;
+        self
+    }
+
+    /// Return the active extended attribute rules.
+    #[must_use]
+    pub fn xattr_rules(&self) -> &XattrRules {
+        &self.xattrs
+    }
+
+    /// Whether extraction may restore the extended attribute `name`.
+    #[must_use]
+    pub fn allows_xattr(&self, name: &str) -> bool {
+        self.xattrs.allows(name)
+    }
+
+    /// Compute the permissions to give the member at `path`, stored with `mode`.
+    ///
+    /// Rejected bits are checked against the mode as stored, before stripping or the
+    /// umask; the result only holds permission bits (`0o7777`).
+    pub fn check_mode(&self, path: &Utf8Path, mode: u32) -> Result<u32, PolicyError> {
+        let mode = mode & 0o7777;
+        let mut effective = mode;
+        for (bit, action) in [
+            (ModeBit::Setuid, self.modes.setuid),
+            (ModeBit::Setgid, self.modes.setgid),
+            (ModeBit::Sticky, self.modes.sticky),
+        ] {
+            if mode & bit.mask() == 0 {
+                continue;
+            }
+            match action {
+                ModeAction::Strip => effective &= !bit.mask(),
+                ModeAction::Reject => {
+                    return Err(PolicyError::ModeBitRejected {
+                        path: path.to_owned(),
+                        bit,
+                        mode,
+                    })
+                }
+                ModeAction::Keep => {}
+            }
+        }
+        if mode & 0o002 != 0 {
+            match self.modes.world_writable {
+                ModeAction::Strip => effective &= !0o002,
+                ModeAction::Reject => {
+                    return Err(PolicyError::WorldWritable {
+                        path: path.to_owned(),
+                        mode,
+                    })
+                }
+                ModeAction::Keep => {}
+            }
+        }
+        Ok(effective & !self.modes.umask)
+    }
+
     /// Normalise and validate `path` against `root`.
     pub fn normalize_and_validate(
         &self,
@@ -139,10 +436,10 @@ impl SecurityPolicy {
                 Utf8Component::ParentDir if !self.allow_parent_components => {
                     return Err(PolicyError::ParentTraversal(cleaned.clone()));
                 }
-                Utf8Component::RootDir | Utf8Component::Prefix(_) if !self.allow_absolute => {
-                    if !cleaned.starts_with(root) {
-                        return Err(PolicyError::RootEscape(cleaned.clone()));
-                    }
+                Utf8Component::RootDir | Utf8Component::Prefix(_)
+                    if !self.allow_absolute && !cleaned.starts_with(root) =>
+                {
+                    return Err(PolicyError::RootEscape(cleaned.clone()));
                 }
                 _ => {}
             }
@@ -160,6 +457,19 @@ impl SecurityPolicy {
         Ok(ValidatedPath { rel, abs: cleaned })
     }
 
+    /// Normalise and validate a raw member name against `root`.
+    ///
+    /// Names that are not valid UTF-8 are rejected unless byte paths are enabled, in
+    /// which case they are encoded losslessly and checked exactly like any other name.
+    pub fn normalize_and_validate_bytes(
+        &self,
+        path: &[u8],
+        root: &Utf8Path,
+    ) -> Result<ValidatedPath, PolicyError> {
+        let encoded = names::from_bytes(path, self.byte_paths).map_err(PolicyError::InvalidName)?;
+        self.normalize_and_validate(&encoded, root)
+    }
+
     /// Enforce link targets remain within `root`.
     pub fn enforce_link_policy(
         &self,
@@ -204,6 +514,7 @@ impl SecurityPolicy {
             policy: self.clone(),
             files_seen: 0,
             total_bytes: 0,
+            stored_bytes: 0,
             max_depth_observed: 0,
         }
     }
@@ -238,14 +549,29 @@ pub struct UsageTracker {
     policy: SecurityPolicy,
     files_seen: u64,
     total_bytes: u64,
+    stored_bytes: u64,
     max_depth_observed: u32,
 }
 
 impl UsageTracker {
     /// Record an entry with the provided size.
     pub fn observe(&mut self, validated: &ValidatedPath, size: u64) -> Result<(), PolicyError> {
+        self.observe_sparse(validated, size, size)
+    }
+
+    /// Record an entry whose logical `size` differs from the bytes `stored` in the archive.
+    ///
+    /// Limits apply to the logical size, so a small sparse member that expands into a
+    /// huge file is still rejected.
+    pub fn observe_sparse(
+        &mut self,
+        validated: &ValidatedPath,
+        size: u64,
+        stored: u64,
+    ) -> Result<(), PolicyError> {
         let depth = depth_of(&validated.rel)?;
         let limits = self.policy.limits;
+        let size = size.max(stored);
 
         if size > limits.max_single_file {
             return Err(PolicyError::SingleFileTooLarge {
@@ -273,6 +599,7 @@ impl UsageTracker {
         }
 
         self.total_bytes = self.total_bytes.saturating_add(size);
+        self.stored_bytes = self.stored_bytes.saturating_add(stored);
         if self.total_bytes > limits.max_total_bytes {
             return Err(PolicyError::TotalBytesExceeded {
                 limit: limits.max_total_bytes,
@@ -294,6 +621,12 @@ impl UsageTracker {
     pub fn total_bytes(&self) -> u64 {
         self.total_bytes
     }
+
+    /// Bytes actually stored in the archive so far (smaller than the total for sparse files).
+    #[must_use]
+    pub fn stored_bytes(&self) -> u64 {
+        self.stored_bytes
+    }
 }
 
 #[derive(Debug, Error)]
@@ -308,8 +641,35 @@ pub enum PolicyError {
     ParentTraversal(Utf8PathBuf),
     #[error("path contains invalid UTF-8: {0}")]
     InvalidUtf8(Utf8PathBuf),
+    #[error(transparent)]
+    InvalidName(names::NameError),
     #[error("link target escapes root: {0}")]
     LinkOutsideRoot(Utf8PathBuf),
+    #[error("hard link {path} targets {target}, which was not extracted earlier")]
+    HardlinkTargetMissing {
+        path: Utf8PathBuf,
+        target: Utf8PathBuf,
+    },
+    #[error("{path} would be written through symlink {link}")]
+    SymlinkInPath {
+        path: Utf8PathBuf,
+        link: Utf8PathBuf,
+    },
+    #[error("{kind} rejected by policy: {path}")]
+    SpecialEntryRejected {
+        path: Utf8PathBuf,
+        kind: SpecialEntryKind,
+    },
+    #[error("{bit} bit rejected by policy: {path} (mode {mode:04o})")]
+    ModeBitRejected {
+        path: Utf8PathBuf,
+        bit: ModeBit,
+        mode: u32,
+    },
+    #[error("world-writable mode rejected by policy: {path} (mode {mode:04o})")]
+    WorldWritable { path: Utf8PathBuf, mode: u32 },
+    #[error("duplicate member path: {0}")]
+    DuplicatePath(Utf8PathBuf),
     #[error("file count exceeded (limit {limit}, actual {actual})")]
     FileCountExceeded { limit: u64, actual: u64 },
     #[error("total bytes exceeded (limit {limit}, actual {actual})")]
@@ -320,12 +680,53 @@ pub enum PolicyError {
         actual: u64,
         limit: u64,
     },
+    #[error(
+        "compression ratio exceeded (limit {limit}:1, {decoded} bytes decoded from {compressed})"
+    )]
+    CompressionRatioExceeded {
+        limit: u64,
+        compressed: u64,
+        decoded: u64,
+    },
+    #[error("compressed input exceeded (limit {limit}, actual {actual})")]
+    CompressedInputExceeded { limit: u64, actual: u64 },
     #[error("directory depth exceeded for {path} (limit {limit}, actual {actual})")]
     DepthExceeded {
         path: Utf8PathBuf,
         actual: u32,
         limit: u32,
     },
+    /// Summary of an audit that found violations; each one is in the audit report.
+    #[error("policy violations found: {count}")]
+    Violations { count: usize },
+}
+
+impl PolicyError {
+    /// Short stable name of the violated rule, as shown in audit reports.
+    #[must_use]
+    pub fn rule(&self) -> &'static str {
+        match self {
+            Self::EmptyPath => "empty_path",
+            Self::AbsolutePath(_) => "absolute_path",
+            Self::RootEscape(_) => "root_escape",
+            Self::ParentTraversal(_) => "parent_traversal",
+            Self::InvalidUtf8(_) | Self::InvalidName(_) => "invalid_name",
+            Self::LinkOutsideRoot(_) => "link_outside_root",
+            Self::HardlinkTargetMissing { .. } => "hardlink_target_missing",
+            Self::SymlinkInPath { .. } => "symlink_in_path",
+            Self::SpecialEntryRejected { .. } => "special_entry",
+            Self::ModeBitRejected { .. } => "mode_bit",
+            Self::WorldWritable { .. } => "world_writable",
+            Self::DuplicatePath(_) => "duplicate_path",
+            Self::FileCountExceeded { .. } => "max_files",
+            Self::TotalBytesExceeded { .. } => "max_total_bytes",
+            Self::SingleFileTooLarge { .. } => "max_single_file",
+            Self::DepthExceeded { .. } => "max_depth",
+            Self::CompressionRatioExceeded { .. } => "max_compression_ratio",
+            Self::CompressedInputExceeded { .. } => "max_compressed_input",
+            Self::Violations { .. } => "violations",
+        }
+    }
 }
 
 fn depth_of(path: &Utf8Path) -> Result<u32, PolicyError> {
@@ -406,6 +807,82 @@ mod tests {
         assert!(matches!(err, PolicyError::LinkOutsideRoot(_)));
     }
 
+    #[test]
+    fn byte_paths_keep_traversal_checks() {
+        let tmp = tempfile::tempdir().expect("tempdir");
+        let root = Utf8PathBuf::from_path_buf(tmp.path().to_path_buf()).expect("utf8");
+        let strict = SecurityPolicy::new();
+        assert!(matches!(
+            strict.normalize_and_validate_bytes(b"caf\xe9.txt", &root),
+            Err(PolicyError::InvalidName(_))
+        ));
+
+        let policy = SecurityPolicy::new().with_byte_paths(true);
+        let validated = policy
+            .normalize_and_validate_bytes(b"dir/caf\xe9.txt", &root)
+            .expect("raw name accepted");
+        assert_eq!(names::to_bytes(&validated.rel).as_ref(), b"dir/caf\xe9.txt");
+        assert!(matches!(
+            policy.normalize_and_validate_bytes(b"../caf\xe9", &root),
+            Err(PolicyError::ParentTraversal(_))
+        ));
+        assert!(matches!(
+            policy.normalize_and_validate_bytes(b"/etc/caf\xe9", &root),
+            Err(PolicyError::AbsolutePath(_))
+        ));
+    }
+
+    #[test]
+    fn mode_rules_strip_reject_and_mask() {
+        let path = Utf8Path::new("bin/tool");
+        let policy = SecurityPolicy::new();
+        assert_eq!(policy.check_mode(path, 0o104_755).ok(), Some(0o755));
+        assert_eq!(policy.check_mode(path, 0o1777).ok(), Some(0o775));
+
+        let policy = policy.with_mode_rules(ModeRules {
+            setgid: ModeAction::Keep,
+            sticky: ModeAction::Keep,
+            world_writable: ModeAction::Reject,
+            umask: 0o027,
+            ..ModeRules::default()
+        });
+        assert_eq!(policy.check_mode(path, 0o2775).ok(), Some(0o2750));
+        assert!(matches!(
+            policy.check_mode(path, 0o1777),
+            Err(PolicyError::WorldWritable { mode: 0o1777, .. })
+        ));
+
+        let policy = policy.with_mode_rules(ModeRules {
+            setuid: ModeAction::Reject,
+            ..ModeRules::default()
+        });
+        let err = policy
+            .check_mode(path, 0o4755)
+            .expect_err("setuid rejected");
+        assert_eq!(
+            err.to_string(),
+            "setuid bit rejected by policy: bin/tool (mode 4755)"
+        );
+    }
+
+    #[test]
+    fn xattr_rules_block_capabilities_by_default() {
+        let policy = SecurityPolicy::new();
+        assert!(policy.allows_xattr("user.mime_type"));
+        assert!(policy.allows_xattr("security.selinux"));
+        assert!(!policy.allows_xattr("security.capability"));
+        assert!(!policy.allows_xattr("trusted.overlay.opaque"));
+
+        let policy = policy.with_xattr_rules(XattrRules {
+            allow: vec!["user.*".into(), "security.capability".into()],
+            deny: vec!["user.secret".into()],
+        });
+        assert!(policy.allows_xattr("user.mime_type"));
+        assert!(policy.allows_xattr("security.capability"));
+        assert!(!policy.allows_xattr("user.secret"));
+        assert!(!policy.allows_xattr("security.selinux"));
+    }
+
     #[test]
     fn usage_tracker_enforces_limits() {
         let tmp = tempdir().expect("tempdir");
@@ -415,6 +892,7 @@ mod tests {
             max_total_bytes: 10,
             max_single_file: 8,
             max_depth: 1,
+            ..PolicyLimits::default()
         };
         let policy = SecurityPolicy::new().with_limits(limits);
         let validated = policy
@@ -429,6 +907,31 @@ mod tests {
         assert!(matches!(err, PolicyError::FileCountExceeded { .. }));
     }
 
+    #[test]
+    fn special_entries_rejected_by_default() {
+        let policy = SecurityPolicy::new();
+        let err = policy
+            .check_special_entry(Utf8Path::new("dev/sda"), SpecialEntryKind::BlockDevice)
+            .expect_err("devices rejected by default");
+        assert!(matches!(err, PolicyError::SpecialEntryRejected { .. }));
+
+        let policy = policy
+            .with_special_entry_action(SpecialEntryKind::Fifo, SpecialEntryAction::Create)
+            .with_special_entry_action(SpecialEntryKind::Unknown, SpecialEntryAction::Create);
+        assert_eq!(
+            policy
+                .check_special_entry(Utf8Path::new("pipe"), SpecialEntryKind::Fifo)
+                .unwrap(),
+            SpecialEntryAction::Create
+        );
+        assert_eq!(
+            policy
+                .check_special_entry(Utf8Path::new("label"), SpecialEntryKind::Unknown)
+                .unwrap(),
+            SpecialEntryAction::Skip
+        );
+    }
+
     proptest! {
         #[test]
         fn normalized_paths_stay_within_root(
@@ -1,13 +1,23 @@
 //! Archive-level integration checks.
 
 use std::fs;
+use std::io;
 
 use anyhow::Result;
 use camino::Utf8PathBuf;
-use safetar::archive::{create_archive, extract_archive, CreateOptions, ExtractOptions};
+use safetar::archive::{
+    append_archive, audit_archive, cat_members, concat_archives, create_archive, delete_members,
+    diff_archive, extract_archive, index_archive, list_archive, read_index, AppendMode,
+    ArchiveFormat, ArchiveIndex, AuditOptions, BackupMode, CatOptions, ConcatOptions,
+    CreateOptions, DeleteOptions, DiffKind, DiffOptions, ExtractOptions, IndexOptions, ListOptions,
+    MemberSelection, NameRewrite, OverwriteMode, OwnerMapping,
+};
+use safetar::io::seekable::SeekableEncoder;
 use safetar::io::Compression;
-use safetar::manifest::ManifestKind;
-use safetar::policy::SecurityPolicy;
+use safetar::manifest::{write_manifest_json, ManifestError, ManifestKind};
+use safetar::policy::{
+    ModeAction, ModeRules, PolicyError, PolicyLimits, SecurityPolicy, XattrRules,
+};
 use tempfile::tempdir;
 
 fn temp_utf8_path(dir: &tempfile::TempDir, segment: &str) -> Utf8PathBuf {
@@ -18,6 +28,57 @@ fn base_workdir(dir: &tempfile::TempDir) -> Utf8PathBuf {
     Utf8PathBuf::from_path_buf(dir.path().to_path_buf()).expect("utf8 temp dir")
 }
 
+fn create_options(archive_path: &Utf8PathBuf, workdir: &Utf8PathBuf) -> CreateOptions {
+    CreateOptions {
+        archive_path: archive_path.clone(),
+        inputs: vec![Utf8PathBuf::from("input")],
+        work_dir: Some(workdir.clone()),
+        compression: Compression::None,
+        format: ArchiveFormat::Gnu,
+        verbose: false,
+        quiet: true,
+        print_plan: false,
+        excludes: Vec::new(),
+        exclude_from: Vec::new(),
+        manifest_out: None,
+        numeric_owner: false,
+        no_same_owner: true,
+        owners: OwnerMapping::default(),
+        include_special: false,
+        sparse: false,
+        rewrite: NameRewrite::default(),
+        seekable: false,
+        preserve_times: false,
+        xattrs: false,
+        acls: false,
+    }
+}
+
+fn extract_options(archive_path: &Utf8PathBuf, dest: &Utf8PathBuf) -> ExtractOptions {
+    ExtractOptions {
+        archive_path: archive_path.clone(),
+        destination: dest.clone(),
+        verbose: false,
+        quiet: true,
+        strict: true,
+        manifest: None,
+        manifest_relaxed: false,
+        numeric_owner: false,
+        no_same_owner: true,
+        owners: OwnerMapping::default(),
+        selection: MemberSelection::default(),
+        rewrite: NameRewrite::default(),
+        index: None,
+        touch: false,
+        xattrs: false,
+        acls: false,
+        overwrite: OverwriteMode::default(),
+        backup: None,
+        delay_symlinks: false,
+        atomic: false,
+    }
+}
+
 #[test]
 fn create_respects_excludes() -> Result<()> {
     let temp = tempdir()?;
@@ -30,18 +91,8 @@ fn create_respects_excludes() -> Result<()> {
     let archive_path = workdir.join("archive.tar");
 
     let options = CreateOptions {
-        archive_path: archive_path.clone(),
-        inputs: vec![Utf8PathBuf::from("input")],
-        work_dir: Some(workdir.clone()),
-        compression: Compression::None,
-        verbose: false,
-        quiet: true,
-        print_plan: false,
         excludes: vec!["*.log".into()],
-        exclude_from: Vec::new(),
-        manifest_out: None,
-        numeric_owner: false,
-        no_same_owner: true,
+        ..create_options(&archive_path, &workdir)
     };
 
     let manifest = create_archive(&options, &SecurityPolicy::new())?;
@@ -53,17 +104,7 @@ fn create_respects_excludes() -> Result<()> {
     );
 
     let extract_dir = workdir.join("extract");
-    let extract_opts = ExtractOptions {
-        archive_path,
-        destination: extract_dir.clone(),
-        verbose: false,
-        quiet: true,
-        strict: true,
-        manifest: None,
-        manifest_relaxed: false,
-        numeric_owner: false,
-        no_same_owner: true,
-    };
+    let extract_opts = extract_options(&archive_path, &extract_dir);
 
     extract_archive(&extract_opts, &SecurityPolicy::new())?;
 
@@ -83,18 +124,8 @@ fn print_plan_does_not_emit_archive() -> Result<()> {
 
     let archive_path = workdir.join("plan.tar");
     let options = CreateOptions {
-        archive_path: archive_path.clone(),
-        inputs: vec![Utf8PathBuf::from("input")],
-        work_dir: Some(workdir.clone()),
-        compression: Compression::None,
-        verbose: false,
-        quiet: true,
         print_plan: true,
-        excludes: Vec::new(),
-        exclude_from: Vec::new(),
-        manifest_out: None,
-        numeric_owner: false,
-        no_same_owner: true,
+        ..create_options(&archive_path, &workdir)
     };
 
     let manifest = create_archive(&options, &SecurityPolicy::new())?;
@@ -120,34 +151,14 @@ fn roundtrip(codec: Compression) -> Result<()> {
     let archive_path = workdir.join(&archive_name);
 
     let create_opts = CreateOptions {
-        archive_path: archive_path.clone(),
-        inputs: vec![Utf8PathBuf::from("input")],
-        work_dir: Some(workdir.clone()),
         compression: codec,
-        verbose: false,
-        quiet: true,
-        print_plan: false,
-        excludes: Vec::new(),
-        exclude_from: Vec::new(),
-        manifest_out: None,
-        numeric_owner: false,
-        no_same_owner: true,
+        ..create_options(&archive_path, &workdir)
     };
     create_archive(&create_opts, &SecurityPolicy::new())?;
 
     let extract_name = Utf8PathBuf::from(format!("extract_{codec:?}"));
     let extract_dir = workdir.join(&extract_name);
-    let extract_opts = ExtractOptions {
-        archive_path: archive_path.clone(),
-        destination: extract_dir.clone(),
-        verbose: false,
-        quiet: true,
-        strict: true,
-        manifest: None,
-        manifest_relaxed: false,
-        numeric_owner: false,
-        no_same_owner: true,
-    };
+    let extract_opts = extract_options(&archive_path, &extract_dir);
     extract_archive(&extract_opts, &SecurityPolicy::new())?;
 
     let extracted = fs::read_to_string(extract_dir.join("nested/file.txt").as_std_path())?;
@@ -189,34 +200,11 @@ fn preserves_symlinks() -> Result<()> {
     symlink("file.txt", input_dir.join("dir/file.link").as_std_path())?;
 
     let archive_path = workdir.join("symlink.tar");
-    let create_opts = CreateOptions {
-        archive_path: archive_path.clone(),
-        inputs: vec![Utf8PathBuf::from("input")],
-        work_dir: Some(workdir.clone()),
-        compression: Compression::None,
-        verbose: false,
-        quiet: true,
-        print_plan: false,
-        excludes: Vec::new(),
-        exclude_from: Vec::new(),
-        manifest_out: None,
-        numeric_owner: false,
-        no_same_owner: true,
-    };
+    let create_opts = create_options(&archive_path, &workdir);
     create_archive(&create_opts, &SecurityPolicy::new())?;
 
     let extract_dir = workdir.join("symlink_extract");
-    let extract_opts = ExtractOptions {
-        archive_path: archive_path.clone(),
-        destination: extract_dir.clone(),
-        verbose: false,
-        quiet: true,
-        strict: true,
-        manifest: None,
-        manifest_relaxed: false,
-        numeric_owner: false,
-        no_same_owner: true,
-    };
+    let extract_opts = extract_options(&archive_path, &extract_dir);
     extract_archive(&extract_opts, &SecurityPolicy::new())?;
 
     let link_target = std::fs::read_link(extract_dir.join("dir/file.link"))?;
@@ -236,23 +224,2341 @@ fn rejects_symlink_escape() -> Result<()> {
     symlink("../outside", input_dir.join("escape.link").as_std_path())?;
 
     let archive_path = workdir.join("reject.tar");
-    let create_opts = CreateOptions {
+    let create_opts = create_options(&archive_path, &workdir);
+
+    let err = create_archive(&create_opts, &SecurityPolicy::new())
+        .expect_err("symlink escape should be rejected");
+    assert!(err.to_string().contains("link target escapes root"));
+    Ok(())
+}
+
+#[test]
+fn list_reports_pax_metadata() -> Result<()> {
+    let temp = tempdir()?;
+    let workdir = base_workdir(&temp);
+    let archive_path = workdir.join("pax.tar");
+
+    let mut builder = tar::Builder::new(fs::File::create(archive_path.as_std_path())?);
+    let mut global = tar::Header::new_ustar();
+    let global_data = b"15 uname=alice\n";
+    global.set_path("pax_global_header")?;
+    global.set_size(global_data.len() as u64);
+    global.set_entry_type(tar::EntryType::XGlobalHeader);
+    global.set_cksum();
+    builder.append(&global, &global_data[..])?;
+
+    let long_name = format!("{}/file.txt", "d".repeat(120));
+    builder.append_pax_extensions([
+        ("path", long_name.as_bytes()),
+        ("mtime", b"1700000000.25".as_slice()),
+        ("SCHILY.xattr.user.tag", b"blue".as_slice()),
+    ])?;
+    let mut header = tar::Header::new_ustar();
+    header.set_path("short.txt")?;
+    header.set_size(4);
+    header.set_mode(0o644);
+    header.set_mtime(1_700_000_000);
+    header.set_entry_type(tar::EntryType::Regular);
+    header.set_cksum();
+    builder.append(&header, &b"data"[..])?;
+    builder.into_inner()?;
+
+    let entries = list_archive(&ListOptions {
         archive_path,
-        inputs: vec![Utf8PathBuf::from("input")],
-        work_dir: Some(workdir.clone()),
-        compression: Compression::None,
         verbose: false,
         quiet: true,
-        print_plan: false,
-        excludes: Vec::new(),
-        exclude_from: Vec::new(),
-        manifest_out: None,
-        numeric_owner: false,
-        no_same_owner: true,
+        json: true,
+        selection: MemberSelection::default(),
+        limits: PolicyLimits::default(),
+    })?;
+    assert_eq!(entries.len(), 1, "global header is not a member");
+    let entry = &entries[0];
+    assert_eq!(entry.path, long_name);
+    let pax = entry.pax.as_ref().expect("pax metadata");
+    assert_eq!(pax.uname.as_deref(), Some("alice"));
+    assert_eq!(
+        pax.mtime.map(|t| (t.secs, t.nanos)),
+        Some((1_700_000_000, 250_000_000))
+    );
+    assert_eq!(
+        pax.xattrs.get("user.tag").map(Vec::as_slice),
+        Some(&b"blue"[..])
+    );
+
+    let json = serde_json::to_value(entry)?;
+    assert_eq!(json["pax"]["xattrs"]["user.tag"], "626c7565");
+    Ok(())
+}
+
+#[test]
+fn pax_format_roundtrips_long_and_non_ascii_names() -> Result<()> {
+    let temp = tempdir()?;
+    let workdir = base_workdir(&temp);
+    let input_dir = temp_utf8_path(&temp, "input");
+    let long_dir = input_dir.join("d".repeat(90)).join("e".repeat(40));
+    fs::create_dir_all(long_dir.as_std_path())?;
+    fs::write(long_dir.join("payload.txt").as_std_path(), b"long")?;
+    fs::write(input_dir.join("größe.txt").as_std_path(), b"umlaut")?;
+
+    let archive_path = workdir.join("pax.tar");
+    let create_opts = CreateOptions {
+        format: ArchiveFormat::Pax,
+        ..create_options(&archive_path, &workdir)
     };
+    create_archive(&create_opts, &SecurityPolicy::new())?;
+
+    let listed = list_archive(&ListOptions {
+        archive_path: archive_path.clone(),
+        verbose: false,
+        quiet: true,
+        json: false,
+        selection: MemberSelection::default(),
+        limits: PolicyLimits::default(),
+    })?;
+    let long_member = format!("{}/{}/payload.txt", "d".repeat(90), "e".repeat(40));
+    let long_entry = listed
+        .iter()
+        .find(|entry| entry.path == long_member)
+        .expect("long member listed");
+    assert_eq!(
+        long_entry.pax.as_ref().and_then(|pax| pax.path.as_deref()),
+        Some(long_member.as_str())
+    );
+    assert!(listed.iter().any(|entry| entry.path == "größe.txt"));
+
+    let extract_dir = workdir.join("pax_extract");
+    let extract_opts = extract_options(&archive_path, &extract_dir);
+    extract_archive(&extract_opts, &SecurityPolicy::new())?;
+    assert_eq!(
+        fs::read(extract_dir.join(&long_member).as_std_path())?,
+        b"long"
+    );
+    assert_eq!(
+        fs::read(extract_dir.join("größe.txt").as_std_path())?,
+        b"umlaut"
+    );
+    Ok(())
+}
+
+#[test]
+fn ustar_format_rejects_overlong_names() -> Result<()> {
+    let temp = tempdir()?;
+    let workdir = base_workdir(&temp);
+    let input_dir = temp_utf8_path(&temp, "input");
+    fs::create_dir_all(input_dir.as_std_path())?;
+    fs::write(input_dir.join("n".repeat(120)).as_std_path(), b"x")?;
 
+    let create_opts = CreateOptions {
+        format: ArchiveFormat::Ustar,
+        ..create_options(&workdir.join("ustar.tar"), &workdir)
+    };
     let err = create_archive(&create_opts, &SecurityPolicy::new())
-        .expect_err("symlink escape should be rejected");
-    assert!(err.to_string().contains("link target escapes root"));
+        .expect_err("ustar cannot store a 120 byte name");
+    assert!(format!("{err:#}").contains("--format pax"));
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn preserves_hardlinks() -> Result<()> {
+    use std::os::unix::fs::MetadataExt;
+
+    let temp = tempdir()?;
+    let workdir = base_workdir(&temp);
+    let input_dir = temp_utf8_path(&temp, "input");
+    fs::create_dir_all(input_dir.as_std_path())?;
+    fs::write(input_dir.join("a.bin").as_std_path(), b"shared payload")?;
+    fs::hard_link(
+        input_dir.join("a.bin").as_std_path(),
+        input_dir.join("b.bin").as_std_path(),
+    )?;
+
+    let archive_path = workdir.join("links.tar");
+    let create_opts = create_options(&archive_path, &workdir);
+    let created = create_archive(&create_opts, &SecurityPolicy::new())?;
+    assert_eq!(
+        created
+            .iter()
+            .filter(|entry| entry.kind == ManifestKind::Hardlink)
+            .count(),
+        1
+    );
+
+    let extract_dir = workdir.join("links_extract");
+    let extract_opts = extract_options(&archive_path, &extract_dir);
+    extract_archive(&extract_opts, &SecurityPolicy::new())?;
+    let first = fs::metadata(extract_dir.join("a.bin").as_std_path())?;
+    let second = fs::metadata(extract_dir.join("b.bin").as_std_path())?;
+    assert_eq!(first.ino(), second.ino());
+    assert_eq!(
+        fs::read(extract_dir.join("b.bin").as_std_path())?,
+        b"shared payload"
+    );
+    Ok(())
+}
+
+fn hardlink_archive(path: &Utf8PathBuf, target: &str) -> Result<()> {
+    let mut builder = tar::Builder::new(fs::File::create(path.as_std_path())?);
+    let mut link = tar::Header::new_gnu();
+    link.set_entry_type(tar::EntryType::Link);
+    link.set_size(0);
+    link.set_mode(0o644);
+    builder.append_link(&mut link, "innocent.txt", target)?;
+    builder.into_inner()?;
+    Ok(())
+}
+
+#[test]
+fn rejects_hardlink_to_unextracted_or_outside_target() -> Result<()> {
+    let temp = tempdir()?;
+    let workdir = base_workdir(&temp);
+    for (name, target) in [
+        ("missing.tar", "not-there.txt"),
+        ("escape.tar", "/etc/passwd"),
+    ] {
+        let archive_path = workdir.join(name);
+        hardlink_archive(&archive_path, target)?;
+        let extract_opts = extract_options(&archive_path, &workdir.join(format!("{name}.out")));
+        let err = extract_archive(&extract_opts, &SecurityPolicy::new())
+            .expect_err("hard link must be rejected");
+        assert!(err.downcast_ref::<PolicyError>().is_some(), "{err:#}");
+        assert!(!workdir.join(format!("{name}.out/innocent.txt")).exists());
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn fifo_entries_follow_special_entry_policy() -> Result<()> {
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+    use safetar::policy::{SpecialEntryAction, SpecialEntryKind};
+
+    let temp = tempdir()?;
+    let workdir = base_workdir(&temp);
+    let input_dir = temp_utf8_path(&temp, "input");
+    fs::create_dir_all(input_dir.as_std_path())?;
+    fs::write(input_dir.join("plain.txt").as_std_path(), b"plain")?;
+    rustix::fs::mknodat(
+        rustix::fs::CWD,
+        input_dir.join("pipe").as_std_path(),
+        rustix::fs::FileType::Fifo,
+        rustix::fs::Mode::from_raw_mode(0o644),
+        0,
+    )?;
+
+    let archive_path = workdir.join("fifo.tar");
+    let mut create_opts = CreateOptions {
+        print_plan: true,
+        ..create_options(&archive_path, &workdir)
+    };
+    let planned = create_archive(&create_opts, &SecurityPolicy::new())?;
+    assert!(!planned.iter().any(|entry| entry.path == "pipe"));
+
+    create_opts.print_plan = false;
+    create_opts.include_special = true;
+    let created = create_archive(&create_opts, &SecurityPolicy::new())?;
+    assert!(created
+        .iter()
+        .any(|entry| entry.path == "pipe" && entry.kind == ManifestKind::Fifo));
+
+    let extract = |name: &str, policy: SecurityPolicy| {
+        let options = extract_options(&archive_path, &workdir.join(name));
+        extract_archive(&options, &policy)
+    };
+
+    let err = extract("rejected", SecurityPolicy::new()).expect_err("fifo rejected by default");
+    assert!(matches!(
+        err.downcast_ref::<PolicyError>(),
+        Some(PolicyError::SpecialEntryRejected { .. })
+    ));
+
+    let skipped = extract(
+        "skipped",
+        SecurityPolicy::new()
+            .with_special_entry_action(SpecialEntryKind::Fifo, SpecialEntryAction::Skip),
+    )?;
+    assert!(!skipped.iter().any(|entry| entry.path == "pipe"));
+    assert!(workdir.join("skipped/plain.txt").exists());
+    assert!(!workdir.join("skipped/pipe").exists());
+
+    extract(
+        "created",
+        SecurityPolicy::new()
+            .with_special_entry_action(SpecialEntryKind::Fifo, SpecialEntryAction::Create)
+            .with_mode_rules(ModeRules {
+                umask: 0o077,
+                ..ModeRules::default()
+            }),
+    )?;
+    let created_pipe = fs::symlink_metadata(workdir.join("created/pipe").as_std_path())?;
+    assert!(created_pipe.file_type().is_fifo());
+    assert_eq!(created_pipe.permissions().mode() & 0o7777, 0o600);
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn sparse_files_roundtrip_with_holes() -> Result<()> {
+    use std::io::{Seek, SeekFrom, Write};
+    use std::os::unix::fs::MetadataExt;
+
+    const LOGICAL: u64 = 8 << 20;
+
+    let temp = tempdir()?;
+    let workdir = base_workdir(&temp);
+    let input_dir = temp_utf8_path(&temp, "input");
+    fs::create_dir_all(input_dir.as_std_path())?;
+    let image = input_dir.join("disk.img");
+    {
+        let mut file = fs::File::create(image.as_std_path())?;
+        file.set_len(LOGICAL)?;
+        file.write_all(b"boot sector")?;
+        file.seek(SeekFrom::Start(LOGICAL / 2))?;
+        file.write_all(&[0xAB; 4096])?;
+    }
+    if fs::metadata(image.as_std_path())?.blocks() * 512 >= LOGICAL {
+        // The filesystem does not support holes; nothing to verify.
+        return Ok(());
+    }
+    let original = fs::read(image.as_std_path())?;
+
+    for format in [ArchiveFormat::Gnu, ArchiveFormat::Pax] {
+        let archive_path = workdir.join(format!("{format:?}.tar"));
+        let create_opts = CreateOptions {
+            format,
+            sparse: true,
+            ..create_options(&archive_path, &workdir)
+        };
+        let created = create_archive(&create_opts, &SecurityPolicy::new())?;
+        assert!(fs::metadata(archive_path.as_std_path())?.len() < LOGICAL / 8);
+
+        let listed = list_archive(&ListOptions {
+            archive_path: archive_path.clone(),
+            verbose: false,
+            quiet: true,
+            json: false,
+            selection: MemberSelection::default(),
+            limits: PolicyLimits::default(),
+        })?;
+        let listed_image = listed
+            .iter()
+            .find(|entry| entry.path == "disk.img")
+            .expect("sparse member listed under its real name");
+        assert_eq!(listed_image.size, LOGICAL);
+        let created_image = created.iter().find(|entry| entry.path == "disk.img");
+        assert_eq!(
+            created_image.map(|entry| &entry.sha256),
+            Some(&listed_image.sha256)
+        );
+
+        let extract_dir = workdir.join(format!("{format:?}_out"));
+        let extract_opts = extract_options(&archive_path, &extract_dir);
+        extract_archive(&extract_opts, &SecurityPolicy::new())?;
+        let restored = extract_dir.join("disk.img");
+        assert_eq!(fs::read(restored.as_std_path())?, original);
+        assert!(fs::metadata(restored.as_std_path())?.blocks() * 512 < LOGICAL);
+
+        // Quotas see the logical size even though little is stored.
+        let err = extract_archive(
+            &ExtractOptions {
+                destination: workdir.join(format!("{format:?}_quota")),
+                ..extract_opts
+            },
+            &SecurityPolicy::new().with_max_total_bytes(Some(1 << 20)),
+        )
+        .expect_err("logical size exceeds quota");
+        assert!(matches!(
+            err.downcast_ref::<PolicyError>(),
+            Some(PolicyError::TotalBytesExceeded { .. } | PolicyError::SingleFileTooLarge { .. })
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn byte_paths_roundtrip_non_utf8_names() -> Result<()> {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    let temp = tempdir()?;
+    let workdir = base_workdir(&temp);
+    let input_dir = temp.path().join("input");
+    fs::create_dir_all(&input_dir)?;
+    let raw_name = OsStr::from_bytes(b"caf\xe9.txt");
+    fs::write(input_dir.join(raw_name), b"latin-1")?;
+    std::os::unix::fs::symlink(raw_name, input_dir.join("link"))?;
+
+    let archive_path = workdir.join("raw.tar");
+    let manifest_path = workdir.join("raw.json");
+    let mut create_opts = CreateOptions {
+        manifest_out: Some(manifest_path.clone()),
+        ..create_options(&archive_path, &workdir)
+    };
+    assert!(create_archive(&create_opts, &SecurityPolicy::new()).is_err());
+
+    let byte_policy = SecurityPolicy::new().with_byte_paths(true);
+    for format in [ArchiveFormat::Gnu, ArchiveFormat::Pax] {
+        create_opts.format = format;
+        let created = create_archive(&create_opts, &byte_policy)?;
+        let file_entry = created
+            .iter()
+            .find(|entry| entry.path == "caf\\xe9.txt")
+            .expect("raw name recorded escaped");
+        assert_eq!(file_entry.path_b64.as_deref(), Some("Y2Fm6S50eHQ="));
+        let link_entry = created
+            .iter()
+            .find(|entry| entry.path == "link")
+            .expect("symlink recorded");
+        assert_eq!(link_entry.target_b64.as_deref(), Some("Y2Fm6S50eHQ="));
+
+        let listed = list_archive(&ListOptions {
+            archive_path: archive_path.clone(),
+            verbose: false,
+            quiet: true,
+            json: false,
+            selection: MemberSelection::default(),
+            limits: PolicyLimits::default(),
+        })?;
+        assert!(listed
+            .iter()
+            .any(|entry| entry.path_b64.as_deref() == Some("Y2Fm6S50eHQ=")));
+
+        let extract_dir = workdir.join(format!("{format:?}_out"));
+        let extract_opts = ExtractOptions {
+            manifest: Some(manifest_path.clone()),
+            ..extract_options(&archive_path, &extract_dir)
+        };
+        let err = extract_archive(&extract_opts, &SecurityPolicy::new())
+            .expect_err("raw names need byte paths");
+        assert!(matches!(
+            err.downcast_ref::<PolicyError>(),
+            Some(PolicyError::InvalidName(_))
+        ));
+
+        extract_archive(&extract_opts, &byte_policy)?;
+        let restored = extract_dir.as_std_path().join(raw_name);
+        assert_eq!(fs::read(&restored)?, b"latin-1");
+        assert_eq!(
+            fs::read_link(extract_dir.join("link").as_std_path())?,
+            std::path::Path::new(raw_name)
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn append_and_update_extend_archive_under_policy() -> Result<()> {
+    let temp = tempdir()?;
+    let workdir = base_workdir(&temp);
+    fs::write(workdir.join("a.txt").as_std_path(), b"first")?;
+    fs::write(workdir.join("b.txt").as_std_path(), b"second")?;
+
+    let archive_path = workdir.join("grow.tar");
+    let manifest_path = workdir.join("grow.json");
+    let mut options = CreateOptions {
+        inputs: vec![Utf8PathBuf::from("a.txt")],
+        manifest_out: Some(manifest_path.clone()),
+        ..create_options(&archive_path, &workdir)
+    };
+    create_archive(&options, &SecurityPolicy::new())?;
+
+    options.inputs = vec![Utf8PathBuf::from("b.txt")];
+    options.format = ArchiveFormat::Pax;
+    let added = append_archive(&options, AppendMode::Append, &SecurityPolicy::new())?;
+    assert_eq!(added.len(), 1);
+
+    // Only the modified file is added again.
+    fs::write(workdir.join("a.txt").as_std_path(), b"first, revised")?;
+    options.inputs = vec![Utf8PathBuf::from("a.txt"), Utf8PathBuf::from("b.txt")];
+    let added = append_archive(&options, AppendMode::Update, &SecurityPolicy::new())?;
+    let added: Vec<_> = added.iter().map(|entry| entry.path.as_str()).collect();
+    assert_eq!(added, ["a.txt"]);
+
+    let listed = list_archive(&ListOptions {
+        archive_path: archive_path.clone(),
+        verbose: false,
+        quiet: true,
+        json: false,
+        selection: MemberSelection::default(),
+        limits: PolicyLimits::default(),
+    })?;
+    let listed: Vec<_> = listed.iter().map(|entry| entry.path.as_str()).collect();
+    assert_eq!(listed, ["a.txt", "b.txt", "a.txt"]);
+
+    // The sidecar manifest describes the combined archive, so extraction verifies.
+    let extract_dir = workdir.join("out");
+    extract_archive(
+        &ExtractOptions {
+            manifest: Some(manifest_path.clone()),
+            ..extract_options(&archive_path, &extract_dir)
+        },
+        &SecurityPolicy::new(),
+    )?;
+    assert_eq!(
+        fs::read_to_string(extract_dir.join("a.txt").as_std_path())?,
+        "first, revised"
+    );
+
+    // Limits cover existing and new members together.
+    let before = fs::read(archive_path.as_std_path())?;
+    options.inputs = vec![Utf8PathBuf::from("b.txt")];
+    let err = append_archive(
+        &options,
+        AppendMode::Append,
+        &SecurityPolicy::new().with_max_files(Some(3)),
+    )
+    .expect_err("fourth member exceeds the file limit");
+    assert!(matches!(
+        err.downcast_ref::<PolicyError>(),
+        Some(PolicyError::FileCountExceeded { limit: 3, .. })
+    ));
+    assert_eq!(fs::read(archive_path.as_std_path())?, before);
+
+    options.archive_path = workdir.join("grow.tar.gz");
+    options.compression = Compression::Gzip;
+    create_archive(&options, &SecurityPolicy::new())?;
+    options.compression = Compression::None;
+    assert!(append_archive(&options, AppendMode::Append, &SecurityPolicy::new()).is_err());
+    Ok(())
+}
+
+#[test]
+fn delete_rewrites_archive_with_same_codec() -> Result<()> {
+    let temp = tempdir()?;
+    let workdir = base_workdir(&temp);
+    let input_dir = temp_utf8_path(&temp, "vendor");
+    let long_dir = input_dir.join("x".repeat(120));
+    fs::create_dir_all(input_dir.join("internal").as_std_path())?;
+    fs::create_dir_all(long_dir.as_std_path())?;
+    fs::write(input_dir.join("lib.rs").as_std_path(), b"pub fn f() {}")?;
+    fs::write(input_dir.join("build.log").as_std_path(), b"noise")?;
+    fs::write(
+        input_dir.join("internal/notes.txt").as_std_path(),
+        b"secret",
+    )?;
+    fs::write(long_dir.join("kept.txt").as_std_path(), b"long name")?;
+
+    for (codec, format) in [
+        (Compression::Zstd, ArchiveFormat::Gnu),
+        (Compression::None, ArchiveFormat::Pax),
+    ] {
+        let archive_path = workdir.join(format!("vendor_{codec:?}.tar"));
+        let options = CreateOptions {
+            inputs: vec![Utf8PathBuf::from("vendor")],
+            compression: codec,
+            format,
+            ..create_options(&archive_path, &workdir)
+        };
+        create_archive(&options, &SecurityPolicy::new())?;
+
+        let mut delete = DeleteOptions {
+            archive_path: archive_path.clone(),
+            patterns: vec!["internal".into(), "*.log".into(), "missing".into()],
+            verbose: false,
+            quiet: true,
+        };
+        let before = fs::read(archive_path.as_std_path())?;
+        let err = delete_members(&delete).expect_err("unmatched pattern");
+        assert!(err.to_string().contains("missing"));
+        assert_eq!(fs::read(archive_path.as_std_path())?, before);
+
+        delete.patterns.pop();
+        let removed = delete_members(&delete)?;
+        assert_eq!(removed.len(), 3);
+        let rewritten = fs::read(archive_path.as_std_path())?;
+        assert_eq!(Compression::detect(&rewritten), codec);
+
+        let listed = list_archive(&ListOptions {
+            archive_path: archive_path.clone(),
+            verbose: false,
+            quiet: true,
+            json: false,
+            selection: MemberSelection::default(),
+            limits: PolicyLimits::default(),
+        })?;
+        let mut listed: Vec<_> = listed.into_iter().map(|entry| entry.path).collect();
+        listed.sort();
+        assert_eq!(
+            listed,
+            [
+                "lib.rs".to_owned(),
+                "x".repeat(120),
+                format!("{}/kept.txt", "x".repeat(120)),
+            ]
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn concat_merges_mixed_codecs_under_one_policy() -> Result<()> {
+    let temp = tempdir()?;
+    let workdir = base_workdir(&temp);
+    let mut components = Vec::new();
+    for (name, codec) in [
+        ("core", Compression::Gzip),
+        ("cli", Compression::Zstd),
+        ("docs", Compression::None),
+    ] {
+        let component = temp_utf8_path(&temp, name);
+        fs::create_dir_all(component.join("share").as_std_path())?;
+        fs::write(
+            component.join(format!("share/{name}.txt")).as_std_path(),
+            name.as_bytes(),
+        )?;
+        if name == "docs" {
+            // Collides with the file shipped by `core`.
+            fs::write(component.join("share/core.txt").as_std_path(), b"shadow")?;
+        }
+        let archive_path = workdir.join(format!("{name}.tar"));
+        create_archive(
+            &CreateOptions {
+                inputs: vec![Utf8PathBuf::from(name)],
+                compression: codec,
+                ..create_options(&archive_path, &workdir)
+            },
+            &SecurityPolicy::new(),
+        )?;
+        components.push(archive_path);
+    }
+
+    let bundle = workdir.join("bundle.tar");
+    let mut options = ConcatOptions {
+        archive_path: bundle.clone(),
+        inputs: components.clone(),
+        compression: Compression::Xz,
+        allow_duplicates: false,
+        verbose: false,
+        quiet: true,
+    };
+    let err = concat_archives(&options, &SecurityPolicy::new()).expect_err("duplicate path");
+    assert!(matches!(
+        err.downcast_ref::<PolicyError>(),
+        Some(PolicyError::DuplicatePath(path)) if path == "share/core.txt"
+    ));
+    assert!(!bundle.exists());
+
+    options.inputs.truncate(2);
+    let merged = concat_archives(&options, &SecurityPolicy::new())?;
+    // The `share` directory appears in both inputs but is stored once.
+    assert_eq!(merged, ["share", "share/core.txt", "share/cli.txt"]);
+    assert_eq!(
+        Compression::detect(&fs::read(bundle.as_std_path())?),
+        Compression::Xz
+    );
+    let listed = list_archive(&ListOptions {
+        archive_path: bundle.clone(),
+        verbose: false,
+        quiet: true,
+        json: false,
+        selection: MemberSelection::default(),
+        limits: PolicyLimits::default(),
+    })?;
+    assert_eq!(listed.len(), 3);
+
+    // Quotas apply to the merged contents, not to each input on its own.
+    let err = concat_archives(
+        &options,
+        &SecurityPolicy::new().with_max_total_bytes(Some(6)),
+    )
+    .expect_err("combined size exceeds the quota");
+    assert!(matches!(
+        err.downcast_ref::<PolicyError>(),
+        Some(PolicyError::TotalBytesExceeded { limit: 6, .. })
+    ));
+
+    options.inputs = components;
+    options.allow_duplicates = true;
+    assert_eq!(concat_archives(&options, &SecurityPolicy::new())?.len(), 5);
+
+    // Members are checked, and stored, under the name extraction resolves.
+    let crafted = workdir.join("crafted.tar");
+    let mut builder = tar::Builder::new(fs::File::create(crafted.as_std_path())?);
+    append_conflicting_names(&mut builder, "../../escape.txt", "ok.txt", b"x")?;
+    builder.into_inner()?;
+    let shadowing = workdir.join("shadowing.tar");
+    let mut builder = tar::Builder::new(fs::File::create(shadowing.as_std_path())?);
+    append_conflicting_names(&mut builder, "share/core.txt", "share/decoy.txt", b"shadow")?;
+    builder.into_inner()?;
+    let merged_path = workdir.join("merged.tar");
+    let concat = |inputs: Vec<Utf8PathBuf>| {
+        concat_archives(
+            &ConcatOptions {
+                archive_path: merged_path.clone(),
+                inputs,
+                compression: Compression::None,
+                allow_duplicates: false,
+                verbose: false,
+                quiet: true,
+            },
+            &SecurityPolicy::new(),
+        )
+    };
+    let err = concat(vec![crafted]).expect_err("the long name escapes");
+    assert!(
+        matches!(
+            err.downcast_ref::<PolicyError>(),
+            Some(PolicyError::ParentTraversal(_))
+        ),
+        "{err:#}"
+    );
+    assert!(!merged_path.exists());
+    let err = concat(vec![options.inputs[0].clone(), shadowing.clone()])
+        .expect_err("the long name repeats a path");
+    assert!(
+        matches!(
+            err.downcast_ref::<PolicyError>(),
+            Some(PolicyError::DuplicatePath(path)) if path == "share/core.txt"
+        ),
+        "{err:#}"
+    );
+    assert_eq!(concat(vec![shadowing])?, ["share/core.txt"]);
+    let merged = fs::read(merged_path.as_std_path())?;
+    assert!(!merged.windows(5).any(|window| window == b"decoy"));
+    let listed = list_archive(&ListOptions {
+        archive_path: merged_path.clone(),
+        verbose: false,
+        quiet: true,
+        json: false,
+        selection: MemberSelection::default(),
+        limits: PolicyLimits::default(),
+    })?;
+    assert_eq!(listed[0].path, "share/core.txt");
+    Ok(())
+}
+
+#[test]
+fn diff_reports_changes_since_archiving() -> Result<()> {
+    let temp = tempdir()?;
+    let workdir = base_workdir(&temp);
+    let input_dir = temp_utf8_path(&temp, "input");
+    fs::create_dir_all(input_dir.join("sub").as_std_path())?;
+    fs::write(input_dir.join("same.txt").as_std_path(), b"same")?;
+    fs::write(input_dir.join("edit.txt").as_std_path(), b"before")?;
+    fs::write(input_dir.join("sub/gone.txt").as_std_path(), b"gone")?;
+    let archive_path = workdir.join("archive.tar");
+    create_archive(
+        &CreateOptions {
+            compression: Compression::Gzip,
+            format: ArchiveFormat::Pax,
+            ..create_options(&archive_path, &workdir)
+        },
+        &SecurityPolicy::new(),
+    )?;
+    let dest = temp_utf8_path(&temp, "restore");
+    extract_archive(
+        &extract_options(&archive_path, &dest),
+        &SecurityPolicy::new(),
+    )?;
+
+    let mut options = DiffOptions {
+        archive_path,
+        directory: dest.clone(),
+        report_extra: true,
+        quiet: true,
+        json: false,
+    };
+    assert!(diff_archive(&options, &SecurityPolicy::new())?.is_empty());
+
+    // Same size, different bytes: only the digest can tell.
+    fs::write(dest.join("edit.txt").as_std_path(), b"after!")?;
+    fs::remove_file(dest.join("sub/gone.txt").as_std_path())?;
+    fs::write(dest.join("sub/new.txt").as_std_path(), b"new")?;
+    let differences = diff_archive(&options, &SecurityPolicy::new())?;
+    let found: Vec<_> = differences
+        .iter()
+        .map(|difference| (difference.path.as_str(), difference.kind))
+        .collect();
+    assert!(found.contains(&("edit.txt", DiffKind::Contents)));
+    assert!(found.contains(&("edit.txt", DiffKind::Mtime)));
+    assert!(found.contains(&("sub/gone.txt", DiffKind::Missing)));
+    assert!(found.contains(&("sub/new.txt", DiffKind::Extra)));
+    assert!(!found.iter().any(|(path, _)| *path == "same.txt"));
+
+    options.report_extra = false;
+    let differences = diff_archive(&options, &SecurityPolicy::new())?;
+    assert!(differences
+        .iter()
+        .all(|difference| difference.kind != DiffKind::Extra));
+    Ok(())
+}
+
+#[test]
+fn extract_and_list_only_selected_members() -> Result<()> {
+    let temp = tempdir()?;
+    let workdir = base_workdir(&temp);
+    let input_dir = temp_utf8_path(&temp, "input");
+    fs::create_dir_all(input_dir.join("docs/drafts").as_std_path())?;
+    fs::create_dir_all(input_dir.join("bin").as_std_path())?;
+    fs::write(input_dir.join("docs/guide.md").as_std_path(), b"guide")?;
+    fs::write(input_dir.join("docs/drafts/wip.md").as_std_path(), b"wip")?;
+    fs::write(input_dir.join("bin/tool").as_std_path(), vec![0_u8; 4096])?;
+    let archive_path = workdir.join("archive.tar");
+    create_archive(
+        &create_options(&archive_path, &workdir),
+        &SecurityPolicy::new(),
+    )?;
+    let list_file = workdir.join("members.txt");
+    fs::write(list_file.as_std_path(), "./docs/\n\n")?;
+    let selection = MemberSelection {
+        files_from: vec![list_file],
+        excludes: vec!["*/drafts".to_owned()],
+        ..MemberSelection::default()
+    };
+
+    let listed = list_archive(&ListOptions {
+        archive_path: archive_path.clone(),
+        verbose: false,
+        quiet: true,
+        json: false,
+        selection: selection.clone(),
+        limits: PolicyLimits::default(),
+    })?;
+    let listed: Vec<_> = listed.iter().map(|entry| entry.path.as_str()).collect();
+    assert_eq!(listed, ["docs", "docs/guide.md"]);
+
+    // `bin/tool` alone would exceed the byte quota; skipped members do not count.
+    let dest = temp_utf8_path(&temp, "restore");
+    let mut options = ExtractOptions {
+        selection,
+        ..extract_options(&archive_path, &dest)
+    };
+    let policy = SecurityPolicy::new()
+        .with_max_total_bytes(Some(1024))
+        .with_max_files(Some(2));
+    extract_archive(&options, &policy)?;
+    assert!(dest.join("docs/guide.md").exists());
+    assert!(!dest.join("docs/drafts").exists());
+    assert!(!dest.join("bin").exists());
+
+    options.destination = temp_utf8_path(&temp, "globbed");
+    options.selection = MemberSelection {
+        members: vec!["*/*.md".to_owned(), "missing".to_owned()],
+        wildcards: true,
+        ..MemberSelection::default()
+    };
+    let err = extract_archive(&options, &SecurityPolicy::new()).expect_err("unmatched operand");
+    assert!(err.to_string().contains("not found in archive: missing"));
+    assert!(options.destination.join("docs/drafts/wip.md").exists());
+    assert!(!options.destination.join("bin/tool").exists());
+
+    // Only selected members are validated, so an unselected hostile name is harmless.
+    let mixed_path = workdir.join("mixed.tar");
+    let mut builder = tar::Builder::new(fs::File::create(mixed_path.as_std_path())?);
+    let mut header = tar::Header::new_gnu();
+    header.as_gnu_mut().expect("gnu header").name[..11].copy_from_slice(b"../evil.txt");
+    header.set_size(4);
+    header.set_mode(0o644);
+    header.set_cksum();
+    builder.append(&header, &b"evil"[..])?;
+    let mut header = tar::Header::new_gnu();
+    header.set_size(4);
+    header.set_mode(0o644);
+    builder.append_data(&mut header, "good.txt", &b"good"[..])?;
+    builder.into_inner()?;
+    options.archive_path = mixed_path;
+    options.destination = temp_utf8_path(&temp, "mixed");
+    options.selection = MemberSelection {
+        members: vec!["good.txt".to_owned()],
+        ..MemberSelection::default()
+    };
+    extract_archive(&options, &SecurityPolicy::new())?;
+    assert!(options.destination.join("good.txt").exists());
+    assert!(!temp_utf8_path(&temp, "evil.txt").exists());
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn strip_components_and_transform_rewrite_names_and_links() -> Result<()> {
+    let temp = tempdir()?;
+    let workdir = base_workdir(&temp);
+    let top = temp_utf8_path(&temp, "input/pkg-1.0");
+    fs::create_dir_all(top.join("src").as_std_path())?;
+    fs::write(top.join("src/main.c").as_std_path(), b"int main;")?;
+    fs::hard_link(
+        top.join("src/main.c").as_std_path(),
+        top.join("src/copy.c").as_std_path(),
+    )?;
+    std::os::unix::fs::symlink("src/main.c", top.join("entry").as_std_path())?;
+    let archive_path = workdir.join("upstream.tar");
+    create_archive(
+        &create_options(&archive_path, &workdir),
+        &SecurityPolicy::new(),
+    )?;
+
+    let extract = |dest: &str, rewrite: NameRewrite| {
+        extract_archive(
+            &ExtractOptions {
+                rewrite,
+                ..extract_options(&archive_path, &temp_utf8_path(&temp, dest))
+            },
+            &SecurityPolicy::new(),
+        )
+    };
+
+    extract(
+        "stripped",
+        NameRewrite {
+            strip_components: 1,
+            transforms: Vec::new(),
+        },
+    )?;
+    let stripped = temp_utf8_path(&temp, "stripped");
+    assert_eq!(
+        fs::read(stripped.join("src/main.c").as_std_path())?,
+        b"int main;"
+    );
+    assert_eq!(
+        fs::read_link(stripped.join("entry").as_std_path())?,
+        std::path::Path::new("src/main.c")
+    );
+    {
+        use std::os::unix::fs::MetadataExt;
+        let main = fs::metadata(stripped.join("src/main.c").as_std_path())?;
+        let copy = fs::metadata(stripped.join("src/copy.c").as_std_path())?;
+        assert_eq!(main.ino(), copy.ino(), "hard link target was stripped too");
+    }
+
+    // Names and link targets are rewritten alike; `S` leaves symlink targets alone.
+    extract(
+        "renamed",
+        NameRewrite {
+            strip_components: 0,
+            transforms: vec![
+                r"s,^pkg-[0-9.]*/,pkg/,".to_owned(),
+                "s,main,app,S".to_owned(),
+            ],
+        },
+    )?;
+    let renamed = temp_utf8_path(&temp, "renamed");
+    assert!(renamed.join("pkg/src/app.c").exists());
+    assert!(renamed.join("pkg/src/copy.c").exists());
+    assert_eq!(
+        fs::read_link(renamed.join("pkg/entry").as_std_path())?,
+        std::path::Path::new("src/main.c")
+    );
+
+    let err = extract(
+        "escaped",
+        NameRewrite {
+            strip_components: 0,
+            transforms: vec!["s,^pkg-1.0,../escaped,".to_owned()],
+        },
+    )
+    .expect_err("transform adds traversal");
+    assert!(matches!(
+        err.downcast_ref::<PolicyError>(),
+        Some(PolicyError::ParentTraversal(_))
+    ));
+    assert_eq!(fs::read_dir(temp.path().join("escaped"))?.count(), 0);
+    Ok(())
+}
+
+#[test]
+fn indexed_extract_seeks_to_members_under_policy() -> Result<()> {
+    let temp = tempdir()?;
+    let workdir = base_workdir(&temp);
+    let input_dir = temp_utf8_path(&temp, "input");
+    fs::create_dir_all(input_dir.join("etc").as_std_path())?;
+    fs::write(input_dir.join("etc/app.conf").as_std_path(), b"port = 80")?;
+    fs::write(
+        input_dir.join("bulk.bin").as_std_path(),
+        vec![7_u8; 3 << 20],
+    )?;
+    let archive_path = workdir.join("bundle.tar.zst");
+    create_archive(
+        &CreateOptions {
+            compression: Compression::Zstd,
+            seekable: true,
+            ..create_options(&archive_path, &workdir)
+        },
+        &SecurityPolicy::new(),
+    )?;
+    let index_path = workdir.join("bundle.tar.zst.idx");
+    let index = index_archive(&IndexOptions {
+        archive_path: archive_path.clone(),
+        output: index_path.clone(),
+    })?;
+    assert_eq!(index.compression, "zstd");
+    assert_eq!(index.members.len(), 3);
+
+    let extract = |archive_path: &Utf8PathBuf, index: &Utf8PathBuf, dest: &str, member: &str| {
+        extract_archive(
+            &ExtractOptions {
+                selection: MemberSelection {
+                    members: vec![member.to_owned()],
+                    ..MemberSelection::default()
+                },
+                index: Some(index.clone()),
+                ..extract_options(archive_path, &temp_utf8_path(&temp, dest))
+            },
+            &SecurityPolicy::new(),
+        )
+    };
+
+    let extracted = extract(&archive_path, &index_path, "one", "etc/app.conf")?;
+    assert_eq!(extracted.len(), 1);
+    let one = temp_utf8_path(&temp, "one");
+    assert_eq!(
+        fs::read(one.join("etc/app.conf").as_std_path())?,
+        b"port = 80"
+    );
+    assert!(!one.join("bulk.bin").exists());
+
+    // An index pointing somewhere else is caught by the headers found there.
+    let mut tampered = read_index(&index_path)?;
+    let conf = tampered
+        .members
+        .iter()
+        .position(|member| member.path == "etc/app.conf")
+        .expect("indexed");
+    tampered.members[conf].offset = 0;
+    let tampered_path = workdir.join("tampered.idx");
+    fs::write(tampered_path.as_std_path(), serde_json::to_vec(&tampered)?)?;
+    let err = extract(&archive_path, &tampered_path, "tampered", "etc/app.conf")
+        .expect_err("offset must match the member");
+    assert!(
+        err.to_string().contains("changed since it was indexed"),
+        "{err:#}"
+    );
+
+    // Seeked members are validated like any other.
+    let hostile_path = workdir.join("hostile.tar");
+    let mut builder = tar::Builder::new(fs::File::create(hostile_path.as_std_path())?);
+    let mut header = tar::Header::new_gnu();
+    header.as_gnu_mut().expect("gnu header").name[..11].copy_from_slice(b"../evil.txt");
+    header.set_size(4);
+    header.set_mode(0o644);
+    header.set_cksum();
+    builder.append(&header, &b"evil"[..])?;
+    builder.into_inner()?;
+    let hostile_index = workdir.join("hostile.idx");
+    index_archive(&IndexOptions {
+        archive_path: hostile_path.clone(),
+        output: hostile_index.clone(),
+    })?;
+    let err = extract(&hostile_path, &hostile_index, "hostile/out", "../evil.txt")
+        .expect_err("traversal must be rejected");
+    assert!(err.downcast_ref::<PolicyError>().is_some(), "{err:#}");
+    assert!(!temp_utf8_path(&temp, "hostile/evil.txt").exists());
+    Ok(())
+}
+
+fn pax_record(key: &str, value: &str) -> Vec<u8> {
+    let rest = key.len() + value.len() + 3;
+    let mut len = rest + 1;
+    while len.to_string().len() + rest > len {
+        len += 1;
+    }
+    format!("{len} {key}={value}\n").into_bytes()
+}
+
+/// Append a file whose GNU long name and PAX `path` disagree. Readers that follow the
+/// `tar` crate take the long name.
+fn append_conflicting_names<W: io::Write>(
+    builder: &mut tar::Builder<W>,
+    long_name: &str,
+    pax_path: &str,
+    data: &[u8],
+) -> io::Result<()> {
+    let mut long = tar::Header::new_gnu();
+    long.set_entry_type(tar::EntryType::GNULongName);
+    long.set_path("././@LongLink")?;
+    long.set_size(long_name.len() as u64 + 1);
+    long.set_cksum();
+    builder.append(&long, format!("{long_name}\0").as_bytes())?;
+    let record = pax_record("path", pax_path);
+    let mut pax = tar::Header::new_ustar();
+    pax.set_entry_type(tar::EntryType::XHeader);
+    pax.set_path("PaxHeaders/member")?;
+    pax.set_size(record.len() as u64);
+    pax.set_cksum();
+    builder.append(&pax, record.as_slice())?;
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    builder.append_data(&mut header, "header.txt", data)
+}
+
+#[test]
+fn cat_follows_links_inside_archive_and_verifies_manifest() -> Result<()> {
+    let temp = tempdir()?;
+    let workdir = base_workdir(&temp);
+    let archive_path = workdir.join("bundle.tar");
+    let mut builder = tar::Builder::new(fs::File::create(archive_path.as_std_path())?);
+    let mut dir = tar::Header::new_gnu();
+    dir.set_entry_type(tar::EntryType::Directory);
+    dir.set_mode(0o755);
+    dir.set_size(0);
+    builder.append_data(&mut dir, "etc/v2", io::empty())?;
+    let mut file = tar::Header::new_gnu();
+    file.set_mode(0o644);
+    file.set_size(9);
+    builder.append_data(&mut file, "etc/v2/app.conf", &b"port = 80"[..])?;
+    let mut link = |kind, name: &str, target: &str| {
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(kind);
+        header.set_mode(0o777);
+        header.set_size(0);
+        builder.append_link(&mut header, name, target)
+    };
+    link(tar::EntryType::Symlink, "etc/current", "v2")?;
+    link(tar::EntryType::Symlink, "etc/app.conf", "current/app.conf")?;
+    link(tar::EntryType::Link, "hard.conf", "etc/v2/app.conf")?;
+    link(tar::EntryType::Symlink, "escape", "../../etc/passwd")?;
+    link(tar::EntryType::Symlink, "absolute", "/etc/passwd")?;
+    builder.into_inner()?;
+
+    let cat = |members: &[&str], manifest: Option<Utf8PathBuf>| {
+        let mut out = Vec::new();
+        cat_members(
+            &CatOptions {
+                archive_path: archive_path.clone(),
+                members: members.iter().map(|member| (*member).to_owned()).collect(),
+                manifest,
+                index: None,
+            },
+            &SecurityPolicy::new(),
+            &mut out,
+        )
+        .map(|entries| (out, entries))
+    };
+
+    let (out, entries) = cat(&["etc/app.conf"], None)?;
+    assert_eq!(out, b"port = 80");
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].path, "etc/v2/app.conf");
+    let (out, _) = cat(&["hard.conf", "./etc/current/app.conf"], None)?;
+    assert_eq!(out, b"port = 80");
+
+    let err = cat(&["etc/v2"], None).expect_err("directories are refused");
+    assert!(err.to_string().contains("is a directory"), "{err:#}");
+    for name in ["escape", "absolute", "../etc/passwd"] {
+        let err = cat(&[name], None).expect_err("escapes are refused");
+        assert!(
+            err.downcast_ref::<PolicyError>().is_some(),
+            "{name}: {err:#}"
+        );
+    }
+
+    let manifest_path = workdir.join("bundle.json");
+    write_manifest_json(&entries, &manifest_path)?;
+    let (out, _) = cat(&["etc/app.conf"], Some(manifest_path.clone()))?;
+    assert_eq!(out, b"port = 80");
+
+    let mut tampered = entries;
+    tampered[0].sha256 = "0".repeat(64);
+    write_manifest_json(&tampered, &manifest_path)?;
+    let err = cat(&["etc/app.conf"], Some(manifest_path)).expect_err("digest must match");
+    assert!(err.downcast_ref::<ManifestError>().is_some(), "{err:#}");
+
+    // Links resolve against the names extraction would use, not a conflicting PAX path.
+    let conflicting = workdir.join("conflicting.tar");
+    let mut builder = tar::Builder::new(fs::File::create(conflicting.as_std_path())?);
+    let mut current = tar::Header::new_gnu();
+    current.set_entry_type(tar::EntryType::Symlink);
+    current.set_size(0);
+    builder.append_link(&mut current, "etc/current", "v2")?;
+    append_conflicting_names(
+        &mut builder,
+        "etc/v2/app.conf",
+        "etc/v2/decoy.conf",
+        b"port = 80",
+    )?;
+    builder.into_inner()?;
+    let cat = |member: &str| {
+        let mut out = Vec::new();
+        cat_members(
+            &CatOptions {
+                archive_path: conflicting.clone(),
+                members: vec![member.to_owned()],
+                manifest: None,
+                index: None,
+            },
+            &SecurityPolicy::new(),
+            &mut out,
+        )
+        .map(|_| out)
+    };
+    assert_eq!(cat("etc/current/app.conf")?, b"port = 80");
+    let err = cat("etc/current/decoy.conf").expect_err("the PAX path is not the name");
+    assert!(err.to_string().contains("not found in archive"), "{err:#}");
+
+    // The kinds and link targets an index records are checked against the headers.
+    let index_path = workdir.join("bundle.idx");
+    let index = index_archive(&IndexOptions {
+        archive_path: archive_path.clone(),
+        output: index_path.clone(),
+    })?;
+    let cat_indexed = |member: &str, index: &ArchiveIndex| -> Result<Vec<u8>> {
+        fs::write(index_path.as_std_path(), serde_json::to_vec(index)?)?;
+        let mut out = Vec::new();
+        cat_members(
+            &CatOptions {
+                archive_path: archive_path.clone(),
+                members: vec![member.to_owned()],
+                manifest: None,
+                index: Some(index_path.clone()),
+            },
+            &SecurityPolicy::new(),
+            &mut out,
+        )?;
+        Ok(out)
+    };
+    assert_eq!(cat_indexed("etc/app.conf", &index)?, b"port = 80");
+    let position = |path: &str| {
+        index
+            .members
+            .iter()
+            .position(|member| member.path == path)
+            .expect("indexed")
+    };
+    let mut retargeted = index.clone();
+    retargeted.members[position("etc/app.conf")].target = Some("../hard.conf".to_owned());
+    let err = cat_indexed("etc/app.conf", &retargeted).expect_err("the target changed");
+    assert!(
+        err.to_string().contains("changed since it was indexed"),
+        "{err:#}"
+    );
+    let mut retyped = index.clone();
+    let hard = position("hard.conf");
+    retyped.members[hard].kind = ManifestKind::File;
+    retyped.members[hard].target = None;
+    let err = cat_indexed("hard.conf", &retyped).expect_err("the kind changed");
+    assert!(
+        err.to_string().contains("changed since it was indexed"),
+        "{err:#}"
+    );
+    Ok(())
+}
+
+#[test]
+fn audit_reports_every_violation_without_writing() -> Result<()> {
+    let temp = tempdir()?;
+    let workdir = base_workdir(&temp);
+    let archive_path = workdir.join("vendor.tar");
+    let mut builder = tar::Builder::new(fs::File::create(archive_path.as_std_path())?);
+    let mut file = |name: &[u8], size: usize| {
+        let mut header = tar::Header::new_gnu();
+        header.as_gnu_mut().expect("gnu header").name[..name.len()].copy_from_slice(name);
+        header.set_size(size as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder.append(&header, &vec![b'x'; size][..])
+    };
+    file(b"ok.txt", 1)?;
+    file(b"../evil.txt", 1)?;
+    file(b"/etc/cron.d/job", 1)?;
+    file(b"big.bin", 64)?;
+    file(b"late-1", 1)?;
+    file(b"late-2", 1)?;
+    let mut link = tar::Header::new_gnu();
+    link.set_entry_type(tar::EntryType::Symlink);
+    link.set_size(0);
+    builder.append_link(&mut link, "escape", "../../etc/passwd")?;
+    let mut fifo = tar::Header::new_gnu();
+    fifo.set_entry_type(tar::EntryType::Fifo);
+    fifo.set_size(0);
+    builder.append_data(&mut fifo, "pipe", io::empty())?;
+    builder.into_inner()?;
+
+    let policy = SecurityPolicy::new().with_limits(PolicyLimits {
+        max_files: 3,
+        max_single_file: 32,
+        ..PolicyLimits::default()
+    });
+    let findings = audit_archive(
+        &AuditOptions {
+            archive_path: archive_path.clone(),
+            quiet: true,
+            json: false,
+        },
+        &policy,
+    )?;
+    let rules: Vec<(&str, &str)> = findings
+        .iter()
+        .map(|finding| (finding.path.as_str(), finding.rule.as_str()))
+        .collect();
+    assert_eq!(
+        rules,
+        [
+            ("../evil.txt", "parent_traversal"),
+            ("/etc/cron.d/job", "absolute_path"),
+            ("big.bin", "max_single_file"),
+            // Reported once, although every later member is over the limit too.
+            ("escape", "max_files"),
+            ("escape", "link_outside_root"),
+            ("pipe", "special_entry"),
+        ]
+    );
+    assert!(findings[4].detail.contains("../../etc/passwd"));
+
+    let written: Vec<_> = fs::read_dir(workdir.as_std_path())?.collect();
+    assert_eq!(written.len(), 1, "audit must not write anything");
+
+    // The audit checks the name extraction would use, not a harmless PAX path.
+    let conflicting = workdir.join("conflicting.tar");
+    let mut builder = tar::Builder::new(fs::File::create(conflicting.as_std_path())?);
+    append_conflicting_names(&mut builder, "../../escape.txt", "ok.txt", b"x")?;
+    builder.into_inner()?;
+    let findings = audit_archive(
+        &AuditOptions {
+            archive_path: conflicting,
+            quiet: true,
+            json: false,
+        },
+        &SecurityPolicy::new(),
+    )?;
+    let rules: Vec<(&str, &str)> = findings
+        .iter()
+        .map(|finding| (finding.path.as_str(), finding.rule.as_str()))
+        .collect();
+    assert_eq!(rules, [("../../escape.txt", "parent_traversal")]);
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn owners_are_recorded_and_restored_through_maps_and_shifts() -> Result<()> {
+    use std::os::unix::fs::MetadataExt;
+
+    if !rustix::process::geteuid().is_root() {
+        return Ok(());
+    }
+    let temp = tempdir()?;
+    let workdir = base_workdir(&temp);
+    let input_dir = temp_utf8_path(&temp, "input");
+    fs::create_dir_all(input_dir.as_std_path())?;
+    fs::write(input_dir.join("owned.txt").as_std_path(), b"owned")?;
+    fs::write(input_dir.join("root.txt").as_std_path(), b"root")?;
+    std::os::unix::fs::lchown(input_dir.join("owned.txt"), Some(1234), Some(2345))?;
+
+    let archive_path = workdir.join("owners.tar");
+    let mut create_opts = CreateOptions {
+        format: ArchiveFormat::Pax,
+        no_same_owner: false,
+        ..create_options(&archive_path, &workdir)
+    };
+    create_archive(&create_opts, &SecurityPolicy::new())?;
+    let recorded = |archive_path: &Utf8PathBuf| -> Result<Vec<(String, u64, u64, String)>> {
+        let mut archive = tar::Archive::new(fs::File::open(archive_path.as_std_path())?);
+        archive
+            .entries()?
+            .map(|entry| {
+                let entry = entry?;
+                let header = entry.header();
+                Ok((
+                    entry.path()?.to_string_lossy().into_owned(),
+                    header.uid()?,
+                    header.gid()?,
+                    header.username()?.unwrap_or_default().to_owned(),
+                ))
+            })
+            .collect()
+    };
+    let members = recorded(&archive_path)?;
+    assert!(members.contains(&("owned.txt".into(), 1234, 2345, String::new())));
+    assert!(members.contains(&("root.txt".into(), 0, 0, "root".into())));
+
+    create_opts.owners.uid_shift = 100_000;
+    create_opts.owners.gid_shift = 100_000;
+    create_archive(&create_opts, &SecurityPolicy::new())?;
+    let members = recorded(&archive_path)?;
+    // Shifted owners are recorded by id only.
+    assert!(members.contains(&("root.txt".into(), 100_000, 100_000, String::new())));
+
+    let mut builder = tar::Builder::new(fs::File::create(archive_path.as_std_path())?);
+    for (name, uid, uname) in [("plain", 1234, ""), ("named", 1234, "root")] {
+        let mut header = tar::Header::new_gnu();
+        header.set_mode(0o644);
+        header.set_size(0);
+        header.set_uid(uid);
+        header.set_gid(2345);
+        header.set_username(uname)?;
+        builder.append_data(&mut header, name, io::empty())?;
+    }
+    builder.into_inner()?;
+
+    let owner_map = workdir.join("owners.map");
+    fs::write(owner_map.as_std_path(), "+1234 +42\n")?;
+    let extract = |dest: &str, numeric_owner: bool, owners: OwnerMapping| {
+        extract_archive(
+            &ExtractOptions {
+                numeric_owner,
+                no_same_owner: false,
+                owners,
+                ..extract_options(&archive_path, &temp_utf8_path(&temp, dest))
+            },
+            &SecurityPolicy::new(),
+        )?;
+        let owner = |name: &str| -> Result<(u32, u32)> {
+            let metadata = fs::symlink_metadata(temp.path().join(dest).join(name))?;
+            Ok((metadata.uid(), metadata.gid()))
+        };
+        Ok::<_, anyhow::Error>((owner("plain")?, owner("named")?))
+    };
+
+    // Names win over ids unless owners are numeric.
+    assert_eq!(
+        extract("by-name", false, OwnerMapping::default())?,
+        ((1234, 2345), (0, 2345))
+    );
+    assert_eq!(
+        extract("by-id", true, OwnerMapping::default())?,
+        ((1234, 2345), (1234, 2345))
+    );
+    let shifted = OwnerMapping {
+        uid_shift: 100_000,
+        gid_shift: 100_000,
+        ..OwnerMapping::default()
+    };
+    assert_eq!(
+        extract("shifted", false, shifted)?,
+        ((101_234, 102_345), (101_234, 102_345))
+    );
+    let mapped = OwnerMapping {
+        owner_map: Some(owner_map),
+        ..OwnerMapping::default()
+    };
+    assert_eq!(extract("mapped", true, mapped)?, ((42, 2345), (42, 2345)));
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn extracted_modes_follow_permission_rules() -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp = tempdir()?;
+    let workdir = base_workdir(&temp);
+    let archive_path = workdir.join("modes.tar");
+    let mut builder = tar::Builder::new(fs::File::create(archive_path.as_std_path())?);
+    for (name, mode) in [("shared", 0o1777), ("locked", 0o555)] {
+        let mut dir = tar::Header::new_gnu();
+        dir.set_entry_type(tar::EntryType::Directory);
+        dir.set_mode(mode);
+        dir.set_size(0);
+        builder.append_data(&mut dir, name, io::empty())?;
+    }
+    for (name, mode) in [("shared/tool", 0o4755), ("locked/readme", 0o644)] {
+        let mut file = tar::Header::new_gnu();
+        file.set_mode(mode);
+        file.set_size(2);
+        builder.append_data(&mut file, name, &b"hi"[..])?;
+    }
+    builder.into_inner()?;
+
+    let extract = |dest: &str, rules: ModeRules| {
+        extract_archive(
+            &extract_options(&archive_path, &temp_utf8_path(&temp, dest)),
+            &SecurityPolicy::new().with_mode_rules(rules),
+        )
+    };
+    let mode = |dest: &str, name: &str| -> Result<u32> {
+        let metadata = fs::symlink_metadata(temp.path().join(dest).join(name))?;
+        Ok(metadata.permissions().mode() & 0o7777)
+    };
+
+    extract("default", ModeRules::default())?;
+    assert_eq!(mode("default", "shared")?, 0o775);
+    assert_eq!(mode("default", "shared/tool")?, 0o755);
+    // Directory modes are applied last, so read-only directories still get filled.
+    assert_eq!(mode("default", "locked")?, 0o555);
+    assert_eq!(mode("default", "locked/readme")?, 0o644);
+
+    extract(
+        "kept",
+        ModeRules {
+            setuid: ModeAction::Keep,
+            sticky: ModeAction::Keep,
+            umask: 0o077,
+            ..ModeRules::default()
+        },
+    )?;
+    assert_eq!(mode("kept", "shared")?, 0o1700);
+    assert_eq!(mode("kept", "shared/tool")?, 0o4700);
+
+    let err = extract(
+        "rejected",
+        ModeRules {
+            world_writable: ModeAction::Reject,
+            ..ModeRules::default()
+        },
+    )
+    .expect_err("world-writable directory rejected");
+    assert!(matches!(
+        err.downcast_ref::<PolicyError>(),
+        Some(PolicyError::WorldWritable { mode: 0o1777, .. })
+    ));
+
+    let findings = audit_archive(
+        &AuditOptions {
+            archive_path: archive_path.clone(),
+            quiet: true,
+            json: false,
+        },
+        &SecurityPolicy::new().with_mode_rules(ModeRules {
+            setuid: ModeAction::Reject,
+            world_writable: ModeAction::Reject,
+            ..ModeRules::default()
+        }),
+    )?;
+    let rules: Vec<_> = findings
+        .iter()
+        .map(|finding| (finding.path.as_str(), finding.rule.as_str()))
+        .collect();
+    assert_eq!(
+        rules,
+        [("shared", "world_writable"), ("shared/tool", "mode_bit")]
+    );
+
+    for dest in ["default", "kept"] {
+        fs::set_permissions(
+            temp.path().join(dest).join("locked"),
+            fs::Permissions::from_mode(0o755),
+        )?;
+    }
+    Ok(())
+}
+
+#[test]
+fn preserved_times_round_trip_with_directories_fixed_last() -> Result<()> {
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    let temp = tempdir()?;
+    let workdir = base_workdir(&temp);
+    let input_dir = temp_utf8_path(&temp, "input");
+    fs::create_dir_all(input_dir.join("src").as_std_path())?;
+    let file_path = input_dir.join("src/main.rs");
+    fs::write(file_path.as_std_path(), b"fn main() {}")?;
+    let modified = UNIX_EPOCH + Duration::new(1_600_000_000, 123_456_789);
+    let accessed = UNIX_EPOCH + Duration::new(1_650_000_000, 987_654_321);
+    let dir_modified = UNIX_EPOCH + Duration::new(1_500_000_000, 500_000_000);
+    fs::File::options()
+        .write(true)
+        .open(file_path.as_std_path())?
+        .set_times(
+            fs::FileTimes::new()
+                .set_accessed(accessed)
+                .set_modified(modified),
+        )?;
+    fs::File::open(input_dir.join("src").as_std_path())?
+        .set_times(fs::FileTimes::new().set_modified(dir_modified))?;
+
+    let archive_path = workdir.join("times.tar");
+    let mut create_opts = CreateOptions {
+        format: ArchiveFormat::Pax,
+        preserve_times: true,
+        ..create_options(&archive_path, &workdir)
+    };
+    let extract = |dest: &str, touch: bool| {
+        extract_archive(
+            &ExtractOptions {
+                touch,
+                ..extract_options(&archive_path, &temp_utf8_path(&temp, dest))
+            },
+            &SecurityPolicy::new(),
+        )
+    };
+    let times = |dest: &str, name: &str| -> Result<(SystemTime, SystemTime)> {
+        let metadata = fs::metadata(temp.path().join(dest).join(name))?;
+        Ok((metadata.modified()?, metadata.accessed()?))
+    };
+
+    create_archive(&create_opts, &SecurityPolicy::new())?;
+    extract("pax", false)?;
+    assert_eq!(times("pax", "src/main.rs")?, (modified, accessed));
+    // Writing `main.rs` into `src` happens before the directory's times are restored.
+    assert_eq!(times("pax", "src")?.0, dir_modified);
+
+    extract("touched", true)?;
+    let (touched, _) = times("touched", "src/main.rs")?;
+    assert!(touched > SystemTime::now() - Duration::from_secs(3600));
+
+    // GNU headers carry whole seconds only, and no access time.
+    create_opts.format = ArchiveFormat::Gnu;
+    create_archive(&create_opts, &SecurityPolicy::new())?;
+    extract("gnu", false)?;
+    let whole = UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+    assert_eq!(times("gnu", "src/main.rs")?, (whole, whole));
+
+    // Without `preserve_times`, archives stay reproducible.
+    create_opts.preserve_times = false;
+    create_archive(&create_opts, &SecurityPolicy::new())?;
+    let first = fs::read(archive_path.as_std_path())?;
+    fs::File::options()
+        .write(true)
+        .open(file_path.as_std_path())?
+        .set_modified(SystemTime::now())?;
+    create_archive(&create_opts, &SecurityPolicy::new())?;
+    assert_eq!(first, fs::read(archive_path.as_std_path())?);
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn xattrs_round_trip_without_honoring_capabilities() -> Result<()> {
+    use rustix::fs::{lgetxattr, lsetxattr, XattrFlags};
+
+    let temp = tempdir()?;
+    let workdir = base_workdir(&temp);
+    let input_dir = temp_utf8_path(&temp, "input");
+    fs::create_dir_all(input_dir.as_std_path())?;
+    let tool = input_dir.join("tool");
+    fs::write(tool.as_std_path(), b"#!/bin/sh\n")?;
+    if lsetxattr(
+        tool.as_std_path(),
+        "user.origin",
+        b"ci",
+        XattrFlags::empty(),
+    )
+    .is_err()
+    {
+        // The file system does not support user attributes.
+        return Ok(());
+    }
+    let xattr = |path: std::path::PathBuf, name: &str| {
+        let mut value = [0_u8; 64];
+        lgetxattr(path, name, &mut value[..])
+            .ok()
+            .map(|len| value[..len].to_vec())
+    };
+
+    let archive_path = workdir.join("xattrs.tar");
+    let manifest_path = workdir.join("xattrs.json");
+    let mut create_opts = CreateOptions {
+        manifest_out: Some(manifest_path.clone()),
+        xattrs: true,
+        ..create_options(&archive_path, &workdir)
+    };
+    let err = create_archive(&create_opts, &SecurityPolicy::new()).expect_err("gnu format");
+    assert!(err.to_string().contains("--format pax"), "{err:#}");
+    create_opts.format = ArchiveFormat::Pax;
+    create_archive(&create_opts, &SecurityPolicy::new())?;
+    let manifest = safetar::manifest::read_manifest_json(&manifest_path)?;
+    assert!(manifest[0]
+        .xattrs
+        .as_ref()
+        .is_some_and(|digests| digests.contains_key("user.origin")));
+
+    let extract = |archive_path: &Utf8PathBuf, dest: &str, xattrs: bool, policy: SecurityPolicy| {
+        extract_archive(
+            &ExtractOptions {
+                xattrs,
+                ..extract_options(archive_path, &temp_utf8_path(&temp, dest))
+            },
+            &policy,
+        )
+    };
+    let restored = extract(&archive_path, "restored", true, SecurityPolicy::new())?;
+    safetar::manifest::verify_manifest(&manifest, &restored, false)?;
+    assert_eq!(
+        xattr(temp.path().join("restored/tool"), "user.origin"),
+        Some(b"ci".to_vec())
+    );
+    extract(&archive_path, "plain", false, SecurityPolicy::new())?;
+    assert_eq!(xattr(temp.path().join("plain/tool"), "user.origin"), None);
+
+    // Capabilities and trusted attributes in an archive are never restored by default.
+    let hostile_path = workdir.join("hostile.tar");
+    let mut builder = tar::Builder::new(fs::File::create(hostile_path.as_std_path())?);
+    builder.append_pax_extensions([
+        (
+            "SCHILY.xattr.security.capability",
+            &[1_u8, 0, 0, 2, 0, 0x20, 0, 0][..],
+        ),
+        ("SCHILY.xattr.trusted.overlay.opaque", &b"y"[..]),
+        ("SCHILY.xattr.user.note", &b"kept"[..]),
+    ])?;
+    let mut header = tar::Header::new_ustar();
+    header.set_mode(0o755);
+    header.set_size(0);
+    builder.append_data(&mut header, "ping", io::empty())?;
+    builder.into_inner()?;
+    extract(&hostile_path, "hostile", true, SecurityPolicy::new())?;
+    let ping = temp.path().join("hostile/ping");
+    assert_eq!(xattr(ping.clone(), "user.note"), Some(b"kept".to_vec()));
+    assert_eq!(xattr(ping.clone(), "security.capability"), None);
+    assert_eq!(xattr(ping, "trusted.overlay.opaque"), None);
+
+    let denied = SecurityPolicy::new().with_xattr_rules(XattrRules {
+        allow: vec!["security.*".into()],
+        ..XattrRules::default()
+    });
+    extract(&hostile_path, "denied", true, denied)?;
+    assert_eq!(xattr(temp.path().join("denied/ping"), "user.note"), None);
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn overwrite_modes_decide_what_happens_to_existing_files() -> Result<()> {
+    use safetar::manifest::{ExtractOutcome, ManifestEntry};
+
+    let temp = tempdir()?;
+    let workdir = base_workdir(&temp);
+    let archive_path = workdir.join("update.tar");
+    let mut builder = tar::Builder::new(fs::File::create(archive_path.as_std_path())?);
+    for (name, data) in [("data.txt", &b"new"[..]), ("dir/inner.txt", &b"inner"[..])] {
+        let mut header = tar::Header::new_ustar();
+        header.set_mode(0o644);
+        header.set_size(data.len() as u64);
+        header.set_mtime(1_000_000_000);
+        builder.append_data(&mut header, name, data)?;
+    }
+    builder.into_inner()?;
+
+    // Each destination starts with `data.txt` and a second hard link to it.
+    let prepare = |dest: &str| -> Result<Utf8PathBuf> {
+        let dest = temp_utf8_path(&temp, dest);
+        fs::create_dir_all(dest.join("dir").as_std_path())?;
+        fs::write(dest.join("data.txt").as_std_path(), b"old")?;
+        fs::hard_link(
+            dest.join("data.txt").as_std_path(),
+            dest.join("alias.txt").as_std_path(),
+        )?;
+        Ok(dest)
+    };
+    let extract = |dest: &Utf8PathBuf, overwrite: OverwriteMode, backup: Option<BackupMode>| {
+        extract_archive(
+            &ExtractOptions {
+                overwrite,
+                backup,
+                ..extract_options(&archive_path, dest)
+            },
+            &SecurityPolicy::new(),
+        )
+    };
+    let read = |dest: &Utf8PathBuf, name: &str| fs::read(dest.join(name).as_std_path());
+    let data_entry = |manifest: &[ManifestEntry]| {
+        manifest
+            .iter()
+            .find(|entry| entry.path == "data.txt")
+            .cloned()
+            .expect("data.txt in manifest")
+    };
+
+    // By default the file is replaced, and its other hard link keeps the old data.
+    let dest = prepare("replace")?;
+    let manifest = extract(&dest, OverwriteMode::Replace, None)?;
+    assert_eq!(read(&dest, "data.txt")?, b"new");
+    assert_eq!(read(&dest, "alias.txt")?, b"old");
+    assert_eq!(
+        data_entry(&manifest).outcome,
+        Some(ExtractOutcome::Replaced)
+    );
+    let inner = manifest.iter().find(|entry| entry.path == "dir/inner.txt");
+    assert_eq!(
+        inner.and_then(|entry| entry.outcome),
+        Some(ExtractOutcome::Created)
+    );
+
+    let dest = prepare("keep_old")?;
+    let err = extract(&dest, OverwriteMode::KeepOld, None).expect_err("data.txt exists");
+    assert!(err.to_string().contains("--keep-old-files"), "{err:#}");
+    assert_eq!(read(&dest, "data.txt")?, b"old");
+
+    // Skipped members are reported with what is on disk.
+    let dest = prepare("skip_old")?;
+    let manifest = extract(&dest, OverwriteMode::SkipOld, None)?;
+    assert_eq!(read(&dest, "data.txt")?, b"old");
+    assert_eq!(read(&dest, "dir/inner.txt")?, b"inner");
+    let skipped = data_entry(&manifest);
+    assert_eq!(skipped.outcome, Some(ExtractOutcome::Skipped));
+    assert_eq!(skipped.size, 3);
+
+    // Overwriting in place is seen through every hard link.
+    let dest = prepare("overwrite")?;
+    let manifest = extract(&dest, OverwriteMode::Overwrite, None)?;
+    assert_eq!(read(&dest, "alias.txt")?, b"new");
+    assert_eq!(
+        data_entry(&manifest).outcome,
+        Some(ExtractOutcome::Overwritten)
+    );
+
+    // The existing file was written after the archived one.
+    let dest = prepare("keep_newer")?;
+    let manifest = extract(&dest, OverwriteMode::KeepNewer, None)?;
+    assert_eq!(read(&dest, "data.txt")?, b"old");
+    assert_eq!(data_entry(&manifest).outcome, Some(ExtractOutcome::Skipped));
+
+    let dest = prepare("backup")?;
+    extract(&dest, OverwriteMode::Replace, Some(BackupMode::Simple))?;
+    assert_eq!(read(&dest, "data.txt~")?, b"old");
+    extract(&dest, OverwriteMode::Replace, Some(BackupMode::Numbered))?;
+    let manifest = extract(&dest, OverwriteMode::Overwrite, Some(BackupMode::Numbered))?;
+    assert_eq!(read(&dest, "data.txt.~1~")?, b"new");
+    assert_eq!(read(&dest, "data.txt.~2~")?, b"new");
+    assert_eq!(read(&dest, "alias.txt")?, b"old");
+    let backed_up = data_entry(&manifest);
+    assert_eq!(backed_up.outcome, Some(ExtractOutcome::Replaced));
+    assert_eq!(backed_up.backup.as_deref(), Some("data.txt.~2~"));
+
+    // Only --unlink-first removes a directory in the way, and only an empty one.
+    let dest = prepare("directory")?;
+    fs::remove_file(dest.join("data.txt").as_std_path())?;
+    fs::create_dir(dest.join("data.txt").as_std_path())?;
+    let err = extract(&dest, OverwriteMode::Replace, None).expect_err("directory in the way");
+    assert!(err.to_string().contains("--unlink-first"), "{err:#}");
+    let manifest = extract(&dest, OverwriteMode::UnlinkFirst, None)?;
+    assert_eq!(read(&dest, "data.txt")?, b"new");
+    assert_eq!(
+        data_entry(&manifest).outcome,
+        Some(ExtractOutcome::Replaced)
+    );
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn extraction_never_follows_a_directory_swapped_for_a_symlink() -> Result<()> {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    let temp = tempdir()?;
+    let workdir = base_workdir(&temp);
+    let outside = temp_utf8_path(&temp, "outside");
+    fs::create_dir_all(outside.as_std_path())?;
+
+    // Many members below one directory give the swapper plenty of windows.
+    let archive_path = workdir.join("race.tar");
+    let mut builder = tar::Builder::new(fs::File::create(archive_path.as_std_path())?);
+    for index in 0..500 {
+        let mut header = tar::Header::new_ustar();
+        header.set_mode(0o644);
+        header.set_size(7);
+        builder.append_data(
+            &mut header,
+            format!("shared/deep/file{index}.txt"),
+            &b"payload"[..],
+        )?;
+    }
+    builder.into_inner()?;
+
+    for round in 0..10 {
+        let dest = temp_utf8_path(&temp, &format!("dest{round}"));
+        fs::create_dir_all(dest.join("shared/deep").as_std_path())?;
+        let done = Arc::new(AtomicBool::new(false));
+        let swapper = {
+            let done = Arc::clone(&done);
+            let shared = dest.join("shared").into_std_path_buf();
+            let parked = dest.join("parked").into_std_path_buf();
+            let outside = outside.clone().into_std_path_buf();
+            std::thread::spawn(move || {
+                while !done.load(Ordering::Relaxed) {
+                    // Swap the directory for a symlink leading out, then back.
+                    if fs::rename(&shared, &parked).is_ok() {
+                        let _ = std::os::unix::fs::symlink(&outside, &shared);
+                        std::thread::yield_now();
+                        let _ = fs::remove_file(&shared);
+                        let _ = fs::rename(&parked, &shared);
+                    }
+                }
+            })
+        };
+        // Extraction may fail when it meets the symlink, but never writes through it.
+        let _ = extract_archive(
+            &extract_options(&archive_path, &dest),
+            &SecurityPolicy::new(),
+        );
+        done.store(true, Ordering::Relaxed);
+        swapper.join().expect("swapper thread");
+        assert!(
+            fs::read_dir(outside.as_std_path())?.next().is_none(),
+            "round {round} wrote outside the destination"
+        );
+    }
+    Ok(())
+}
+
+/// Archive of `(name, symlink target)` pairs; members without a target are files.
+fn symlink_archive(path: &Utf8PathBuf, members: &[(&str, Option<&str>)]) -> Result<()> {
+    let mut builder = tar::Builder::new(fs::File::create(path.as_std_path())?);
+    for (name, target) in members {
+        let mut header = tar::Header::new_gnu();
+        header.set_mode(0o644);
+        match target {
+            Some(target) => {
+                header.set_entry_type(tar::EntryType::Symlink);
+                header.set_size(0);
+                builder.append_link(&mut header, name, target)?;
+            }
+            None => {
+                header.set_size(4);
+                builder.append_data(&mut header, name, &b"evil"[..])?;
+            }
+        }
+    }
+    builder.into_inner()?;
+    Ok(())
+}
+
+fn symlink_extract_options(
+    archive_path: &Utf8PathBuf,
+    dest: &Utf8PathBuf,
+    delay_symlinks: bool,
+) -> ExtractOptions {
+    ExtractOptions {
+        delay_symlinks,
+        ..extract_options(archive_path, dest)
+    }
+}
+
+#[test]
+fn members_below_a_symlink_from_the_archive_are_rejected() -> Result<()> {
+    let temp = tempdir()?;
+    let workdir = base_workdir(&temp);
+    let dest = temp_utf8_path(&temp, "nested/dest");
+    fs::create_dir_all(dest.as_std_path())?;
+
+    // Each link is harmless on its own: `x/y -> ..` stays inside the root lexically,
+    // but on disk `x` is the root, so `x/y` would be its parent.
+    let chained = workdir.join("chained.tar");
+    symlink_archive(
+        &chained,
+        &[
+            ("x", Some(".")),
+            ("x/y", Some("..")),
+            ("x/y/escape.txt", None),
+        ],
+    )?;
+    let err = extract_archive(
+        &symlink_extract_options(&chained, &dest, false),
+        &SecurityPolicy::new(),
+    )
+    .expect_err("x/y goes through the symlink x");
+    match err.downcast_ref::<PolicyError>() {
+        Some(PolicyError::SymlinkInPath { path, link }) => {
+            assert_eq!(path, "x/y");
+            assert_eq!(link, "x");
+        }
+        other => panic!("unexpected error {other:?}: {err:#}"),
+    }
+    assert!(fs::symlink_metadata(dest.join("x/y").as_std_path()).is_err());
+    assert!(!workdir.join("nested/escape.txt").exists());
+
+    let findings = audit_archive(
+        &AuditOptions {
+            archive_path: chained,
+            quiet: true,
+            json: false,
+        },
+        &SecurityPolicy::new(),
+    )?;
+    let rules: Vec<(&str, &str)> = findings
+        .iter()
+        .map(|finding| (finding.path.as_str(), finding.rule.as_str()))
+        .collect();
+    assert_eq!(
+        rules,
+        [
+            ("x/y", "symlink_in_path"),
+            ("x/y/escape.txt", "symlink_in_path"),
+        ]
+    );
+
+    // A member that replaces the symlink clears the way again.
+    let replaced = workdir.join("replaced.tar");
+    symlink_archive(
+        &replaced,
+        &[("conf", Some("other")), ("conf", None), ("data/conf", None)],
+    )?;
+    let fresh = temp_utf8_path(&temp, "fresh");
+    extract_archive(
+        &symlink_extract_options(&replaced, &fresh, false),
+        &SecurityPolicy::new(),
+    )?;
+    assert!(fresh.join("conf").is_file());
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn members_below_a_symlink_in_the_destination_are_rejected() -> Result<()> {
+    let temp = tempdir()?;
+    let workdir = base_workdir(&temp);
+    let outside = temp_utf8_path(&temp, "outside");
+    fs::create_dir_all(outside.as_std_path())?;
+    let dest = temp_utf8_path(&temp, "dest");
+    fs::create_dir_all(dest.join("real").as_std_path())?;
+    std::os::unix::fs::symlink(outside.as_std_path(), dest.join("planted").as_std_path())?;
+
+    let archive_path = workdir.join("planted.tar");
+    symlink_archive(
+        &archive_path,
+        &[("real/ok.txt", None), ("planted/passwd", None)],
+    )?;
+    for delay_symlinks in [false, true] {
+        let err = extract_archive(
+            &symlink_extract_options(&archive_path, &dest, delay_symlinks),
+            &SecurityPolicy::new(),
+        )
+        .expect_err("planted is a symlink");
+        assert!(
+            matches!(
+                err.downcast_ref::<PolicyError>(),
+                Some(PolicyError::SymlinkInPath { link, .. }) if link == "planted"
+            ),
+            "{err:#}"
+        );
+    }
+    assert!(dest.join("real/ok.txt").is_file());
+    assert!(fs::read_dir(outside.as_std_path())?.next().is_none());
+    Ok(())
+}
+
+#[test]
+fn delayed_symlinks_leave_members_below_them_in_directories() -> Result<()> {
+    let temp = tempdir()?;
+    let workdir = base_workdir(&temp);
+    let archive_path = workdir.join("rootfs.tar");
+    symlink_archive(
+        &archive_path,
+        &[
+            ("lib", Some("usr/lib")),
+            ("bin", Some("usr/bin")),
+            ("lib/libc.so", None),
+            ("usr/bin/sh", None),
+        ],
+    )?;
+
+    let rejected = temp_utf8_path(&temp, "rejected");
+    let err = extract_archive(
+        &symlink_extract_options(&archive_path, &rejected, false),
+        &SecurityPolicy::new(),
+    )
+    .expect_err("lib/libc.so goes through the symlink lib");
+    assert!(err.downcast_ref::<PolicyError>().is_some(), "{err:#}");
+
+    let dest = temp_utf8_path(&temp, "delayed");
+    let manifest = extract_archive(
+        &symlink_extract_options(&archive_path, &dest, true),
+        &SecurityPolicy::new(),
+    )?;
+    assert!(fs::symlink_metadata(dest.join("lib").as_std_path())?.is_dir());
+    assert_eq!(fs::read(dest.join("lib/libc.so").as_std_path())?, b"evil");
+    assert!(fs::symlink_metadata(dest.join("bin").as_std_path())?.is_symlink());
+    assert_eq!(fs::read(dest.join("bin/sh").as_std_path())?, b"evil");
+    let bin = manifest.iter().find(|entry| entry.path == "bin");
+    assert!(matches!(bin, Some(entry) if entry.kind == ManifestKind::Symlink));
+    assert!(!manifest.iter().any(|entry| entry.path == "lib"));
+    Ok(())
+}
+
+/// Archive with `app.conf`, `lib/` (mtime 1000000) holding `lib/core.so`, then
+/// whatever `tail` appends.
+fn deployment_archive(
+    path: &Utf8PathBuf,
+    tail: impl FnOnce(&mut tar::Builder<fs::File>) -> io::Result<()>,
+) -> Result<()> {
+    let mut builder = tar::Builder::new(fs::File::create(path.as_std_path())?);
+    let file = |builder: &mut tar::Builder<fs::File>, name: &str, data: &[u8]| {
+        let mut header = tar::Header::new_gnu();
+        header.set_mode(0o644);
+        header.set_size(data.len() as u64);
+        builder.append_data(&mut header, name, data)
+    };
+    file(&mut builder, "app.conf", b"port = 80")?;
+    let mut dir = tar::Header::new_gnu();
+    dir.set_entry_type(tar::EntryType::Directory);
+    dir.set_mode(0o755);
+    dir.set_mtime(1_000_000);
+    dir.set_size(0);
+    builder.append_data(&mut dir, "lib", io::empty())?;
+    file(&mut builder, "lib/core.so", b"v2")?;
+    tail(&mut builder)?;
+    builder.into_inner()?;
+    Ok(())
+}
+
+fn atomic_options(archive_path: &Utf8PathBuf, dest: &Utf8PathBuf) -> ExtractOptions {
+    ExtractOptions {
+        atomic: true,
+        ..extract_options(archive_path, dest)
+    }
+}
+
+#[test]
+fn atomic_extraction_leaves_nothing_behind_on_failure() -> Result<()> {
+    let temp = tempdir()?;
+    let workdir = base_workdir(&temp);
+    let releases = temp_utf8_path(&temp, "releases");
+    fs::create_dir_all(releases.as_std_path())?;
+    let listing = |dir: &Utf8PathBuf| -> Result<Vec<String>> {
+        let mut names = fs::read_dir(dir.as_std_path())?
+            .map(|entry| Ok(entry?.file_name().to_string_lossy().into_owned()))
+            .collect::<Result<Vec<_>>>()?;
+        names.sort();
+        Ok(names)
+    };
+
+    // The escaping symlink comes after members that were already written.
+    let escaping = workdir.join("escaping.tar");
+    deployment_archive(&escaping, |builder| {
+        let mut link = tar::Header::new_gnu();
+        link.set_entry_type(tar::EntryType::Symlink);
+        link.set_size(0);
+        builder.append_link(&mut link, "escape", "../../etc/passwd")
+    })?;
+    let fresh = releases.join("v2");
+    let err = extract_archive(&atomic_options(&escaping, &fresh), &SecurityPolicy::new())
+        .expect_err("the symlink escapes");
+    assert!(err.downcast_ref::<PolicyError>().is_some(), "{err:#}");
+    assert!(listing(&releases)?.is_empty(), "{:?}", listing(&releases)?);
+
+    let archive_path = workdir.join("release.tar");
+    deployment_archive(&archive_path, |_| Ok(()))?;
+    let current = releases.join("current");
+    fs::create_dir_all(current.join("lib").as_std_path())?;
+    fs::write(current.join("app.conf").as_std_path(), b"port = 8080")?;
+    fs::write(current.join("lib/core.so").as_std_path(), b"v1")?;
+    let untouched = |current: &Utf8PathBuf| -> Result<()> {
+        assert_eq!(
+            fs::read(current.join("app.conf").as_std_path())?,
+            b"port = 8080"
+        );
+        assert_eq!(fs::read(current.join("lib/core.so").as_std_path())?, b"v1");
+        Ok(())
+    };
+
+    // Quotas and the manifest are checked before anything is moved into place.
+    let quota = SecurityPolicy::new().with_max_files(Some(2));
+    let err = extract_archive(&atomic_options(&archive_path, &current), &quota)
+        .expect_err("three members are over the limit");
+    assert!(err.downcast_ref::<PolicyError>().is_some(), "{err:#}");
+    untouched(&current)?;
+
+    let good = extract_archive(
+        &atomic_options(&archive_path, &fresh),
+        &SecurityPolicy::new(),
+    )?;
+    let mut tampered = good.clone();
+    let core = tampered
+        .iter_mut()
+        .find(|entry| entry.path == "lib/core.so")
+        .expect("lib/core.so in manifest");
+    core.sha256 = "0".repeat(64);
+    let manifest_path = workdir.join("release.json");
+    write_manifest_json(&tampered, &manifest_path)?;
+    let err = extract_archive(
+        &ExtractOptions {
+            manifest: Some(manifest_path),
+            ..atomic_options(&archive_path, &current)
+        },
+        &SecurityPolicy::new(),
+    )
+    .expect_err("digest must match");
+    assert!(err.downcast_ref::<ManifestError>().is_some(), "{err:#}");
+    untouched(&current)?;
+
+    // A directory in the way is found before the first member moves.
+    let blocked = releases.join("blocked");
+    fs::create_dir_all(blocked.join("app.conf").as_std_path())?;
+    fs::write(blocked.join("lib").as_std_path(), b"not a directory")?;
+    let err = extract_archive(
+        &atomic_options(&archive_path, &blocked),
+        &SecurityPolicy::new(),
+    )
+    .expect_err("app.conf is a directory");
+    assert!(err.to_string().contains("over a directory"), "{err:#}");
+    assert_eq!(
+        fs::read(blocked.join("lib").as_std_path())?,
+        b"not a directory"
+    );
+
+    let err = extract_archive(
+        &ExtractOptions {
+            overwrite: OverwriteMode::KeepOld,
+            ..atomic_options(&archive_path, &current)
+        },
+        &SecurityPolicy::new(),
+    )
+    .expect_err("--atomic always replaces");
+    assert!(
+        err.downcast_ref::<safetar::error::UserInputError>()
+            .is_some(),
+        "{err:#}"
+    );
+    untouched(&current)?;
+    assert_eq!(listing(&releases)?, ["blocked", "current", "v2"]);
+    Ok(())
+}
+
+#[test]
+fn atomic_extraction_moves_members_into_place() -> Result<()> {
+    use safetar::manifest::ExtractOutcome;
+
+    let temp = tempdir()?;
+    let workdir = base_workdir(&temp);
+    let archive_path = workdir.join("release.tar");
+    deployment_archive(&archive_path, |_| Ok(()))?;
+
+    let fresh = temp_utf8_path(&temp, "releases/v2");
+    let manifest = extract_archive(
+        &atomic_options(&archive_path, &fresh),
+        &SecurityPolicy::new(),
+    )?;
+    assert_eq!(fs::read(fresh.join("lib/core.so").as_std_path())?, b"v2");
+    assert!(manifest
+        .iter()
+        .all(|entry| entry.outcome == Some(ExtractOutcome::Created)));
+
+    let current = temp_utf8_path(&temp, "current");
+    fs::create_dir_all(current.join("lib").as_std_path())?;
+    fs::write(current.join("app.conf").as_std_path(), b"port = 8080")?;
+    fs::write(current.join("lib/local.so"), b"kept")?;
+    let manifest = extract_archive(
+        &atomic_options(&archive_path, &current),
+        &SecurityPolicy::new(),
+    )?;
+    assert_eq!(
+        fs::read(current.join("app.conf").as_std_path())?,
+        b"port = 80"
+    );
+    assert_eq!(fs::read(current.join("lib/core.so").as_std_path())?, b"v2");
+    assert_eq!(
+        fs::read(current.join("lib/local.so").as_std_path())?,
+        b"kept"
+    );
+    let outcome = |path: &str| {
+        manifest
+            .iter()
+            .find(|entry| entry.path == path)
+            .and_then(|entry| entry.outcome)
+    };
+    assert_eq!(outcome("app.conf"), Some(ExtractOutcome::Replaced));
+    assert_eq!(outcome("lib"), Some(ExtractOutcome::Merged));
+    assert_eq!(outcome("lib/core.so"), Some(ExtractOutcome::Created));
+    // Times are restored after merging, which changed the directory.
+    let modified = fs::metadata(current.join("lib").as_std_path())?.modified()?;
+    assert_eq!(
+        modified,
+        std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000)
+    );
+
+    // Both staging directories are gone.
+    let mut leftovers = fs::read_dir(temp.path())?
+        .map(|entry| Ok(entry?.file_name().to_string_lossy().into_owned()))
+        .collect::<Result<Vec<_>>>()?;
+    leftovers.sort();
+    assert_eq!(leftovers, ["current", "release.tar", "releases"]);
+    Ok(())
+}
+
+/// A zstd-compressed archive holding 32 MiB of zeros in a few kilobytes.
+fn zstd_bomb(path: &Utf8PathBuf, seekable: bool) -> Result<()> {
+    use std::io::{Read as _, Write as _};
+
+    let mut builder = tar::Builder::new(Vec::new());
+    let mut header = tar::Header::new_gnu();
+    header.set_mode(0o644);
+    header.set_size(32 << 20);
+    builder.append_data(&mut header, "zeros.bin", io::repeat(0).take(32 << 20))?;
+    let tar = builder.into_inner()?;
+    let compressed = if seekable {
+        let mut encoder = SeekableEncoder::new(Vec::new(), 19)?;
+        encoder.write_all(&tar)?;
+        encoder.finish()?
+    } else {
+        zstd::encode_all(&tar[..], 19)?
+    };
+    fs::write(path.as_std_path(), compressed)?;
+    Ok(())
+}
+
+#[test]
+fn compression_limits_stop_archive_bombs() -> Result<()> {
+    let temp = tempdir()?;
+    let workdir = base_workdir(&temp);
+    let archive_path = workdir.join("bomb.tar.zst");
+    zstd_bomb(&archive_path, false)?;
+    let dest = temp_utf8_path(&temp, "dest");
+    let extract =
+        |policy: &SecurityPolicy| extract_archive(&extract_options(&archive_path, &dest), policy);
+    let list = |limits: PolicyLimits| {
+        list_archive(&ListOptions {
+            archive_path: archive_path.clone(),
+            verbose: false,
+            quiet: true,
+            json: false,
+            selection: MemberSelection::default(),
+            limits,
+        })
+    };
+
+    // Well below the total-size quota, but far above the default ratio.
+    let err = extract(&SecurityPolicy::new()).expect_err("the ratio is over 1000");
+    assert!(
+        matches!(
+            err.downcast_ref::<PolicyError>(),
+            Some(PolicyError::CompressionRatioExceeded { limit: 1000, .. })
+        ),
+        "{err:#}"
+    );
+    let err = list(PolicyLimits::default()).expect_err("listing decodes the archive too");
+    assert!(err.downcast_ref::<PolicyError>().is_some(), "{err:#}");
+
+    let input = SecurityPolicy::new()
+        .with_max_compression_ratio(Some(u64::MAX))
+        .with_max_compressed_input(Some(256));
+    let err = extract(&input).expect_err("the archive is over 256 bytes");
+    assert!(
+        matches!(
+            err.downcast_ref::<PolicyError>(),
+            Some(PolicyError::CompressedInputExceeded { limit: 256, .. })
+        ),
+        "{err:#}"
+    );
+
+    let trusted = SecurityPolicy::new().with_max_compression_ratio(Some(u64::MAX));
+    extract(&trusted)?;
+    assert_eq!(
+        fs::metadata(dest.join("zeros.bin").as_std_path())?.len(),
+        32 << 20
+    );
+    assert_eq!(list(trusted.limits())?.len(), 1);
+
+    // Members reached through an index are held to the same limits.
+    let seekable_path = workdir.join("seekable-bomb.tar.zst");
+    zstd_bomb(&seekable_path, true)?;
+    let index_path = workdir.join("seekable-bomb.tar.zst.idx");
+    index_archive(&IndexOptions {
+        archive_path: seekable_path.clone(),
+        output: index_path.clone(),
+    })?;
+    let indexed_dest = temp_utf8_path(&temp, "indexed");
+    let extract_indexed = |policy: &SecurityPolicy| {
+        extract_archive(
+            &ExtractOptions {
+                index: Some(index_path.clone()),
+                ..extract_options(&seekable_path, &indexed_dest)
+            },
+            policy,
+        )
+    };
+    let err = extract_indexed(&SecurityPolicy::new()).expect_err("the ratio is over 1000");
+    assert!(
+        matches!(
+            err.downcast_ref::<PolicyError>(),
+            Some(PolicyError::CompressionRatioExceeded { limit: 1000, .. })
+        ),
+        "{err:#}"
+    );
+    let err = extract_indexed(&input).expect_err("the archive is over 256 bytes");
+    assert!(
+        matches!(
+            err.downcast_ref::<PolicyError>(),
+            Some(PolicyError::CompressedInputExceeded { limit: 256, .. })
+        ),
+        "{err:#}"
+    );
+    extract_indexed(&trusted)?;
+    assert_eq!(
+        fs::metadata(indexed_dest.join("zeros.bin").as_std_path())?.len(),
+        32 << 20
+    );
     Ok(())
 }
@@ -79,6 +79,116 @@ fn list_outputs_paths() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[test]
+fn archives_stream_through_stdin_and_stdout() -> anyhow::Result<()> {
+    let temp = tempdir()?;
+    let input_dir = temp.path().join("input");
+    fs::create_dir_all(input_dir.join("nested"))?;
+    fs::write(input_dir.join("nested/piped.txt"), b"through a pipe")?;
+
+    let created = cargo_bin_cmd!("safetar")
+        .args(["create", "--gzip", "-v", "-f", "-"])
+        .arg(&input_dir)
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("added nested/piped.txt"));
+    let archive = created.get_output().stdout.clone();
+    assert_eq!(
+        &archive[..2],
+        &[0x1F, 0x8B],
+        "stdout carries only the archive"
+    );
+
+    let extract_dir = temp.path().join("extract");
+    cargo_bin_cmd!("safetar")
+        .args(["extract", "-f", "-", "-C"])
+        .arg(&extract_dir)
+        .write_stdin(archive.clone())
+        .assert()
+        .success();
+    assert_eq!(collect_files(&input_dir), collect_files(&extract_dir));
+
+    cargo_bin_cmd!("safetar")
+        .args(["list", "-f", "-"])
+        .write_stdin(archive.clone())
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("nested/piped.txt"));
+
+    cargo_bin_cmd!("safetar")
+        .args(["cat", "-f", "-", "nested/piped.txt"])
+        .write_stdin(archive)
+        .assert()
+        .success()
+        .stdout("through a pipe");
+
+    Ok(())
+}
+
+#[test]
+fn audit_reports_violations_through_exit_status() -> anyhow::Result<()> {
+    let temp = tempdir()?;
+    let input_dir = temp.path().join("input");
+    fs::create_dir_all(&input_dir)?;
+    fs::write(input_dir.join("a.txt"), b"a")?;
+    fs::write(input_dir.join("b.txt"), b"b")?;
+    let archive_path = temp.path().join("drop.tar");
+
+    cargo_bin_cmd!("safetar")
+        .args(["create", "--file"])
+        .arg(&archive_path)
+        .arg(&input_dir)
+        .assert()
+        .success();
+
+    cargo_bin_cmd!("safetar")
+        .args(["audit", "--file"])
+        .arg(&archive_path)
+        .assert()
+        .success()
+        .stdout("");
+
+    let audited = cargo_bin_cmd!("safetar")
+        .args(["audit", "--json", "--max-files", "1", "--file"])
+        .arg(&archive_path)
+        .assert()
+        .code(3)
+        .stderr(predicate::str::contains("policy violations found: 1"));
+    let findings: serde_json::Value = serde_json::from_slice(&audited.get_output().stdout)?;
+    assert_eq!(findings[0]["rule"], "max_files");
+
+    Ok(())
+}
+
+#[test]
+fn compression_ratio_limit_applies_to_list() -> anyhow::Result<()> {
+    let temp = tempdir()?;
+    let mut builder = tar::Builder::new(Vec::new());
+    let mut header = tar::Header::new_gnu();
+    header.set_mode(0o644);
+    header.set_size(8 << 20);
+    builder.append_data(&mut header, "zeros.bin", &vec![0_u8; 8 << 20][..])?;
+    let archive_path = temp.path().join("bomb.tar.zst");
+    fs::write(
+        &archive_path,
+        zstd::encode_all(&builder.into_inner()?[..], 19)?,
+    )?;
+
+    cargo_bin_cmd!("safetar")
+        .args(["list", "--file"])
+        .arg(&archive_path)
+        .assert()
+        .code(3)
+        .stderr(predicate::str::contains("compression ratio exceeded"));
+    cargo_bin_cmd!("safetar")
+        .args(["list", "--max-compression-ratio", "100000", "--file"])
+        .arg(&archive_path)
+        .assert()
+        .success()
+        .stdout("zeros.bin\n");
+    Ok(())
+}
+
 fn collect_files(root: &std::path::Path) -> Vec<(Utf8PathBuf, String)> {
     let mut entries = Vec::new();
     for entry in WalkDir::new(root).into_iter().filter_map(Result::ok) {
@@ -4,7 +4,7 @@ use std::fs;
 
 use anyhow::Result;
 use camino::Utf8PathBuf;
-use safetar::manifest::{self, ManifestItem, ManifestKind};
+use safetar::manifest::{self, ManifestError, ManifestItem, ManifestKind};
 use tempfile::tempdir;
 
 #[test]
@@ -26,6 +26,9 @@ fn write_and_read_manifest() -> Result<()> {
         link_target: None,
         size: metadata.len(),
         mtime: metadata.modified().ok(),
+        xattrs: None,
+        outcome: None,
+        backup: None,
     };
     let dir_item = ManifestItem {
         relative: Utf8PathBuf::from("dir"),
@@ -34,6 +37,9 @@ fn write_and_read_manifest() -> Result<()> {
         link_target: None,
         size: 0,
         mtime: None,
+        xattrs: None,
+        outcome: None,
+        backup: None,
     };
 
     let entries = manifest::collect_manifest(&[file_item, dir_item])?;
@@ -60,6 +66,9 @@ fn manifest_verification_detects_mismatch() -> Result<()> {
         link_target: None,
         size: fs::metadata(&expected_file)?.len(),
         mtime: None,
+        xattrs: None,
+        outcome: None,
+        backup: None,
     };
     let actual_item = ManifestItem {
         relative: Utf8PathBuf::from("file.txt"),
@@ -68,6 +77,9 @@ fn manifest_verification_detects_mismatch() -> Result<()> {
         link_target: None,
         size: fs::metadata(&actual_file)?.len(),
         mtime: None,
+        xattrs: None,
+        outcome: None,
+        backup: None,
     };
 
     let expected = manifest::collect_manifest(&[expected_item])?;
@@ -94,6 +106,9 @@ fn relaxed_manifest_allows_new_entries() -> Result<()> {
         link_target: None,
         size: fs::metadata(expected_file.as_std_path())?.len(),
         mtime: None,
+        xattrs: None,
+        outcome: None,
+        backup: None,
     };
     let extra_item = ManifestItem {
         relative: Utf8PathBuf::from("extra.txt"),
@@ -102,6 +117,9 @@ fn relaxed_manifest_allows_new_entries() -> Result<()> {
         link_target: None,
         size: fs::metadata(actual_extra.as_std_path())?.len(),
         mtime: None,
+        xattrs: None,
+        outcome: None,
+        backup: None,
     };
 
     let expected = manifest::collect_manifest(std::slice::from_ref(&expected_item))?;
@@ -110,6 +128,41 @@ fn relaxed_manifest_allows_new_entries() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn manifest_verification_compares_xattr_digests() -> Result<()> {
+    let temp = tempdir()?;
+    let file = base_utf8_path(&temp, "file.txt");
+    fs::write(file.as_std_path(), b"hello")?;
+    let item = |xattrs: Option<&[(&str, &[u8])]>| ManifestItem {
+        relative: Utf8PathBuf::from("file.txt"),
+        absolute: file.clone(),
+        kind: ManifestKind::File,
+        link_target: None,
+        size: 5,
+        mtime: None,
+        xattrs: xattrs.map(|xattrs| manifest::xattr_digests(xattrs.iter().copied())),
+        outcome: None,
+        backup: None,
+    };
+
+    let expected = manifest::collect_manifest(&[item(Some(&[("user.origin", b"build")]))])?;
+    let same = manifest::collect_manifest(&[item(Some(&[("user.origin", b"build")]))])?;
+    manifest::verify_manifest(&expected, &same, false)?;
+    // Extractions that do not restore attributes cannot check them.
+    let untracked = manifest::collect_manifest(&[item(None)])?;
+    manifest::verify_manifest(&expected, &untracked, false)?;
+
+    let changed = manifest::collect_manifest(&[item(Some(&[("user.origin", b"other")]))])?;
+    let err = manifest::verify_manifest(&expected, &changed, false).expect_err("mismatch");
+    assert!(matches!(
+        err.downcast_ref::<ManifestError>(),
+        Some(ManifestError::XattrMismatch { name, .. }) if name == "user.origin"
+    ));
+    let missing = manifest::collect_manifest(&[item(Some(&[]))])?;
+    assert!(manifest::verify_manifest(&expected, &missing, false).is_err());
+    Ok(())
+}
+
 fn base_utf8_path(dir: &tempfile::TempDir, segment: &str) -> Utf8PathBuf {
     Utf8PathBuf::from_path_buf(dir.path().join(segment)).expect("utf8 temp path")
 }